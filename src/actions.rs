@@ -201,7 +201,7 @@ impl EGraph {
         Ok(Program(compiler.instructions))
     }
 
-    fn perform_set(
+    pub(crate) fn perform_set(
         &mut self,
         table: Symbol,
         new_value: Value,
@@ -248,6 +248,9 @@ impl EGraph {
             }
         } else {
             function.insert(args, new_value, self.timestamp);
+            if function.schema.output.is_eq_sort() {
+                self.unionfind.record_row(table, args, new_value);
+            }
         }
         Ok(())
     }
@@ -292,6 +295,7 @@ impl EGraph {
                                 let id = self.unionfind.make_set();
                                 let value = Value::from_id(out.name(), id);
                                 function.insert(values, value, ts);
+                                self.unionfind.record_node(id, *f, values);
                                 value
                             }
                             Some(default) => {
@@ -302,14 +306,14 @@ impl EGraph {
                             }
                             _ => {
                                 return Err(Error::NotFoundError(NotFoundError(Expr::Var(
-                                    (),
+                                    DUMMY_SPAN.clone(),
                                     format!("No value found for {f} {:?}", values).into(),
                                 ))))
                             }
                         }
                     } else {
                         return Err(Error::NotFoundError(NotFoundError(Expr::Var(
-                            (),
+                            DUMMY_SPAN.clone(),
                             format!("No value found for {f} {:?}", values).into(),
                         ))));
                     };
@@ -344,11 +348,13 @@ impl EGraph {
                     let new_len = stack.len() - arity;
                     let values = &stack[new_len..];
                     let sort = values[0].tag;
-                    let first = self.unionfind.find(Id::from(values[0].bits as usize));
-                    values[1..].iter().fold(first, |a, b| {
-                        let b = self.unionfind.find(Id::from(b.bits as usize));
-                        self.unionfind.union(a, b, sort)
-                    });
+                    // union the ids as they were computed, rather than their
+                    // canonical representatives, so proofs mention the terms the
+                    // action built
+                    let first = Id::from(values[0].bits as usize);
+                    for b in &values[1..] {
+                        self.unionfind.union(first, Id::from(b.bits as usize), sort);
+                    }
                     stack.truncate(new_len);
                 }
                 Instruction::Extract(arity) => {
@@ -365,7 +371,7 @@ impl EGraph {
                             values[0],
                             &mut termdag,
                             self.type_info().sorts.get(&values[0].tag).unwrap(),
-                        );
+                        )?;
                         let extracted = termdag.to_string(&term);
                         log::info!("extracted with cost {cost}: {extracted}");
                         self.print_msg(extracted);
@@ -376,7 +382,9 @@ impl EGraph {
                         });
                     } else {
                         if variants < 0 {
-                            panic!("Cannot extract negative number of variants");
+                            return Err(Error::ExtractError(
+                                "Cannot extract negative number of variants".into(),
+                            ));
                         }
                         let terms =
                             self.extract_variants(values[0], variants as usize, &mut termdag);
@@ -1,3 +1,5 @@
+use lalrpop_util::ParseError;
+
 use super::{Rewrite, Rule};
 use crate::*;
 
@@ -46,12 +48,20 @@ fn desugar_rewrite(
     subsume: bool,
 ) -> Vec<NCommand> {
     let var = Symbol::from("rewrite_var__");
-    let mut head = Actions::singleton(Action::Union((), Expr::Var((), var), rewrite.rhs.clone()));
+    let mut head = Actions::singleton(Action::Union(
+        DUMMY_SPAN.clone(),
+        Expr::Var(DUMMY_SPAN.clone(), var),
+        rewrite.rhs.clone(),
+    ));
     if subsume {
         match &rewrite.lhs {
             Expr::Call(_, f, args) => {
-                head.0
-                    .push(Action::Change((), Change::Subsume, *f, args.to_vec()));
+                head.0.push(Action::Change(
+                    DUMMY_SPAN.clone(),
+                    Change::Subsume,
+                    *f,
+                    args.to_vec(),
+                ));
             }
             _ => {
                 panic!("Subsumed rewrite must have a function call on the lhs");
@@ -65,10 +75,13 @@ fn desugar_rewrite(
         ruleset,
         name,
         rule: Rule {
-            body: [Fact::Eq(vec![Expr::Var((), var), rewrite.lhs.clone()])]
-                .into_iter()
-                .chain(rewrite.conditions.clone())
-                .collect(),
+            body: [Fact::Eq(
+                DUMMY_SPAN.clone(),
+                vec![Expr::Var(DUMMY_SPAN.clone(), var), rewrite.lhs.clone()],
+            )]
+            .into_iter()
+            .chain(rewrite.conditions.clone())
+            .collect(),
             head,
         },
     }]
@@ -104,22 +117,22 @@ fn add_semi_naive_rule(desugar: &mut Desugar, rule: Rule) -> Option<Rule> {
         match head_slice {
             Action::Set(_ann, _, _, expr) => {
                 var_set.extend(expr.vars());
-                if let Expr::Call((), _, _) = expr {
+                if let Expr::Call(_, _, _) = expr {
                     add_new_rule = true;
 
                     let fresh_symbol = desugar.get_fresh();
-                    let fresh_var = Expr::Var((), fresh_symbol);
+                    let fresh_var = Expr::Var(DUMMY_SPAN.clone(), fresh_symbol);
                     let expr = std::mem::replace(expr, fresh_var.clone());
-                    new_head_atoms.push(Fact::Eq(vec![fresh_var, expr]));
+                    new_head_atoms.push(Fact::Eq(DUMMY_SPAN.clone(), vec![fresh_var, expr]));
                 };
             }
             Action::Let(_ann, symbol, expr) if var_set.contains(symbol) => {
                 var_set.extend(expr.vars());
-                if let Expr::Call((), _, _) = expr {
+                if let Expr::Call(_, _, _) = expr {
                     add_new_rule = true;
 
-                    let var = Expr::Var((), *symbol);
-                    new_head_atoms.push(Fact::Eq(vec![var, expr.clone()]));
+                    let var = Expr::Var(DUMMY_SPAN.clone(), *symbol);
+                    new_head_atoms.push(Fact::Eq(DUMMY_SPAN.clone(), vec![var, expr.clone()]));
                 }
             }
             _ => (),
@@ -142,13 +155,17 @@ fn add_semi_naive_rule(desugar: &mut Desugar, rule: Rule) -> Option<Rule> {
 fn desugar_simplify(desugar: &mut Desugar, expr: &Expr, schedule: &Schedule) -> Vec<NCommand> {
     let mut res = vec![NCommand::Push(1)];
     let lhs = desugar.get_fresh();
-    res.push(NCommand::CoreAction(Action::Let((), lhs, expr.clone())));
+    res.push(NCommand::CoreAction(Action::Let(
+        DUMMY_SPAN.clone(),
+        lhs,
+        expr.clone(),
+    )));
     res.push(NCommand::RunSchedule(schedule.clone()));
     res.extend(
         desugar_command(
             Command::QueryExtract {
                 variants: 0,
-                expr: Expr::Var((), lhs),
+                expr: Expr::Var(DUMMY_SPAN.clone(), lhs),
             },
             desugar,
             false,
@@ -182,23 +199,33 @@ pub(crate) fn desugar_calc(
 
         // add the two exprs only when they are calls (consts and vars don't need to be populated).
         if let Expr::Call(..) = expr1 {
-            res.push(Command::Action(Action::Expr((), expr1.clone())));
+            res.push(Command::Action(Action::Expr(
+                DUMMY_SPAN.clone(),
+                expr1.clone(),
+            )));
         }
         if let Expr::Call(..) = expr2 {
-            res.push(Command::Action(Action::Expr((), expr2.clone())));
+            res.push(Command::Action(Action::Expr(
+                DUMMY_SPAN.clone(),
+                expr2.clone(),
+            )));
         }
 
         res.push(Command::RunSchedule(Schedule::Saturate(Box::new(
             Schedule::Run(RunConfig {
                 ruleset: "".into(),
-                until: Some(vec![Fact::Eq(vec![expr1.clone(), expr2.clone()])]),
+                until: Some(vec![Fact::Eq(
+                    DUMMY_SPAN.clone(),
+                    vec![expr1.clone(), expr2.clone()],
+                )]),
+                backoff: None,
             }),
         ))));
 
-        res.push(Command::Check(vec![Fact::Eq(vec![
-            expr1.clone(),
-            expr2.clone(),
-        ])]));
+        res.push(Command::Check(vec![Fact::Eq(
+            DUMMY_SPAN.clone(),
+            vec![expr1.clone(), expr2.clone()],
+        )]));
 
         res.push(Command::Pop(1));
     }
@@ -237,8 +264,7 @@ pub(crate) fn desugar_command(
             desugar_birewrite(ruleset, rewrite_name(&rewrite).into(), &rewrite)
         }
         Command::Include(file) => {
-            let s = std::fs::read_to_string(&file)
-                .unwrap_or_else(|_| panic!("Failed to read file {file}"));
+            let s = std::fs::read_to_string(&file).map_err(|e| Error::IoError(file.into(), e))?;
             return desugar_commands(
                 desugar.parse_program(&s)?,
                 desugar,
@@ -290,7 +316,7 @@ pub(crate) fn desugar_command(
         Command::QueryExtract { variants, expr } => {
             let fresh = desugar.get_fresh();
             let fresh_ruleset = desugar.get_fresh();
-            let desugaring = if let Expr::Var((), v) = expr {
+            let desugaring = if let Expr::Var(_, v) = expr {
                 format!("(extract {v} {variants})")
             } else {
                 format!(
@@ -319,6 +345,8 @@ pub(crate) fn desugar_command(
             res
         }
         Command::CheckProof => vec![NCommand::CheckProof],
+        Command::Explain(lhs, rhs) => vec![NCommand::Explain(lhs, rhs)],
+        Command::Extract { expr, options } => vec![NCommand::Extract { expr, options }],
         Command::PrintFunction(symbol, size) => {
             vec![NCommand::PrintTable(symbol, size)]
         }
@@ -337,9 +365,39 @@ pub(crate) fn desugar_command(
             desugared.push(NCommand::Fail(Box::new(last)));
             return Ok(desugared);
         }
-        Command::Input { name, file } => {
-            vec![NCommand::Input { name, file }]
+        Command::Input {
+            name,
+            file,
+            options,
+        } => {
+            vec![NCommand::Input {
+                name,
+                file,
+                options,
+            }]
         }
+        Command::InputJson {
+            name,
+            file,
+            columns,
+        } => vec![NCommand::InputJson {
+            name,
+            file,
+            columns,
+        }],
+        Command::Save(file) => vec![NCommand::Save(file)],
+        Command::Load(file) => vec![NCommand::Load(file)],
+        Command::ImportEgraph(file) => vec![NCommand::ImportEgraph(file)],
+        Command::Dump(file) => vec![NCommand::Dump(file)],
+        Command::ExportFunction {
+            name,
+            file,
+            options,
+        } => vec![NCommand::ExportFunction {
+            name,
+            file,
+            options,
+        }],
     };
 
     Ok(res)
@@ -384,10 +442,36 @@ impl Desugar {
     }
 
     pub fn parse_program(&self, input: &str) -> Result<Vec<Command>, Error> {
-        Ok(self
-            .parser
-            .parse(input)
-            .map_err(|e| e.map_token(|tok| tok.to_string()))?)
+        let program = self.parse_source(None, input)?;
+        Ok(program.into_iter().map(|(_, command)| command).collect())
+    }
+
+    /// Parse a program read from the named file, with the span of each
+    /// command. Parse errors are located at the token that caused them.
+    pub fn parse_source(
+        &self,
+        filename: Option<String>,
+        input: &str,
+    ) -> Result<Vec<(Span, Command)>, Error> {
+        let srcfile = Arc::new(SrcFile::new(filename, input.to_string()));
+        self.parser.parse(&srcfile, input).map_err(|e| {
+            let (start, end) = match &e {
+                ParseError::InvalidToken { location }
+                | ParseError::UnrecognizedEof { location, .. } => (*location, *location),
+                ParseError::UnrecognizedToken {
+                    token: (start, _, end),
+                    ..
+                }
+                | ParseError::ExtraToken {
+                    token: (start, _, end),
+                } => (*start, *end),
+                ParseError::User { .. } => {
+                    return Error::ParseError(e.map_token(|tok| tok.to_string()))
+                }
+            };
+            let span = Span::new(srcfile.clone(), start, end);
+            Error::ParseError(e.map_token(|tok| tok.to_string())).located(span)
+        })
     }
 
     // TODO declare by creating a new global function. See issue #334
@@ -407,7 +491,11 @@ impl Desugar {
                 unextractable: false,
                 ignore_viz: false,
             }),
-            NCommand::CoreAction(Action::Let((), name, Expr::Call((), fresh, vec![]))),
+            NCommand::CoreAction(Action::Let(
+                DUMMY_SPAN.clone(),
+                name,
+                Expr::Call(DUMMY_SPAN.clone(), fresh, vec![]),
+            )),
         ]
     }
 
@@ -105,8 +105,8 @@ impl ToSexp for ResolvedVar {
     }
 }
 
-pub type Expr = GenericExpr<Symbol, Symbol, ()>;
-pub(crate) type ResolvedExpr = GenericExpr<ResolvedCall, ResolvedVar, ()>;
+pub type Expr = GenericExpr<Symbol, Symbol, Span>;
+pub(crate) type ResolvedExpr = GenericExpr<ResolvedCall, ResolvedVar, Span>;
 /// A [`MappedExpr`] arises naturally when you want a mapping between an expression
 /// and its flattened form. It records this mapping by annotating each `Head`
 /// with a `Leaf`, which it maps to in the flattened form.
@@ -139,11 +139,19 @@ impl ResolvedExpr {
 
 impl Expr {
     pub fn call(op: impl Into<Symbol>, children: impl IntoIterator<Item = Self>) -> Self {
-        Self::Call((), op.into(), children.into_iter().collect())
+        Self::Call(
+            DUMMY_SPAN.clone(),
+            op.into(),
+            children.into_iter().collect(),
+        )
     }
 
     pub fn lit(lit: impl Into<Literal>) -> Self {
-        Self::Lit((), lit.into())
+        Self::Lit(DUMMY_SPAN.clone(), lit.into())
+    }
+
+    pub fn var(name: impl Into<Symbol>) -> Self {
+        Self::Var(DUMMY_SPAN.clone(), name.into())
     }
 }
 
@@ -276,12 +284,11 @@ where
     }
 }
 
-// currently only used for testing, but no reason it couldn't be used elsewhere later
-#[cfg(test)]
 pub(crate) fn parse_expr(s: &str) -> Result<Expr, lalrpop_util::ParseError<usize, String, String>> {
     let parser = ast::parse::ExprParser::new();
+    let srcfile = Arc::new(SrcFile::new(None, s.to_string()));
     parser
-        .parse(s)
+        .parse(&srcfile, s)
         .map_err(|e| e.map_token(|tok| tok.to_string()))
 }
 
@@ -20,10 +20,13 @@ use crate::{
 
 mod expr;
 pub use expr::*;
+mod span;
+pub use span::*;
 pub mod desugar;
 pub(crate) mod remove_globals;
 
 #[derive(Clone, Copy, Hash, PartialEq, Eq, PartialOrd, Ord, Debug)]
+#[cfg_attr(feature = "serde-1", derive(serde::Serialize, serde::Deserialize))]
 pub struct Id(usize);
 
 impl From<usize> for Id {
@@ -51,11 +54,11 @@ pub(crate) enum Ruleset {
     Combined(Symbol, Vec<Symbol>),
 }
 
-pub type NCommand = GenericNCommand<Symbol, Symbol, ()>;
+pub type NCommand = GenericNCommand<Symbol, Symbol, Span>;
 /// [`ResolvedNCommand`] is another specialization of [`GenericNCommand`], which
 /// adds the type information to heads and leaves of commands.
 /// [`TypeInfo::typecheck_command`] turns an [`NCommand`] into a [`ResolvedNCommand`].
-pub(crate) type ResolvedNCommand = GenericNCommand<ResolvedCall, ResolvedVar, ()>;
+pub(crate) type ResolvedNCommand = GenericNCommand<ResolvedCall, ResolvedVar, Span>;
 
 /// A [`NCommand`] is a desugared [`Command`], where syntactic sugars
 /// like [`Command::Datatype`], [`Command::Declare`], and [`Command::Rewrite`]
@@ -96,6 +99,11 @@ where
     PrintOverallStatistics,
     Check(Vec<GenericFact<Head, Leaf, Ann>>),
     CheckProof,
+    Explain(GenericExpr<Head, Leaf, Ann>, GenericExpr<Head, Leaf, Ann>),
+    Extract {
+        expr: GenericExpr<Head, Leaf, Ann>,
+        options: ExtractOptions,
+    },
     PrintTable(Symbol, usize),
     PrintSize(Option<Symbol>),
     Output {
@@ -108,10 +116,25 @@ where
     Input {
         name: Symbol,
         file: String,
+        options: InputOptions,
+    },
+    InputJson {
+        name: Symbol,
+        file: String,
+        columns: Option<Vec<String>>,
+    },
+    Save(String),
+    Load(String),
+    ImportEgraph(String),
+    Dump(String),
+    ExportFunction {
+        name: Symbol,
+        file: String,
+        options: ExportOptions,
     },
 }
 
-impl<Head, Leaf> GenericNCommand<Head, Leaf, ()>
+impl<Head, Leaf> GenericNCommand<Head, Leaf, Span>
 where
     Head: Clone + Display,
     Leaf: Clone + PartialEq + Eq + Display + Hash,
@@ -142,6 +165,11 @@ where
             GenericNCommand::CoreAction(action) => GenericCommand::Action(action.clone()),
             GenericNCommand::Check(facts) => GenericCommand::Check(facts.clone()),
             GenericNCommand::CheckProof => GenericCommand::CheckProof,
+            GenericNCommand::Explain(lhs, rhs) => GenericCommand::Explain(lhs.clone(), rhs.clone()),
+            GenericNCommand::Extract { expr, options } => GenericCommand::Extract {
+                expr: expr.clone(),
+                options: options.clone(),
+            },
             GenericNCommand::PrintTable(name, n) => GenericCommand::PrintFunction(*name, *n),
             GenericNCommand::PrintSize(name) => GenericCommand::PrintSize(*name),
             GenericNCommand::Output { file, exprs } => GenericCommand::Output {
@@ -151,16 +179,43 @@ where
             GenericNCommand::Push(n) => GenericCommand::Push(*n),
             GenericNCommand::Pop(n) => GenericCommand::Pop(*n),
             GenericNCommand::Fail(cmd) => GenericCommand::Fail(Box::new(cmd.to_command())),
-            GenericNCommand::Input { name, file } => GenericCommand::Input {
+            GenericNCommand::Input {
+                name,
+                file,
+                options,
+            } => GenericCommand::Input {
                 name: *name,
                 file: file.clone(),
+                options: options.clone(),
+            },
+            GenericNCommand::InputJson {
+                name,
+                file,
+                columns,
+            } => GenericCommand::InputJson {
+                name: *name,
+                file: file.clone(),
+                columns: columns.clone(),
+            },
+            GenericNCommand::Save(file) => GenericCommand::Save(file.clone()),
+            GenericNCommand::Load(file) => GenericCommand::Load(file.clone()),
+            GenericNCommand::ImportEgraph(file) => GenericCommand::ImportEgraph(file.clone()),
+            GenericNCommand::Dump(file) => GenericCommand::Dump(file.clone()),
+            GenericNCommand::ExportFunction {
+                name,
+                file,
+                options,
+            } => GenericCommand::ExportFunction {
+                name: *name,
+                file: file.clone(),
+                options: options.clone(),
             },
         }
     }
 
     pub fn visit_exprs(
         self,
-        f: &mut impl FnMut(GenericExpr<Head, Leaf, ()>) -> GenericExpr<Head, Leaf, ()>,
+        f: &mut impl FnMut(GenericExpr<Head, Leaf, Span>) -> GenericExpr<Head, Leaf, Span>,
     ) -> Self {
         match self {
             GenericNCommand::SetOption { name, value } => GenericNCommand::SetOption {
@@ -193,6 +248,11 @@ where
                 GenericNCommand::Check(facts.into_iter().map(|fact| fact.visit_exprs(f)).collect())
             }
             GenericNCommand::CheckProof => GenericNCommand::CheckProof,
+            GenericNCommand::Explain(lhs, rhs) => GenericNCommand::Explain(f(lhs), f(rhs)),
+            GenericNCommand::Extract { expr, options } => GenericNCommand::Extract {
+                expr: expr.visit_exprs(f),
+                options,
+            },
             GenericNCommand::PrintTable(name, n) => GenericNCommand::PrintTable(name, n),
             GenericNCommand::PrintSize(name) => GenericNCommand::PrintSize(name),
             GenericNCommand::Output { file, exprs } => GenericNCommand::Output {
@@ -202,13 +262,43 @@ where
             GenericNCommand::Push(n) => GenericNCommand::Push(n),
             GenericNCommand::Pop(n) => GenericNCommand::Pop(n),
             GenericNCommand::Fail(cmd) => GenericNCommand::Fail(Box::new(cmd.visit_exprs(f))),
-            GenericNCommand::Input { name, file } => GenericNCommand::Input { name, file },
+            GenericNCommand::Input {
+                name,
+                file,
+                options,
+            } => GenericNCommand::Input {
+                name,
+                file,
+                options,
+            },
+            GenericNCommand::InputJson {
+                name,
+                file,
+                columns,
+            } => GenericNCommand::InputJson {
+                name,
+                file,
+                columns,
+            },
+            GenericNCommand::Save(file) => GenericNCommand::Save(file),
+            GenericNCommand::Load(file) => GenericNCommand::Load(file),
+            GenericNCommand::ImportEgraph(file) => GenericNCommand::ImportEgraph(file),
+            GenericNCommand::Dump(file) => GenericNCommand::Dump(file),
+            GenericNCommand::ExportFunction {
+                name,
+                file,
+                options,
+            } => GenericNCommand::ExportFunction {
+                name,
+                file,
+                options,
+            },
         }
     }
 }
 
-pub type Schedule = GenericSchedule<Symbol, Symbol, ()>;
-pub(crate) type ResolvedSchedule = GenericSchedule<ResolvedCall, ResolvedVar, ()>;
+pub type Schedule = GenericSchedule<Symbol, Symbol, Span>;
+pub(crate) type ResolvedSchedule = GenericSchedule<ResolvedCall, ResolvedVar, Span>;
 
 #[derive(Debug, Clone, PartialEq, Eq, Hash)]
 pub enum GenericSchedule<Head, Leaf, Ann> {
@@ -326,11 +416,18 @@ where
     /// Once this limit is reached, egglog stops running rules.
     ///
     /// Other options supported include:
+    /// - "time_limit" (default: unlimited): the number of seconds each `run-schedule` may take.
+    ///   The limit is checked between iterations, so a slow iteration may overrun it.
+    /// - "memory_limit" (default: unlimited): an approximate limit on the memory
+    ///   used by the database, in megabytes.
+    /// - "match_limit" (default: unlimited): a rule that finds more matches than this
+    ///   in one iteration is skipped for that iteration. The `backoff` schedule
+    ///   uses its own limit instead.
     /// - "interactive_mode" (default: false): when enabled, egglog prints "(done)" after each command, allowing an external
     /// tool to know when each command has finished running.
     SetOption {
         name: Symbol,
-        value: GenericExpr<Head, Leaf, ()>,
+        value: GenericExpr<Head, Leaf, Span>,
     },
     /// Declare a user-defined datatype.
     /// Datatypes can be unioned with [`Action::Union`] either
@@ -406,7 +503,15 @@ where
     ///        (:merge <Expr>)?
     ///        (:default <Expr>)?)
     ///```
-    /// A function can have a `cost` for extraction.
+    /// A function can have a `cost` for extraction. This is either a number
+    /// or the name of a cost table, an `i64` function with the same inputs,
+    /// whose rows give the cost of each row of this function:
+    /// ```text
+    /// (function Const-cost (i64) i64)
+    /// (function Const (i64) Expr :cost Const-cost)
+    /// (rule ((Const n) (< n 256)) ((set (Const-cost n) 1)))
+    /// (rule ((Const n) (>= n 256)) ((set (Const-cost n) 4)))
+    /// ```
     /// It can also have a `default` value, which is used when calling the function.
     ///
     /// Finally, it can have a `merge` and `on_merge`, which are triggered when
@@ -442,7 +547,7 @@ where
     ///
     /// Functions that are not a datatype can be `set`
     /// with [`Action::Set`].
-    Function(GenericFunctionDecl<Head, Leaf, ()>),
+    Function(GenericFunctionDecl<Head, Leaf, Span>),
     /// The `relation` is syntactic sugar for a named function which returns the `Unit` type.
     /// Example:
     /// ```text
@@ -516,7 +621,7 @@ where
     Rule {
         name: Symbol,
         ruleset: Symbol,
-        rule: GenericRule<Head, Leaf, ()>,
+        rule: GenericRule<Head, Leaf, Span>,
     },
     /// `rewrite` is syntactic sugar for a specific form of `rule`
     /// which simply unions the left and right hand sides.
@@ -557,7 +662,7 @@ where
     ///       ((union lhs (bitshift-left a 1))
     ///        (subsume (Mul a 2))))
     /// ```
-    Rewrite(Symbol, GenericRewrite<Head, Leaf, ()>, Subsume),
+    Rewrite(Symbol, GenericRewrite<Head, Leaf, Span>, Subsume),
     /// Similar to [`Command::Rewrite`], but
     /// generates two rules, one for each direction.
     ///
@@ -574,14 +679,14 @@ where
     /// (rule ((= lhs (Var x)))
     ///       ((union lhs (Mul (Var x) (Num 0)))))
     /// ```
-    BiRewrite(Symbol, GenericRewrite<Head, Leaf, ()>),
+    BiRewrite(Symbol, GenericRewrite<Head, Leaf, Span>),
     /// Perform an [`Action`] on the global database
     /// (see documentation for [`Action`] for more details).
     /// Example:
     /// ```text
     /// (let xplusone (Add (Var "x") (Num 1)))
     /// ```
-    Action(GenericAction<Head, Leaf, ()>),
+    Action(GenericAction<Head, Leaf, Span>),
     /// Runs a [`Schedule`], which specifies
     /// rulesets and the number of times to run them.
     ///
@@ -596,17 +701,17 @@ where
     /// then runs `my-ruleset-2` four times.
     ///
     /// See [`Schedule`] for more details.
-    RunSchedule(GenericSchedule<Head, Leaf, ()>),
+    RunSchedule(GenericSchedule<Head, Leaf, Span>),
     /// Print runtime statistics about rules
     /// and rulesets so far.
     PrintOverallStatistics,
     // TODO provide simplify docs
     Simplify {
-        expr: GenericExpr<Head, Leaf, ()>,
-        schedule: GenericSchedule<Head, Leaf, ()>,
+        expr: GenericExpr<Head, Leaf, Span>,
+        schedule: GenericSchedule<Head, Leaf, Span>,
     },
     // TODO provide calc docs
-    Calc(Vec<IdentSort>, Vec<GenericExpr<Head, Leaf, ()>>),
+    Calc(Vec<IdentSort>, Vec<GenericExpr<Head, Leaf, Span>>),
     /// The `query-extract` command runs a query,
     /// extracting the result for each match that it finds.
     /// For a simpler extraction command, use [`Action::Extract`] instead.
@@ -629,12 +734,14 @@ where
     ///     (Num 1)
     ///     (Num 1))
     /// ```
+    /// To count each common sub-expression once, use
+    /// [`Command::Extract`] with `:mode dag`.
     ///
     /// Under the hood, this command is implemented with the [`EGraph::extract`]
     /// function.
     QueryExtract {
         variants: usize,
-        expr: GenericExpr<Head, Leaf, ()>,
+        expr: GenericExpr<Head, Leaf, Span>,
     },
     /// The `check` command checks that the given facts
     /// match at least once in the current database.
@@ -656,9 +763,72 @@ where
     /// [ERROR] Check failed
     /// [INFO ] Command failed as expected.
     /// ```
-    Check(Vec<GenericFact<Head, Leaf, ()>>),
-    /// Currently unused, this command will check proofs when they are implemented.
+    ///
+    /// When proofs are enabled with `(set-option enable_proofs 1)`, a
+    /// successful check also prints a proof of each of its ground facts.
+    Check(Vec<GenericFact<Head, Leaf, Span>>),
+    /// Report how many proofs [`Command::Check`] and `extract` produced since
+    /// the last `check-proof`. Each proof is checked against the database
+    /// when it is produced, and the command producing it fails if it is
+    /// invalid. Requires proofs to be enabled.
     CheckProof,
+    /// Explain why two terms are equal by printing a chain of rewrites from
+    /// one to the other, each annotated with the rule or action responsible.
+    /// Requires proofs to be enabled.
+    /// Example:
+    /// ```text
+    /// (set-option enable_proofs 1)
+    /// (datatype Math (Num i64) (Add Math Math))
+    /// (rewrite (Add a b) (Add b a))
+    /// (Add (Num 1) (Num 2))
+    /// (run 1)
+    /// (explain (= (Add (Num 1) (Num 2)) (Add (Num 2) (Num 1))))
+    /// ```
+    /// prints
+    /// ```text
+    /// (Add (Num 1) (Num 2))
+    /// (Add (Num 2) (Num 1)) ; rule (rule ((= rewrite_var__ (Add a b))) ((union rewrite_var__ (Add b a))))
+    /// ```
+    Explain(GenericExpr<Head, Leaf, Span>, GenericExpr<Head, Leaf, Span>),
+    /// `extract` a term with options that the [`Action::Extract`] action
+    /// does not take.
+    /// With `:mode dag`, the cost of a term counts each distinct subterm
+    /// once, however many times it occurs, and the term is chosen greedily.
+    /// With `:mode dag-exact`, the cheapest such term is found by a
+    /// branch-and-bound search, which can take exponential time.
+    ///
+    /// Example:
+    /// ```text
+    /// (datatype Math (Num i64 :cost 5) (Add Math Math) (Double Math :cost 3))
+    /// (let x (Add (Num 1) (Num 1)))
+    /// (union x (Double (Num 1)))
+    /// (extract x :mode dag)
+    /// ```
+    /// prints `(Add (Num 1) (Num 1))`, which has a DAG cost of 7,
+    /// rather than `(Double (Num 1))`, which has the smaller tree cost of 9.
+    ///
+    /// With `:best k`, the `k` cheapest distinct terms are printed,
+    /// cheapest first. Unlike the variants of [`Action::Extract`], these
+    /// may differ anywhere in the term, not just in the root e-node.
+    /// ```text
+    /// (extract x :best 3)
+    /// ```
+    ///
+    /// With `:forbid (f g ...)`, the term may not use the functions `f`,
+    /// `g`, ..., and with `:require-root f` it must be an application of `f`.
+    /// This lets one e-graph be extracted for targets that support
+    /// different operators, which `:unextractable` cannot vary.
+    /// ```text
+    /// (extract e :forbid (Div Mod) :require-root Add)
+    /// ```
+    ///
+    /// Under the hood, this command is implemented with the
+    /// [`EGraph::extract_with_mode`], [`EGraph::extract_k_best`],
+    /// and [`EGraph::extract_filtered`] functions.
+    Extract {
+        expr: GenericExpr<Head, Leaf, Span>,
+        options: ExtractOptions,
+    },
     /// Print out rows a given function, extracting each of the elements of the function.
     /// Example:
     /// ```text
@@ -670,14 +840,110 @@ where
     /// Print out the number of rows in a function or all functions.
     PrintSize(Option<Symbol>),
     /// Input a CSV file directly into a function.
+    /// Each line of the file is a row of the function, with a field for
+    /// each input and one for the output, unless the function is a
+    /// constructor or its output is `Unit`.
+    /// Example:
+    /// ```text
+    /// (function edge (i64 i64) f64)
+    /// (input edge "edges.csv" :delimiter "," :header)
+    /// ```
+    ///
+    /// Fields are separated by tabs unless another `:delimiter` is given,
+    /// and may be quoted with `"`. Fields of `i64`, `f64`, `bool`,
+    /// `String`, `Rational` (as in `3/4`), and `Unit` columns are read as
+    /// such, and the fields of other columns are read as expressions,
+    /// such as `(Num 3)`. With `:header`, the first line is skipped, and
+    /// `:columns ("from" "to" "weight")` picks the fields to read by the
+    /// names in it.
     Input {
         name: Symbol,
         file: String,
+        options: InputOptions,
+    },
+    /// Input a JSON file directly into a function. The file holds an array
+    /// with an array for each row, in the order of the columns of
+    /// [`Command::Input`], or an object for each row, with the names of
+    /// the columns given by `:columns`.
+    /// Example:
+    /// ```text
+    /// (function edge (i64 i64) f64)
+    /// (input-json edge "edges.json" :columns ("from" "to" "weight"))
+    /// ```
+    /// reads rows such as `{"from": 1, "to": 2, "weight": 0.5}`.
+    ///
+    /// Numbers, booleans, and strings are read as `i64`, `f64`, `bool`, and
+    /// `String`, `null` as `Unit`, and arrays as `Vec` and `Set`
+    /// containers. Rationals are written as integers or strings such as
+    /// `"3/4"`, and the values of other sorts as strings holding
+    /// expressions, such as `"(Num 3)"`.
+    InputJson {
+        name: Symbol,
+        file: String,
+        columns: Option<Vec<String>>,
     },
     /// Extract and output a set of expressions to a file.
     Output {
         file: String,
-        exprs: Vec<GenericExpr<Head, Leaf, ()>>,
+        exprs: Vec<GenericExpr<Head, Leaf, Span>>,
+    },
+    /// Save a snapshot of the egraph to a file, with the sorts, functions,
+    /// rulesets, and rules declared so far and every row in the database.
+    /// See [`EGraph::save_snapshot`].
+    /// Example:
+    /// ```text
+    /// (run 1000)
+    /// (save "saturated.json")
+    /// ```
+    Save(String),
+    /// Load a snapshot saved by [`Command::Save`], replacing the egraph.
+    /// This must come before any declarations.
+    /// See [`EGraph::load_snapshot`].
+    /// Example:
+    /// ```text
+    /// (load "saturated.json")
+    /// (run 1000)
+    /// ```
+    Load(String),
+    /// Add the e-classes and rows of an e-graph written as JSON by
+    /// `--to-json` or [`EGraph::serialize`], declaring any sorts and
+    /// functions that are not declared yet.
+    /// See [`EGraph::import_serialized`].
+    /// Example:
+    /// ```text
+    /// (import-egraph "saturated.json")
+    /// (extract (Add (Num 1) (Num 2)))
+    /// ```
+    ImportEgraph(String),
+    /// Write a program that recreates every row of every function and the
+    /// equivalence classes, after declaring the sorts and functions.
+    /// Unlike [`Command::PrintFunction`], nothing is extracted, so no
+    /// equivalences are lost.
+    /// See [`EGraph::to_program`].
+    /// Example:
+    /// ```text
+    /// (run 10)
+    /// (dump "state.egg")
+    /// ```
+    Dump(String),
+    /// Write every row of a function to a file as relational data, one
+    /// line per row, with a field for each input and one for the output
+    /// unless it is `Unit`.
+    /// See [`EGraph::export_function`].
+    /// Example:
+    /// ```text
+    /// (export-function edge "edges.csv")
+    /// (export-function path "paths.json" :format json :terms)
+    /// ```
+    ///
+    /// The format is `csv`, `tsv`, or `json`, which writes an array with
+    /// an array for each row. Primitive values are written as literals,
+    /// and e-classes as their canonical ids, unless `:terms` is given,
+    /// in which case their cheapest terms are extracted.
+    ExportFunction {
+        name: Symbol,
+        file: String,
+        options: ExportOptions,
     },
     /// `push` the current egraph `n` times so that it is saved.
     /// Later, the current database and rules can be restored using `pop`.
@@ -691,6 +957,122 @@ where
     Include(String),
 }
 
+impl Command {
+    /// Read a top-level action as a command that reads or writes files,
+    /// such as `(load "saturated.json")`. The names of these commands are not
+    /// keywords, so programs can still use them for their own functions:
+    /// only a call whose arguments fit the command is read as the command,
+    /// and [`EGraph`](crate::EGraph) reads it back as a call with
+    /// [`Command::as_call`] when the program declared a function of that name.
+    pub(crate) fn from_action(action: Action) -> Command {
+        if let GenericAction::Expr(_, GenericExpr::Call(_, head, args)) = &action {
+            if let Some(command) = Self::file_command(*head, args) {
+                return command;
+            }
+        }
+        Command::Action(action)
+    }
+
+    /// Like [`Command::from_action`], for a call followed by `:columns`.
+    pub(crate) fn with_columns(
+        head: Symbol,
+        args: &[Expr],
+        columns: Vec<String>,
+    ) -> Result<Command, String> {
+        match Self::file_command(head, args) {
+            Some(Command::InputJson { name, file, .. }) => Ok(Command::InputJson {
+                name,
+                file,
+                columns: Some(columns),
+            }),
+            _ => Err(format!(
+                "Expected (input-json <function> <file> :columns (...)) but found ({head} ...)"
+            )),
+        }
+    }
+
+    /// Like [`Command::from_action`], for a call followed by export options.
+    pub(crate) fn with_export_options(
+        head: Symbol,
+        args: &[Expr],
+        options: ExportOptions,
+    ) -> Result<Command, String> {
+        match Self::file_command(head, args) {
+            Some(Command::ExportFunction { name, file, .. }) => Ok(Command::ExportFunction {
+                name,
+                file,
+                options,
+            }),
+            _ => Err(format!(
+                "Expected (export-function <function> <file> ...) but found ({head} ...)"
+            )),
+        }
+    }
+
+    /// The call that [`Command::from_action`] read as this file command,
+    /// along with the name of the command.
+    pub(crate) fn as_call(&self) -> Option<(Symbol, Action)> {
+        let span = DUMMY_SPAN.clone();
+        let file = |file: &str| Expr::Lit(span.clone(), Literal::String(file.into()));
+        let (head, args) = match self {
+            Command::Save(path) => ("save", vec![file(path)]),
+            Command::Load(path) => ("load", vec![file(path)]),
+            Command::ImportEgraph(path) => ("import-egraph", vec![file(path)]),
+            Command::Dump(path) => ("dump", vec![file(path)]),
+            Command::Include(path) => ("include", vec![file(path)]),
+            Command::InputJson {
+                name,
+                file: path,
+                columns: None,
+            } => (
+                "input-json",
+                vec![Expr::Var(span.clone(), *name), file(path)],
+            ),
+            Command::ExportFunction {
+                name,
+                file: path,
+                options,
+            } if *options == ExportOptions::default() => (
+                "export-function",
+                vec![Expr::Var(span.clone(), *name), file(path)],
+            ),
+            _ => return None,
+        };
+        let head = Symbol::from(head);
+        Some((
+            head,
+            Action::Expr(span.clone(), Expr::Call(span, head, args)),
+        ))
+    }
+
+    fn file_command(head: Symbol, args: &[Expr]) -> Option<Command> {
+        let file = |arg: &Expr| match arg {
+            GenericExpr::Lit(_, Literal::String(file)) => Some(file.to_string()),
+            _ => None,
+        };
+        match (head.as_str(), args) {
+            ("save", [arg]) => Some(Command::Save(file(arg)?)),
+            ("load", [arg]) => Some(Command::Load(file(arg)?)),
+            ("import-egraph", [arg]) => Some(Command::ImportEgraph(file(arg)?)),
+            ("dump", [arg]) => Some(Command::Dump(file(arg)?)),
+            ("include", [arg]) => Some(Command::Include(file(arg)?)),
+            ("input-json", [GenericExpr::Var(_, name), arg]) => Some(Command::InputJson {
+                name: *name,
+                file: file(arg)?,
+                columns: None,
+            }),
+            ("export-function", [GenericExpr::Var(_, name), arg]) => {
+                Some(Command::ExportFunction {
+                    name: *name,
+                    file: file(arg)?,
+                    options: Default::default(),
+                })
+            }
+            _ => None,
+        }
+    }
+}
+
 impl<Head, Leaf> ToSexp for GenericCommand<Head, Leaf>
 where
     Head: Clone + Display + ToSexp,
@@ -732,14 +1114,50 @@ where
             }
             GenericCommand::Check(facts) => list!("check", ++ facts),
             GenericCommand::CheckProof => list!("check-proof"),
+            GenericCommand::Explain(lhs, rhs) => list!("explain", list!("=", lhs, rhs)),
+            GenericCommand::Extract { expr, options } => {
+                list!("extract", expr, ++ options.to_sexps())
+            }
             GenericCommand::Push(n) => list!("push", n),
             GenericCommand::Pop(n) => list!("pop", n),
             GenericCommand::PrintFunction(name, n) => list!("print-function", name, n),
             GenericCommand::PrintSize(name) => list!("print-size", ++ name),
-            GenericCommand::Input { name, file } => list!("input", name, format!("\"{}\"", file)),
+            GenericCommand::Input {
+                name,
+                file,
+                options,
+            } => list!("input", name, format!("\"{}\"", file), ++ options.to_sexps()),
+            GenericCommand::InputJson {
+                name,
+                file,
+                columns,
+            } => {
+                let mut options = vec![];
+                if let Some(columns) = columns {
+                    options.push(Sexp::Symbol(":columns".into()));
+                    options.push(Sexp::List(
+                        columns
+                            .iter()
+                            .map(|column| Sexp::Symbol(format!("\"{column}\"")))
+                            .collect(),
+                    ));
+                }
+                list!("input-json", name, format!("\"{}\"", file), ++ options)
+            }
             GenericCommand::Output { file, exprs } => {
                 list!("output", format!("\"{}\"", file), ++ exprs)
             }
+            GenericCommand::Save(file) => list!("save", format!("\"{}\"", file)),
+            GenericCommand::Load(file) => list!("load", format!("\"{}\"", file)),
+            GenericCommand::ImportEgraph(file) => {
+                list!("import-egraph", format!("\"{}\"", file))
+            }
+            GenericCommand::Dump(file) => list!("dump", format!("\"{}\"", file)),
+            GenericCommand::ExportFunction {
+                name,
+                file,
+                options,
+            } => list!("export-function", name, format!("\"{}\"", file), ++ options.to_sexps()),
             GenericCommand::Fail(cmd) => list!("fail", cmd),
             GenericCommand::Include(file) => list!("include", format!("\"{}\"", file)),
             GenericCommand::Simplify { expr, schedule } => list!("simplify", schedule, expr),
@@ -747,7 +1165,7 @@ where
     }
 }
 
-impl<Head, Leaf> Display for GenericNCommand<Head, Leaf, ()>
+impl<Head, Leaf> Display for GenericNCommand<Head, Leaf, Span>
 where
     Head: Clone + Display + ToSexp,
     Leaf: Clone + PartialEq + Eq + Display + Hash + ToSexp,
@@ -795,13 +1213,232 @@ impl Display for IdentSort {
     }
 }
 
-pub type RunConfig = GenericRunConfig<Symbol, Symbol, ()>;
-pub(crate) type ResolvedRunConfig = GenericRunConfig<ResolvedCall, ResolvedVar, ()>;
+pub type RunConfig = GenericRunConfig<Symbol, Symbol, Span>;
+pub(crate) type ResolvedRunConfig = GenericRunConfig<ResolvedCall, ResolvedVar, Span>;
 
 #[derive(Clone, Debug, PartialEq, Eq, Hash)]
 pub struct GenericRunConfig<Head, Leaf, Ann> {
     pub ruleset: Symbol,
     pub until: Option<Vec<GenericFact<Head, Leaf, Ann>>>,
+    /// When set, rules that find too many matches are banned
+    /// for a while instead of being applied.
+    pub backoff: Option<Backoff>,
+}
+
+/// Configuration for the egg-style backoff scheduler,
+/// used by the `backoff` schedule:
+/// ```text
+/// (run-schedule (backoff my-ruleset :match-limit 1000 :ban-length 5))
+/// ```
+/// A rule that finds more than `match_limit` matches in an iteration
+/// is not applied, and is banned for `ban_length` iterations.
+/// Each time a rule is banned, both its match limit and its ban length double.
+#[derive(Clone, Copy, Debug, PartialEq, Eq, Hash)]
+pub struct Backoff {
+    pub match_limit: usize,
+    pub ban_length: usize,
+}
+
+impl Default for Backoff {
+    fn default() -> Self {
+        Backoff {
+            match_limit: 1000,
+            ban_length: 5,
+        }
+    }
+}
+
+/// How the `extract` command counts the cost of shared subterms.
+#[derive(Clone, Copy, Debug, Default, PartialEq, Eq, Hash)]
+pub enum ExtractMode {
+    /// Each occurrence of a subterm is counted, as by [`Action::Extract`].
+    #[default]
+    Tree,
+    /// Each distinct subterm is counted once, choosing a term greedily.
+    Dag,
+    /// Each distinct subterm is counted once, choosing the cheapest term.
+    DagExact,
+}
+
+impl Display for ExtractMode {
+    fn fmt(&self, f: &mut Formatter<'_>) -> std::fmt::Result {
+        match self {
+            ExtractMode::Tree => write!(f, "tree"),
+            ExtractMode::Dag => write!(f, "dag"),
+            ExtractMode::DagExact => write!(f, "dag-exact"),
+        }
+    }
+}
+
+impl FromStr for ExtractMode {
+    type Err = String;
+
+    fn from_str(s: &str) -> Result<Self, Self::Err> {
+        match s {
+            "tree" => Ok(ExtractMode::Tree),
+            "dag" => Ok(ExtractMode::Dag),
+            "dag-exact" => Ok(ExtractMode::DagExact),
+            _ => Err(format!(
+                "unknown extraction mode {s}, expected tree, dag, or dag-exact"
+            )),
+        }
+    }
+}
+
+/// The options of a [`Command::Extract`].
+#[derive(Clone, Debug, Default, PartialEq, Eq, Hash)]
+pub struct ExtractOptions {
+    pub mode: ExtractMode,
+    /// Extract this many of the cheapest distinct terms, rather than one.
+    /// Only supported in [`ExtractMode::Tree`].
+    pub best: Option<usize>,
+    /// Functions that may not occur anywhere in the extracted term.
+    pub forbid: Vec<Symbol>,
+    /// The function the extracted term must be an application of.
+    pub require_root: Option<Symbol>,
+}
+
+impl ExtractOptions {
+    fn to_sexps(&self) -> Vec<Sexp> {
+        let mut sexps = vec![
+            Sexp::Symbol(":mode".into()),
+            Sexp::Symbol(self.mode.to_string()),
+        ];
+        if let Some(best) = self.best {
+            sexps.push(Sexp::Symbol(":best".into()));
+            sexps.push(Sexp::Symbol(best.to_string()));
+        }
+        if !self.forbid.is_empty() {
+            sexps.push(Sexp::Symbol(":forbid".into()));
+            sexps.push(Sexp::List(
+                self.forbid
+                    .iter()
+                    .map(|name| Sexp::Symbol(name.to_string()))
+                    .collect(),
+            ));
+        }
+        if let Some(root) = self.require_root {
+            sexps.push(Sexp::Symbol(":require-root".into()));
+            sexps.push(Sexp::Symbol(root.to_string()));
+        }
+        sexps
+    }
+}
+
+/// The options of a [`Command::Input`].
+#[derive(Clone, Debug, PartialEq, Eq, Hash)]
+pub struct InputOptions {
+    /// The character separating the fields of a line.
+    pub delimiter: char,
+    /// Whether the first line of the file names the columns.
+    pub header: bool,
+    /// The names of the columns to read from the header, in the order of
+    /// the columns of the function. Implies `header`.
+    pub columns: Option<Vec<String>>,
+}
+
+impl Default for InputOptions {
+    fn default() -> Self {
+        InputOptions {
+            delimiter: '\t',
+            header: false,
+            columns: None,
+        }
+    }
+}
+
+impl InputOptions {
+    /// Parse the string given to `:delimiter`, which must be a single
+    /// character or `\t`.
+    pub(crate) fn parse_delimiter(s: &str) -> Result<char, String> {
+        if s == "\\t" {
+            return Ok('\t');
+        }
+        let mut chars = s.chars();
+        match (chars.next(), chars.next()) {
+            (Some(c), None) if c != '"' && c != '\n' => Ok(c),
+            _ => Err(format!(
+                "invalid delimiter \"{s}\", expected a single character"
+            )),
+        }
+    }
+
+    fn to_sexps(&self) -> Vec<Sexp> {
+        let mut sexps = vec![];
+        if self.delimiter != '\t' {
+            sexps.push(Sexp::Symbol(":delimiter".into()));
+            sexps.push(Sexp::Symbol(format!("\"{}\"", self.delimiter)));
+        }
+        match &self.columns {
+            Some(columns) => {
+                sexps.push(Sexp::Symbol(":columns".into()));
+                sexps.push(Sexp::List(
+                    columns
+                        .iter()
+                        .map(|column| Sexp::Symbol(format!("\"{column}\"")))
+                        .collect(),
+                ));
+            }
+            None if self.header => sexps.push(Sexp::Symbol(":header".into())),
+            None => {}
+        }
+        sexps
+    }
+}
+
+/// The file format of a [`Command::ExportFunction`].
+#[derive(Clone, Copy, Debug, Default, PartialEq, Eq, Hash)]
+pub enum ExportFormat {
+    #[default]
+    Csv,
+    Tsv,
+    Json,
+}
+
+impl Display for ExportFormat {
+    fn fmt(&self, f: &mut Formatter<'_>) -> std::fmt::Result {
+        match self {
+            ExportFormat::Csv => write!(f, "csv"),
+            ExportFormat::Tsv => write!(f, "tsv"),
+            ExportFormat::Json => write!(f, "json"),
+        }
+    }
+}
+
+impl FromStr for ExportFormat {
+    type Err = String;
+
+    fn from_str(s: &str) -> Result<Self, Self::Err> {
+        match s {
+            "csv" => Ok(ExportFormat::Csv),
+            "tsv" => Ok(ExportFormat::Tsv),
+            "json" => Ok(ExportFormat::Json),
+            _ => Err(format!(
+                "unknown export format {s}, expected csv, tsv, or json"
+            )),
+        }
+    }
+}
+
+/// The options of a [`Command::ExportFunction`].
+#[derive(Clone, Debug, Default, PartialEq, Eq, Hash)]
+pub struct ExportOptions {
+    pub format: ExportFormat,
+    /// Write the cheapest term of each e-class rather than its id.
+    pub terms: bool,
+}
+
+impl ExportOptions {
+    fn to_sexps(&self) -> Vec<Sexp> {
+        let mut sexps = vec![
+            Sexp::Symbol(":format".into()),
+            Sexp::Symbol(self.format.to_string()),
+        ];
+        if self.terms {
+            sexps.push(Sexp::Symbol(":terms".into()));
+        }
+        sexps
+    }
 }
 
 impl<Head, Leaf, Ann> GenericRunConfig<Head, Leaf, Ann>
@@ -819,6 +1456,7 @@ where
             until: self
                 .until
                 .map(|until| until.into_iter().map(|fact| fact.visit_exprs(f)).collect()),
+            backoff: self.backoff,
         }
     }
 }
@@ -829,10 +1467,21 @@ where
     Leaf: Display,
 {
     fn to_sexp(&self) -> Sexp {
-        let mut res = vec![Sexp::Symbol("run".into())];
+        let head = if self.backoff.is_some() {
+            "backoff"
+        } else {
+            "run"
+        };
+        let mut res = vec![Sexp::Symbol(head.into())];
         if self.ruleset != "".into() {
             res.push(Sexp::Symbol(self.ruleset.to_string()));
         }
+        if let Some(backoff) = &self.backoff {
+            res.push(Sexp::Symbol(":match-limit".into()));
+            res.push(backoff.match_limit.to_sexp());
+            res.push(Sexp::Symbol(":ban-length".into()));
+            res.push(backoff.ban_length.to_sexp());
+        }
         if let Some(until) = &self.until {
             res.push(Sexp::Symbol(":until".into()));
             res.extend(until.iter().map(|fact| fact.to_sexp()));
@@ -842,8 +1491,8 @@ where
     }
 }
 
-pub type FunctionDecl = GenericFunctionDecl<Symbol, Symbol, ()>;
-pub(crate) type ResolvedFunctionDecl = GenericFunctionDecl<ResolvedCall, ResolvedVar, ()>;
+pub type FunctionDecl = GenericFunctionDecl<Symbol, Symbol, Span>;
+pub(crate) type ResolvedFunctionDecl = GenericFunctionDecl<ResolvedCall, ResolvedVar, Span>;
 
 /// Represents the declaration of a function
 /// directly parsed from source syntax.
@@ -859,7 +1508,7 @@ where
     pub default: Option<GenericExpr<Head, Leaf, Ann>>,
     pub merge: Option<GenericExpr<Head, Leaf, Ann>>,
     pub merge_action: GenericActions<Head, Leaf, Ann>,
-    pub cost: Option<usize>,
+    pub cost: Option<FunctionCost>,
     pub unextractable: bool,
     /// Globals are desugared to functions, with this flag set to true.
     /// This is used by visualization to handle globals differently.
@@ -870,7 +1519,26 @@ where
 pub struct Variant {
     pub name: Symbol,
     pub types: Vec<Symbol>,
-    pub cost: Option<usize>,
+    pub cost: Option<FunctionCost>,
+}
+
+/// The `:cost` of a function, used by extraction.
+#[derive(Clone, Copy, Debug, PartialEq, Eq, Hash)]
+pub enum FunctionCost {
+    /// Every row has the same cost.
+    Fixed(usize),
+    /// Each row costs the output of this table, an `i64` function with the
+    /// same inputs, at the row's inputs. Rows missing from the table cost 1.
+    Table(Symbol),
+}
+
+impl Display for FunctionCost {
+    fn fmt(&self, f: &mut Formatter<'_>) -> std::fmt::Result {
+        match self {
+            FunctionCost::Fixed(cost) => write!(f, "{cost}"),
+            FunctionCost::Table(table) => write!(f, "{table}"),
+        }
+    }
 }
 
 impl ToSexp for Variant {
@@ -915,7 +1583,7 @@ impl FunctionDecl {
             },
             merge: None,
             merge_action: Actions::default(),
-            default: Some(Expr::Lit((), Literal::Unit)),
+            default: Some(Expr::Lit(DUMMY_SPAN.clone(), Literal::Unit)),
             cost: None,
             unextractable: false,
             ignore_viz: false,
@@ -996,8 +1664,8 @@ where
     }
 }
 
-pub type Fact = GenericFact<Symbol, Symbol, ()>;
-pub(crate) type ResolvedFact = GenericFact<ResolvedCall, ResolvedVar, ()>;
+pub type Fact = GenericFact<Symbol, Symbol, Span>;
+pub(crate) type ResolvedFact = GenericFact<ResolvedCall, ResolvedVar, Span>;
 pub(crate) type MappedFact<Head, Leaf, Ann> = GenericFact<CorrespondingVar<Head, Leaf>, Leaf, Ann>;
 
 /// Facts are the left-hand side of a [`Command::Rule`].
@@ -1013,7 +1681,7 @@ pub(crate) type MappedFact<Head, Leaf, Ann> = GenericFact<CorrespondingVar<Head,
 #[derive(Clone, Debug, PartialEq, Eq, Hash)]
 pub enum GenericFact<Head, Leaf, Ann> {
     /// Must be at least two things in an eq fact
-    Eq(Vec<GenericExpr<Head, Leaf, Ann>>),
+    Eq(Ann, Vec<GenericExpr<Head, Leaf, Ann>>),
     Fact(GenericExpr<Head, Leaf, Ann>),
 }
 
@@ -1048,7 +1716,7 @@ where
 
         for fact in self.0.iter() {
             match fact {
-                GenericFact::Eq(exprs) => {
+                GenericFact::Eq(span, exprs) => {
                     let mut new_exprs = vec![];
                     let mut to_equate = vec![];
                     for expr in exprs {
@@ -1061,7 +1729,7 @@ where
                         head: HeadOrEq::Eq,
                         args: to_equate,
                     });
-                    new_body.push(GenericFact::Eq(new_exprs));
+                    new_body.push(GenericFact::Eq(span.clone(), new_exprs));
                 }
                 GenericFact::Fact(expr) => {
                     let (child_atoms, expr) = expr.to_query(typeinfo, fresh_gen);
@@ -1081,7 +1749,7 @@ where
 {
     fn to_sexp(&self) -> Sexp {
         match self {
-            GenericFact::Eq(exprs) => list!("=", ++ exprs),
+            GenericFact::Eq(_, exprs) => list!("=", ++ exprs),
             GenericFact::Fact(expr) => expr.to_sexp(),
         }
     }
@@ -1098,9 +1766,10 @@ where
         f: &mut impl FnMut(GenericExpr<Head, Leaf, Ann>) -> GenericExpr<Head, Leaf, Ann>,
     ) -> GenericFact<Head, Leaf, Ann> {
         match self {
-            GenericFact::Eq(exprs) => {
-                GenericFact::Eq(exprs.into_iter().map(|expr| expr.visit_exprs(f)).collect())
-            }
+            GenericFact::Eq(span, exprs) => GenericFact::Eq(
+                span,
+                exprs.into_iter().map(|expr| expr.visit_exprs(f)).collect(),
+            ),
             GenericFact::Fact(expr) => GenericFact::Fact(expr.visit_exprs(f)),
         }
     }
@@ -1110,7 +1779,9 @@ where
         f: &mut impl FnMut(&GenericExpr<Head, Leaf, Ann>) -> GenericExpr<Head2, Leaf2, Ann>,
     ) -> GenericFact<Head2, Leaf2, Ann> {
         match self {
-            GenericFact::Eq(exprs) => GenericFact::Eq(exprs.iter().map(f).collect()),
+            GenericFact::Eq(span, exprs) => {
+                GenericFact::Eq(span.clone(), exprs.iter().map(f).collect())
+            }
             GenericFact::Fact(expr) => GenericFact::Fact(f(expr)),
         }
     }
@@ -1124,7 +1795,7 @@ where
     }
 }
 
-impl<Head, Leaf> GenericFact<Head, Leaf, ()>
+impl<Head, Leaf> GenericFact<Head, Leaf, Span>
 where
     Leaf: Clone + PartialEq + Eq + Display + Hash,
     Head: Clone + Display,
@@ -1134,9 +1805,10 @@ where
         Leaf: SymbolLike,
         Head: SymbolLike,
     {
-        self.subst(&mut |v| Expr::Var((), v.to_symbol()), &mut |h| {
-            h.to_symbol()
-        })
+        self.subst(
+            &mut |v| Expr::Var(DUMMY_SPAN.clone(), v.to_symbol()),
+            &mut |h| h.to_symbol(),
+        )
     }
 }
 
@@ -1191,9 +1863,9 @@ pub enum Change {
     Subsume,
 }
 
-pub type Action = GenericAction<Symbol, Symbol, ()>;
-pub(crate) type MappedAction = GenericAction<CorrespondingVar<Symbol, Symbol>, Symbol, ()>;
-pub(crate) type ResolvedAction = GenericAction<ResolvedCall, ResolvedVar, ()>;
+pub type Action = GenericAction<Symbol, Symbol, Span>;
+pub(crate) type MappedAction = GenericAction<CorrespondingVar<Symbol, Symbol>, Symbol, Span>;
+pub(crate) type ResolvedAction = GenericAction<ResolvedCall, ResolvedVar, Span>;
 
 #[derive(Clone, Debug, PartialEq, Eq, Hash)]
 pub enum GenericAction<Head, Leaf, Ann>
@@ -1238,7 +1910,7 @@ where
     /// the smallest representative.
     /// By default, each constructor costs 1 to extract
     /// (common subexpressions are not shared in the cost
-    /// model).
+    /// model, unless the `extract` command is given `:mode dag`).
     /// The second argument is the number of variants to
     /// extract, picking different terms in the
     /// same equivalence class.
@@ -1259,8 +1931,8 @@ pub struct GenericActions<
     Leaf: Clone + PartialEq + Eq + Display + Hash,
     Ann: Clone + Default,
 >(pub Vec<GenericAction<Head, Leaf, Ann>>);
-pub type Actions = GenericActions<Symbol, Symbol, ()>;
-pub(crate) type ResolvedActions = GenericActions<ResolvedCall, ResolvedVar, ()>;
+pub type Actions = GenericActions<Symbol, Symbol, Span>;
+pub(crate) type ResolvedActions = GenericActions<ResolvedCall, ResolvedVar, Span>;
 pub(crate) type MappedActions<Head, Leaf, Ann> =
     GenericActions<CorrespondingVar<Head, Leaf>, Leaf, Ann>;
 
@@ -1465,10 +2137,14 @@ where
 pub(crate) struct CompiledRule {
     pub(crate) query: CompiledQuery,
     pub(crate) program: Program,
+    /// The rule as it was written, after desugaring.
+    pub(crate) rule: ResolvedRule,
+    /// The canonicalized rule, which proofs are checked against.
+    pub(crate) core: crate::core::ResolvedCoreRule,
 }
 
-pub type Rule = GenericRule<Symbol, Symbol, ()>;
-pub(crate) type ResolvedRule = GenericRule<ResolvedCall, ResolvedVar, ()>;
+pub type Rule = GenericRule<Symbol, Symbol, Span>;
+pub(crate) type ResolvedRule = GenericRule<ResolvedCall, ResolvedVar, Span>;
 
 #[derive(Clone, Debug, PartialEq, Eq, Hash)]
 pub struct GenericRule<Head, Leaf, Ann>
@@ -1588,7 +2264,7 @@ where
     }
 }
 
-type Rewrite = GenericRewrite<Symbol, Symbol, ()>;
+type Rewrite = GenericRewrite<Symbol, Symbol, Span>;
 
 #[derive(Clone, Debug)]
 pub struct GenericRewrite<Head, Leaf, Ann> {
@@ -1661,16 +2337,16 @@ where
     }
 }
 
-impl<Head, Leaf> GenericActions<Head, Leaf, ()>
+impl<Head, Leaf> GenericActions<Head, Leaf, Span>
 where
     Head: Clone + Display,
     Leaf: Clone + PartialEq + Eq + Display + Hash,
 {
-    pub fn new(actions: Vec<GenericAction<Head, Leaf, ()>>) -> Self {
+    pub fn new(actions: Vec<GenericAction<Head, Leaf, Span>>) -> Self {
         Self(actions)
     }
 
-    pub fn singleton(action: GenericAction<Head, Leaf, ()>) -> Self {
+    pub fn singleton(action: GenericAction<Head, Leaf, Span>) -> Self {
         Self(vec![action])
     }
 }
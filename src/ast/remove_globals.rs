@@ -9,6 +9,7 @@ use crate::{
     core::ResolvedCall, typechecking::FuncType, FreshGen, GenericAction, GenericActions,
     GenericExpr, GenericFact, GenericNCommand, GenericRule, HashMap, ResolvedAction, ResolvedExpr,
     ResolvedFact, ResolvedFunctionDecl, ResolvedNCommand, ResolvedVar, Schema, SymbolGen, TypeInfo,
+    DUMMY_SPAN,
 };
 
 struct GlobalRemover<'a> {
@@ -126,7 +127,7 @@ impl<'a> GlobalRemover<'a> {
                         // output is eq-able, so generate a union
                         if ty.is_eq_sort() {
                             GenericNCommand::CoreAction(GenericAction::Union(
-                                ann,
+                                ann.clone(),
                                 GenericExpr::Call(ann, resolved_call, vec![]),
                                 remove_globals_expr(expr),
                             ))
@@ -156,7 +157,7 @@ impl<'a> GlobalRemover<'a> {
                         globals.insert(
                             resolved_var.clone(),
                             GenericExpr::Var(
-                                (),
+                                DUMMY_SPAN.clone(),
                                 ResolvedVar {
                                     name: new_name,
                                     sort: resolved_var.sort.clone(),
@@ -170,10 +171,17 @@ impl<'a> GlobalRemover<'a> {
                 let new_facts: Vec<ResolvedFact> = globals
                     .iter()
                     .map(|(old, new)| {
-                        GenericFact::Eq(vec![
-                            GenericExpr::Call((), resolved_var_to_call(old), vec![]),
-                            new.clone(),
-                        ])
+                        GenericFact::Eq(
+                            DUMMY_SPAN.clone(),
+                            vec![
+                                GenericExpr::Call(
+                                    DUMMY_SPAN.clone(),
+                                    resolved_var_to_call(old),
+                                    vec![],
+                                ),
+                                new.clone(),
+                            ],
+                        )
                     })
                     .collect();
 
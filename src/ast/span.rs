@@ -0,0 +1,149 @@
+use std::fmt::{Debug, Display, Formatter};
+use std::hash::{Hash, Hasher};
+use std::sync::Arc;
+
+use lazy_static::lazy_static;
+
+/// The source of a program, with the name of the file it was read from.
+#[derive(Debug, Default)]
+pub struct SrcFile {
+    pub name: Option<String>,
+    pub contents: String,
+}
+
+impl SrcFile {
+    pub fn new(name: Option<String>, contents: String) -> Self {
+        SrcFile { name, contents }
+    }
+
+    /// The line and column of a byte offset, counting from 1.
+    fn line_col(&self, offset: usize) -> (usize, usize) {
+        let before = &self.contents[..offset.min(self.contents.len())];
+        let line_start = before.rfind('\n').map_or(0, |i| i + 1);
+        let line = before.matches('\n').count() + 1;
+        let col = before[line_start..].chars().count() + 1;
+        (line, col)
+    }
+}
+
+/// A range of bytes in a [`SrcFile`], giving the location of some syntax
+/// for error messages.
+///
+/// Spans are ignored when comparing and hashing syntax, so that the same
+/// program is equal however it is laid out.
+#[derive(Clone)]
+pub struct Span(pub(crate) Arc<SrcFile>, pub(crate) usize, pub(crate) usize);
+
+lazy_static! {
+    /// The span of syntax that was not parsed from a program, such as
+    /// syntax made by desugaring or from Rust.
+    pub static ref DUMMY_SPAN: Span = Span(Arc::new(SrcFile::default()), 0, 0);
+}
+
+impl Span {
+    pub fn new(file: Arc<SrcFile>, start: usize, end: usize) -> Self {
+        Span(file, start, end)
+    }
+
+    pub fn is_dummy(&self) -> bool {
+        Arc::ptr_eq(&self.0, &DUMMY_SPAN.0)
+    }
+
+    pub fn file(&self) -> &SrcFile {
+        &self.0
+    }
+
+    /// The range of bytes of the span in its file.
+    pub fn range(&self) -> std::ops::Range<usize> {
+        self.1..self.2
+    }
+
+    /// The text of the span.
+    pub fn text(&self) -> &str {
+        &self.0.contents[self.range()]
+    }
+
+    /// The line and column where the span starts, counting from 1.
+    pub fn start(&self) -> (usize, usize) {
+        self.0.line_col(self.1)
+    }
+
+    /// The line and column where the span ends, counting from 1.
+    pub fn end(&self) -> (usize, usize) {
+        self.0.line_col(self.2)
+    }
+
+    /// The first line of the span, with carets under the part of it in
+    /// the span.
+    pub fn snippet(&self) -> String {
+        let (line, col) = self.start();
+        let contents = &self.0.contents;
+        let line_start = contents[..self.1].rfind('\n').map_or(0, |i| i + 1);
+        let line_end = contents[self.1..]
+            .find('\n')
+            .map_or(contents.len(), |i| self.1 + i);
+        let text = contents[line_start..line_end].trim_end();
+        let width = contents[self.1..self.2.min(line_end).max(self.1)]
+            .chars()
+            .count()
+            .max(1);
+        let number = line.to_string();
+        let margin = " ".repeat(number.len());
+        format!(
+            "{margin} |\n{number} | {text}\n{margin} | {}{}",
+            " ".repeat(col - 1),
+            "^".repeat(width)
+        )
+    }
+}
+
+impl Default for Span {
+    fn default() -> Self {
+        DUMMY_SPAN.clone()
+    }
+}
+
+/// `file:line:col`, or `line:col` for a program not read from a file.
+impl Display for Span {
+    fn fmt(&self, f: &mut Formatter<'_>) -> std::fmt::Result {
+        let (line, col) = self.start();
+        match &self.0.name {
+            Some(name) => write!(f, "{name}:{line}:{col}"),
+            None => write!(f, "{line}:{col}"),
+        }
+    }
+}
+
+impl Debug for Span {
+    fn fmt(&self, f: &mut Formatter<'_>) -> std::fmt::Result {
+        if self.is_dummy() {
+            write!(f, "DUMMY_SPAN")
+        } else {
+            write!(f, "Span({self})")
+        }
+    }
+}
+
+impl PartialEq for Span {
+    fn eq(&self, _other: &Self) -> bool {
+        true
+    }
+}
+
+impl Eq for Span {}
+
+impl PartialOrd for Span {
+    fn partial_cmp(&self, other: &Self) -> Option<std::cmp::Ordering> {
+        Some(self.cmp(other))
+    }
+}
+
+impl Ord for Span {
+    fn cmp(&self, _other: &Self) -> std::cmp::Ordering {
+        std::cmp::Ordering::Equal
+    }
+}
+
+impl Hash for Span {
+    fn hash<H: Hasher>(&self, _state: &mut H) {}
+}
@@ -0,0 +1,353 @@
+use std::cell::OnceCell;
+
+use egglog::ast::{Action, Command, Schema, Span};
+use egglog::{EGraph, Error, RunMode};
+use lsp_types::{
+    CompletionItem, CompletionItemKind, Diagnostic, DiagnosticSeverity, Position, Range,
+};
+
+/// The kinds of names a program can declare.
+#[derive(Debug, Clone, Copy, PartialEq, Eq)]
+pub enum DeclarationKind {
+    Sort,
+    Function,
+    Ruleset,
+    Global,
+}
+
+/// A name declared by a command, with where the name is written and a
+/// short signature to show on hover.
+#[derive(Debug, Clone)]
+pub struct Declaration {
+    pub name: String,
+    pub kind: DeclarationKind,
+    pub range: std::ops::Range<usize>,
+    pub signature: String,
+}
+
+/// An open `.egg` file and the names it declares.
+pub struct Document {
+    path: Option<String>,
+    text: String,
+    /// The version the client gave the text, which increases with each edit.
+    version: i32,
+    declarations: Vec<Declaration>,
+    /// The diagnostics for this version of the text, once they are asked for.
+    diagnostics: OnceCell<Vec<Diagnostic>>,
+}
+
+impl Document {
+    pub fn new(path: Option<String>, text: String, version: i32) -> Self {
+        let mut document = Document {
+            path,
+            text: String::new(),
+            version,
+            declarations: vec![],
+            diagnostics: OnceCell::new(),
+        };
+        document.set_text(text, version);
+        document
+    }
+
+    pub fn version(&self) -> i32 {
+        self.version
+    }
+
+    /// Replace the text of the document. The declarations from the last
+    /// text that parsed are kept while the new text has a syntax error.
+    pub fn set_text(&mut self, text: String, version: i32) {
+        self.text = text;
+        self.version = version;
+        self.diagnostics = OnceCell::new();
+        if let Ok(commands) = EGraph::default().parse_source(self.path.clone(), &self.text) {
+            self.declarations = commands
+                .iter()
+                .flat_map(|(span, command)| declarations(span, command))
+                .collect();
+        }
+    }
+
+    /// Parse and typecheck the document without running it, returning an
+    /// error for each command that fails. They are only computed once per
+    /// version of the text, since typechecking reads any `include`d files.
+    pub fn diagnostics(&self) -> &[Diagnostic] {
+        self.diagnostics.get_or_init(|| self.check())
+    }
+
+    fn check(&self) -> Vec<Diagnostic> {
+        let mut egraph = EGraph::default();
+        egraph.run_mode = RunMode::ShowDesugaredEgglog;
+        let commands = match egraph.parse_source(self.path.clone(), &self.text) {
+            Ok(commands) => commands,
+            Err(error) => return vec![self.diagnostic(&error, None)],
+        };
+        let mut diagnostics = vec![];
+        for (span, command) in commands {
+            if let Err(error) = egraph.run_spanned_program(vec![(span.clone(), command)]) {
+                diagnostics.push(self.diagnostic(&error, Some(&span)));
+            }
+        }
+        diagnostics
+    }
+
+    /// Report an error at its own span if it is in this document, and
+    /// otherwise at the command that caused it, such as an `include`.
+    fn diagnostic(&self, error: &Error, command: Option<&Span>) -> Diagnostic {
+        let range = match error.span() {
+            Some(span) if span.file().name == self.path => span.range(),
+            _ => command.map_or(0..0, |span| span.range()),
+        };
+        Diagnostic {
+            range: self.lsp_range(range),
+            severity: Some(DiagnosticSeverity::ERROR),
+            source: Some("egglog".into()),
+            message: error.inner().to_string(),
+            ..Default::default()
+        }
+    }
+
+    /// The declaration of the name under the cursor.
+    pub fn declaration_at(&self, position: Position) -> Option<&Declaration> {
+        let word = self.word_at(self.offset(position));
+        self.declarations.iter().find(|decl| decl.name == word)
+    }
+
+    pub fn completions(&self) -> Vec<CompletionItem> {
+        self.declarations
+            .iter()
+            .map(|decl| CompletionItem {
+                label: decl.name.clone(),
+                kind: Some(match decl.kind {
+                    DeclarationKind::Sort => CompletionItemKind::CLASS,
+                    DeclarationKind::Function => CompletionItemKind::FUNCTION,
+                    DeclarationKind::Ruleset => CompletionItemKind::MODULE,
+                    DeclarationKind::Global => CompletionItemKind::VARIABLE,
+                }),
+                detail: Some(decl.signature.clone()),
+                ..Default::default()
+            })
+            .collect()
+    }
+
+    /// The identifier containing a byte offset, or the empty string.
+    fn word_at(&self, offset: usize) -> &str {
+        let is_delimiter = |c: char| c.is_whitespace() || "();\"".contains(c);
+        let start = self.text[..offset].rfind(is_delimiter).map_or(0, |i| i + 1);
+        let end = self.text[offset..]
+            .find(is_delimiter)
+            .map_or(self.text.len(), |i| offset + i);
+        &self.text[start..end]
+    }
+
+    /// Convert a byte offset to a line and UTF-16 column.
+    fn position(&self, offset: usize) -> Position {
+        let before = &self.text[..offset];
+        let line_start = before.rfind('\n').map_or(0, |i| i + 1);
+        Position {
+            line: before.matches('\n').count() as u32,
+            character: before[line_start..].encode_utf16().count() as u32,
+        }
+    }
+
+    /// Convert a line and UTF-16 column to a byte offset, clamping
+    /// positions past the end of a line or of the document.
+    fn offset(&self, position: Position) -> usize {
+        let line_start = self
+            .text
+            .split_inclusive('\n')
+            .take(position.line as usize)
+            .map(str::len)
+            .sum::<usize>();
+        let mut column = 0;
+        for (i, c) in self.text[line_start..].char_indices() {
+            if c == '\n' || column >= position.character as usize {
+                return line_start + i;
+            }
+            column += c.len_utf16();
+        }
+        self.text.len()
+    }
+
+    pub fn lsp_range(&self, range: std::ops::Range<usize>) -> Range {
+        Range {
+            start: self.position(range.start),
+            end: self.position(range.end),
+        }
+    }
+}
+
+/// The names declared by a command, found in the command's text.
+fn declarations(span: &Span, command: &Command) -> Vec<Declaration> {
+    let mut names = NameFinder {
+        span,
+        from: 0,
+        found: vec![],
+    };
+    match command {
+        Command::Sort(name, None) => {
+            names.find(name, DeclarationKind::Sort, format!("(sort {name})"));
+        }
+        Command::Sort(name, Some((container, args))) => {
+            let args: Vec<_> = args.iter().map(|arg| arg.to_string()).collect();
+            let signature = format!("(sort {name} ({container} {}))", args.join(" "));
+            names.find(name, DeclarationKind::Sort, signature);
+        }
+        Command::Datatype { name, variants } => {
+            names.find(name, DeclarationKind::Sort, format!("(datatype {name})"));
+            for variant in variants {
+                let schema = Schema {
+                    input: variant.types.clone(),
+                    output: *name,
+                };
+                names.find(
+                    &variant.name,
+                    DeclarationKind::Function,
+                    function_signature(&variant.name, &schema),
+                );
+            }
+        }
+        Command::Function(decl) => {
+            names.find(
+                &decl.name,
+                DeclarationKind::Function,
+                function_signature(&decl.name, &decl.schema),
+            );
+        }
+        Command::Relation {
+            constructor,
+            inputs,
+        } => {
+            let inputs: Vec<_> = inputs.iter().map(|input| input.to_string()).collect();
+            let signature = format!("(relation {constructor} ({}))", inputs.join(" "));
+            names.find(constructor, DeclarationKind::Function, signature);
+        }
+        Command::Declare { name, sort } => {
+            let signature = format!("(declare {name} {sort})");
+            names.find(name, DeclarationKind::Global, signature);
+        }
+        Command::Action(Action::Let(_, name, expr)) => {
+            names.find(
+                name,
+                DeclarationKind::Global,
+                format!("(let {name} {expr})"),
+            );
+        }
+        Command::AddRuleset(name) => {
+            names.find(name, DeclarationKind::Ruleset, format!("(ruleset {name})"));
+        }
+        Command::UnstableCombinedRuleset(name, rulesets) => {
+            let rulesets: Vec<_> = rulesets.iter().map(|r| r.to_string()).collect();
+            let signature = format!("(unstable-combined-ruleset {name} {})", rulesets.join(" "));
+            names.find(name, DeclarationKind::Ruleset, signature);
+        }
+        _ => {}
+    }
+    names.found
+}
+
+fn function_signature(name: &impl std::fmt::Display, schema: &Schema) -> String {
+    let input: Vec<_> = schema.input.iter().map(|sort| sort.to_string()).collect();
+    format!("(function {name} ({}) {})", input.join(" "), schema.output)
+}
+
+/// Finds declared names in the text of a command, in the order they are
+/// written, so that each name is found after the previous one.
+struct NameFinder<'a> {
+    span: &'a Span,
+    from: usize,
+    found: Vec<Declaration>,
+}
+
+impl<'a> NameFinder<'a> {
+    fn find(&mut self, name: &impl std::fmt::Display, kind: DeclarationKind, signature: String) {
+        let name = name.to_string();
+        let text = self.span.text();
+        let is_delimiter = |c: Option<char>| match c {
+            Some(c) => c.is_whitespace() || "()".contains(c),
+            None => true,
+        };
+        let mut search = self.from;
+        while let Some(i) = text[search..].find(&name) {
+            let start = search + i;
+            let end = start + name.len();
+            if is_delimiter(text[..start].chars().next_back())
+                && is_delimiter(text[end..].chars().next())
+            {
+                self.from = end;
+                let offset = self.span.range().start;
+                self.found.push(Declaration {
+                    name,
+                    kind,
+                    range: offset + start..offset + end,
+                    signature,
+                });
+                return;
+            }
+            search = end;
+        }
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    const PROGRAM: &str = "(datatype Math (Num i64) (Add Math Math))
+(function cost (Math) i64 :merge (min old new))
+(ruleset opt)
+(let two (Num 2))
+(rule ((Add x y)) ((cost x)) :ruleset opt)
+";
+
+    #[test]
+    fn test_declarations() {
+        let document = Document::new(None, PROGRAM.into(), 0);
+        let names: Vec<_> = document
+            .declarations
+            .iter()
+            .map(|decl| (decl.name.as_str(), decl.kind))
+            .collect();
+        assert_eq!(
+            names,
+            [
+                ("Math", DeclarationKind::Sort),
+                ("Num", DeclarationKind::Function),
+                ("Add", DeclarationKind::Function),
+                ("cost", DeclarationKind::Function),
+                ("opt", DeclarationKind::Ruleset),
+                ("two", DeclarationKind::Global),
+            ]
+        );
+
+        // `Add` in the rule on line 5 goes to the variant on line 1
+        let add = document.declaration_at(Position::new(4, 8)).unwrap();
+        assert_eq!(add.signature, "(function Add (Math Math) Math)");
+        assert_eq!(
+            document.lsp_range(add.range.clone()),
+            Range::new(Position::new(0, 26), Position::new(0, 29))
+        );
+        let opt = document.declaration_at(Position::new(4, 40)).unwrap();
+        assert_eq!(opt.kind, DeclarationKind::Ruleset);
+    }
+
+    #[test]
+    fn test_diagnostics() {
+        let document = Document::new(None, PROGRAM.into(), 0);
+        assert!(document.diagnostics().is_empty());
+
+        let text = format!("{PROGRAM}(check (cost (Num 1) 2))\n(rule ((Mul x)) ())\n");
+        let document = Document::new(None, text, 0);
+        let diagnostics = document.diagnostics();
+        assert_eq!(diagnostics.len(), 2);
+        // the same version is only checked once
+        assert!(std::ptr::eq(diagnostics, document.diagnostics()));
+        assert_eq!(diagnostics[0].range.start.line, 5);
+        assert_eq!(diagnostics[1].range.start.line, 6);
+
+        let document = Document::new(None, "(datatype Math\n  (Num i64)".into(), 0);
+        let diagnostics = document.diagnostics();
+        assert_eq!(diagnostics.len(), 1);
+        // a document that never parsed declares nothing
+        assert!(document.completions().is_empty());
+    }
+}
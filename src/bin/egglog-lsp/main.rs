@@ -0,0 +1,394 @@
+//! A language server for egglog programs, speaking the Language Server
+//! Protocol over stdio. It typechecks each file when it is opened or saved,
+//! rather than on every edit, without running it, and answers go-to-definition, hover and completion
+//! requests for the sorts, functions, rulesets and globals a file declares.
+
+mod document;
+
+use document::Document;
+use egglog::util::IndexMap;
+use lsp_server::{Connection, ExtractError, Message, Notification, Request, RequestId, Response};
+use lsp_types::notification::{
+    DidChangeTextDocument, DidCloseTextDocument, DidOpenTextDocument, DidSaveTextDocument,
+    Notification as _, PublishDiagnostics,
+};
+use lsp_types::request::{Completion, GotoDefinition, HoverRequest};
+use lsp_types::{
+    CompletionOptions, CompletionResponse, GotoDefinitionResponse, Hover, HoverContents,
+    HoverProviderCapability, LanguageString, Location, MarkedString, OneOf,
+    PublishDiagnosticsParams, SaveOptions, ServerCapabilities, TextDocumentSyncCapability,
+    TextDocumentSyncKind, TextDocumentSyncOptions, TextDocumentSyncSaveOptions, Url,
+};
+
+type Result<T> = std::result::Result<T, Box<dyn std::error::Error + Send + Sync>>;
+
+fn main() -> Result<()> {
+    let (connection, io_threads) = Connection::stdio();
+    // `serve` owns the connection so that it is closed before joining the
+    // threads that read and write stdio
+    serve(connection)?;
+    io_threads.join()?;
+    Ok(())
+}
+
+/// Initialize the server on a connection and handle messages until the
+/// client shuts it down.
+fn serve(connection: Connection) -> Result<()> {
+    let capabilities = ServerCapabilities {
+        text_document_sync: Some(TextDocumentSyncCapability::Options(
+            TextDocumentSyncOptions {
+                open_close: Some(true),
+                change: Some(TextDocumentSyncKind::FULL),
+                save: Some(TextDocumentSyncSaveOptions::SaveOptions(SaveOptions {
+                    include_text: Some(false),
+                })),
+                ..Default::default()
+            },
+        )),
+        definition_provider: Some(OneOf::Left(true)),
+        hover_provider: Some(HoverProviderCapability::Simple(true)),
+        completion_provider: Some(CompletionOptions::default()),
+        ..Default::default()
+    };
+    connection.initialize(serde_json::to_value(capabilities)?)?;
+    Server {
+        connection,
+        documents: Default::default(),
+    }
+    .run()
+}
+
+struct Server {
+    connection: Connection,
+    documents: IndexMap<Url, Document>,
+}
+
+impl Server {
+    fn run(mut self) -> Result<()> {
+        while let Ok(message) = self.connection.receiver.recv() {
+            match message {
+                Message::Request(request) => {
+                    if self.connection.handle_shutdown(&request)? {
+                        return Ok(());
+                    }
+                    let response = self.handle_request(request)?;
+                    self.connection.sender.send(Message::Response(response))?;
+                }
+                Message::Notification(notification) => self.handle_notification(notification)?,
+                Message::Response(_) => {}
+            }
+        }
+        Ok(())
+    }
+
+    fn handle_request(&self, request: Request) -> Result<Response> {
+        let request = match cast::<GotoDefinition>(request)? {
+            Ok((id, params)) => {
+                let position = params.text_document_position_params;
+                let uri = position.text_document.uri;
+                let location = self.documents.get(&uri).and_then(|document| {
+                    let decl = document.declaration_at(position.position)?;
+                    Some(GotoDefinitionResponse::Scalar(Location {
+                        uri: uri.clone(),
+                        range: document.lsp_range(decl.range.clone()),
+                    }))
+                });
+                return Ok(Response::new_ok(id, location));
+            }
+            Err(request) => request,
+        };
+        let request = match cast::<HoverRequest>(request)? {
+            Ok((id, params)) => {
+                let position = params.text_document_position_params;
+                let hover = self
+                    .documents
+                    .get(&position.text_document.uri)
+                    .and_then(|document| document.declaration_at(position.position))
+                    .map(|decl| Hover {
+                        contents: HoverContents::Scalar(MarkedString::LanguageString(
+                            LanguageString {
+                                language: "egglog".into(),
+                                value: decl.signature.clone(),
+                            },
+                        )),
+                        range: None,
+                    });
+                return Ok(Response::new_ok(id, hover));
+            }
+            Err(request) => request,
+        };
+        let request = match cast::<Completion>(request)? {
+            Ok((id, params)) => {
+                let uri = params.text_document_position.text_document.uri;
+                let items = self
+                    .documents
+                    .get(&uri)
+                    .map(|document| document.completions())
+                    .unwrap_or_default();
+                return Ok(Response::new_ok(id, CompletionResponse::Array(items)));
+            }
+            Err(request) => request,
+        };
+        Ok(Response::new_err(
+            request.id,
+            lsp_server::ErrorCode::MethodNotFound as i32,
+            format!("Unsupported request {}", request.method),
+        ))
+    }
+
+    fn handle_notification(&mut self, notification: Notification) -> Result<()> {
+        match notification.method.as_str() {
+            DidOpenTextDocument::METHOD => {
+                let params = params::<DidOpenTextDocument>(notification)?;
+                let uri = params.text_document.uri;
+                let path = uri
+                    .to_file_path()
+                    .ok()
+                    .map(|path| path.display().to_string());
+                let document = Document::new(
+                    path,
+                    params.text_document.text,
+                    params.text_document.version,
+                );
+                self.documents.insert(uri.clone(), document);
+                self.publish_diagnostics(uri)?;
+            }
+            DidChangeTextDocument::METHOD => {
+                let mut params = params::<DidChangeTextDocument>(notification)?;
+                let uri = params.text_document.uri;
+                // with full sync, the last change holds the whole text
+                if let (Some(document), Some(change)) =
+                    (self.documents.get_mut(&uri), params.content_changes.pop())
+                {
+                    document.set_text(change.text, params.text_document.version);
+                }
+            }
+            DidSaveTextDocument::METHOD => {
+                let params = params::<DidSaveTextDocument>(notification)?;
+                self.publish_diagnostics(params.text_document.uri)?;
+            }
+            DidCloseTextDocument::METHOD => {
+                let params = params::<DidCloseTextDocument>(notification)?;
+                self.documents.shift_remove(&params.text_document.uri);
+            }
+            _ => {}
+        }
+        Ok(())
+    }
+
+    fn publish_diagnostics(&self, uri: Url) -> Result<()> {
+        let Some(document) = self.documents.get(&uri) else {
+            return Ok(());
+        };
+        let params = PublishDiagnosticsParams {
+            uri,
+            diagnostics: document.diagnostics().to_vec(),
+            version: Some(document.version()),
+        };
+        let notification = Notification::new(PublishDiagnostics::METHOD.into(), params);
+        self.connection
+            .sender
+            .send(Message::Notification(notification))?;
+        Ok(())
+    }
+}
+
+/// Extract the parameters of a request if it is for the method `R`, and
+/// otherwise give the request back.
+fn cast<R: lsp_types::request::Request>(
+    request: Request,
+) -> Result<std::result::Result<(RequestId, R::Params), Request>> {
+    match request.extract(R::METHOD) {
+        Ok(extracted) => Ok(Ok(extracted)),
+        Err(ExtractError::MethodMismatch(request)) => Ok(Err(request)),
+        Err(error) => Err(error.into()),
+    }
+}
+
+fn params<N: lsp_types::notification::Notification>(
+    notification: Notification,
+) -> Result<N::Params> {
+    Ok(notification.extract(N::METHOD)?)
+}
+
+#[cfg(test)]
+mod tests {
+    use std::collections::VecDeque;
+    use std::thread::JoinHandle;
+
+    use lsp_types::notification::{Exit, Initialized};
+    use lsp_types::request::{Initialize, Shutdown};
+    use lsp_types::{
+        CompletionParams, DidChangeTextDocumentParams, DidOpenTextDocumentParams,
+        DidSaveTextDocumentParams, GotoDefinitionParams, InitializeParams, InitializeResult,
+        InitializedParams, Position, Range, TextDocumentContentChangeEvent, TextDocumentIdentifier,
+        TextDocumentItem, TextDocumentPositionParams, VersionedTextDocumentIdentifier,
+    };
+
+    use super::*;
+
+    const PROGRAM: &str = "(datatype Math (Num i64) (Add Math Math))
+(rule ((Add x y)) ((Add y x)))
+";
+
+    /// A client talking to a server running on another thread.
+    struct Client {
+        connection: Connection,
+        server: JoinHandle<Result<()>>,
+        next_id: i32,
+        notifications: VecDeque<Notification>,
+        initialized: InitializeResult,
+    }
+
+    impl Client {
+        fn start() -> Self {
+            let (server, connection) = Connection::memory();
+            let server = std::thread::spawn(move || serve(server));
+            let mut client = Client {
+                connection,
+                server,
+                next_id: 0,
+                notifications: VecDeque::new(),
+                initialized: InitializeResult::default(),
+            };
+            client.initialized = client.request::<Initialize>(InitializeParams::default());
+            client.notify::<Initialized>(InitializedParams {});
+            client
+        }
+
+        fn request<R: lsp_types::request::Request>(&mut self, params: R::Params) -> R::Result {
+            self.next_id += 1;
+            let id = RequestId::from(self.next_id);
+            let request = Request::new(id.clone(), R::METHOD.into(), params);
+            self.connection
+                .sender
+                .send(Message::Request(request))
+                .unwrap();
+            loop {
+                match self.connection.receiver.recv().unwrap() {
+                    Message::Response(response) if response.id == id => {
+                        assert!(response.error.is_none(), "{:?}", response.error);
+                        let result = response.result.unwrap_or_default();
+                        return serde_json::from_value(result).unwrap();
+                    }
+                    Message::Notification(notification) => {
+                        self.notifications.push_back(notification)
+                    }
+                    message => panic!("unexpected message {message:?}"),
+                }
+            }
+        }
+
+        fn notify<N: lsp_types::notification::Notification>(&self, params: N::Params) {
+            let notification = Notification::new(N::METHOD.into(), params);
+            self.connection
+                .sender
+                .send(Message::Notification(notification))
+                .unwrap();
+        }
+
+        /// The next diagnostics the server publishes.
+        fn diagnostics(&mut self) -> PublishDiagnosticsParams {
+            let notification = match self.notifications.pop_front() {
+                Some(notification) => notification,
+                None => match self.connection.receiver.recv().unwrap() {
+                    Message::Notification(notification) => notification,
+                    message => panic!("unexpected message {message:?}"),
+                },
+            };
+            params::<PublishDiagnostics>(notification).unwrap()
+        }
+
+        fn open(&mut self, uri: &Url, text: &str) -> PublishDiagnosticsParams {
+            self.notify::<DidOpenTextDocument>(DidOpenTextDocumentParams {
+                text_document: TextDocumentItem::new(uri.clone(), "egglog".into(), 1, text.into()),
+            });
+            self.diagnostics()
+        }
+
+        fn shutdown(mut self) {
+            self.request::<Shutdown>(());
+            self.notify::<Exit>(());
+            self.server.join().unwrap().unwrap();
+            assert!(self.notifications.is_empty());
+        }
+    }
+
+    fn uri() -> Url {
+        Url::parse("file:///test.egg").unwrap()
+    }
+
+    #[test]
+    fn test_initialize() {
+        let client = Client::start();
+        let capabilities = &client.initialized.capabilities;
+        assert_eq!(capabilities.definition_provider, Some(OneOf::Left(true)));
+        assert!(capabilities.completion_provider.is_some());
+        client.shutdown();
+    }
+
+    #[test]
+    fn test_diagnostics_on_open_and_save() {
+        let mut client = Client::start();
+        let diagnostics = client.open(&uri(), &format!("{PROGRAM}(Mul 1)\n"));
+        assert_eq!(diagnostics.version, Some(1));
+        assert_eq!(diagnostics.diagnostics.len(), 1);
+        assert_eq!(diagnostics.diagnostics[0].range.start.line, 2);
+
+        // edits are only checked once they are saved
+        client.notify::<DidChangeTextDocument>(DidChangeTextDocumentParams {
+            text_document: VersionedTextDocumentIdentifier::new(uri(), 2),
+            content_changes: vec![TextDocumentContentChangeEvent {
+                range: None,
+                range_length: None,
+                text: PROGRAM.into(),
+            }],
+        });
+        client.notify::<DidSaveTextDocument>(DidSaveTextDocumentParams {
+            text_document: TextDocumentIdentifier::new(uri()),
+            text: None,
+        });
+        let diagnostics = client.diagnostics();
+        assert_eq!(diagnostics.version, Some(2));
+        assert!(diagnostics.diagnostics.is_empty());
+        client.shutdown();
+    }
+
+    #[test]
+    fn test_definition_and_completion() {
+        let mut client = Client::start();
+        assert!(client.open(&uri(), PROGRAM).diagnostics.is_empty());
+        let position = TextDocumentPositionParams::new(
+            TextDocumentIdentifier::new(uri()),
+            Position::new(1, 8),
+        );
+
+        // `Add` in the rule goes to the variant on the first line
+        let definition = client.request::<GotoDefinition>(GotoDefinitionParams {
+            text_document_position_params: position.clone(),
+            work_done_progress_params: Default::default(),
+            partial_result_params: Default::default(),
+        });
+        let Some(GotoDefinitionResponse::Scalar(location)) = definition else {
+            panic!("no definition for Add: {definition:?}");
+        };
+        assert_eq!(location.uri, uri());
+        assert_eq!(
+            location.range,
+            Range::new(Position::new(0, 26), Position::new(0, 29))
+        );
+
+        let completions = client.request::<Completion>(CompletionParams {
+            text_document_position: position,
+            work_done_progress_params: Default::default(),
+            partial_result_params: Default::default(),
+            context: None,
+        });
+        let Some(CompletionResponse::Array(items)) = completions else {
+            panic!("no completions: {completions:?}");
+        };
+        let labels: Vec<_> = items.iter().map(|item| item.label.as_str()).collect();
+        assert_eq!(labels, ["Math", "Num", "Add"]);
+        client.shutdown();
+    }
+}
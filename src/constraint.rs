@@ -1,7 +1,7 @@
 use crate::{
     ast::{
         GenericAction, GenericActions, GenericExpr, GenericFact, MappedAction, ResolvedAction,
-        ResolvedActions, ResolvedExpr, ResolvedFact, ResolvedVar,
+        ResolvedActions, ResolvedExpr, ResolvedFact, ResolvedVar, Span,
     },
     core::{
         Atom, AtomTerm, CoreAction, CoreRule, GenericCoreActions, Query, ResolvedCall, SymbolOrEq,
@@ -217,19 +217,19 @@ where
 impl Assignment<AtomTerm, ArcSort> {
     pub(crate) fn annotate_expr(
         &self,
-        expr: &GenericExpr<CorrespondingVar<Symbol, Symbol>, Symbol, ()>,
+        expr: &GenericExpr<CorrespondingVar<Symbol, Symbol>, Symbol, Span>,
         typeinfo: &TypeInfo,
     ) -> ResolvedExpr {
         match &expr {
-            GenericExpr::Lit((), literal) => ResolvedExpr::Lit((), literal.clone()),
-            GenericExpr::Var((), var) => {
+            GenericExpr::Lit(span, literal) => ResolvedExpr::Lit(span.clone(), literal.clone()),
+            GenericExpr::Var(span, var) => {
                 let global_ty = typeinfo.lookup_global(var);
                 let ty = global_ty
                     .clone()
                     .or_else(|| self.get(&AtomTerm::Var(*var)).cloned())
                     .expect("All variables should be assigned before annotation");
                 ResolvedExpr::Var(
-                    (),
+                    span.clone(),
                     ResolvedVar {
                         name: *var,
                         sort: ty.clone(),
@@ -238,7 +238,7 @@ impl Assignment<AtomTerm, ArcSort> {
                 )
             }
             GenericExpr::Call(
-                (),
+                span,
                 CorrespondingVar {
                     head,
                     to: corresponding_var,
@@ -260,18 +260,19 @@ impl Assignment<AtomTerm, ArcSort> {
                     ))
                     .collect();
                 let resolved_call = ResolvedCall::from_resolution(head, &types, typeinfo);
-                GenericExpr::Call((), resolved_call, args)
+                GenericExpr::Call(span.clone(), resolved_call, args)
             }
         }
     }
 
     pub(crate) fn annotate_fact(
         &self,
-        facts: &GenericFact<CorrespondingVar<Symbol, Symbol>, Symbol, ()>,
+        facts: &GenericFact<CorrespondingVar<Symbol, Symbol>, Symbol, Span>,
         typeinfo: &TypeInfo,
     ) -> ResolvedFact {
         match facts {
-            GenericFact::Eq(facts) => ResolvedFact::Eq(
+            GenericFact::Eq(span, facts) => ResolvedFact::Eq(
+                span.clone(),
                 facts
                     .iter()
                     .map(|expr| self.annotate_expr(expr, typeinfo))
@@ -283,7 +284,7 @@ impl Assignment<AtomTerm, ArcSort> {
 
     pub(crate) fn annotate_facts(
         &self,
-        mapped_facts: &[GenericFact<CorrespondingVar<Symbol, Symbol>, Symbol, ()>],
+        mapped_facts: &[GenericFact<CorrespondingVar<Symbol, Symbol>, Symbol, Span>],
         typeinfo: &TypeInfo,
     ) -> Vec<ResolvedFact> {
         mapped_facts
@@ -298,12 +299,12 @@ impl Assignment<AtomTerm, ArcSort> {
         typeinfo: &TypeInfo,
     ) -> Result<ResolvedAction, TypeError> {
         match action {
-            GenericAction::Let((), var, expr) => {
+            GenericAction::Let(span, var, expr) => {
                 let ty = self
                     .get(&AtomTerm::Var(*var))
                     .expect("All variables should be assigned before annotation");
                 Ok(ResolvedAction::Let(
-                    (),
+                    span.clone(),
                     ResolvedVar {
                         name: *var,
                         sort: ty.clone(),
@@ -314,7 +315,7 @@ impl Assignment<AtomTerm, ArcSort> {
             }
             // Note mapped_var for set is a dummy variable that does not mean anything
             GenericAction::Set(
-                (),
+                span,
                 CorrespondingVar {
                     head,
                     to: _mapped_var,
@@ -336,11 +337,16 @@ impl Assignment<AtomTerm, ArcSort> {
                 if !matches!(resolved_call, ResolvedCall::Func(_)) {
                     return Err(TypeError::UnboundFunction(*head));
                 }
-                Ok(ResolvedAction::Set((), resolved_call, children, rhs))
+                Ok(ResolvedAction::Set(
+                    span.clone(),
+                    resolved_call,
+                    children,
+                    rhs,
+                ))
             }
             // Note mapped_var for delete is a dummy variable that does not mean anything
             GenericAction::Change(
-                (),
+                span,
                 change,
                 CorrespondingVar {
                     head,
@@ -360,32 +366,33 @@ impl Assignment<AtomTerm, ArcSort> {
                     ResolvedCall::from_resolution_func_types(head, &types, typeinfo)
                         .ok_or_else(|| TypeError::UnboundFunction(*head))?;
                 Ok(ResolvedAction::Change(
-                    (),
+                    span.clone(),
                     *change,
                     resolved_call,
                     children.clone(),
                 ))
             }
-            GenericAction::Union((), lhs, rhs) => Ok(ResolvedAction::Union(
-                (),
+            GenericAction::Union(span, lhs, rhs) => Ok(ResolvedAction::Union(
+                span.clone(),
                 self.annotate_expr(lhs, typeinfo),
                 self.annotate_expr(rhs, typeinfo),
             )),
-            GenericAction::Extract((), lhs, rhs) => Ok(ResolvedAction::Extract(
-                (),
+            GenericAction::Extract(span, lhs, rhs) => Ok(ResolvedAction::Extract(
+                span.clone(),
                 self.annotate_expr(lhs, typeinfo),
                 self.annotate_expr(rhs, typeinfo),
             )),
-            GenericAction::Panic((), msg) => Ok(ResolvedAction::Panic((), msg.clone())),
-            GenericAction::Expr((), expr) => {
-                Ok(ResolvedAction::Expr((), self.annotate_expr(expr, typeinfo)))
-            }
+            GenericAction::Panic(span, msg) => Ok(ResolvedAction::Panic(span.clone(), msg.clone())),
+            GenericAction::Expr(span, expr) => Ok(ResolvedAction::Expr(
+                span.clone(),
+                self.annotate_expr(expr, typeinfo),
+            )),
         }
     }
 
     pub(crate) fn annotate_actions(
         &self,
-        mapped_actions: &GenericActions<CorrespondingVar<Symbol, Symbol>, Symbol, ()>,
+        mapped_actions: &GenericActions<CorrespondingVar<Symbol, Symbol>, Symbol, Span>,
         typeinfo: &TypeInfo,
     ) -> Result<ResolvedActions, TypeError> {
         let actions = mapped_actions
@@ -141,9 +141,9 @@ pub type ResolvedAtomTerm = GenericAtomTerm<ResolvedVar>;
 impl AtomTerm {
     pub fn to_expr(&self) -> Expr {
         match self {
-            AtomTerm::Var(v) => Expr::Var((), *v),
-            AtomTerm::Literal(l) => Expr::Lit((), l.clone()),
-            AtomTerm::Global(v) => Expr::Var((), *v),
+            AtomTerm::Var(v) => Expr::Var(DUMMY_SPAN.clone(), *v),
+            AtomTerm::Literal(l) => Expr::Lit(DUMMY_SPAN.clone(), l.clone()),
+            AtomTerm::Global(v) => Expr::Var(DUMMY_SPAN.clone(), *v),
         }
     }
 }
@@ -213,7 +213,7 @@ impl Atom<Symbol> {
     pub(crate) fn to_expr(&self) -> Expr {
         let n = self.args.len();
         Expr::Call(
-            (),
+            DUMMY_SPAN.clone(),
             self.head,
             self.args[0..n - 1]
                 .iter()
@@ -368,7 +368,7 @@ where
 }
 
 #[allow(clippy::type_complexity)]
-impl<Head, Leaf> GenericActions<Head, Leaf, ()>
+impl<Head, Leaf> GenericActions<Head, Leaf, Span>
 where
     Head: Clone + Display,
     Leaf: Clone + PartialEq + Eq + Display + Hash,
@@ -381,7 +381,7 @@ where
     ) -> Result<
         (
             GenericCoreActions<Head, Leaf>,
-            MappedActions<Head, Leaf, ()>,
+            MappedActions<Head, Leaf, Span>,
         ),
         TypeError,
     >
@@ -389,14 +389,14 @@ where
         Leaf: SymbolLike,
     {
         let mut norm_actions = vec![];
-        let mut mapped_actions: MappedActions<Head, Leaf, ()> = GenericActions(vec![]);
+        let mut mapped_actions: MappedActions<Head, Leaf, Span> = GenericActions(vec![]);
 
         // During the lowering, there are two important guaratees:
         //   Every used variable should be bound.
         //   Every introduced variable should be unbound before.
         for action in self.0.iter() {
             match action {
-                GenericAction::Let(_ann, var, expr) => {
+                GenericAction::Let(ann, var, expr) => {
                     if binding.contains(var) {
                         return Err(TypeError::AlreadyDefined(var.to_symbol()));
                     }
@@ -407,12 +407,14 @@ where
                         var.clone(),
                         mapped_expr.get_corresponding_var_or_lit(typeinfo),
                     ));
-                    mapped_actions
-                        .0
-                        .push(GenericAction::Let((), var.clone(), mapped_expr));
+                    mapped_actions.0.push(GenericAction::Let(
+                        ann.clone(),
+                        var.clone(),
+                        mapped_expr,
+                    ));
                     binding.insert(var.clone());
                 }
-                GenericAction::Set(_ann, head, args, expr) => {
+                GenericAction::Set(ann, head, args, expr) => {
                     let mut mapped_args = vec![];
                     for arg in args {
                         let (actions, mapped_arg) =
@@ -433,13 +435,13 @@ where
                     ));
                     let v = fresh_gen.fresh(head);
                     mapped_actions.0.push(GenericAction::Set(
-                        (),
+                        ann.clone(),
                         CorrespondingVar::new(head.clone(), v),
                         mapped_args,
                         mapped_expr,
                     ));
                 }
-                GenericAction::Change(_ann, change, head, args) => {
+                GenericAction::Change(ann, change, head, args) => {
                     let mut mapped_args = vec![];
                     for arg in args {
                         let (actions, mapped_arg) =
@@ -457,13 +459,13 @@ where
                     ));
                     let v = fresh_gen.fresh(head);
                     mapped_actions.0.push(GenericAction::Change(
-                        (),
+                        ann.clone(),
                         *change,
                         CorrespondingVar::new(head.clone(), v),
                         mapped_args,
                     ));
                 }
-                GenericAction::Union(_ann, e1, e2) => {
+                GenericAction::Union(ann, e1, e2) => {
                     let (actions1, mapped_e1) = e1.to_core_actions(typeinfo, binding, fresh_gen)?;
                     norm_actions.extend(actions1.0);
                     let (actions2, mapped_e2) = e2.to_core_actions(typeinfo, binding, fresh_gen)?;
@@ -474,9 +476,9 @@ where
                     ));
                     mapped_actions
                         .0
-                        .push(GenericAction::Union((), mapped_e1, mapped_e2));
+                        .push(GenericAction::Union(ann.clone(), mapped_e1, mapped_e2));
                 }
-                GenericAction::Extract(_ann, e, n) => {
+                GenericAction::Extract(ann, e, n) => {
                     let (actions, mapped_e) = e.to_core_actions(typeinfo, binding, fresh_gen)?;
                     norm_actions.extend(actions.0);
                     let (actions, mapped_n) = n.to_core_actions(typeinfo, binding, fresh_gen)?;
@@ -487,19 +489,28 @@ where
                     ));
                     mapped_actions
                         .0
-                        .push(GenericAction::Extract((), mapped_e, mapped_n));
+                        .push(GenericAction::Extract(ann.clone(), mapped_e, mapped_n));
                 }
-                GenericAction::Panic(_ann, string) => {
-                    norm_actions.push(GenericCoreAction::Panic(string.clone()));
+                GenericAction::Panic(ann, string) => {
+                    // the panic happens when a rule runs, so it says where the
+                    // rule is itself
+                    let message = if ann.is_dummy() {
+                        string.clone()
+                    } else {
+                        format!("{ann}: {string}")
+                    };
+                    norm_actions.push(GenericCoreAction::Panic(message));
                     mapped_actions
                         .0
-                        .push(GenericAction::Panic((), string.clone()));
+                        .push(GenericAction::Panic(ann.clone(), string.clone()));
                 }
-                GenericAction::Expr(_ann, expr) => {
+                GenericAction::Expr(ann, expr) => {
                     let (actions, mapped_expr) =
                         expr.to_core_actions(typeinfo, binding, fresh_gen)?;
                     norm_actions.extend(actions.0);
-                    mapped_actions.0.push(GenericAction::Expr((), mapped_expr));
+                    mapped_actions
+                        .0
+                        .push(GenericAction::Expr(ann.clone(), mapped_expr));
                 }
             }
         }
@@ -564,27 +575,27 @@ where
         typeinfo: &TypeInfo,
         binding: &mut IndexSet<Leaf>,
         fresh_gen: &mut FG,
-    ) -> Result<(GenericCoreActions<Head, Leaf>, MappedExpr<Head, Leaf, ()>), TypeError>
+    ) -> Result<(GenericCoreActions<Head, Leaf>, MappedExpr<Head, Leaf, Ann>), TypeError>
     where
         Leaf: Hash + Eq + SymbolLike,
     {
         match self {
-            GenericExpr::Lit(_ann, lit) => Ok((
+            GenericExpr::Lit(ann, lit) => Ok((
                 GenericCoreActions::default(),
-                GenericExpr::Lit((), lit.clone()),
+                GenericExpr::Lit(ann.clone(), lit.clone()),
             )),
-            GenericExpr::Var(_ann, v) => {
+            GenericExpr::Var(ann, v) => {
                 let sym = v.to_symbol();
                 if binding.contains(v) || typeinfo.is_global(sym) {
                     Ok((
                         GenericCoreActions::default(),
-                        GenericExpr::Var((), v.clone()),
+                        GenericExpr::Var(ann.clone(), v.clone()),
                     ))
                 } else {
                     Err(TypeError::Unbound(sym))
                 }
             }
-            GenericExpr::Call(_ann, f, args) => {
+            GenericExpr::Call(ann, f, args) => {
                 let mut norm_actions = vec![];
                 let mut norm_args = vec![];
                 let mut mapped_args = vec![];
@@ -602,7 +613,11 @@ where
                 norm_actions.push(GenericCoreAction::Let(var.clone(), f.clone(), norm_args));
                 Ok((
                     GenericCoreActions::new(norm_actions),
-                    GenericExpr::Call((), CorrespondingVar::new(f.clone(), var), mapped_args),
+                    GenericExpr::Call(
+                        ann.clone(),
+                        CorrespondingVar::new(f.clone(), var),
+                        mapped_args,
+                    ),
                 ))
             }
         }
@@ -658,8 +673,10 @@ where
             let mut to_subst = None;
             for atom in result_rule.body.atoms.iter() {
                 if atom.head.is_eq() && atom.args[0] != atom.args[1] {
+                    // Substitute away the right-hand variable first: in the usual
+                    // `(= x (f ...))` it is the fresh one, so `x` keeps its name.
                     match &atom.args[..] {
-                        [GenericAtomTerm::Var(x), y] | [y, GenericAtomTerm::Var(x)] => {
+                        [y, GenericAtomTerm::Var(x)] | [GenericAtomTerm::Var(x), y] => {
                             to_subst = Some((x, y));
                             break;
                         }
@@ -719,7 +736,7 @@ where
     }
 }
 
-impl<Head, Leaf> GenericRule<Head, Leaf, ()>
+impl<Head, Leaf> GenericRule<Head, Leaf, Span>
 where
     Head: Clone + Display,
     Leaf: Clone + PartialEq + Eq + Display + Hash + Debug,
@@ -0,0 +1,255 @@
+//! Dumping the database of an [`EGraph`] as an egglog program that
+//! recreates it, for bug reports and for diffing states.
+//!
+//! Each e-class is named by a global, defined by the first row found that
+//! builds it from e-classes that are already defined. The remaining rows
+//! are then added with `union` or `set`, which recreates the equivalences.
+use crate::ast::{Action, Change, Command};
+use crate::util::{HashMap, HashSet};
+use crate::*;
+
+impl EGraph {
+    /// A program declaring the rulesets of the egraph and the rules added
+    /// to them, in order. Rules are shown as they were desugared, so
+    /// rewrites appear as rules.
+    pub fn rules_program(&self) -> String {
+        let commands: Vec<_> = self
+            .declarations
+            .iter()
+            .filter(|declaration| {
+                matches!(
+                    declaration,
+                    ResolvedNCommand::AddRuleset(_)
+                        | ResolvedNCommand::UnstableCombinedRuleset(..)
+                        | ResolvedNCommand::NormRule { .. }
+                )
+            })
+            .map(|declaration| declaration.to_command().to_string())
+            .collect();
+        commands.join("\n")
+    }
+
+    /// A program declaring the sorts and functions of the egraph and adding
+    /// every row of every function, so that running it in a new egraph
+    /// recreates the current tables and equivalence classes.
+    ///
+    /// Rulesets and rules are not included, and neither are sorts,
+    /// functions, or primitives added from Rust. Each e-class is named by a
+    /// new global, and an e-class that no row can build without building
+    /// it first is made with `declare`.
+    /// ```
+    /// use egglog::EGraph;
+    /// let mut egraph = EGraph::default();
+    /// egraph
+    ///     .parse_and_run_program(
+    ///         "(datatype Math (Num i64) (Add Math Math))
+    ///          (union (Num 2) (Add (Num 1) (Num 1)))",
+    ///     )
+    ///     .unwrap();
+    /// let program = egraph.to_program();
+    ///
+    /// let mut replayed = EGraph::default();
+    /// replayed.parse_and_run_program(&program).unwrap();
+    /// replayed
+    ///     .parse_and_run_program("(check (= (Num 2) (Add (Num 1) (Num 1))))")
+    ///     .unwrap();
+    /// ```
+    pub fn to_program(&self) -> String {
+        let globals = &self.type_info().global_types;
+        let mut commands = vec![];
+        let mut functions = vec![];
+        for declaration in &self.declarations {
+            match declaration {
+                ResolvedNCommand::Sort(..) => {}
+                ResolvedNCommand::Function(decl) if !globals.contains_key(&decl.name) => {
+                    functions.push(&self.functions[&decl.name]);
+                }
+                _ => continue,
+            }
+            commands.push(declaration.to_command().to_string());
+        }
+
+        let (defining, undefined) = self.defining_rows(&functions);
+        for (class, sort) in undefined {
+            commands.push(
+                Command::Declare {
+                    name: self.class_name(class),
+                    sort,
+                }
+                .to_string(),
+            );
+        }
+        for &(name, off) in &defining {
+            let function = &self.functions[&name];
+            let (inputs, output) = function.nodes.get_index(off, true).unwrap();
+            let class = Id::from(self.find(output.value).bits as usize);
+            let call = Expr::Call(DUMMY_SPAN.clone(), name, self.args(function, inputs));
+            let action = Action::Let(DUMMY_SPAN.clone(), self.class_name(class), call);
+            commands.push(Command::Action(action).to_string());
+        }
+
+        let defining: HashSet<(Symbol, usize)> = defining.into_iter().collect();
+        for function in functions {
+            let name = function.decl.name;
+            for off in 0..function.nodes.num_offsets() {
+                let Some((inputs, output)) = function.nodes.get_index(off, true) else {
+                    continue;
+                };
+                let args = self.args(function, inputs);
+                if !defining.contains(&(name, off)) {
+                    let output = self.value_expr(&function.schema.output, output.value);
+                    let action = if is_constructor(function) {
+                        Action::Union(
+                            DUMMY_SPAN.clone(),
+                            output,
+                            Expr::Call(DUMMY_SPAN.clone(), name, args.clone()),
+                        )
+                    } else {
+                        Action::Set(DUMMY_SPAN.clone(), name, args.clone(), output)
+                    };
+                    commands.push(Command::Action(action).to_string());
+                }
+                if output.subsumed {
+                    let action = Action::Change(DUMMY_SPAN.clone(), Change::Subsume, name, args);
+                    commands.push(Command::Action(action).to_string());
+                }
+            }
+        }
+
+        let mut globals: Vec<_> = globals.keys().copied().collect();
+        globals.sort();
+        for global in globals {
+            let function = &self.functions[&global];
+            let Some(output) = function.nodes.get(&[]) else {
+                continue;
+            };
+            let expr = self.value_expr(&function.schema.output, output.value);
+            commands
+                .push(Command::Action(Action::Let(DUMMY_SPAN.clone(), global, expr)).to_string());
+        }
+
+        commands.into_iter().map(|command| command + "\n").collect()
+    }
+
+    /// The rows that define each e-class, as function names and offsets, in
+    /// an order where every row only uses e-classes defined before it, along
+    /// with the e-classes no row can define and their sorts.
+    fn defining_rows(&self, functions: &[&Function]) -> (Vec<(Symbol, usize)>, Vec<(Id, Symbol)>) {
+        let mut rows = vec![];
+        let mut missing = vec![];
+        let mut waiting: HashMap<Id, Vec<usize>> = HashMap::default();
+        // the sorts of all e-classes used by a row, in the order they are found
+        let mut sorts: IndexMap<Id, Symbol> = IndexMap::default();
+        for function in functions {
+            for off in 0..function.nodes.num_offsets() {
+                let Some((inputs, output)) = function.nodes.get_index(off, true) else {
+                    continue;
+                };
+                let mut classes = vec![];
+                for (sort, value) in function.schema.input.iter().zip(inputs) {
+                    self.eq_classes(sort, *value, &mut classes);
+                }
+                let mut output_classes = vec![];
+                self.eq_classes(&function.schema.output, output.value, &mut output_classes);
+                for &(class, sort) in classes.iter().chain(&output_classes) {
+                    sorts.entry(class).or_insert(sort);
+                }
+                if !is_constructor(function) {
+                    continue;
+                }
+                let mut classes: Vec<Id> = classes.into_iter().map(|(class, _)| class).collect();
+                classes.sort_unstable();
+                classes.dedup();
+                for &class in &classes {
+                    waiting.entry(class).or_default().push(rows.len());
+                }
+                missing.push(classes.len());
+                rows.push((function.decl.name, off, output.value));
+            }
+        }
+
+        let mut defined = HashSet::default();
+        let mut defining = vec![];
+        let mut ready: Vec<usize> = (0..rows.len()).filter(|&i| missing[i] == 0).collect();
+        ready.reverse();
+        while let Some(i) = ready.pop() {
+            let (name, off, output) = rows[i];
+            let class = Id::from(self.find(output).bits as usize);
+            if !defined.insert(class) {
+                continue;
+            }
+            defining.push((name, off));
+            for &parent in waiting.get(&class).into_iter().flatten() {
+                missing[parent] -= 1;
+                if missing[parent] == 0 {
+                    ready.push(parent);
+                }
+            }
+        }
+
+        let undefined = sorts
+            .into_iter()
+            .filter(|(class, _)| !defined.contains(class))
+            .collect();
+        (defining, undefined)
+    }
+
+    /// The e-classes of the eq-sort values in `value`, including those
+    /// inside containers, with the names of their sorts.
+    fn eq_classes(&self, sort: &ArcSort, value: Value, classes: &mut Vec<(Id, Symbol)>) {
+        if sort.is_eq_sort() {
+            classes.push((Id::from(self.find(value).bits as usize), sort.name()));
+        } else if sort.is_container_sort() {
+            for (inner_sort, inner) in sort.inner_values(&value) {
+                self.eq_classes(&inner_sort, inner, classes);
+            }
+        }
+    }
+
+    /// The name of the global for an e-class.
+    fn class_name(&self, class: Id) -> Symbol {
+        format!(
+            "{}class{}",
+            self.desugar.fresh_gen.reserved_string(),
+            usize::from(class)
+        )
+        .into()
+    }
+
+    fn args(&self, function: &Function, inputs: &[Value]) -> Vec<Expr> {
+        function
+            .schema
+            .input
+            .iter()
+            .zip(inputs)
+            .map(|(sort, value)| self.value_expr(sort, *value))
+            .collect()
+    }
+
+    /// An expression for a value, naming e-classes by their globals.
+    fn value_expr(&self, sort: &ArcSort, value: Value) -> Expr {
+        if sort.is_eq_sort() {
+            Expr::Var(
+                DUMMY_SPAN.clone(),
+                self.class_name(Id::from(self.find(value).bits as usize)),
+            )
+        } else if sort.is_container_sort() {
+            let inner = sort
+                .inner_values(&value)
+                .into_iter()
+                .map(|(inner_sort, inner)| self.value_expr(&inner_sort, inner))
+                .collect();
+            sort.reconstruct_expr(self, value, inner)
+        } else {
+            sort.make_expr(self, value).1
+        }
+    }
+}
+
+/// Whether calling the function makes a new e-class for inputs it has no
+/// row for, as the functions of a datatype do.
+fn is_constructor(function: &Function) -> bool {
+    function.schema.output.is_eq_sort()
+        && function.decl.merge.is_none()
+        && function.decl.default.is_none()
+}
@@ -0,0 +1,179 @@
+//! Explanations of why two terms are equal.
+//!
+//! An [`Explanation`] flattens a [`Proof`] into a chain of terms, where each
+//! term is obtained from the previous one by a single rewrite of one of its
+//! subterms. This is the form printed by the `explain` command.
+use crate::gj::is_internal_var;
+use crate::*;
+
+/// What justifies a single step of an [`Explanation`].
+#[derive(Debug, Clone, PartialEq, Eq)]
+pub enum Rewrite {
+    /// A rule unioned the rewritten subterm with its replacement, after its
+    /// query matched with the given substitution.
+    Rule {
+        /// The rule, printed on one line.
+        rule: String,
+        /// The terms the rule's variables were bound to, in the
+        /// [`Explanation`]'s term dag.
+        subst: Vec<(Symbol, Term)>,
+    },
+    /// A top-level action unioned the rewritten subterm with its replacement.
+    Action,
+    /// The rewritten subterm was looked up in its table.
+    Table,
+    /// The rewritten subterm was a primitive call that was evaluated.
+    Compute,
+}
+
+impl Display for Rewrite {
+    fn fmt(&self, f: &mut Formatter<'_>) -> std::fmt::Result {
+        match self {
+            Rewrite::Rule { rule, .. } => write!(f, "rule {rule}"),
+            Rewrite::Action => write!(f, "action"),
+            Rewrite::Table => write!(f, "table lookup"),
+            Rewrite::Compute => write!(f, "computation"),
+        }
+    }
+}
+
+/// A chain of rewrites showing that two terms are equal.
+#[derive(Debug, Clone)]
+pub struct Explanation {
+    pub termdag: TermDag,
+    /// The term the chain starts from.
+    pub start: Term,
+    /// Each rewrite in the chain, with the term it produced.
+    pub steps: Vec<(Rewrite, Term)>,
+}
+
+impl Explanation {
+    fn from_proof(egraph: &EGraph, proof: &Proof, mut termdag: TermDag) -> Self {
+        let start = proof.lhs().clone();
+        let steps = flatten(egraph, proof, &mut termdag);
+        Explanation {
+            termdag,
+            start,
+            steps,
+        }
+    }
+
+    /// The term the chain ends with.
+    pub fn end(&self) -> &Term {
+        self.steps.last().map_or(&self.start, |(_, term)| term)
+    }
+}
+
+impl Display for Explanation {
+    fn fmt(&self, f: &mut Formatter<'_>) -> std::fmt::Result {
+        write!(f, "{}", self.termdag.to_string(&self.start))?;
+        for (rewrite, term) in &self.steps {
+            write!(f, "\n{} ; {rewrite}", self.termdag.to_string(term))?;
+            if let Rewrite::Rule { subst, .. } = rewrite {
+                let bindings: Vec<_> = subst
+                    .iter()
+                    .filter(|(var, _)| !is_internal_var(*var))
+                    .map(|(var, term)| format!("{var} = {}", self.termdag.to_string(term)))
+                    .collect();
+                if !bindings.is_empty() {
+                    write!(f, " with {}", bindings.join(", "))?;
+                }
+            }
+        }
+        Ok(())
+    }
+}
+
+/// The rewrites taking the left-hand side of a proof to its right-hand side.
+fn flatten(egraph: &EGraph, proof: &Proof, termdag: &mut TermDag) -> Vec<(Rewrite, Term)> {
+    match proof {
+        Proof::Refl(_) => vec![],
+        Proof::Rule {
+            rule, subst, rhs, ..
+        } => {
+            let rule = match egraph.compiled_rule(*rule) {
+                Some(compiled) => compiled.rule.to_sexp("".into(), "".into()).to_string(),
+                None => rule.to_string(),
+            };
+            let subst = subst.clone();
+            vec![(Rewrite::Rule { rule, subst }, rhs.clone())]
+        }
+        Proof::Action { rhs, .. } => vec![(Rewrite::Action, rhs.clone())],
+        Proof::Table { rhs, .. } => vec![(Rewrite::Table, rhs.clone())],
+        Proof::Compute { rhs, .. } => vec![(Rewrite::Compute, rhs.clone())],
+        Proof::Transitivity(proofs) => proofs
+            .iter()
+            .flat_map(|p| flatten(egraph, p, termdag))
+            .collect(),
+        Proof::Flip(inner) => {
+            // walk the inner chain backwards: each rewrite now leads to the
+            // term it was applied to
+            let steps = flatten(egraph, inner, termdag);
+            let mut terms = vec![inner.lhs().clone()];
+            terms.extend(steps.iter().map(|(_, term)| term.clone()));
+            steps
+                .into_iter()
+                .zip(terms)
+                .rev()
+                .map(|((rewrite, _), term)| (rewrite, term))
+                .collect()
+        }
+        Proof::Congruence { lhs, children, .. } => {
+            let Term::App(head, args) = lhs else {
+                unreachable!("congruence proofs are between applications")
+            };
+            let mut args: Vec<Term> = args.iter().map(|arg| termdag.get(*arg)).collect();
+            let mut steps = vec![];
+            for (i, child) in children.iter().enumerate() {
+                for (rewrite, term) in flatten(egraph, child, termdag) {
+                    args[i] = term;
+                    steps.push((rewrite, termdag.app(*head, args.clone())));
+                }
+            }
+            steps
+        }
+    }
+}
+
+impl EGraph {
+    /// Explain why two expressions are equal.
+    ///
+    /// Requires proofs to be enabled with `(set-option enable_proofs 1)`
+    /// before the terms were added. The expressions must be ground.
+    /// ```
+    /// use egglog::{ast::Expr, EGraph};
+    /// let mut egraph = EGraph::default();
+    /// egraph
+    ///     .parse_and_run_program(
+    ///         "(set-option enable_proofs 1)
+    ///          (datatype Math (Num i64) (Add Math Math))
+    ///          (rewrite (Add a b) (Add b a))
+    ///          (Add (Num 1) (Num 2))
+    ///          (run 1)",
+    ///     )
+    ///     .unwrap();
+    /// let num = |n: i64| Expr::call("Num", [Expr::lit(n)]);
+    /// let lhs = Expr::call("Add", [num(1), num(2)]);
+    /// let rhs = Expr::call("Add", [num(2), num(1)]);
+    /// let explanation = egraph.explain_equal(&lhs, &rhs).unwrap();
+    /// assert_eq!(explanation.steps.len(), 1);
+    /// ```
+    pub fn explain_equal(&mut self, lhs: &Expr, rhs: &Expr) -> Result<Explanation, Error> {
+        self.rebuild()?;
+        let command = Command::Explain(lhs.clone(), rhs.clone());
+        match self.process_command(command)?.as_slice() {
+            [ResolvedNCommand::Explain(lhs, rhs)] => self.explain_resolved(lhs, rhs),
+            _ => unreachable!("explain desugars to a single command"),
+        }
+    }
+
+    pub(crate) fn explain_resolved(
+        &self,
+        lhs: &ResolvedExpr,
+        rhs: &ResolvedExpr,
+    ) -> Result<Explanation, Error> {
+        let mut termdag = TermDag::default();
+        let proof = self.prove_exprs_equal(lhs, rhs, &mut termdag)?;
+        Ok(Explanation::from_proof(self, &proof, termdag))
+    }
+}
@@ -0,0 +1,192 @@
+//! Writing the rows of a function to a file as relational data, for the
+//! `export-function` command.
+use std::fs::File;
+use std::io::{BufWriter, Write};
+use std::path::Path;
+
+use crate::ast::{ExportFormat, ExportOptions, Expr, Literal};
+use crate::typechecking::TypeError;
+use crate::*;
+
+/// A field of an exported row.
+enum Field {
+    /// A number, boolean, or e-class id, written as is in every format.
+    Plain(String),
+    /// Any other value, written as a string.
+    Text(String),
+}
+
+impl EGraph {
+    /// Write every row of a function to a file, one line per row, with a
+    /// field for each input and one for the output unless it is `Unit`.
+    /// Subsumed rows are included.
+    ///
+    /// Primitive values are written as literals, with rationals as `n/d`
+    /// and strings unquoted where the format allows, so that CSV and TSV
+    /// files can be read back with [`Command::Input`]. E-classes are
+    /// written as their canonical ids, or as their cheapest terms if
+    /// [`ExportOptions::terms`] is set.
+    /// ```
+    /// use egglog::{ast::ExportOptions, EGraph};
+    /// let mut egraph = EGraph::default();
+    /// egraph
+    ///     .parse_and_run_program(
+    ///         "(function edge (i64 i64) f64)
+    ///          (set (edge 1 2) 0.5)",
+    ///     )
+    ///     .unwrap();
+    /// let path = std::env::temp_dir()
+    ///     .join(format!("egglog-doc-edges-{}.csv", std::process::id()));
+    /// egraph
+    ///     .export_function("edge".into(), &path, &ExportOptions::default())
+    ///     .unwrap();
+    /// assert_eq!(std::fs::read_to_string(&path).unwrap(), "1,2,0.5\n");
+    /// std::fs::remove_file(&path).unwrap();
+    /// ```
+    pub fn export_function(
+        &self,
+        name: Symbol,
+        path: impl AsRef<Path>,
+        options: &ExportOptions,
+    ) -> Result<(), Error> {
+        let path = path.as_ref();
+        let function = self
+            .functions
+            .get(&name)
+            .ok_or(TypeError::UnboundFunction(name))?;
+        let mut sorts = function.schema.input.clone();
+        let has_output = function.schema.output.name() != UNIT_SYM.into();
+        if has_output {
+            sorts.push(function.schema.output.clone());
+        }
+
+        let io_error = |e| Error::IoError(path.to_owned(), e);
+        let mut writer = BufWriter::new(File::create(path).map_err(io_error)?);
+        let mut termdag = TermDag::default();
+        let extractor = options.terms.then(|| Extractor::new(self, &mut termdag));
+        let mut num_rows = 0;
+        if options.format == ExportFormat::Json {
+            write!(writer, "[").map_err(io_error)?;
+        }
+        for (inputs, output) in function.nodes.iter(true) {
+            let values = inputs.iter().chain(has_output.then_some(&output.value));
+            let mut fields = vec![];
+            for (sort, value) in sorts.iter().zip(values) {
+                fields.push(self.export_field(sort, *value, extractor.as_ref(), &mut termdag)?);
+            }
+            match options.format {
+                ExportFormat::Csv => writeln!(writer, "{}", delimited(&fields, ',')),
+                ExportFormat::Tsv => writeln!(writer, "{}", delimited(&fields, '\t')),
+                ExportFormat::Json => {
+                    let separator = if num_rows == 0 { "\n  " } else { ",\n  " };
+                    write!(writer, "{separator}[{}]", json_array(&fields))
+                }
+            }
+            .map_err(io_error)?;
+            num_rows += 1;
+        }
+        if options.format == ExportFormat::Json {
+            let end = if num_rows == 0 { "]" } else { "\n]" };
+            writeln!(writer, "{end}").map_err(io_error)?;
+        }
+        writer.flush().map_err(io_error)?;
+        log::info!("Exported {num_rows} rows of {name} to '{path:?}'.");
+        Ok(())
+    }
+
+    fn export_field(
+        &self,
+        sort: &ArcSort,
+        value: Value,
+        extractor: Option<&Extractor>,
+        termdag: &mut TermDag,
+    ) -> Result<Field, Error> {
+        if let Some(extractor) = extractor {
+            if sort.is_eq_sort() || sort.is_container_sort() {
+                let (_, term) = extractor
+                    .find_best(value, termdag, sort)
+                    .ok_or_else(|| Error::ExtractError(format!("no term for {value:?}")))?;
+                return Ok(Field::Text(termdag.to_string(&term)));
+            }
+        }
+        if sort.is_eq_sort() {
+            return Ok(Field::Plain(self.find(value).bits.to_string()));
+        }
+        Ok(match self.id_expr(sort, value) {
+            Expr::Lit(_, Literal::Int(i)) => Field::Plain(i.to_string()),
+            Expr::Lit(_, Literal::Bool(b)) => Field::Plain(b.to_string()),
+            Expr::Lit(_, Literal::F64(f)) if f.is_finite() => Field::Plain(f.to_string()),
+            Expr::Lit(_, Literal::String(s)) => Field::Text(s.to_string()),
+            Expr::Call(_, head, args) if head == "rational".into() => match args.as_slice() {
+                [Expr::Lit(_, Literal::Int(numer)), Expr::Lit(_, Literal::Int(denom))] => {
+                    Field::Text(format!("{numer}/{denom}"))
+                }
+                _ => Field::Text(Expr::Call(DUMMY_SPAN.clone(), head, args).to_string()),
+            },
+            expr => Field::Text(expr.to_string()),
+        })
+    }
+
+    /// An expression for a value, with e-classes as their ids.
+    fn id_expr(&self, sort: &ArcSort, value: Value) -> Expr {
+        if sort.is_eq_sort() {
+            Expr::Lit(
+                DUMMY_SPAN.clone(),
+                Literal::Int(self.find(value).bits as i64),
+            )
+        } else if sort.is_container_sort() {
+            let inner = sort
+                .inner_values(&value)
+                .into_iter()
+                .map(|(inner_sort, inner)| self.id_expr(&inner_sort, inner))
+                .collect();
+            sort.reconstruct_expr(self, value, inner)
+        } else {
+            sort.make_expr(self, value).1
+        }
+    }
+}
+
+/// A line of fields separated by the delimiter, quoting the strings that
+/// would not be read back as they are.
+fn delimited(fields: &[Field], delimiter: char) -> String {
+    let fields: Vec<String> = fields
+        .iter()
+        .map(|field| match field {
+            Field::Plain(s) => s.clone(),
+            Field::Text(s) if s.contains([delimiter, '"', '\n', '\r']) || s.trim() != s => {
+                format!("\"{}\"", s.replace('"', "\"\""))
+            }
+            Field::Text(s) => s.clone(),
+        })
+        .collect();
+    fields.join(&delimiter.to_string())
+}
+
+fn json_array(fields: &[Field]) -> String {
+    let fields: Vec<String> = fields
+        .iter()
+        .map(|field| match field {
+            Field::Plain(s) => s.clone(),
+            Field::Text(s) => json_string(s),
+        })
+        .collect();
+    fields.join(", ")
+}
+
+fn json_string(s: &str) -> String {
+    let mut json = String::from('"');
+    for c in s.chars() {
+        match c {
+            '"' => json.push_str("\\\""),
+            '\\' => json.push_str("\\\\"),
+            '\n' => json.push_str("\\n"),
+            '\r' => json.push_str("\\r"),
+            '\t' => json.push_str("\\t"),
+            c if c.is_control() => json.push_str(&format!("\\u{:04x}", c as u32)),
+            c => json.push(c),
+        }
+    }
+    json.push('"');
+    json
+}
@@ -0,0 +1,148 @@
+//! Extraction following a choice of row for each e-class, such as one made
+//! by an external extractor over the serialized e-graph.
+use crate::ast::Symbol;
+use crate::termdag::{Term, TermDag};
+use crate::util::{HashMap, HashSet};
+use crate::{EGraph, Error, Id, Value};
+
+use super::{find_id, Cost, CostFunction, Extractor, TreeCost};
+
+/// The row chosen for an e-class, as a function name and its inputs.
+pub(crate) type Choices = HashMap<Id, (Symbol, Vec<Value>)>;
+
+pub(crate) struct ChoiceExtractor<'a> {
+    egraph: &'a EGraph,
+    tree: Extractor<'a>,
+    choices: Choices,
+    /// Whether an e-class without a usable choice is an error. Otherwise
+    /// `choices` also holds the cheapest row of every other e-class, and
+    /// only e-classes whose rows lead back to themselves are extracted as
+    /// their cheapest term.
+    strict: bool,
+    /// The e-classes whose terms are being built.
+    visiting: HashSet<Id>,
+    built: HashMap<Id, (Cost, Term)>,
+}
+
+impl<'a> ChoiceExtractor<'a> {
+    pub(crate) fn new(
+        egraph: &'a EGraph,
+        termdag: &mut TermDag,
+        choices: &Choices,
+        strict: bool,
+    ) -> Self {
+        let tree = Extractor::new(egraph, termdag);
+        let mut rows = if strict {
+            Choices::default()
+        } else {
+            cheapest_rows(&tree)
+        };
+        rows.extend(current_choices(egraph, choices));
+        ChoiceExtractor {
+            egraph,
+            tree,
+            choices: rows,
+            strict,
+            visiting: HashSet::default(),
+            built: HashMap::default(),
+        }
+    }
+
+    /// The term for an eq-sort value made of the chosen rows, and its cost
+    /// under [`TreeCost`].
+    pub(crate) fn extract(
+        &mut self,
+        value: Value,
+        termdag: &mut TermDag,
+    ) -> Result<(Cost, Term), Error> {
+        let class = find_id(self.egraph, value);
+        if let Some(built) = self.built.get(&class) {
+            return Ok(built.clone());
+        }
+        let cycle = self.visiting.contains(&class);
+        let Some((sym, inputs)) = self.choices.get(&class).filter(|_| !cycle).cloned() else {
+            if self.strict {
+                return Err(Error::ExtractError(if cycle {
+                    format!("the nodes chosen for e-class {class} form a cycle")
+                } else {
+                    format!("no node was chosen for e-class {class}")
+                }));
+            }
+            return self.cheapest(value, termdag);
+        };
+
+        self.visiting.insert(class);
+        let function = &self.egraph.functions[&sym];
+        let mut costs = vec![];
+        let mut children = vec![];
+        for (sort, input) in function.schema.input.iter().zip(&inputs) {
+            let (cost, term) = if sort.is_eq_sort() {
+                self.extract(*input, termdag)?
+            } else {
+                self.cheapest(*input, termdag)?
+            };
+            costs.push(cost);
+            children.push(term);
+        }
+        self.visiting.remove(&class);
+
+        let cost = TreeCost.cost(self.egraph, sym, &inputs, &costs);
+        let built = (cost, termdag.app(sym, children));
+        self.built.insert(class, built.clone());
+        Ok(built)
+    }
+
+    fn cheapest(&self, value: Value, termdag: &mut TermDag) -> Result<(Cost, Term), Error> {
+        let sort = self.egraph.get_sort_from_value(&value).unwrap();
+        self.tree
+            .find_best(value, termdag, sort)
+            .ok_or_else(|| Error::ExtractError(format!("no term for {value:?}")))
+    }
+}
+
+/// The choices whose rows are still in the e-graph, by canonical e-class,
+/// with their inputs canonicalized.
+fn current_choices(egraph: &EGraph, choices: &Choices) -> Choices {
+    let mut current = Choices::default();
+    for (&class, (sym, inputs)) in choices {
+        let Some(function) = egraph.functions.get(sym) else {
+            continue;
+        };
+        let inputs: Vec<Value> = inputs.iter().map(|input| egraph.find(*input)).collect();
+        let class = egraph.unionfind.find(class);
+        let Some(output) = function.nodes.get(&inputs) else {
+            continue;
+        };
+        if find_id(egraph, output.value) == class {
+            current.insert(class, (*sym, inputs));
+        }
+    }
+    current
+}
+
+/// The cheapest row of each e-class, given the cheapest terms of its inputs.
+fn cheapest_rows(tree: &Extractor) -> Choices {
+    let mut best: HashMap<Id, (Cost, Symbol, &[Value])> = HashMap::default();
+    for &sym in &tree.ctors {
+        let function = &tree.egraph.functions[&sym];
+        if !function.schema.output.is_eq_sort() {
+            continue;
+        }
+        for (inputs, output) in function.nodes.iter(false) {
+            let Some(cost) = tree.node_cost(function, inputs) else {
+                continue;
+            };
+            let class = tree.find_id(output.value);
+            let improved = match best.get(&class) {
+                Some((old, _, _)) => cost < *old,
+                None => true,
+            };
+            if improved {
+                best.insert(class, (cost, sym, inputs));
+            }
+        }
+    }
+    best.into_iter()
+        .map(|(class, (_, sym, inputs))| (class, (sym, inputs.to_vec())))
+        .collect()
+}
@@ -0,0 +1,270 @@
+//! Extraction of terms whose cost counts each shared subterm once.
+//!
+//! The cost of a DAG is the sum of the costs of the e-nodes it chooses,
+//! one for each e-class it reaches, however many times that e-class is
+//! used. The greedy extractor gives each e-class the e-node whose own
+//! DAG is cheapest, which need not be optimal when e-classes share
+//! children. The exact extractor searches every choice of e-nodes
+//! reachable from the root, pruning with the greedy cost as an upper bound.
+use crate::ast::Symbol;
+use crate::termdag::{Term, TermDag};
+use crate::util::{HashMap, HashSet};
+use crate::{EGraph, Function, Id, Value};
+
+use super::{declared_cost, Cost, Extractor};
+
+/// An e-node of an eq sort, with the inputs that are not in eq sorts
+/// folded into its cost.
+struct DagNode<'a> {
+    sym: Symbol,
+    function: &'a Function,
+    inputs: &'a [Value],
+    cost: Cost,
+    /// The e-classes of the inputs in eq sorts.
+    children: Vec<Id>,
+}
+
+pub(crate) struct DagExtractor<'a> {
+    /// Extracts the inputs that are not in eq sorts.
+    tree: Extractor<'a>,
+    classes: HashMap<Id, Vec<DagNode<'a>>>,
+}
+
+impl<'a> DagExtractor<'a> {
+    pub(crate) fn new(egraph: &'a EGraph, termdag: &mut TermDag) -> Self {
+        let tree = Extractor::new(egraph, termdag);
+        let mut classes: HashMap<Id, Vec<DagNode>> = HashMap::default();
+        for &sym in &tree.ctors {
+            let function = &egraph.functions[&sym];
+            if !function.schema.output.is_eq_sort() {
+                continue;
+            }
+            'nodes: for (inputs, output) in function.nodes.iter(false) {
+                let mut cost = declared_cost(egraph, function, inputs);
+                let mut children = vec![];
+                for (sort, value) in function.schema.input.iter().zip(inputs) {
+                    if sort.is_eq_sort() {
+                        let child = tree.find_id(*value);
+                        // skip nodes that no term can be extracted for
+                        if !tree.state.costs.contains_key(&child) {
+                            continue 'nodes;
+                        }
+                        children.push(child);
+                    } else {
+                        let Some((input_cost, _)) = tree.find_best(*value, termdag, sort) else {
+                            continue 'nodes;
+                        };
+                        cost = cost.saturating_add(input_cost);
+                    }
+                }
+                classes
+                    .entry(tree.find_id(output.value))
+                    .or_default()
+                    .push(DagNode {
+                        sym,
+                        function,
+                        inputs,
+                        cost,
+                        children,
+                    });
+            }
+        }
+        DagExtractor { tree, classes }
+    }
+
+    /// Extract a term for an eq-sort value, returning its DAG cost.
+    pub(crate) fn extract(
+        &self,
+        value: Value,
+        termdag: &mut TermDag,
+        exact: bool,
+    ) -> Option<(Cost, Term)> {
+        let root = self.tree.find_id(value);
+        let mut choices = self.greedy();
+        choices.get(&root)?;
+        if exact {
+            let cost = self.dag_cost(root, &choices);
+            choices = BranchAndBound::new(self, cost, choices).search(root);
+        }
+        let cost = self.dag_cost(root, &choices);
+        let term = self.build(root, &choices, termdag, &mut HashMap::default());
+        Some((cost, term))
+    }
+
+    /// Choose, for each e-class, the e-node whose DAG is cheapest given the
+    /// choices made so far, until no choice improves.
+    fn greedy(&self) -> HashMap<Id, usize> {
+        let mut choices = HashMap::default();
+        let mut costs: HashMap<Id, Cost> = HashMap::default();
+        let mut did_something = true;
+        while did_something {
+            did_something = false;
+            for (&class, nodes) in &self.classes {
+                for (i, node) in nodes.iter().enumerate() {
+                    if node.children.iter().any(|c| !choices.contains_key(c)) {
+                        continue;
+                    }
+                    let Some(cost) = self.node_dag_cost(class, node, &choices) else {
+                        continue;
+                    };
+                    let improved = match costs.get(&class) {
+                        Some(old) => cost < *old,
+                        None => true,
+                    };
+                    if improved {
+                        did_something = true;
+                        choices.insert(class, i);
+                        costs.insert(class, cost);
+                    }
+                }
+            }
+        }
+        choices
+    }
+
+    /// The cost of the DAG made by choosing `node` for `class` and following
+    /// `choices` below it, or `None` if that DAG would reach `class` again.
+    fn node_dag_cost(
+        &self,
+        class: Id,
+        node: &DagNode,
+        choices: &HashMap<Id, usize>,
+    ) -> Option<Cost> {
+        let mut seen = HashSet::default();
+        let mut stack = node.children.clone();
+        let mut cost = node.cost;
+        while let Some(child) = stack.pop() {
+            if child == class {
+                return None;
+            }
+            if seen.insert(child) {
+                let child_node = &self.classes[&child][choices[&child]];
+                cost = cost.saturating_add(child_node.cost);
+                stack.extend(&child_node.children);
+            }
+        }
+        Some(cost)
+    }
+
+    fn dag_cost(&self, root: Id, choices: &HashMap<Id, usize>) -> Cost {
+        let node = &self.classes[&root][choices[&root]];
+        self.node_dag_cost(root, node, choices)
+            .expect("extracted DAGs are acyclic")
+    }
+
+    fn build(
+        &self,
+        class: Id,
+        choices: &HashMap<Id, usize>,
+        termdag: &mut TermDag,
+        built: &mut HashMap<Id, Term>,
+    ) -> Term {
+        if let Some(term) = built.get(&class) {
+            return term.clone();
+        }
+        let node = &self.classes[&class][choices[&class]];
+        let mut children = vec![];
+        for (sort, value) in node.function.schema.input.iter().zip(node.inputs) {
+            let child = if sort.is_eq_sort() {
+                self.build(self.tree.find_id(*value), choices, termdag, built)
+            } else {
+                self.tree.find_best(*value, termdag, sort).unwrap().1
+            };
+            children.push(child);
+        }
+        let term = termdag.app(node.sym, children);
+        built.insert(class, term.clone());
+        term
+    }
+}
+
+/// A depth-first search over the choices of nodes for the classes reachable
+/// from a root, pruned by a lower bound on the cost of the classes that are
+/// reachable but not yet chosen.
+struct BranchAndBound<'a, 'b> {
+    extractor: &'b DagExtractor<'a>,
+    /// The cheapest node of each class, a lower bound on its cost.
+    min_costs: HashMap<Id, Cost>,
+    choices: HashMap<Id, usize>,
+    best_cost: Cost,
+    best_choices: HashMap<Id, usize>,
+}
+
+impl<'a, 'b> BranchAndBound<'a, 'b> {
+    fn new(
+        extractor: &'b DagExtractor<'a>,
+        best_cost: Cost,
+        best_choices: HashMap<Id, usize>,
+    ) -> Self {
+        let min_costs = extractor
+            .classes
+            .iter()
+            .map(|(&class, nodes)| (class, nodes.iter().map(|n| n.cost).min().unwrap()))
+            .collect();
+        BranchAndBound {
+            extractor,
+            min_costs,
+            choices: HashMap::default(),
+            best_cost,
+            best_choices,
+        }
+    }
+
+    fn search(mut self, root: Id) -> HashMap<Id, usize> {
+        self.visit(vec![root], 0);
+        self.best_choices
+    }
+
+    fn visit(&mut self, pending: Vec<Id>, cost: Cost) {
+        let Some((&class, rest)) = pending.split_last() else {
+            if cost < self.best_cost {
+                self.best_cost = cost;
+                self.best_choices = self.choices.clone();
+            }
+            return;
+        };
+        for (i, node) in self.extractor.classes[&class].iter().enumerate() {
+            if node
+                .children
+                .iter()
+                .any(|&child| self.reaches(child, class))
+            {
+                continue;
+            }
+            let mut next = rest.to_vec();
+            for &child in &node.children {
+                if !self.choices.contains_key(&child) && !next.contains(&child) {
+                    next.push(child);
+                }
+            }
+            let cost = cost.saturating_add(node.cost);
+            let bound = next
+                .iter()
+                .fold(cost, |bound, c| bound.saturating_add(self.min_costs[c]));
+            if bound >= self.best_cost {
+                continue;
+            }
+            self.choices.insert(class, i);
+            self.visit(next, cost);
+            self.choices.remove(&class);
+        }
+    }
+
+    /// Whether `target` can be reached from `class` through the nodes chosen
+    /// so far.
+    fn reaches(&self, class: Id, target: Id) -> bool {
+        let mut seen = HashSet::default();
+        let mut stack = vec![class];
+        while let Some(class) = stack.pop() {
+            if class == target {
+                return true;
+            }
+            if let Some(&i) = self.choices.get(&class) {
+                if seen.insert(class) {
+                    stack.extend(&self.extractor.classes[&class][i].children);
+                }
+            }
+        }
+        false
+    }
+}
@@ -0,0 +1,191 @@
+//! Extraction of the `k` cheapest distinct terms of an e-class.
+//!
+//! Each e-class keeps a list of the cheapest terms found so far, at most `k`
+//! of them, ordered by cost. A row combines one term from the list of each of
+//! its children; since [`TreeCost`](super::TreeCost) adds the costs of the
+//! children, the `k` cheapest combinations are enumerated cheapest first
+//! from a heap. Whenever the list of an e-class changes, the rows using it
+//! are queued to be combined again.
+use std::cmp::Reverse;
+use std::collections::BinaryHeap;
+
+use crate::ast::Symbol;
+use crate::termdag::{Term, TermDag};
+use crate::util::{HashMap, HashSet};
+use crate::{EGraph, Id, Value};
+
+use super::{declared_cost, Cost, Extractor, Worklist};
+
+enum Input {
+    /// An input in an eq sort, which can be any term of its e-class.
+    Class(Id),
+    /// An input that is not in an eq sort, which has a single term.
+    Fixed(Term),
+}
+
+/// A row of a function with an eq-sort output, with the costs of the inputs
+/// that are not in eq sorts folded into its cost.
+struct Row {
+    sym: Symbol,
+    class: Id,
+    cost: Cost,
+    inputs: Vec<Input>,
+}
+
+impl Row {
+    fn children(&self) -> impl Iterator<Item = Id> + '_ {
+        self.inputs.iter().filter_map(|input| match input {
+            Input::Class(class) => Some(*class),
+            Input::Fixed(_) => None,
+        })
+    }
+}
+
+pub(crate) struct KBestExtractor {
+    k: usize,
+    rows: Vec<Row>,
+    /// The rows with an input in each e-class, by index.
+    parents: HashMap<Id, Vec<usize>>,
+    /// The cheapest terms found for each e-class, cheapest first.
+    best: HashMap<Id, Vec<(Cost, Term)>>,
+}
+
+impl KBestExtractor {
+    pub(crate) fn new(egraph: &EGraph, termdag: &mut TermDag, k: usize) -> Self {
+        let tree = Extractor::new(egraph, termdag);
+        let mut rows = vec![];
+        let mut parents: HashMap<Id, Vec<usize>> = HashMap::default();
+        for &sym in &tree.ctors {
+            let function = &egraph.functions[&sym];
+            if !function.schema.output.is_eq_sort() {
+                continue;
+            }
+            'rows: for (inputs, output) in function.nodes.iter(false) {
+                let mut cost = declared_cost(egraph, function, inputs);
+                let mut row_inputs = vec![];
+                for (sort, value) in function.schema.input.iter().zip(inputs) {
+                    if sort.is_eq_sort() {
+                        row_inputs.push(Input::Class(tree.find_id(*value)));
+                    } else {
+                        // skip rows that no term can be extracted for
+                        let Some((input_cost, term)) = tree.find_best(*value, termdag, sort) else {
+                            continue 'rows;
+                        };
+                        cost = cost.saturating_add(input_cost);
+                        row_inputs.push(Input::Fixed(term));
+                    }
+                }
+                let row = Row {
+                    sym,
+                    class: tree.find_id(output.value),
+                    cost,
+                    inputs: row_inputs,
+                };
+                for child in row.children() {
+                    parents.entry(child).or_default().push(rows.len());
+                }
+                rows.push(row);
+            }
+        }
+        KBestExtractor {
+            k,
+            rows,
+            parents,
+            best: HashMap::default(),
+        }
+    }
+
+    /// The `k` cheapest terms for an eq-sort value, cheapest first.
+    pub(crate) fn extract(
+        mut self,
+        egraph: &EGraph,
+        value: Value,
+        termdag: &mut TermDag,
+    ) -> Vec<(Cost, Term)> {
+        let mut worklist = Worklist::default();
+        worklist.extend(0..self.rows.len());
+        while let Some(i) = worklist.pop() {
+            let row = &self.rows[i];
+            let mut terms = vec![];
+            for (cost, picks) in self.combinations(row) {
+                let mut picks = picks.into_iter();
+                let children = row
+                    .inputs
+                    .iter()
+                    .map(|input| match input {
+                        Input::Class(class) => self.best[class][picks.next().unwrap()].1.clone(),
+                        Input::Fixed(term) => term.clone(),
+                    })
+                    .collect();
+                terms.push((cost, termdag.app(row.sym, children)));
+            }
+            let class = row.class;
+            let mut changed = false;
+            for (cost, term) in terms {
+                changed |= self.insert(class, cost, term);
+            }
+            if changed {
+                if let Some(parents) = self.parents.get(&class) {
+                    worklist.extend(parents.iter().copied());
+                }
+            }
+        }
+        let class = Id::from(egraph.find(value).bits as usize);
+        self.best.remove(&class).unwrap_or_default()
+    }
+
+    /// The `k` cheapest ways of choosing a term for each child of `row`, as
+    /// indices into the lists of their e-classes, cheapest first.
+    fn combinations(&self, row: &Row) -> Vec<(Cost, Vec<usize>)> {
+        let mut lists = vec![];
+        for child in row.children() {
+            match self.best.get(&child) {
+                Some(terms) => lists.push(terms.as_slice()),
+                None => return vec![],
+            }
+        }
+        let cost_of = |picks: &[usize]| {
+            picks
+                .iter()
+                .zip(&lists)
+                .fold(row.cost, |cost, (&i, terms)| {
+                    cost.saturating_add(terms[i].0)
+                })
+        };
+
+        let first = vec![0; lists.len()];
+        let mut heap = BinaryHeap::from([Reverse((cost_of(&first), first.clone()))]);
+        let mut seen = HashSet::from_iter([first]);
+        let mut combinations = vec![];
+        while combinations.len() < self.k {
+            let Some(Reverse((cost, picks))) = heap.pop() else {
+                break;
+            };
+            for (i, terms) in lists.iter().enumerate() {
+                if picks[i] + 1 < terms.len() {
+                    let mut next = picks.clone();
+                    next[i] += 1;
+                    if seen.insert(next.clone()) {
+                        heap.push(Reverse((cost_of(&next), next)));
+                    }
+                }
+            }
+            combinations.push((cost, picks));
+        }
+        combinations
+    }
+
+    /// Add a term to the list of an e-class if it is among the `k` cheapest,
+    /// returning whether the list changed.
+    fn insert(&mut self, class: Id, cost: Cost, term: Term) -> bool {
+        let terms = self.best.entry(class).or_default();
+        let full = terms.len() == self.k;
+        if terms.iter().any(|(_, old)| *old == term) || (full && cost >= terms[self.k - 1].0) {
+            return false;
+        }
+        let at = terms.partition_point(|(old, _)| *old <= cost);
+        terms.insert(at, (cost, term));
+        terms.truncate(self.k);
+        true
+    }
+}
@@ -0,0 +1,1159 @@
+use std::cell::RefCell;
+use std::collections::VecDeque;
+use std::fmt::Debug;
+use std::hash::Hash;
+use std::mem;
+use std::rc::Rc;
+
+use crate::ast::{Expr, ExtractMode, FunctionCost, Symbol};
+use crate::function::table::RowChange;
+use crate::sort::Sort;
+use crate::termdag::{Term, TermDag, TermId};
+use crate::util::{HashMap, HashSet};
+use crate::{ArcSort, EGraph, Error, Function, Id, Value};
+
+mod choices;
+mod dag;
+mod k_best;
+
+pub(crate) use choices::{ChoiceExtractor, Choices};
+
+/// The cost of a term under the default [`TreeCost`] model.
+pub type Cost = usize;
+
+/// A cost model for extraction, choosing which term represents each e-class.
+///
+/// The cost of a term is built bottom-up: [`CostFunction::cost`] combines the
+/// costs of a function's inputs into the cost of applying it, and
+/// [`CostFunction::value_cost`] gives the cost of a primitive or container
+/// value. Extraction picks the term with the least cost for each e-class,
+/// comparing costs with `<`.
+/// ```
+/// use egglog::{ast::Symbol, sort::Sort, CostFunction, EGraph, TermDag, Value};
+///
+/// /// Costs terms by (latency, area), minimizing latency first.
+/// struct LatencyArea;
+///
+/// impl CostFunction for LatencyArea {
+///     type Cost = (f64, f64);
+///
+///     fn cost(&self, _: &EGraph, sym: Symbol, _: &[Value], input_costs: &[(f64, f64)]) -> (f64, f64) {
+///         let (latency, area) = match sym.as_str() {
+///             "Mul" => (3.0, 4.0),
+///             "Shl" => (1.0, 1.0),
+///             _ => (0.0, 1.0),
+///         };
+///         let slowest = input_costs.iter().map(|c| c.0).fold(0.0, f64::max);
+///         let total_area: f64 = input_costs.iter().map(|c| c.1).sum();
+///         (latency + slowest, area + total_area)
+///     }
+///
+///     fn value_cost(&self, _: &EGraph, _: &dyn Sort, _: Value, _: &[(f64, f64)]) -> (f64, f64) {
+///         (0.0, 0.0)
+///     }
+/// }
+///
+/// let mut egraph = EGraph::default();
+/// egraph
+///     .parse_and_run_program(
+///         "(datatype Math (Num i64) (Var String) (Mul Math Math) (Shl Math Math))
+///          (let expr (Mul (Var \"x\") (Num 2)))
+///          (union expr (Shl (Var \"x\") (Num 1)))",
+///     )
+///     .unwrap();
+/// let (sort, value) = egraph
+///     .eval_expr(&egglog::ast::Expr::var("expr"))
+///     .unwrap();
+/// let mut termdag = TermDag::default();
+/// let (cost, term) = egraph
+///     .extract_with_cost_function(value, &mut termdag, &sort, LatencyArea)
+///     .unwrap();
+/// assert_eq!(termdag.to_string(&term), "(Shl (Var \"x\") (Num 1))");
+/// assert_eq!(cost, (1.0, 3.0));
+/// ```
+pub trait CostFunction {
+    type Cost: Clone + Debug + PartialOrd;
+
+    /// The cost of applying the function `sym` to `inputs`, where
+    /// `input_costs` are the costs of the terms chosen for each input.
+    fn cost(
+        &self,
+        egraph: &EGraph,
+        sym: Symbol,
+        inputs: &[Value],
+        input_costs: &[Self::Cost],
+    ) -> Self::Cost;
+
+    /// The cost of a value of a sort that is not an eq sort.
+    /// For a container, `element_costs` are the costs of the terms chosen
+    /// for its [`Sort::inner_values`]; otherwise it is empty.
+    fn value_cost(
+        &self,
+        egraph: &EGraph,
+        sort: &dyn Sort,
+        value: Value,
+        element_costs: &[Self::Cost],
+    ) -> Self::Cost;
+
+    /// Whether extracted terms may apply the function `sym` to `inputs`.
+    /// Rows that are not allowed are never extracted, whatever their cost.
+    fn allows(&self, _egraph: &EGraph, _sym: Symbol, _inputs: &[Value]) -> bool {
+        true
+    }
+}
+
+/// The cost of a row of `function` given by its `:cost`, not counting its inputs.
+pub(crate) fn declared_cost(egraph: &EGraph, function: &Function, inputs: &[Value]) -> Cost {
+    match function.decl.cost {
+        None => 1,
+        Some(FunctionCost::Fixed(cost)) => cost,
+        Some(FunctionCost::Table(table)) => egraph
+            .functions
+            .get(&table)
+            .and_then(|table| table.nodes.get(inputs))
+            // negative costs are treated as free
+            .map_or(1, |output| (output.value.bits as i64).max(0) as Cost),
+    }
+}
+
+/// The default cost model: the `:cost` of a function (1 if it has none) plus
+/// the costs of its inputs. Each distinct occurrence of a subterm is counted.
+#[derive(Clone, Copy, Debug, Default)]
+pub struct TreeCost;
+
+impl CostFunction for TreeCost {
+    type Cost = Cost;
+
+    fn cost(&self, egraph: &EGraph, sym: Symbol, inputs: &[Value], input_costs: &[Cost]) -> Cost {
+        let cost = declared_cost(egraph, &egraph.functions[&sym], inputs);
+        input_costs
+            .iter()
+            .fold(cost, |cost, input| cost.saturating_add(*input))
+    }
+
+    fn value_cost(
+        &self,
+        egraph: &EGraph,
+        sort: &dyn Sort,
+        value: Value,
+        element_costs: &[Cost],
+    ) -> Cost {
+        if sort.is_container_sort() {
+            element_costs
+                .iter()
+                .fold(0, |cost, element| cost.saturating_add(*element))
+        } else {
+            sort.make_expr(egraph, value).0
+        }
+    }
+}
+
+/// [`TreeCost`], using only the rows that `allow` accepts.
+struct Filtered<F> {
+    allow: F,
+}
+
+impl<F: Fn(Symbol, &[Value]) -> bool> CostFunction for Filtered<F> {
+    type Cost = Cost;
+
+    fn cost(&self, egraph: &EGraph, sym: Symbol, inputs: &[Value], input_costs: &[Cost]) -> Cost {
+        TreeCost.cost(egraph, sym, inputs, input_costs)
+    }
+
+    fn value_cost(
+        &self,
+        egraph: &EGraph,
+        sort: &dyn Sort,
+        value: Value,
+        element_costs: &[Cost],
+    ) -> Cost {
+        TreeCost.value_cost(egraph, sort, value, element_costs)
+    }
+
+    fn allows(&self, _egraph: &EGraph, sym: Symbol, inputs: &[Value]) -> bool {
+        (self.allow)(sym, inputs)
+    }
+}
+
+#[derive(Debug)]
+pub(crate) struct Node<'a> {
+    sym: Symbol,
+    inputs: &'a [Value],
+}
+
+/// The cheapest term found so far for each e-class under a cost function.
+///
+/// The state is brought up to date by [`ExtractionState::update`], which only
+/// looks at the rows added, moved, or removed and the e-classes merged since
+/// the last update. Costs only go down as rows are added or e-classes are
+/// merged, so a worklist re-evaluates just the rows using an e-class whose
+/// cost improved. Removing the row that gave an e-class its cost can raise
+/// it, so the costs that depend on that row are dropped and found again.
+/// Costs are only computed from scratch when a table is cleared or rolled
+/// back, or a cost table changes.
+#[derive(Clone)]
+pub(crate) struct ExtractionState<C: CostFunction> {
+    cost_function: C,
+    /// Holds the terms in `costs`.
+    termdag: TermDag,
+    /// The size of `termdag` after it was last compacted.
+    compacted_size: usize,
+    costs: HashMap<Id, (C::Cost, Term)>,
+    /// The row that gave each e-class its cost, as a function name and offset.
+    best_rows: HashMap<Id, (Symbol, usize)>,
+    /// The inverse of `best_rows`, by function name and then offset.
+    best_of: HashMap<Symbol, HashMap<usize, Id>>,
+    /// The rows with an input in each e-class.
+    parents: HashMap<Id, Vec<(Symbol, usize)>>,
+    /// The rows with their output in each e-class.
+    rows: HashMap<Id, Vec<(Symbol, usize)>>,
+    /// How much of each function's table was seen by the last update.
+    seen: HashMap<Symbol, Seen>,
+    n_unions: usize,
+    /// The number of times the costs were computed from scratch.
+    n_restarts: usize,
+}
+
+/// The state of a table when an [`ExtractionState`] was last updated.
+#[derive(Clone, Copy, Debug, Default, PartialEq, Eq)]
+struct Seen {
+    epoch: usize,
+    num_offsets: usize,
+    num_changes: usize,
+}
+
+impl Seen {
+    fn new(function: &Function) -> Self {
+        Seen {
+            epoch: function.nodes.epoch(),
+            num_offsets: function.nodes.num_offsets(),
+            num_changes: function.nodes.changes().len(),
+        }
+    }
+}
+
+impl<C: CostFunction + Default> Default for ExtractionState<C> {
+    fn default() -> Self {
+        Self::new(C::default())
+    }
+}
+
+impl<C: CostFunction> ExtractionState<C> {
+    fn new(cost_function: C) -> Self {
+        ExtractionState {
+            cost_function,
+            termdag: TermDag::default(),
+            compacted_size: 0,
+            costs: HashMap::default(),
+            best_rows: HashMap::default(),
+            best_of: HashMap::default(),
+            parents: HashMap::default(),
+            rows: HashMap::default(),
+            seen: HashMap::default(),
+            n_unions: 0,
+            n_restarts: 0,
+        }
+    }
+
+    /// Whether the e-graph has not changed since the last update.
+    fn is_current(&self, egraph: &EGraph) -> bool {
+        self.n_unions == egraph.unionfind.n_unions()
+            && self.seen.len() == egraph.functions.len()
+            && egraph
+                .functions
+                .iter()
+                .all(|(name, function)| self.seen.get(name) == Some(&Seen::new(function)))
+    }
+
+    /// Whether the e-graph changed in a way that the costs cannot be updated
+    /// for, so that they must be computed from scratch.
+    fn must_restart(&self, egraph: &EGraph) -> bool {
+        let cost_tables: HashSet<Symbol> = egraph
+            .functions
+            .values()
+            .filter_map(|function| match function.decl.cost {
+                Some(FunctionCost::Table(table)) => Some(table),
+                _ => None,
+            })
+            .collect();
+        self.seen
+            .keys()
+            .any(|name| !egraph.functions.contains_key(name))
+            || egraph.functions.iter().any(|(name, function)| {
+                self.seen.get(name).is_some_and(|seen| {
+                    seen.epoch != function.nodes.epoch()
+                        || (cost_tables.contains(name) && *seen != Seen::new(function))
+                })
+            })
+    }
+
+    /// Bring the costs up to date with the e-graph.
+    pub(crate) fn update(&mut self, egraph: &EGraph) {
+        if self.is_current(egraph) {
+            return;
+        }
+        if self.must_restart(egraph) {
+            self.termdag = TermDag::default();
+            self.compacted_size = 0;
+            self.costs.clear();
+            self.best_rows.clear();
+            self.best_of.clear();
+            self.parents.clear();
+            self.rows.clear();
+            self.seen.clear();
+            self.n_restarts += 1;
+        }
+
+        let mut worklist = Worklist::default();
+        let (dropped, moved) = self.apply_changes(egraph);
+        if self.n_unions != egraph.unionfind.n_unions() {
+            self.merge_classes(egraph, &mut worklist);
+            self.n_unions = egraph.unionfind.n_unions();
+        }
+        for (&sym, function) in &egraph.functions {
+            let first = self
+                .seen
+                .insert(sym, Seen::new(function))
+                .unwrap_or_default()
+                .num_offsets;
+            if !is_extractable(function) {
+                continue;
+            }
+            for off in first..function.nodes.num_offsets() {
+                let Some((inputs, output)) = function.nodes.get_index(off, false) else {
+                    continue;
+                };
+                let mut children = vec![];
+                for (sort, value) in function.schema.input.iter().zip(inputs) {
+                    eq_classes(egraph, sort, *value, &mut children);
+                }
+                for child in children {
+                    self.parents.entry(child).or_default().push((sym, off));
+                }
+                let class = find_id(egraph, output.value);
+                self.rows.entry(class).or_default().push((sym, off));
+                worklist.push((sym, off));
+            }
+        }
+
+        self.drop_dependent_costs(egraph, dropped, moved, &mut worklist);
+
+        while let Some((sym, off)) = worklist.pop() {
+            let function = &egraph.functions[&sym];
+            let Some((inputs, output)) = function.nodes.get_index(off, false) else {
+                continue;
+            };
+            if !self.cost_function.allows(egraph, sym, inputs) {
+                continue;
+            }
+            let Some((cost, children)) = self.node_total_cost(egraph, function, inputs) else {
+                continue;
+            };
+            let class = find_id(egraph, output.value);
+            let improved = match self.costs.get(&class) {
+                Some((old, _)) => cost < *old,
+                None => true,
+            };
+            if improved {
+                let term = self.termdag.app(sym, children);
+                self.costs.insert(class, (cost, term));
+                self.set_best_row(class, (sym, off));
+                if let Some(parents) = self.parents.get(&class) {
+                    worklist.extend(parents.iter().copied());
+                }
+            }
+        }
+
+        // terms that are no longer the cheapest stay in the term dag until
+        // it is compacted
+        if self.termdag.size() > 2 * self.compacted_size + 1024 {
+            self.termdag
+                .retain(self.costs.values_mut().map(|(_, term)| term));
+            self.compacted_size = self.termdag.size();
+        }
+    }
+
+    /// Follow the rows moved, removed, and compacted away since the last
+    /// update, before e-classes are merged. Returns the e-classes whose best
+    /// row was removed, whose costs are dropped, and those whose best row
+    /// moved.
+    fn apply_changes(&mut self, egraph: &EGraph) -> (Vec<Id>, Vec<Id>) {
+        let mut dropped = vec![];
+        let mut moved = vec![];
+        let mut compactions: HashMap<Symbol, Vec<&[usize]>> = HashMap::default();
+        for (&sym, function) in &egraph.functions {
+            let Some(seen) = self.seen.get_mut(&sym) else {
+                continue;
+            };
+            if !is_extractable(function) {
+                continue;
+            }
+            let best_of = self.best_of.entry(sym).or_default();
+            for change in &function.nodes.changes()[seen.num_changes..] {
+                match change {
+                    RowChange::Moved(old, new) => {
+                        if let Some(class) = best_of.remove(old) {
+                            best_of.insert(*new, class);
+                            self.best_rows.insert(class, (sym, *new));
+                            moved.push(class);
+                        }
+                    }
+                    RowChange::Removed(off) => {
+                        if let Some(class) = best_of.remove(off) {
+                            self.best_rows.remove(&class);
+                            self.costs.remove(&class);
+                            dropped.push(class);
+                        }
+                    }
+                    RowChange::Compacted(offs) => {
+                        // the rows not seen yet stay at the end
+                        seen.num_offsets -= offs.partition_point(|&off| off < seen.num_offsets);
+                        for (off, class) in mem::take(best_of) {
+                            if let Some(off) = RowChange::compacted_offset(offs, off) {
+                                best_of.insert(off, class);
+                                self.best_rows.insert(class, (sym, off));
+                            } else {
+                                self.best_rows.remove(&class);
+                                self.costs.remove(&class);
+                                dropped.push(class);
+                            }
+                        }
+                        compactions.entry(sym).or_default().push(offs);
+                    }
+                }
+            }
+        }
+
+        if !compactions.is_empty() {
+            for rows in self.parents.values_mut().chain(self.rows.values_mut()) {
+                rows.retain_mut(|(sym, off)| {
+                    let Some(compactions) = compactions.get(sym) else {
+                        return true;
+                    };
+                    match compactions
+                        .iter()
+                        .try_fold(*off, |off, offs| RowChange::compacted_offset(offs, off))
+                    {
+                        Some(new) => {
+                            *off = new;
+                            true
+                        }
+                        None => false,
+                    }
+                });
+            }
+        }
+        (dropped, moved)
+    }
+
+    /// Drop the costs that depended on the e-classes whose costs were dropped
+    /// or whose best row moved to another e-class, queueing the rows that
+    /// could give them a cost again.
+    fn drop_dependent_costs(
+        &mut self,
+        egraph: &EGraph,
+        dropped: Vec<Id>,
+        moved: Vec<Id>,
+        worklist: &mut Worklist<(Symbol, usize)>,
+    ) {
+        let mut dropped: Vec<Id> = dropped
+            .into_iter()
+            .map(|class| egraph.unionfind.find(class))
+            .collect();
+        for class in moved {
+            let class = egraph.unionfind.find(class);
+            let Some(&(sym, off)) = self.best_rows.get(&class) else {
+                continue;
+            };
+            let function = &egraph.functions[&sym];
+            if (function.nodes.get_index(off, false).is_none()
+                || row_class(egraph, sym, off) != class)
+                && self.drop_cost(class)
+            {
+                dropped.push(class);
+            }
+        }
+
+        while let Some(class) = dropped.pop() {
+            if let Some(rows) = self.rows.get(&class) {
+                worklist.extend(rows.iter().copied());
+            }
+            let parents = self.parents.get(&class).cloned().unwrap_or_default();
+            for (sym, off) in parents {
+                let parent = row_class(egraph, sym, off);
+                if self.best_rows.get(&parent) == Some(&(sym, off)) && self.drop_cost(parent) {
+                    dropped.push(parent);
+                }
+            }
+        }
+    }
+
+    fn set_best_row(&mut self, class: Id, row: (Symbol, usize)) {
+        if let Some((sym, off)) = self.best_rows.insert(class, row) {
+            self.best_of.entry(sym).or_default().remove(&off);
+        }
+        self.best_of.entry(row.0).or_default().insert(row.1, class);
+    }
+
+    fn remove_best_row(&mut self, class: Id) -> Option<(Symbol, usize)> {
+        let (sym, off) = self.best_rows.remove(&class)?;
+        self.best_of.entry(sym).or_default().remove(&off);
+        Some((sym, off))
+    }
+
+    /// Drop the cost of an e-class, returning whether it had one.
+    fn drop_cost(&mut self, class: Id) -> bool {
+        self.remove_best_row(class);
+        self.costs.remove(&class).is_some()
+    }
+
+    /// Move the costs, parents, and rows of e-classes that are no longer
+    /// canonical to their canonical e-class, queueing the parents of those
+    /// whose cost may have improved.
+    fn merge_classes(&mut self, egraph: &EGraph, worklist: &mut Worklist<(Symbol, usize)>) {
+        let mut merged: Vec<Id> = self
+            .costs
+            .keys()
+            .chain(self.parents.keys())
+            .chain(self.rows.keys())
+            .copied()
+            .filter(|&id| egraph.unionfind.find(id) != id)
+            .collect();
+        merged.sort_unstable();
+        merged.dedup();
+
+        let mut changed = HashSet::default();
+        for id in merged {
+            let root = egraph.unionfind.find(id);
+            if let Some(parents) = self.parents.remove(&id) {
+                self.parents.entry(root).or_default().extend(parents);
+            }
+            if let Some(rows) = self.rows.remove(&id) {
+                self.rows.entry(root).or_default().extend(rows);
+            }
+            let best_row = self.remove_best_row(id);
+            if let Some((cost, term)) = self.costs.remove(&id) {
+                let improved = match self.costs.get(&root) {
+                    Some((old, _)) => cost < *old,
+                    None => true,
+                };
+                if improved {
+                    self.costs.insert(root, (cost, term));
+                    if let Some(best_row) = best_row {
+                        self.set_best_row(root, best_row);
+                    }
+                }
+            }
+            changed.insert(root);
+        }
+        for root in changed {
+            if let Some(parents) = self.parents.get(&root) {
+                worklist.extend(parents.iter().copied());
+            }
+        }
+    }
+
+    /// The cost of a row given the costs of its inputs, along with the terms
+    /// for its inputs, or `None` if some input has no term yet.
+    fn node_total_cost(
+        &mut self,
+        egraph: &EGraph,
+        function: &Function,
+        inputs: &[Value],
+    ) -> Option<(C::Cost, Vec<Term>)> {
+        let mut costs = vec![];
+        let mut terms = vec![];
+        for (sort, value) in function.schema.input.iter().zip(inputs) {
+            let (cost, term) = if sort.is_eq_sort() {
+                self.costs.get(&find_id(egraph, *value))?.clone()
+            } else {
+                let (cost, expr) = self.value_expr(egraph, &**sort, *value)?;
+                (cost, self.termdag.expr_to_term(&expr))
+            };
+            costs.push(cost);
+            terms.push(term);
+        }
+        let cost = self
+            .cost_function
+            .cost(egraph, function.decl.name, inputs, &costs);
+        Some((cost, terms))
+    }
+
+    /// The cost and expression of a value that is not in an eq sort.
+    fn value_expr(
+        &self,
+        egraph: &EGraph,
+        sort: &dyn Sort,
+        value: Value,
+    ) -> Option<(C::Cost, Expr)> {
+        if !sort.is_container_sort() {
+            let cost = self.cost_function.value_cost(egraph, sort, value, &[]);
+            return Some((cost, sort.make_expr(egraph, value).1));
+        }
+        let mut costs = vec![];
+        let mut exprs = vec![];
+        for (inner_sort, inner) in sort.inner_values(&value) {
+            let (cost, expr) = if inner_sort.is_eq_sort() {
+                let (cost, term) = self.costs.get(&find_id(egraph, inner))?;
+                (cost.clone(), self.termdag.term_to_expr(term))
+            } else {
+                self.value_expr(egraph, &*inner_sort, inner)?
+            };
+            costs.push(cost);
+            exprs.push(expr);
+        }
+        let cost = self.cost_function.value_cost(egraph, sort, value, &costs);
+        Some((cost, sort.reconstruct_expr(egraph, value, exprs)))
+    }
+}
+
+/// The rows waiting to be re-evaluated, in the order they were queued,
+/// each at most once.
+struct Worklist<T> {
+    queue: VecDeque<T>,
+    queued: HashSet<T>,
+}
+
+impl<T> Default for Worklist<T> {
+    fn default() -> Self {
+        Worklist {
+            queue: VecDeque::default(),
+            queued: HashSet::default(),
+        }
+    }
+}
+
+impl<T: Copy + Eq + Hash> Worklist<T> {
+    fn push(&mut self, row: T) {
+        if self.queued.insert(row) {
+            self.queue.push_back(row);
+        }
+    }
+
+    fn pop(&mut self) -> Option<T> {
+        let row = self.queue.pop_front()?;
+        self.queued.remove(&row);
+        Some(row)
+    }
+}
+
+impl<T: Copy + Eq + Hash> Extend<T> for Worklist<T> {
+    fn extend<I: IntoIterator<Item = T>>(&mut self, rows: I) {
+        rows.into_iter().for_each(|row| self.push(row));
+    }
+}
+
+/// The e-classes of the eq-sort values in `value`, including those inside
+/// containers.
+fn eq_classes(egraph: &EGraph, sort: &ArcSort, value: Value, classes: &mut Vec<Id>) {
+    if sort.is_eq_sort() {
+        classes.push(find_id(egraph, value));
+    } else if sort.is_container_sort() {
+        for (inner_sort, inner) in sort.inner_values(&value) {
+            eq_classes(egraph, &inner_sort, inner, classes);
+        }
+    }
+}
+
+/// Whether the rows of a function can be extracted.
+fn is_extractable(function: &Function) -> bool {
+    !function.decl.unextractable && function.schema.output.is_eq_sort()
+}
+
+/// The e-class of the output of a row, which may be stale.
+fn row_class(egraph: &EGraph, sym: Symbol, off: usize) -> Id {
+    find_id(egraph, egraph.functions[&sym].nodes.vals[off].1.value)
+}
+
+fn no_cost(value: Value) -> Error {
+    Error::ExtractError(format!("No cost for {value:?}"))
+}
+
+fn find_id(egraph: &EGraph, value: Value) -> Id {
+    Id::from(egraph.find(value).bits as usize)
+}
+
+pub struct Extractor<'a, C: CostFunction = TreeCost> {
+    ctors: Vec<Symbol>,
+    egraph: &'a EGraph,
+    state: Rc<ExtractionState<C>>,
+    /// The terms of `state` already copied into the term dag the extractor
+    /// is used with, by their ids in the term dag of `state`.
+    copied: RefCell<HashMap<TermId, Term>>,
+}
+
+impl EGraph {
+    /// This example uses [`EGraph::extract`] to extract a term. The example is
+    /// trivial, as there is only a single variant of the expression in the
+    /// egraph.
+    /// ```
+    /// use egglog::{EGraph, TermDag};
+    /// let mut egraph = EGraph::default();
+    /// egraph
+    ///     .parse_and_run_program(
+    ///         "(datatype Op (Add i64 i64))
+    ///          (let expr (Add 1 1))",
+    ///     )
+    ///     .unwrap();
+    /// let mut termdag = TermDag::default();
+    /// let (sort, value) = egraph
+    ///     .eval_expr(&egglog::ast::Expr::var("expr"))
+    ///     .unwrap();
+    /// let (_, extracted) = egraph.extract(value, &mut termdag, &sort).unwrap();
+    /// assert_eq!(termdag.to_string(&extracted), "(Add 1 1)");
+    /// ```
+    ///
+    /// E-classes given a row by [`EGraph::set_preferred_choices`] are
+    /// extracted as that row, even if it is not the cheapest.
+    ///
+    /// Returns an [`Error::ExtractError`] if the value has no term, such as
+    /// an e-class whose every row is unextractable.
+    pub fn extract(
+        &self,
+        value: Value,
+        termdag: &mut TermDag,
+        arcsort: &ArcSort,
+    ) -> Result<(Cost, Term), Error> {
+        if arcsort.is_eq_sort() && !self.preferred_choices.is_empty() {
+            let mut extractor = ChoiceExtractor::new(self, termdag, &self.preferred_choices, false);
+            if let Ok(extracted) = extractor.extract(value, termdag) {
+                return Ok(extracted);
+            }
+        }
+        let extractor = Extractor::new(self, termdag);
+        self.find_best_or_log(&extractor, value, termdag, arcsort)
+    }
+
+    /// Extract the term for a value with the least cost under the given
+    /// [`CostFunction`], rather than the `:cost` of each function.
+    pub fn extract_with_cost_function<C: CostFunction>(
+        &self,
+        value: Value,
+        termdag: &mut TermDag,
+        arcsort: &ArcSort,
+        cost_function: C,
+    ) -> Result<(C::Cost, Term), Error> {
+        let extractor = Extractor::with_cost_function(self, termdag, cost_function);
+        self.find_best_or_log(&extractor, value, termdag, arcsort)
+    }
+
+    /// The cheapest term for a value, logging the rows that produce it if it
+    /// has none.
+    fn find_best_or_log<C: CostFunction>(
+        &self,
+        extractor: &Extractor<C>,
+        value: Value,
+        termdag: &mut TermDag,
+        arcsort: &ArcSort,
+    ) -> Result<(C::Cost, Term), Error> {
+        extractor.find_best(value, termdag, arcsort).ok_or_else(|| {
+            log::error!("No cost for {:?}", value);
+            for func in self.functions.values() {
+                for (inputs, output) in func.nodes.iter(false) {
+                    if output.value == value {
+                        log::error!("Found unextractable function: {:?}", func.decl.name);
+                        log::error!("Inputs: {:?}", inputs);
+                        log::error!(
+                            "{:?}",
+                            inputs
+                                .iter()
+                                .map(|input| {
+                                    extractor
+                                        .state
+                                        .costs
+                                        .get(&extractor.find_id(*input))
+                                        .map(|(cost, _)| cost)
+                                })
+                                .collect::<Vec<_>>()
+                        );
+                    }
+                }
+            }
+
+            no_cost(value)
+        })
+    }
+
+    /// Extract a term like [`EGraph::extract`], counting the cost of shared
+    /// subterms as `mode` specifies.
+    /// In the DAG modes, the returned cost counts each distinct subterm once.
+    /// Values inside containers are still extracted as trees.
+    /// ```
+    /// use egglog::{ast::ExtractMode, EGraph, TermDag};
+    /// let mut egraph = EGraph::default();
+    /// egraph
+    ///     .parse_and_run_program(
+    ///         "(datatype Math (Num i64 :cost 5) (Add Math Math) (Double Math :cost 3))
+    ///          (let expr (Add (Num 1) (Num 1)))
+    ///          (union expr (Double (Num 1)))",
+    ///     )
+    ///     .unwrap();
+    /// let mut termdag = TermDag::default();
+    /// let (sort, value) = egraph
+    ///     .eval_expr(&egglog::ast::Expr::var("expr"))
+    ///     .unwrap();
+    /// let (cost, extracted) = egraph.extract(value, &mut termdag, &sort).unwrap();
+    /// assert_eq!((cost, termdag.to_string(&extracted)), (9, "(Double (Num 1))".into()));
+    /// let (cost, extracted) = egraph
+    ///     .extract_with_mode(value, &mut termdag, &sort, ExtractMode::Dag)
+    ///     .unwrap();
+    /// assert_eq!((cost, termdag.to_string(&extracted)), (7, "(Add (Num 1) (Num 1))".into()));
+    /// ```
+    pub fn extract_with_mode(
+        &self,
+        value: Value,
+        termdag: &mut TermDag,
+        arcsort: &ArcSort,
+        mode: ExtractMode,
+    ) -> Result<(Cost, Term), Error> {
+        let exact = match mode {
+            ExtractMode::Tree => return self.extract(value, termdag, arcsort),
+            ExtractMode::Dag => false,
+            ExtractMode::DagExact => true,
+        };
+        if !arcsort.is_eq_sort() {
+            return self.extract(value, termdag, arcsort);
+        }
+        dag::DagExtractor::new(self, termdag)
+            .extract(value, termdag, exact)
+            .ok_or_else(|| no_cost(value))
+    }
+
+    /// Extract the `k` cheapest distinct terms for a value under [`TreeCost`],
+    /// cheapest first, with their costs.
+    /// Fewer are returned if the value has fewer than `k` terms.
+    /// Values inside containers are only extracted as their cheapest term.
+    /// ```
+    /// use egglog::{EGraph, TermDag};
+    /// let mut egraph = EGraph::default();
+    /// egraph
+    ///     .parse_and_run_program(
+    ///         "(datatype Math (Num i64) (Add Math Math))
+    ///          (let expr (Add (Num 1) (Num 2)))
+    ///          (union expr (Num 3))",
+    ///     )
+    ///     .unwrap();
+    /// let mut termdag = TermDag::default();
+    /// let (sort, value) = egraph
+    ///     .eval_expr(&egglog::ast::Expr::var("expr"))
+    ///     .unwrap();
+    /// let best = egraph.extract_k_best(value, &mut termdag, &sort, 5).unwrap();
+    /// let best: Vec<_> = best
+    ///     .iter()
+    ///     .map(|(cost, term)| (*cost, termdag.to_string(term)))
+    ///     .collect();
+    /// assert_eq!(best, [(2, "(Num 3)".into()), (5, "(Add (Num 1) (Num 2))".into())]);
+    /// ```
+    pub fn extract_k_best(
+        &self,
+        value: Value,
+        termdag: &mut TermDag,
+        arcsort: &ArcSort,
+        k: usize,
+    ) -> Result<Vec<(Cost, Term)>, Error> {
+        if k == 0 {
+            return Ok(vec![]);
+        }
+        if !arcsort.is_eq_sort() {
+            return Ok(vec![self.extract(value, termdag, arcsort)?]);
+        }
+        let best = k_best::KBestExtractor::new(self, termdag, k).extract(self, value, termdag);
+        if best.is_empty() {
+            return Err(no_cost(value));
+        }
+        Ok(best)
+    }
+
+    /// Extract the cheapest term for a value under [`TreeCost`] that only
+    /// applies functions to inputs that `allow` accepts, and whose root
+    /// is also accepted by `allow_root`. Returns `None` if there is no
+    /// such term.
+    /// Values that are not in eq sorts are not rows, so `allow_root` is
+    /// only consulted for eq sorts.
+    /// ```
+    /// use egglog::{EGraph, TermDag};
+    /// let mut egraph = EGraph::default();
+    /// egraph
+    ///     .parse_and_run_program(
+    ///         "(datatype Math (Num i64) (Var String) (Mul Math Math) (Shl Math Math))
+    ///          (let expr (Mul (Var \"x\") (Num 2)))
+    ///          (union expr (Shl (Var \"x\") (Num 1)))",
+    ///     )
+    ///     .unwrap();
+    /// let mut termdag = TermDag::default();
+    /// let (sort, value) = egraph
+    ///     .eval_expr(&egglog::ast::Expr::var("expr"))
+    ///     .unwrap();
+    /// let no_shifts = |sym: egglog::ast::Symbol, _: &[egglog::Value]| sym.as_str() != "Shl";
+    /// let (_, term) = egraph
+    ///     .extract_filtered(value, &mut termdag, &sort, no_shifts, |_, _| true)
+    ///     .unwrap();
+    /// assert_eq!(termdag.to_string(&term), "(Mul (Var \"x\") (Num 2))");
+    /// ```
+    pub fn extract_filtered(
+        &self,
+        value: Value,
+        termdag: &mut TermDag,
+        arcsort: &ArcSort,
+        allow: impl Fn(Symbol, &[Value]) -> bool,
+        allow_root: impl Fn(Symbol, &[Value]) -> bool,
+    ) -> Option<(Cost, Term)> {
+        let extractor = Extractor::with_cost_function(self, termdag, Filtered { allow: &allow });
+        if !arcsort.is_eq_sort() {
+            return extractor.find_best(value, termdag, arcsort);
+        }
+        let root = extractor.find_id(value);
+        let mut best: Option<(Cost, Symbol, &[Value])> = None;
+        for &sym in &extractor.ctors {
+            let function = &self.functions[&sym];
+            if !function.schema.output.is_eq_sort() {
+                continue;
+            }
+            for (inputs, output) in function.nodes.iter(false) {
+                if extractor.find_id(output.value) != root
+                    || !allow(sym, inputs)
+                    || !allow_root(sym, inputs)
+                {
+                    continue;
+                }
+                let Some(cost) = extractor.node_cost(function, inputs) else {
+                    continue;
+                };
+                let improved = match &best {
+                    Some((best, _, _)) => cost < *best,
+                    None => true,
+                };
+                if improved {
+                    best = Some((cost, sym, inputs));
+                }
+            }
+        }
+        let (cost, sym, inputs) = best?;
+        let term = extractor.expr_from_node(&Node { sym, inputs }, termdag)?;
+        Some((cost, term))
+    }
+
+    pub fn extract_variants(
+        &mut self,
+        value: Value,
+        limit: usize,
+        termdag: &mut TermDag,
+    ) -> Vec<Term> {
+        let output_value = self.find(value);
+        let ext = &Extractor::new(self, termdag);
+        ext.ctors
+            .iter()
+            .flat_map(|&sym| {
+                let func = &self.functions[&sym];
+                if !func.schema.output.is_eq_sort() {
+                    return vec![];
+                }
+                assert!(func.schema.output.is_eq_sort());
+
+                func.nodes
+                    .iter(false)
+                    .filter(|&(_, output)| (output.value == output_value))
+                    .map(|(inputs, _output)| {
+                        let node = Node { sym, inputs };
+                        ext.expr_from_node(&node, termdag).expect(
+                            "extract_variants should be called after extractor initialization",
+                        )
+                    })
+                    .collect()
+            })
+            .take(limit)
+            .collect()
+    }
+}
+
+impl<'a> Extractor<'a> {
+    /// Extract with [`TreeCost`], reusing the costs computed by earlier
+    /// extractions from the same e-graph.
+    pub fn new(egraph: &'a EGraph, _termdag: &mut TermDag) -> Self {
+        // Take the state out while updating it, so that containers extracted
+        // during the update don't see it borrowed.
+        let mut state = egraph.extraction.take();
+        if !state.is_current(egraph) {
+            Rc::make_mut(&mut state).update(egraph);
+        }
+        *egraph.extraction.borrow_mut() = state.clone();
+        Self::with_state(egraph, state)
+    }
+}
+
+impl<'a, C: CostFunction> Extractor<'a, C> {
+    pub fn with_cost_function(
+        egraph: &'a EGraph,
+        _termdag: &mut TermDag,
+        cost_function: C,
+    ) -> Self {
+        let mut state = ExtractionState::new(cost_function);
+        state.update(egraph);
+        Self::with_state(egraph, Rc::new(state))
+    }
+
+    fn with_state(egraph: &'a EGraph, state: Rc<ExtractionState<C>>) -> Self {
+        // only consider "extractable" functions
+        let ctors: Vec<Symbol> = egraph
+            .functions
+            .iter()
+            .filter(|(_, function)| !function.decl.unextractable)
+            .map(|(&name, _)| name)
+            .collect();
+        log::debug!("Extracting from ctors: {:?}", ctors);
+        Extractor {
+            ctors,
+            egraph,
+            state,
+            copied: RefCell::default(),
+        }
+    }
+
+    fn expr_from_node(&self, node: &Node, termdag: &mut TermDag) -> Option<Term> {
+        let mut children = vec![];
+        for value in node.inputs {
+            let arcsort = self.egraph.get_sort_from_value(value).unwrap();
+            children.push(self.find_best(*value, termdag, arcsort)?.1)
+        }
+
+        Some(termdag.app(node.sym, children))
+    }
+
+    /// The cost of a row, given the cheapest terms for its inputs.
+    fn node_cost(&self, function: &Function, inputs: &[Value]) -> Option<C::Cost> {
+        let mut costs = vec![];
+        for (sort, value) in function.schema.input.iter().zip(inputs) {
+            let cost = if sort.is_eq_sort() {
+                self.state.costs.get(&self.find_id(*value))?.0.clone()
+            } else {
+                self.state.value_expr(self.egraph, &**sort, *value)?.0
+            };
+            costs.push(cost);
+        }
+        Some(
+            self.state
+                .cost_function
+                .cost(self.egraph, function.decl.name, inputs, &costs),
+        )
+    }
+
+    /// The cheapest term for a value and its cost. The term is added to
+    /// `termdag`, which must be the same for every call on this extractor.
+    pub fn find_best(
+        &self,
+        value: Value,
+        termdag: &mut TermDag,
+        sort: &ArcSort,
+    ) -> Option<(C::Cost, Term)> {
+        if sort.is_eq_sort() {
+            let (cost, term) = self.state.costs.get(&self.find_id(value))?;
+            Some((cost.clone(), self.copy_term(term, termdag)))
+        } else {
+            let (cost, expr) = self.state.value_expr(self.egraph, &**sort, value)?;
+            Some((cost, termdag.expr_to_term(&expr)))
+        }
+    }
+
+    /// Extract a container from the terms chosen for the values inside it.
+    pub(crate) fn find_best_container(
+        &self,
+        sort: &dyn Sort,
+        value: Value,
+    ) -> Option<(C::Cost, Expr)> {
+        self.state.value_expr(self.egraph, sort, value)
+    }
+
+    /// Copy a term of `state` into `termdag`.
+    fn copy_term(&self, term: &Term, termdag: &mut TermDag) -> Term {
+        let mut copied = self.copied.borrow_mut();
+        let root = self.state.termdag.lookup(term);
+        let mut stack = vec![root];
+        while let Some(&id) = stack.last() {
+            if copied.contains_key(&id) {
+                stack.pop();
+                continue;
+            }
+            let copy = match self.state.termdag.get(id) {
+                Term::App(sym, children) => {
+                    let missing: Vec<TermId> = children
+                        .iter()
+                        .filter(|child| !copied.contains_key(*child))
+                        .copied()
+                        .collect();
+                    if !missing.is_empty() {
+                        stack.extend(missing);
+                        continue;
+                    }
+                    let children = children.iter().map(|child| copied[child].clone()).collect();
+                    termdag.app(sym, children)
+                }
+                Term::Lit(lit) => termdag.lit(lit),
+                Term::Var(var) => termdag.var(var),
+            };
+            copied.insert(id, copy);
+            stack.pop();
+        }
+        copied[&root].clone()
+    }
+
+    fn find_id(&self, value: Value) -> Id {
+        find_id(self.egraph, value)
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    fn extract(egraph: &mut EGraph, expr: &str) -> String {
+        let (sort, value) = egraph
+            .eval_expr(&crate::ast::parse_expr(expr).unwrap())
+            .unwrap();
+        let mut termdag = TermDag::default();
+        let (_, term) = egraph.extract(value, &mut termdag, &sort).unwrap();
+        let mut fresh_termdag = TermDag::default();
+        let (_, fresh) = egraph
+            .extract_with_cost_function(value, &mut fresh_termdag, &sort, TreeCost)
+            .unwrap();
+        assert_eq!(termdag.to_string(&term), fresh_termdag.to_string(&fresh));
+        termdag.to_string(&term)
+    }
+
+    fn n_restarts(egraph: &EGraph) -> usize {
+        egraph.extraction.borrow().n_restarts
+    }
+
+    #[test]
+    fn test_small_edits_do_not_restart_extraction() {
+        let mut egraph = EGraph::default();
+        egraph
+            .parse_and_run_program(
+                "(datatype Math (Num i64) (Add Math Math) (Mul Math Math))
+                 (let e (Add (Mul (Num 1) (Num 2)) (Num 3)))",
+            )
+            .unwrap();
+        assert_eq!(
+            extract(&mut egraph, "e"),
+            "(Add (Mul (Num 1) (Num 2)) (Num 3))"
+        );
+        let restarts = n_restarts(&egraph);
+
+        egraph
+            .parse_and_run_program("(union (Mul (Num 1) (Num 2)) (Num 2))")
+            .unwrap();
+        assert_eq!(extract(&mut egraph, "e"), "(Add (Num 2) (Num 3))");
+
+        // removing the row an e-class was extracted as raises its cost
+        egraph
+            .parse_and_run_program(
+                "(union (Num 3) (Add (Num 1) (Num 2)))
+                 (delete (Num 3))",
+            )
+            .unwrap();
+        assert_eq!(
+            extract(&mut egraph, "e"),
+            "(Add (Num 2) (Add (Num 1) (Num 2)))"
+        );
+        assert_eq!(n_restarts(&egraph), restarts);
+    }
+}
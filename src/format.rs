@@ -0,0 +1,284 @@
+//! A formatter for egglog source that keeps comments.
+//!
+//! Programs are formatted as s-expressions rather than through the AST, so
+//! that comments and blank lines between commands survive. A list is
+//! printed on one line when it fits and has no comments in it, and is
+//! otherwise broken with its arguments aligned under the first one:
+//!
+//! ```text
+//! (rewrite (Add a b)
+//!          (Add b a)
+//!          :ruleset comm)
+//! ```
+//!
+//! `rule`, `rewrite` and `birewrite` are always broken this way, and
+//! `datatype` and `run-schedule` are always broken with one variant or
+//! schedule per line, indented by two spaces.
+
+use crate::ast::desugar::Desugar;
+use crate::Error;
+
+const WIDTH: usize = 80;
+const INDENT: usize = 2;
+
+/// Format a program in the canonical style, keeping its comments.
+/// Formatting is idempotent, and the formatted program parses to the same
+/// commands as the original.
+pub fn format_program(input: &str) -> Result<String, Error> {
+    // Check the program parses, so only balanced programs are formatted.
+    Desugar::default().parse_source(None, input)?;
+    let items = Reader { input, pos: 0 }.items(true);
+    let mut printer = Printer::default();
+    for (i, item) in items.iter().enumerate() {
+        match &item.sexp {
+            Sexp::Comment(comment) if item.trailing => {
+                printer.push(" ");
+                printer.push(comment);
+            }
+            sexp => {
+                if i > 0 {
+                    printer.push(if item.blank_before { "\n\n" } else { "\n" });
+                }
+                printer.print(sexp);
+            }
+        }
+    }
+    if !items.is_empty() {
+        printer.push("\n");
+    }
+    Ok(printer.out)
+}
+
+enum Sexp<'a> {
+    /// An identifier, number or string.
+    Atom(&'a str),
+    List(char, Vec<Item<'a>>, char),
+    Comment(&'a str),
+}
+
+struct Item<'a> {
+    sexp: Sexp<'a>,
+    /// Whether a blank line separates this from the previous item.
+    blank_before: bool,
+    /// Whether this is a comment on the same line as the previous token.
+    trailing: bool,
+}
+
+impl Sexp<'_> {
+    fn atom(&self) -> Option<&str> {
+        match self {
+            Sexp::Atom(atom) => Some(atom),
+            _ => None,
+        }
+    }
+
+    fn is_keyword(&self) -> bool {
+        self.atom().is_some_and(|atom| atom.starts_with(':'))
+    }
+
+    /// The list on one line, unless it has a comment in it or a form
+    /// that is always broken.
+    fn flat(&self) -> Option<String> {
+        match self {
+            Sexp::Atom(atom) => Some(atom.to_string()),
+            Sexp::Comment(_) => None,
+            Sexp::List(_, items, _) if Layout::new(items, 0).always_break => None,
+            Sexp::List(open, items, close) => {
+                let items = items
+                    .iter()
+                    .map(|item| item.sexp.flat())
+                    .collect::<Option<Vec<_>>>()?;
+                Some(format!("{open}{}{close}", items.join(" ")))
+            }
+        }
+    }
+}
+
+/// Reads the s-expressions of a program that has already been parsed, so
+/// its lists and strings are known to be balanced.
+struct Reader<'a> {
+    input: &'a str,
+    pos: usize,
+}
+
+impl<'a> Reader<'a> {
+    fn peek(&self) -> Option<char> {
+        self.input[self.pos..].chars().next()
+    }
+
+    fn take_while(&mut self, mut f: impl FnMut(char) -> bool) -> &'a str {
+        let start = self.pos;
+        let len = self.input[start..]
+            .find(|c| !f(c))
+            .unwrap_or(self.input.len() - start);
+        self.pos += len;
+        &self.input[start..self.pos]
+    }
+
+    /// Read the items of a list up to its closing paren, or of the whole
+    /// program.
+    fn items(&mut self, top_level: bool) -> Vec<Item<'a>> {
+        let mut items = vec![];
+        loop {
+            let whitespace = self.take_while(char::is_whitespace);
+            let newlines = whitespace.matches('\n').count();
+            let first = items.is_empty();
+            let sexp = match self.peek() {
+                None | Some(')' | ']') => break,
+                Some(open @ ('(' | '[')) => {
+                    self.pos += 1;
+                    let children = self.items(false);
+                    let close = self.peek().unwrap_or(')');
+                    self.pos += close.len_utf8();
+                    Sexp::List(open, children, close)
+                }
+                Some(';') => Sexp::Comment(self.take_while(|c| c != '\n' && c != '\r').trim_end()),
+                Some('"') => {
+                    // strings escape quotes by doubling them
+                    let start = self.pos;
+                    while self.peek() == Some('"') {
+                        self.pos += 1;
+                        self.take_while(|c| c != '"');
+                        self.pos += 1;
+                    }
+                    Sexp::Atom(&self.input[start..self.pos])
+                }
+                Some(_) => {
+                    Sexp::Atom(self.take_while(|c| !c.is_whitespace() && !"()[];\"".contains(c)))
+                }
+            };
+            items.push(Item {
+                trailing: newlines == 0 && !(top_level && first),
+                blank_before: newlines > 1 && !first,
+                sexp,
+            });
+        }
+        items
+    }
+}
+
+#[derive(Default)]
+struct Printer {
+    out: String,
+    /// The column the next character is printed at.
+    col: usize,
+}
+
+impl Printer {
+    fn push(&mut self, s: &str) {
+        self.out.push_str(s);
+        self.col = match s.rfind('\n') {
+            Some(i) => s[i + 1..].chars().count(),
+            None => self.col + s.chars().count(),
+        };
+    }
+
+    fn newline(&mut self, col: usize, blank: bool) {
+        self.push(if blank { "\n\n" } else { "\n" });
+        self.push(&" ".repeat(col));
+    }
+
+    fn print(&mut self, sexp: &Sexp) {
+        match sexp {
+            Sexp::Atom(atom) | Sexp::Comment(atom) => self.push(atom),
+            Sexp::List(open, items, close) => {
+                let layout = Layout::new(items, self.col);
+                match sexp.flat() {
+                    Some(flat) if self.col + flat.chars().count() <= WIDTH => self.push(&flat),
+                    _ => self.print_broken(*open, items, *close, &layout),
+                }
+            }
+        }
+    }
+
+    fn print_broken(&mut self, open: char, items: &[Item], close: char, layout: &Layout) {
+        let start = self.col;
+        self.push(&open.to_string());
+        let mut first_line = true;
+        let mut placed = 0;
+        // after a comment, the next item has to start a new line
+        let mut after_comment = false;
+        let mut items = items.iter().peekable();
+        while let Some(item) = items.next() {
+            match &item.sexp {
+                Sexp::Comment(comment) if item.trailing => {
+                    self.push(" ");
+                    self.push(comment);
+                    after_comment = true;
+                }
+                Sexp::Comment(comment) => {
+                    first_line = false;
+                    self.newline(layout.rest, item.blank_before);
+                    self.push(comment);
+                    after_comment = true;
+                }
+                sexp if first_line && placed < layout.header && !after_comment => {
+                    if placed > 0 {
+                        self.push(" ");
+                    }
+                    self.print(sexp);
+                    placed += 1;
+                }
+                sexp => {
+                    first_line = false;
+                    after_comment = false;
+                    self.newline(layout.rest, item.blank_before);
+                    self.print(sexp);
+                    // keep a keyword on the same line as its value
+                    if sexp.is_keyword() {
+                        if let Some(value) = items.next_if(|next| {
+                            !matches!(next.sexp, Sexp::Comment(_)) && !next.sexp.is_keyword()
+                        }) {
+                            self.push(" ");
+                            self.print(&value.sexp);
+                        }
+                    }
+                }
+            }
+        }
+        if after_comment {
+            self.newline(start, false);
+        }
+        self.push(&close.to_string());
+    }
+}
+
+/// How a list is broken over lines: how many of its items go on the first
+/// line, and the column the rest are aligned at.
+struct Layout {
+    header: usize,
+    rest: usize,
+    always_break: bool,
+}
+
+impl Layout {
+    fn new(items: &[Item], col: usize) -> Self {
+        let exprs: Vec<_> = items
+            .iter()
+            .filter(|item| !matches!(item.sexp, Sexp::Comment(_)))
+            .map(|item| &item.sexp)
+            .collect();
+        let Some(head) = exprs.first().and_then(|sexp| sexp.atom()) else {
+            return Layout {
+                header: 1,
+                rest: col + 1,
+                always_break: false,
+            };
+        };
+        let (header, rest) = match head {
+            "datatype" | "repeat" => (2, col + INDENT),
+            "run-schedule" | "seq" | "saturate" => (1, col + INDENT),
+            _ if exprs.get(1).is_some_and(|sexp| !sexp.is_keyword()) => (2, col + head.len() + 2),
+            _ => (1, col + head.len() + 2),
+        };
+        let always_break = matches!(
+            head,
+            "rule" | "rewrite" | "birewrite" | "datatype" | "run-schedule"
+        ) && exprs.len() > header;
+        Layout {
+            header,
+            rest,
+            always_break,
+        }
+    }
+}
@@ -33,6 +33,18 @@ impl ColumnIndex {
         self.ids.clear()
     }
 
+    /// Remove offset `i` for `v`, if it was the last one added for `v`.
+    pub(crate) fn remove(&mut self, v: Value, i: usize) {
+        if let Some(offsets) = self.ids.get_mut(&v.bits) {
+            while offsets.last() == Some(&(i as Offset)) {
+                offsets.pop();
+            }
+            if offsets.is_empty() {
+                self.ids.remove(&v.bits);
+            }
+        }
+    }
+
     pub(crate) fn len(&self) -> usize {
         self.ids.len()
     }
@@ -94,6 +106,13 @@ impl CompositeColumnIndex {
         }
     }
 
+    /// Remove offset `i` for `v`, if it was the last one added for `v`.
+    pub(crate) fn remove(&mut self, v: Value, i: usize) {
+        if let Some(index) = self.0.iter_mut().find(|index| index.sort() == v.tag) {
+            index.remove(v, i);
+        }
+    }
+
     pub(crate) fn iter(&self) -> impl Iterator<Item = &ColumnIndex> {
         self.0.iter()
     }
@@ -24,6 +24,13 @@ pub struct Function {
     scratch: IndexSet<usize>,
 }
 
+/// The state of a [`Function`] that [`Function::rollback`] returns to.
+#[derive(Clone, Debug)]
+pub(crate) struct FunctionCheckpoint {
+    nodes: table::Checkpoint,
+    updates: usize,
+}
+
 #[derive(Clone)]
 pub struct MergeAction {
     pub on_merge: Option<Rc<Program>>,
@@ -189,6 +196,15 @@ impl Function {
         self.insert_internal(inputs, value, timestamp, true)
     }
     pub fn clear(&mut self) {
+        if self.nodes.is_recording() {
+            // remove the rows one by one, so that they can be restored
+            let rows: Vec<ValueVec> = self.nodes.iter(true).map(|(inp, _)| inp.into()).collect();
+            let ts = self.nodes.max_ts();
+            for row in rows {
+                self.nodes.remove(&row, ts);
+            }
+            return;
+        }
         self.nodes.clear();
         self.indexes
             .iter_mut()
@@ -227,7 +243,61 @@ impl Function {
 
     /// Mark the given inputs as subsumed.
     pub fn subsume(&mut self, inputs: &[Value]) {
-        self.nodes.get_mut(inputs).unwrap().subsumed = true;
+        let found = self.nodes.subsume(inputs);
+        assert!(found, "cannot subsume a row that is not in the table");
+    }
+
+    /// Start logging changes, so that the function can be rolled back to its
+    /// current contents.
+    pub(crate) fn checkpoint(&mut self) -> FunctionCheckpoint {
+        FunctionCheckpoint {
+            nodes: self.nodes.checkpoint(),
+            updates: self.updates,
+        }
+    }
+
+    /// Undo every change made since the given checkpoint was taken, which
+    /// must be the most recent open checkpoint.
+    pub(crate) fn rollback(&mut self, checkpoint: FunctionCheckpoint) {
+        let num_offsets = checkpoint.nodes.num_offsets();
+        self.unindex(num_offsets);
+        self.nodes.rollback(checkpoint.nodes);
+        self.updates = checkpoint.updates;
+    }
+
+    /// Forget an open checkpoint, keeping the changes made since it was
+    /// taken, see [`table::Table::forget`].
+    pub(crate) fn forget(&mut self, checkpoint: FunctionCheckpoint) {
+        self.nodes.forget(checkpoint.nodes);
+    }
+
+    /// Drop the index entries of the rows from the given offset on.
+    /// Tables only move rows added since the innermost checkpoint, so
+    /// these are the last offsets stored for each value.
+    fn unindex(&mut self, num_offsets: usize) {
+        for off in (num_offsets..self.index_updated_through).rev() {
+            let (inputs, output) = &self.nodes.vals[off];
+            for (col, (index, rebuild_index)) in self
+                .indexes
+                .iter_mut()
+                .zip(self.rebuild_indexes.iter_mut())
+                .enumerate()
+            {
+                let (value, sort) = if col == self.schema.input.len() {
+                    (output.value, &self.schema.output)
+                } else {
+                    (inputs.data[col], &self.schema.input[col])
+                };
+                Rc::make_mut(index).remove(value, off);
+                if let Some(rebuild_index) = rebuild_index {
+                    sort.foreach_tracked_values(
+                        &value,
+                        Box::new(|value| rebuild_index.remove(value, off)),
+                    );
+                }
+            }
+        }
+        self.index_updated_through = self.index_updated_through.min(num_offsets);
     }
 
     /// Return a column index that contains (a superset of) the offsets for the
@@ -310,6 +380,14 @@ impl Function {
             return;
         }
 
+        if self.nodes.is_recording() {
+            // only the rows added since the innermost checkpoint move
+            self.unindex(self.nodes.rehash_start());
+            self.nodes.rehash();
+            self.update_indexes(self.nodes.num_offsets());
+            return;
+        }
+
         for index in &mut self.indexes {
             // Everything works if we don't have a unique copy of the indexes,
             // but we ought to be able to avoid this copy.
@@ -421,6 +499,8 @@ impl Function {
         let mut out_val = out.value;
         scratch.clear();
         scratch.extend(args.iter().copied());
+        // the row's inputs before canonicalization, to track where it moves
+        let old_args = scratch.clone();
 
         for (val, ty) in scratch.iter_mut().zip(&self.schema.input) {
             modified |= ty.canonicalize(val, uf);
@@ -444,7 +524,14 @@ impl Function {
                     match &self.merge.merge_vals {
                         MergeFn::Union => {
                             debug_assert!(self.schema.output.is_eq_sort());
-                            uf.union_values(prev, out_val, self.schema.output.name())
+                            uf.union_congruent(
+                                prev,
+                                out_val,
+                                self.schema.output.name(),
+                                self.decl.name,
+                                scratch,
+                                &old_args,
+                            )
                         }
                         MergeFn::AssertEq => {
                             if prev != out_val {
@@ -465,9 +552,8 @@ impl Function {
             });
         if let Some((inputs, _)) = self.nodes.get_index(i, true) {
             if inputs != &scratch[..] {
-                scratch.clear();
-                scratch.extend_from_slice(inputs);
-                self.nodes.remove(scratch, timestamp);
+                uf.move_row(self.decl.name, inputs, scratch);
+                self.nodes.remove_moved(i, scratch, timestamp);
                 scratch.clear();
             }
         }
@@ -19,6 +19,11 @@
 //! making it efficient to iterate over subsets of a table matching a given
 //! timestamp range.
 //!
+//! Tables can be rolled back to a [`Checkpoint`]. While a checkpoint is open,
+//! the table logs how to undo each change to the entries that existed when it
+//! was taken. Rehashing then only compacts the entries added since the
+//! innermost checkpoint, so that the offsets of older entries stay stable.
+//!
 //! Note on rehashing: We will eventually want to keep old/stale entries around
 //! to facilitate proofs/provenance. Early testing found that removing this in
 //! the "obvious" way (keeping 'vals' around, avoiding `mem::take()`s for stale
@@ -53,6 +58,88 @@ pub(crate) struct Table {
     n_stale: usize,
     table: RawTable<TableOffset>,
     pub(crate) vals: Vec<(Input, TupleOutput)>,
+    /// The open checkpoints, innermost last. Changes are only logged when
+    /// there is one.
+    checkpoints: Vec<Recording>,
+    undo_log: Vec<Undo>,
+    /// The number of entries dropped from the front of `undo_log` when the
+    /// outermost checkpoint was forgotten, which the positions in
+    /// checkpoints count.
+    log_start: usize,
+    /// The number of placeholder offsets in the undo log, see
+    /// [`Table::rehash_since`].
+    n_placeholders: usize,
+    /// Bumped whenever offsets into the table are invalidated without being
+    /// logged in `changes`.
+    epoch: usize,
+    /// The changes to the entries since the epoch last changed, other than
+    /// adding entries.
+    changes: Vec<RowChange>,
+}
+
+/// A change to the entries of a [`Table`], other than adding one, for
+/// following the contents of a table without rereading all of it.
+#[derive(Clone, Debug, PartialEq, Eq)]
+pub(crate) enum RowChange {
+    /// The entry at this offset was removed or subsumed.
+    Removed(Offset),
+    /// The entry at the first offset was replaced by the one at the second,
+    /// with the same inputs or inputs that rebuilding made canonical.
+    Moved(Offset, Offset),
+    /// The stale entries at these offsets, in increasing order, were dropped,
+    /// moving each later entry down by the number of them before it.
+    Compacted(Vec<Offset>),
+}
+
+impl RowChange {
+    /// Where an entry is after the entries at `dropped` are compacted away,
+    /// or `None` if it was one of them.
+    pub(crate) fn compacted_offset(dropped: &[Offset], off: Offset) -> Option<Offset> {
+        match dropped.binary_search(&off) {
+            Ok(_) => None,
+            Err(n_before) => Some(off - n_before),
+        }
+    }
+}
+
+/// A change to a [`Table`], logged so that it can be undone.
+#[derive(Copy, Clone, Debug)]
+enum Undo {
+    /// The entry at this offset was marked stale.
+    Stale(Offset),
+    /// The entry at this offset was marked subsumed, having the given flag before.
+    Subsumed(Offset, bool),
+    /// A key was added, pointing at the given offset.
+    Inserted { hash: u64, off: Offset },
+    /// A key was moved from the `old` offset to the `new` one.
+    Moved { hash: u64, old: Offset, new: Offset },
+    /// A key pointing at the given offset was removed.
+    Removed { hash: u64, off: Offset },
+}
+
+/// What a [`Table`] tracks about an open checkpoint.
+#[derive(Copy, Clone, Debug)]
+struct Recording {
+    num_offsets: usize,
+    log_len: usize,
+    /// The number of entries added since the checkpoint that are now stale.
+    n_stale_since: usize,
+}
+
+/// The state of a [`Table`] that [`Table::rollback`] returns to.
+#[derive(Clone, Debug)]
+pub(crate) struct Checkpoint {
+    num_offsets: usize,
+    n_stale: usize,
+    max_ts: u32,
+    log_len: usize,
+}
+
+impl Checkpoint {
+    /// The number of entries the table had when the checkpoint was taken.
+    pub(crate) fn num_offsets(&self) -> usize {
+        self.num_offsets
+    }
 }
 
 /// Used for the RawTable probe sequence.
@@ -77,6 +164,7 @@ impl Debug for Table {
             .field("max_ts", &self.max_ts)
             .field("n_stale", &self.n_stale)
             .field("vals", &self.vals)
+            .field("checkpoints", &self.checkpoints)
             .finish()
     }
 }
@@ -88,17 +176,173 @@ impl Table {
         self.n_stale = 0;
         self.table.clear();
         self.vals.clear();
+        self.new_epoch();
+    }
+
+    /// Changes whenever offsets into the table are invalidated.
+    pub(crate) fn epoch(&self) -> usize {
+        self.epoch
+    }
+
+    /// The changes to the entries since the epoch last changed, in order.
+    pub(crate) fn changes(&self) -> &[RowChange] {
+        &self.changes
+    }
+
+    fn new_epoch(&mut self) {
+        self.epoch += 1;
+        self.changes.clear();
+    }
+
+    /// Log that the entries at `dropped` were compacted away, unless the log
+    /// has grown much larger than the table, in which case it is cheaper for
+    /// readers to start over.
+    fn compacted(&mut self, dropped: Vec<Offset>) {
+        if self.changes.len() > self.vals.len() + 1024 {
+            self.new_epoch();
+        } else if !dropped.is_empty() {
+            self.changes.push(RowChange::Compacted(dropped));
+        }
     }
 
     /// Indicates whether or not the table should be rehashed.
     pub(crate) fn too_stale(&self) -> bool {
-        self.n_stale > (self.vals.len() / 2)
+        match self.checkpoints.last() {
+            Some(recording) => {
+                recording.n_stale_since > (self.vals.len() - recording.num_offsets) / 2
+            }
+            None => self.n_stale > (self.vals.len() / 2),
+        }
+    }
+
+    /// Whether the table has open checkpoints.
+    pub(crate) fn is_recording(&self) -> bool {
+        !self.checkpoints.is_empty()
+    }
+
+    /// The first offset that [`Table::rehash`] may move.
+    pub(crate) fn rehash_start(&self) -> usize {
+        self.checkpoints.last().map_or(0, |r| r.num_offsets)
+    }
+
+    /// Start logging changes, so that the table can be rolled back to its
+    /// current state.
+    pub(crate) fn checkpoint(&mut self) -> Checkpoint {
+        let log_len = self.log_start + self.undo_log.len();
+        self.checkpoints.push(Recording {
+            num_offsets: self.vals.len(),
+            log_len,
+            n_stale_since: 0,
+        });
+        Checkpoint {
+            num_offsets: self.vals.len(),
+            n_stale: self.n_stale,
+            max_ts: self.max_ts,
+            log_len,
+        }
+    }
+
+    /// Forget an open checkpoint, keeping the changes made since it was
+    /// taken. Rolling back to the checkpoint before it then undoes those
+    /// changes as well.
+    pub(crate) fn forget(&mut self, checkpoint: Checkpoint) {
+        // checkpoints taken with nothing logged between them are
+        // interchangeable
+        let i = self
+            .checkpoints
+            .iter()
+            .position(|recording| {
+                recording.num_offsets == checkpoint.num_offsets
+                    && recording.log_len == checkpoint.log_len
+            })
+            .expect("checkpoint is open");
+        let recording = self.checkpoints.remove(i);
+        let end = self
+            .checkpoints
+            .get(i)
+            .map_or(self.log_start + self.undo_log.len(), |next| next.log_len);
+        if i == 0 {
+            // nothing rolls back to before the next checkpoint anymore
+            self.undo_log.drain(..end - self.log_start);
+            self.log_start = end;
+            if self.checkpoints.is_empty() {
+                self.n_placeholders = 0;
+            }
+        } else {
+            // the entries it saw go stale are now seen by the one before
+            let prev = &mut self.checkpoints[i - 1];
+            prev.n_stale_since += self.undo_log
+                [recording.log_len - self.log_start..end - self.log_start]
+                .iter()
+                .filter(|undo| matches!(undo, Undo::Stale(off) if *off >= prev.num_offsets))
+                .count();
+        }
+    }
+
+    /// Undo every change made since the given checkpoint was taken, which
+    /// must be the most recent open checkpoint.
+    pub(crate) fn rollback(&mut self, checkpoint: Checkpoint) {
+        debug_assert!(self.is_recording());
+        for undo in self
+            .undo_log
+            .drain(checkpoint.log_len - self.log_start..)
+            .rev()
+        {
+            match undo {
+                Undo::Stale(off) => self.vals[off].0.stale_at = u32::MAX,
+                Undo::Subsumed(off, subsumed) => self.vals[off].1.subsumed = subsumed,
+                Undo::Inserted { hash, off } => {
+                    self.table.remove_entry(hash, |to| to.off == off);
+                }
+                Undo::Moved { hash, old, new } => {
+                    if let Some(to) = self.table.get_mut(hash, |to| to.off == new) {
+                        to.off = old;
+                    }
+                }
+                Undo::Removed { hash, off } => {
+                    self.table
+                        .insert(hash, TableOffset { hash, off }, |to| to.hash);
+                }
+            }
+        }
+        self.vals.truncate(checkpoint.num_offsets);
+        self.n_stale = checkpoint.n_stale;
+        self.max_ts = checkpoint.max_ts;
+        self.checkpoints.pop();
+        if self.checkpoints.is_empty() {
+            self.n_placeholders = 0;
+        }
+        self.new_epoch();
+    }
+
+    fn log(&mut self, undo: Undo) {
+        if self.is_recording() {
+            self.undo_log.push(undo);
+        }
+    }
+
+    /// Mark the entry at the given offset stale.
+    fn make_stale(&mut self, off: Offset, ts: u32) {
+        self.vals[off].0.stale_at = ts;
+        self.n_stale += 1;
+        if let Some(recording) = self.checkpoints.last_mut() {
+            if off >= recording.num_offsets {
+                recording.n_stale_since += 1;
+            }
+        }
+        self.log(Undo::Stale(off));
     }
 
-    /// Rehashes the table, invalidating any offsets stored into the table.
+    /// Rehashes the table, invalidating any offsets stored into the table
+    /// from [`Table::rehash_start`] on.
     pub(crate) fn rehash(&mut self) {
+        if let Some(&recording) = self.checkpoints.last() {
+            self.rehash_since(recording);
+            return;
+        }
         let mut src = 0usize;
         let mut dst = 0usize;
+        let mut dropped = vec![];
         self.table.clear();
         self.vals.retain(|(inp, _)| {
             if inp.live() {
@@ -109,11 +353,86 @@ impl Table {
                 dst += 1;
                 true
             } else {
+                dropped.push(src);
                 src += 1;
                 false
             }
         });
         self.n_stale = 0;
+        self.compacted(dropped);
+    }
+
+    /// Drop the stale entries added since the given checkpoint, rewriting the
+    /// undo log to use the new offsets. Entries that are dropped get distinct
+    /// placeholder offsets counting down from `usize::MAX`, which rolling back
+    /// the log only ever stores in the hash table and then removes again.
+    fn rehash_since(&mut self, recording: Recording) {
+        let start = recording.num_offsets;
+        let end = self.vals.len();
+        let mut moved_to = Vec::with_capacity(end - start);
+        let mut dropped = vec![];
+        let mut dst = start;
+        for (off, (inp, _)) in self.vals.iter().enumerate().skip(start) {
+            if inp.live() {
+                moved_to.push(dst);
+                dst += 1;
+            } else {
+                self.n_placeholders += 1;
+                moved_to.push(usize::MAX - self.n_placeholders);
+                dropped.push(off);
+            }
+        }
+        // placeholders from earlier rehashes stay as they are
+        let remap = |off: Offset| {
+            if (start..end).contains(&off) {
+                moved_to[off - start]
+            } else {
+                off
+            }
+        };
+        let kept = |off: Offset| remap(off) < dst;
+
+        // entries move to lower offsets, so each is found before
+        // another entry can move to its offset
+        for off in start..self.vals.len() {
+            if kept(off) {
+                let hash = hash_values(self.vals[off].0.data());
+                let to = self.table.get_mut(hash, |to| to.off == off).unwrap();
+                to.off = remap(off);
+            }
+        }
+
+        let log = self.undo_log.split_off(recording.log_len - self.log_start);
+        self.undo_log
+            .extend(log.into_iter().filter_map(|undo| match undo {
+                // dropped entries are truncated on rollback anyway
+                Undo::Stale(off) if off >= start => None,
+                Undo::Stale(off) => Some(Undo::Stale(off)),
+                Undo::Subsumed(off, prev) => kept(off).then(|| Undo::Subsumed(remap(off), prev)),
+                Undo::Inserted { hash, off } => Some(Undo::Inserted {
+                    hash,
+                    off: remap(off),
+                }),
+                Undo::Moved { hash, old, new } => Some(Undo::Moved {
+                    hash,
+                    old: remap(old),
+                    new: remap(new),
+                }),
+                Undo::Removed { hash, off } => Some(Undo::Removed {
+                    hash,
+                    off: remap(off),
+                }),
+            }));
+
+        let mut off = 0;
+        self.vals.retain(|(inp, _)| {
+            let keep = off < start || inp.live();
+            off += 1;
+            keep
+        });
+        self.n_stale -= recording.n_stale_since;
+        self.checkpoints.last_mut().unwrap().n_stale_since = 0;
+        self.compacted(dropped);
     }
 
     /// Get the entry in the table for the given values, if they are in the
@@ -125,11 +444,21 @@ impl Table {
         Some(&self.vals[*off].1)
     }
 
-    pub(crate) fn get_mut(&mut self, inputs: &[Value]) -> Option<&mut TupleOutput> {
+    /// Mark the entry for the given inputs as subsumed. Returns whether the
+    /// inputs were in the table.
+    pub(crate) fn subsume(&mut self, inputs: &[Value]) -> bool {
         let hash: u64 = hash_values(inputs);
-        let TableOffset { off, .. } = self.table.get(hash, search_for!(self, hash, inputs))?;
-        debug_assert!(self.vals[*off].0.live());
-        Some(&mut self.vals[*off].1)
+        let Some(&TableOffset { off, .. }) = self.table.get(hash, search_for!(self, hash, inputs))
+        else {
+            return false;
+        };
+        debug_assert!(self.vals[off].0.live());
+        let prev = mem::replace(&mut self.vals[off].1.subsumed, true);
+        self.log(Undo::Subsumed(off, prev));
+        if !prev {
+            self.changes.push(RowChange::Removed(off));
+        }
+        true
     }
 
     /// Insert the given data into the table at the given timestamp. Return the
@@ -169,9 +498,13 @@ impl Table {
             if next == prev.value && prev_subsumed == subsumed {
                 return;
             }
-            inp.stale_at = ts;
-            self.n_stale += 1;
-            let k = mem::take(&mut inp.data);
+            // stale entries keep their data while recording, to roll back indexes
+            let k = if !self.checkpoints.is_empty() {
+                inp.data.clone()
+            } else {
+                mem::take(&mut inp.data)
+            };
+            let old_offset = *off;
             let new_offset = self.vals.len();
             self.vals.push((
                 Input::new(k),
@@ -182,6 +515,13 @@ impl Table {
                 },
             ));
             *off = new_offset;
+            self.make_stale(old_offset, ts);
+            self.changes.push(RowChange::Moved(old_offset, new_offset));
+            self.log(Undo::Moved {
+                hash,
+                old: old_offset,
+                new: new_offset,
+            });
             return;
         }
         let new_offset = self.vals.len();
@@ -201,6 +541,10 @@ impl Table {
             },
             |off| off.hash,
         );
+        self.log(Undo::Inserted {
+            hash,
+            off: new_offset,
+        });
     }
 
     /// One more than the maximum (potentially) valid offset into the table.
@@ -213,6 +557,13 @@ impl Table {
         self.vals.len() - self.n_stale
     }
 
+    /// A rough estimate of the memory used by the table, in bytes.
+    /// Values spilled from inline storage onto the heap are not counted.
+    pub(crate) fn approx_bytes(&self) -> usize {
+        self.vals.capacity() * std::mem::size_of::<(Input, TupleOutput)>()
+            + self.table.capacity() * std::mem::size_of::<TableOffset>()
+    }
+
     /// Whether the table is completely empty, including stale entries.
     pub(crate) fn is_empty(&self) -> bool {
         self.num_offsets() == 0
@@ -243,11 +594,32 @@ impl Table {
         } else {
             return false;
         };
-        self.vals[entry.off].0.stale_at = ts;
-        self.n_stale += 1;
+        self.log(Undo::Removed {
+            hash,
+            off: entry.off,
+        });
+        self.make_stale(entry.off, ts);
+        self.changes.push(RowChange::Removed(entry.off));
         true
     }
 
+    /// Remove the entry at the given offset, which rebuilding replaced by the
+    /// entry for the given canonical inputs.
+    pub(crate) fn remove_moved(&mut self, off: Offset, to: &[Value], ts: u32) {
+        let hash = hash_values(self.vals[off].0.data());
+        self.table
+            .remove_entry(hash, |entry| entry.off == off)
+            .unwrap();
+        self.log(Undo::Removed { hash, off });
+        self.make_stale(off, ts);
+        let to_hash = hash_values(to);
+        let to = self
+            .table
+            .get(to_hash, search_for!(self, to_hash, to))
+            .unwrap();
+        self.changes.push(RowChange::Moved(off, to.off));
+    }
+
     /// Returns the entries at the given index if the entry is live (and possibly not subsumed) and the index in bounds.
     pub(crate) fn get_index(
         &self,
@@ -4,7 +4,10 @@ use log::log_enabled;
 use smallvec::SmallVec;
 
 use crate::{
-    core::{Atom, AtomTerm, ResolvedAtomTerm, ResolvedCall},
+    core::{
+        Atom, AtomTerm, GenericAtom, GenericAtomTerm, ResolvedAtomTerm, ResolvedCall,
+        SpecializedPrimitive,
+    },
     function::index::Offset,
     *,
 };
@@ -326,6 +329,38 @@ pub struct CompiledQuery {
     pub vars: IndexMap<Symbol, VarInfo>,
 }
 
+/// The closest a query with no matches came to matching: how many of its
+/// atoms matched together, the variable bindings of one such match, and the
+/// next atom, which had no rows consistent with those bindings.
+#[derive(Debug, Clone)]
+pub struct PartialMatch {
+    pub matched: usize,
+    pub bindings: Vec<(Symbol, String)>,
+    pub failed: String,
+}
+
+impl std::fmt::Display for PartialMatch {
+    fn fmt(&self, f: &mut std::fmt::Formatter<'_>) -> std::fmt::Result {
+        match self.matched {
+            0 => {}
+            1 => write!(f, "atom 1 matched")?,
+            n => write!(f, "atoms 1–{n} matched")?,
+        }
+        if !self.bindings.is_empty() {
+            let bindings: Vec<_> = self
+                .bindings
+                .iter()
+                .map(|(var, term)| format!("{var}={term}"))
+                .collect();
+            write!(f, " with {}", bindings.join(", "))?;
+        }
+        if self.matched > 0 {
+            write!(f, ", ")?;
+        }
+        write!(f, "atom {} `{}` had no rows", self.matched + 1, self.failed)
+    }
+}
+
 impl EGraph {
     pub(crate) fn compile_gj_query(
         &self,
@@ -776,6 +811,79 @@ impl EGraph {
                 .unwrap_or(());
         }
     }
+
+    /// Find how far a query with no matches gets by joining its atoms one
+    /// at a time, returning `None` if the whole query matches.
+    /// Function atoms are joined in order, and each primitive is joined as
+    /// soon as its inputs are bound.
+    pub(crate) fn partial_match(
+        &self,
+        query: &core::Query<ResolvedCall, ResolvedVar>,
+    ) -> Option<PartialMatch> {
+        let mut pending: Vec<_> = query.atoms.iter().collect();
+        let mut bound = IndexSet::<ResolvedVar>::default();
+        let mut atoms = vec![];
+        while !pending.is_empty() {
+            let ready = pending
+                .iter()
+                .position(|atom| match &atom.head {
+                    ResolvedCall::Primitive(_) => {
+                        let (_, inputs) = atom.args.split_last().unwrap();
+                        inputs.iter().all(|arg| match arg {
+                            GenericAtomTerm::Var(v) => bound.contains(v),
+                            _ => true,
+                        })
+                    }
+                    ResolvedCall::Func(_) => false,
+                })
+                .or_else(|| {
+                    pending
+                        .iter()
+                        .position(|atom| matches!(atom.head, ResolvedCall::Func(_)))
+                })
+                .unwrap_or(0);
+            let atom = pending.remove(ready);
+            bound.extend(atom.vars());
+            atoms.push(atom.clone());
+        }
+
+        let mut last_match: Vec<(Symbol, Value)> = vec![];
+        for matched in 0..atoms.len() {
+            let prefix = core::Query {
+                atoms: atoms[..=matched].to_vec(),
+            };
+            let ordering = prefix.get_vars();
+            let cq = self.compile_gj_query(prefix, &ordering);
+            let mut found = None;
+            self.run_query(&cq, 0, true, |values| {
+                found = Some(values.to_vec());
+                Err(())
+            });
+            match found {
+                Some(values) => last_match = cq.vars.keys().copied().zip(values).collect(),
+                None => {
+                    let failed = &atoms[matched];
+                    let failed_vars: IndexSet<Symbol> = failed.vars().map(|v| v.name).collect();
+                    let bindings = last_match
+                        .iter()
+                        .filter(|(var, _)| !is_internal_var(*var) || failed_vars.contains(var))
+                        .map(|(var, value)| {
+                            let term = self
+                                .extract_value_to_string(*value)
+                                .unwrap_or_else(|_| format!("{value:?}"));
+                            (*var, term)
+                        })
+                        .collect();
+                    return Some(PartialMatch {
+                        matched,
+                        bindings,
+                        failed: atom_to_string(failed),
+                    });
+                }
+            }
+        }
+        None
+    }
 }
 
 struct LazyTrie(UnsafeCell<LazyTrieInner>);
@@ -998,3 +1106,37 @@ impl<'a> TrieAccess<'a> {
         LazyTrieInner::Sparse(map)
     }
 }
+
+/// Variables introduced while desugaring or canonicalizing a query,
+/// as opposed to ones written by the user.
+pub(crate) fn is_internal_var(var: Symbol) -> bool {
+    let name = var.as_str();
+    name.starts_with('$') || name.starts_with("__")
+}
+
+/// Display an atom the way it could be written in a query, eliding
+/// outputs that are internal variables.
+fn atom_to_string(atom: &GenericAtom<ResolvedCall, ResolvedVar>) -> String {
+    let term = |arg: &ResolvedAtomTerm| match arg {
+        GenericAtomTerm::Var(v) => v.name.to_string(),
+        GenericAtomTerm::Literal(lit) => lit.to_string(),
+        GenericAtomTerm::Global(v) => v.name.to_string(),
+    };
+    let (output, inputs) = atom.args.split_last().unwrap();
+    let inputs: Vec<_> = inputs.iter().map(term).collect();
+    if let ResolvedCall::Primitive(SpecializedPrimitive { primitive, .. }) = &atom.head {
+        if primitive.name() == "value-eq".into() {
+            return format!("(= {})", inputs.join(" "));
+        }
+    }
+    let call = if inputs.is_empty() {
+        format!("({})", atom.head)
+    } else {
+        format!("({} {})", atom.head, inputs.join(" "))
+    };
+    match output {
+        GenericAtomTerm::Var(v) if is_internal_var(v.name) => call,
+        GenericAtomTerm::Literal(Literal::Unit) => call,
+        _ => format!("(= {call} {})", term(output)),
+    }
+}
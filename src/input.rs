@@ -0,0 +1,318 @@
+//! Reading the rows of a function from a delimited text file or a JSON
+//! file, for the `input` and `input-json` commands.
+use ordered_float::OrderedFloat;
+
+use crate::ast::{parse_expr, Action, Expr, InputOptions, Literal, NCommand};
+use crate::typechecking::TypeError;
+use crate::*;
+
+impl EGraph {
+    pub(crate) fn input_file(
+        &mut self,
+        func_name: Symbol,
+        file: &str,
+        options: &InputOptions,
+    ) -> Result<(), Error> {
+        let (sorts, has_output) = self.input_columns(func_name)?;
+        let mut filename = self.fact_directory.clone().unwrap_or_default();
+        filename.push(file);
+        log::info!("Opening file '{:?}'...", filename);
+        let contents =
+            std::fs::read_to_string(&filename).map_err(|e| Error::IoError(filename.clone(), e))?;
+        let error =
+            |line: usize, message: String| Error::InputError(filename.clone(), line, message);
+
+        let mut lines = contents
+            .lines()
+            .enumerate()
+            .map(|(i, text)| (i + 1, text))
+            .filter(|(_, text)| !text.trim().is_empty());
+        // the field read for each column, if not all of them in order
+        let mut picks: Option<Vec<usize>> = None;
+        if options.header || options.columns.is_some() {
+            if let (Some((line, header)), Some(columns)) = (lines.next(), &options.columns) {
+                check_num_columns(func_name, &sorts, columns).map_err(|e| error(line, e))?;
+                let names = split_fields(header, options.delimiter).map_err(|e| error(line, e))?;
+                let mut indices = vec![];
+                for column in columns {
+                    let index = names
+                        .iter()
+                        .position(|name| name == column)
+                        .ok_or_else(|| {
+                            error(line, format!("no column named \"{column}\" in the header"))
+                        })?;
+                    indices.push(index);
+                }
+                picks = Some(indices);
+            }
+        }
+
+        let mut num_facts = 0;
+        for (line, text) in lines {
+            let fields = split_fields(text, options.delimiter).map_err(|e| error(line, e))?;
+            let fields: Vec<&str> = match &picks {
+                Some(picks) => picks
+                    .iter()
+                    .map(|&i| fields.get(i).map(String::as_str))
+                    .collect::<Option<_>>()
+                    .ok_or_else(|| error(line, "missing a named column".into()))?,
+                None => fields.iter().map(String::as_str).collect(),
+            };
+            if fields.len() != sorts.len() {
+                return Err(error(
+                    line,
+                    format!("expected {} fields, found {}", sorts.len(), fields.len()),
+                ));
+            }
+            let exprs = sorts
+                .iter()
+                .zip(fields)
+                .map(|(sort, field)| field_expr(sort, field))
+                .collect::<Result<Vec<_>, _>>()
+                .map_err(|e| error(line, e))?;
+            self.input_row(func_name, has_output, exprs)
+                .map_err(|e| error(line, e.to_string()))?;
+            num_facts += 1;
+        }
+        log::info!("Read {num_facts} facts into {func_name} from '{file}'.");
+        Ok(())
+    }
+
+    /// Read a JSON file with an array of rows, each an array of values in
+    /// the order of the columns, or an object with a value for each of
+    /// `columns`.
+    #[cfg(any(feature = "bin", feature = "serde-1"))]
+    pub(crate) fn input_json_file(
+        &mut self,
+        func_name: Symbol,
+        file: &str,
+        columns: Option<&[String]>,
+    ) -> Result<(), Error> {
+        use serde_json::Value as Json;
+
+        let (sorts, has_output) = self.input_columns(func_name)?;
+        let mut filename = self.fact_directory.clone().unwrap_or_default();
+        filename.push(file);
+        log::info!("Opening file '{:?}'...", filename);
+        let contents =
+            std::fs::read_to_string(&filename).map_err(|e| Error::IoError(filename.clone(), e))?;
+        let error = |message: String| Error::JsonInputError(filename.clone(), message);
+
+        let json: Json = serde_json::from_str(&contents).map_err(|e| error(e.to_string()))?;
+        let Json::Array(rows) = json else {
+            return Err(error("expected an array of rows".into()));
+        };
+        if let Some(columns) = columns {
+            check_num_columns(func_name, &sorts, columns).map_err(error)?;
+        }
+        for (i, row) in rows.iter().enumerate() {
+            let row_error = |message: String| error(format!("row {}: {message}", i + 1));
+            let values: Vec<&Json> = match (row, columns) {
+                (Json::Array(values), _) => values.iter().collect(),
+                (Json::Object(object), Some(columns)) => columns
+                    .iter()
+                    .map(|column| {
+                        object
+                            .get(column)
+                            .ok_or_else(|| row_error(format!("no field named \"{column}\"")))
+                    })
+                    .collect::<Result<_, _>>()?,
+                (Json::Object(_), None) => {
+                    return Err(row_error(
+                        "rows can only be objects if :columns are given".into(),
+                    ))
+                }
+                _ => return Err(row_error("expected an array or an object".into())),
+            };
+            if values.len() != sorts.len() {
+                return Err(row_error(format!(
+                    "expected {} values, found {}",
+                    sorts.len(),
+                    values.len()
+                )));
+            }
+            let exprs = sorts
+                .iter()
+                .zip(values)
+                .map(|(sort, value)| json_expr(sort, value))
+                .collect::<Result<Vec<_>, _>>()
+                .map_err(row_error)?;
+            self.input_row(func_name, has_output, exprs)
+                .map_err(|e| row_error(e.to_string()))?;
+        }
+        log::info!("Read {} facts into {func_name} from '{file}'.", rows.len());
+        Ok(())
+    }
+
+    /// The sorts of the columns read into a function, and whether the last
+    /// is its output. Constructors and functions with a `Unit` output are
+    /// called on the inputs rather than set to an output.
+    fn input_columns(&self, func_name: Symbol) -> Result<(Vec<ArcSort>, bool), Error> {
+        let function_type = self
+            .type_info()
+            .lookup_user_func(func_name)
+            .ok_or(TypeError::UnboundFunction(func_name))?;
+        let has_output =
+            !function_type.is_datatype && function_type.output.name() != UNIT_SYM.into();
+        let mut sorts = function_type.input;
+        if has_output {
+            sorts.push(function_type.output);
+        }
+        Ok((sorts, has_output))
+    }
+
+    /// Typecheck and run the action adding a row read from a file.
+    fn input_row(
+        &mut self,
+        func_name: Symbol,
+        has_output: bool,
+        mut exprs: Vec<Expr>,
+    ) -> Result<(), Error> {
+        let action = if has_output {
+            let out = exprs.pop().unwrap();
+            Action::Set(DUMMY_SPAN.clone(), func_name, exprs, out)
+        } else {
+            Action::Expr(
+                DUMMY_SPAN.clone(),
+                Expr::Call(DUMMY_SPAN.clone(), func_name, exprs),
+            )
+        };
+        let commands = self
+            .type_info_mut()
+            .typecheck_program(&vec![NCommand::CoreAction(action)])?;
+        for command in commands {
+            self.run_command(command)?;
+        }
+        Ok(())
+    }
+}
+
+fn check_num_columns(
+    func_name: Symbol,
+    sorts: &[ArcSort],
+    columns: &[String],
+) -> Result<(), String> {
+    if columns.len() == sorts.len() {
+        Ok(())
+    } else {
+        Err(format!(
+            "{func_name} has {} columns, but {} were named",
+            sorts.len(),
+            columns.len()
+        ))
+    }
+}
+
+/// Split a line into its fields. Quoted fields may contain the delimiter
+/// and `""` for a quote, and the whitespace around other fields is removed.
+fn split_fields(line: &str, delimiter: char) -> Result<Vec<String>, String> {
+    let is_space = |c: &char| *c != delimiter && c.is_whitespace();
+    let mut fields = vec![];
+    let mut chars = line.chars().peekable();
+    loop {
+        let mut field = String::new();
+        while chars.next_if(is_space).is_some() {}
+        if chars.next_if_eq(&'"').is_some() {
+            loop {
+                match chars.next() {
+                    Some('"') if chars.next_if_eq(&'"').is_some() => field.push('"'),
+                    Some('"') => break,
+                    Some(c) => field.push(c),
+                    None => return Err("unterminated quoted field".into()),
+                }
+            }
+            while chars.next_if(is_space).is_some() {}
+            if matches!(chars.peek(), Some(&c) if c != delimiter) {
+                return Err("unexpected text after a quoted field".into());
+            }
+        } else {
+            while let Some(c) = chars.next_if(|c| *c != delimiter) {
+                field.push(c);
+            }
+            field.truncate(field.trim_end().len());
+        }
+        fields.push(field);
+        if chars.next().is_none() {
+            return Ok(fields);
+        }
+    }
+}
+
+/// The expression for a field of a column of the given sort. Fields of
+/// sorts without literals, such as datatypes and containers, are read as
+/// expressions.
+fn field_expr(sort: &ArcSort, field: &str) -> Result<Expr, String> {
+    let literal = match sort.name().as_str() {
+        "i64" => field.parse().ok().map(Literal::Int),
+        "f64" => field.parse().ok().map(|f| Literal::F64(OrderedFloat(f))),
+        "bool" => field.parse().ok().map(Literal::Bool),
+        "String" => Some(Literal::String(field.into())),
+        "Unit" => matches!(field, "" | "()").then_some(Literal::Unit),
+        "Rational" => return rational_expr(field).ok_or_else(|| cannot_read(sort, field)),
+        _ => return parse_expr(field).map_err(|_| cannot_read(sort, field)),
+    };
+    literal
+        .map(|literal| Expr::Lit(DUMMY_SPAN.clone(), literal))
+        .ok_or_else(|| cannot_read(sort, field))
+}
+
+/// The expression for a rational written as `n/d` or `n`.
+fn rational_expr(field: &str) -> Option<Expr> {
+    let (numer, denom) = field.split_once('/').unwrap_or((field, "1"));
+    let numer: i64 = numer.trim().parse().ok()?;
+    let denom: i64 = denom.trim().parse().ok()?;
+    (denom != 0).then(|| {
+        let args = vec![
+            Expr::Lit(DUMMY_SPAN.clone(), Literal::Int(numer)),
+            Expr::Lit(DUMMY_SPAN.clone(), Literal::Int(denom)),
+        ];
+        Expr::Call(DUMMY_SPAN.clone(), "rational".into(), args)
+    })
+}
+
+/// The expression for a JSON value in a column of the given sort. Strings
+/// in columns of sorts without literals are read as expressions.
+#[cfg(any(feature = "bin", feature = "serde-1"))]
+fn json_expr(sort: &ArcSort, json: &serde_json::Value) -> Result<Expr, String> {
+    use serde_json::Value as Json;
+
+    let error = || format!("cannot read {json} as {}", sort.name());
+    let literal = match (sort.name().as_str(), json) {
+        ("i64", Json::Number(n)) => n.as_i64().map(Literal::Int),
+        ("f64", Json::Number(n)) => n.as_f64().map(|f| Literal::F64(OrderedFloat(f))),
+        ("bool", Json::Bool(b)) => Some(Literal::Bool(*b)),
+        ("String", Json::String(s)) => Some(Literal::String(s.into())),
+        ("Unit", Json::Null) => Some(Literal::Unit),
+        ("Rational", Json::Number(n)) => return rational_expr(&n.to_string()).ok_or_else(error),
+        ("Rational", Json::String(s)) => return rational_expr(s).ok_or_else(error),
+        ("i64" | "f64" | "bool" | "String" | "Unit" | "Rational", _) => None,
+        (_, Json::String(s)) => return parse_expr(s).map_err(|_| error()),
+        (_, Json::Array(elements)) => {
+            let sort = sort.clone().as_arc_any();
+            let (element, empty, of) = if let Ok(vec) = Arc::downcast::<VecSort>(sort.clone()) {
+                (vec.element(), "vec-empty", "vec-of")
+            } else if let Ok(set) = Arc::downcast::<SetSort>(sort) {
+                (set.element(), "set-empty", "set-of")
+            } else {
+                return Err(error());
+            };
+            let elements = elements
+                .iter()
+                .map(|element_json| json_expr(&element, element_json))
+                .collect::<Result<Vec<_>, _>>()?;
+            return Ok(if elements.is_empty() {
+                Expr::call(empty, [])
+            } else {
+                Expr::call(of, elements)
+            });
+        }
+        _ => None,
+    };
+    literal
+        .map(|literal| Expr::Lit(DUMMY_SPAN.clone(), literal))
+        .ok_or_else(error)
+}
+
+fn cannot_read(sort: &ArcSort, field: &str) -> String {
+    format!("cannot read \"{field}\" as {}", sort.name())
+}
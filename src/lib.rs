@@ -15,10 +15,18 @@ mod actions;
 pub mod ast;
 pub mod constraint;
 mod core;
+mod dump;
+mod explain;
+mod export;
 mod extract;
+mod format;
 mod function;
 mod gj;
+mod input;
+mod proofs;
 mod serialize;
+#[cfg(feature = "serde-1")]
+mod snapshot;
 pub mod sort;
 mod termdag;
 mod typechecking;
@@ -28,10 +36,16 @@ mod value;
 
 use ast::desugar::Desugar;
 use ast::remove_globals::remove_globals;
-use extract::Extractor;
+pub use explain::{Explanation, Rewrite};
+use extract::{Choices, ExtractionState};
+pub use extract::{Cost, CostFunction, Extractor, TreeCost};
+pub use format::format_program;
+pub use gj::PartialMatch;
 use hashbrown::hash_map::Entry;
 use index::ColumnIndex;
 use instant::{Duration, Instant};
+use proofs::Justification;
+pub use proofs::Proof;
 pub use serialize::SerializeConfig;
 use sort::*;
 pub use termdag::{Term, TermDag, TermId};
@@ -45,15 +59,17 @@ pub use typechecking::{TypeInfo, UNIT_SYM};
 use crate::core::{AtomTerm, ResolvedCall};
 use actions::Program;
 use constraint::{Constraint, SimpleTypeConstraint, TypeConstraint};
+use std::cell::RefCell;
 use std::fmt::{Display, Formatter};
 use std::fs::File;
 use std::hash::Hash;
-use std::io::Read;
 use std::iter::once;
+use std::mem;
 use std::ops::{Deref, Range};
 use std::path::PathBuf;
 use std::rc::Rc;
 use std::str::FromStr;
+use std::sync::atomic::{AtomicBool, Ordering};
 use std::{fmt::Debug, sync::Arc};
 
 pub type ArcSort = Arc<dyn Sort>;
@@ -81,7 +97,7 @@ pub trait PrimitiveLike {
 /// This includes rough timing information and whether
 /// the database was updated.
 /// Calling `union` on two run reports adds the timing
-/// information together.
+/// information together, keeping the stop reason of the second.
 #[derive(Debug, Clone, Default)]
 pub struct RunReport {
     /// If any changes were made to the database, this is
@@ -94,6 +110,105 @@ pub struct RunReport {
     pub num_matches_per_rule: HashMap<Symbol, usize>,
     pub apply_time_per_ruleset: HashMap<Symbol, Duration>,
     pub rebuild_time_per_ruleset: HashMap<Symbol, Duration>,
+    /// Why the schedule stopped running.
+    pub stop_reason: StopReason,
+}
+
+/// The reason running a schedule stopped.
+#[derive(Debug, Clone, Copy, Default, PartialEq, Eq)]
+pub enum StopReason {
+    /// Running the rules made no more changes to the database.
+    #[default]
+    Saturated,
+    /// The schedule ran for all of its iterations.
+    IterationLimit,
+    /// The database grew past the `node_limit` option.
+    NodeLimit,
+    /// The schedule ran for longer than the `time_limit` option.
+    TimeLimit,
+    /// The database grew past the `memory_limit` option.
+    MemoryLimit,
+    /// The `:until` facts of a run held.
+    UntilSatisfied,
+    /// The run was cancelled through a [`CancellationToken`].
+    Cancelled,
+    /// Running the rules made no more changes to the database, but some
+    /// rules found more matches than the `match_limit` option allows and
+    /// were skipped.
+    MatchLimit,
+}
+
+impl StopReason {
+    /// Whether this is a limit that stops the whole schedule,
+    /// rather than just the innermost loop.
+    pub fn is_hard_limit(&self) -> bool {
+        matches!(
+            self,
+            StopReason::NodeLimit
+                | StopReason::TimeLimit
+                | StopReason::MemoryLimit
+                | StopReason::Cancelled
+        )
+    }
+}
+
+impl Display for StopReason {
+    fn fmt(&self, f: &mut Formatter<'_>) -> std::fmt::Result {
+        match self {
+            StopReason::Saturated => write!(f, "saturated"),
+            StopReason::IterationLimit => write!(f, "iteration limit reached"),
+            StopReason::NodeLimit => write!(f, "node limit reached"),
+            StopReason::TimeLimit => write!(f, "time limit reached"),
+            StopReason::MemoryLimit => write!(f, "memory limit reached"),
+            StopReason::UntilSatisfied => write!(f, "until facts satisfied"),
+            StopReason::Cancelled => write!(f, "cancelled"),
+            StopReason::MatchLimit => write!(f, "match limit reached"),
+        }
+    }
+}
+
+/// A handle for cancelling a running schedule from another thread.
+/// Obtain one with [`EGraph::cancellation_token`].
+///
+/// Cancellation is checked between iterations, between the search
+/// and apply phases of an iteration and between the rounds of a rebuild.
+/// A cancelled run finishes rebuilding once the token is cleared,
+/// so the e-graph is left in a consistent state, and the running program
+/// returns [`Error::Cancelled`].
+///
+/// The token is cleared when a program starts running and after a
+/// cancelled run has been reported, so a cancel issued between programs
+/// has no effect, while one issued during a program stops its next run.
+#[derive(Debug, Clone, Default)]
+pub struct CancellationToken(Arc<AtomicBool>);
+
+impl CancellationToken {
+    /// Request that the running schedule stops.
+    pub fn cancel(&self) {
+        self.0.store(true, Ordering::Relaxed);
+    }
+
+    pub fn is_cancelled(&self) -> bool {
+        self.0.load(Ordering::Relaxed)
+    }
+
+    /// Clear a pending cancellation.
+    pub fn reset(&self) {
+        self.0.store(false, Ordering::Relaxed);
+    }
+}
+
+/// Statistics about one iteration of running a ruleset,
+/// passed to the callback set with [`EGraph::set_progress_callback`].
+#[derive(Debug, Clone)]
+pub struct RunProgress {
+    /// The index of the iteration, counted over all runs so far.
+    pub iteration: usize,
+    pub ruleset: Symbol,
+    /// The number of tuples in the database after the iteration.
+    pub num_tuples: usize,
+    /// The number of unions performed by the iteration.
+    pub num_unions: usize,
 }
 
 impl RunReport {
@@ -231,6 +346,12 @@ pub enum ExtractReport {
         termdag: TermDag,
         terms: Vec<Term>,
     },
+    /// The cheapest distinct terms extracted by `(extract e :best k)`,
+    /// cheapest first, with their costs.
+    Ranked {
+        termdag: TermDag,
+        terms: Vec<(usize, Term)>,
+    },
 }
 
 impl RunReport {
@@ -285,6 +406,7 @@ impl RunReport {
                 &self.rebuild_time_per_ruleset,
                 &other.rebuild_time_per_ruleset,
             ),
+            stop_reason: other.stop_reason,
         }
     }
 }
@@ -423,12 +545,19 @@ impl FromStr for RunMode {
 
 #[derive(Clone)]
 pub struct EGraph {
-    egraphs: Vec<Self>,
+    /// The scopes opened by `push`, innermost last.
+    scopes: Vec<Scope>,
     unionfind: UnionFind,
     pub(crate) desugar: Desugar,
     pub functions: HashMap<Symbol, Function>,
     rulesets: HashMap<Symbol, Ruleset>,
+    /// The commands declaring sorts, functions, rulesets, and rules, in order.
+    declarations: Vec<ResolvedNCommand>,
     rule_last_run_timestamp: HashMap<Symbol, u32>,
+    /// Ban state of the rules run with a `backoff` schedule.
+    rule_backoff: HashMap<Symbol, RuleBackoff>,
+    /// The number of iterations run so far, used to time rule bans.
+    iteration: usize,
     proofs_enabled: bool,
     terms_enabled: bool,
     interactive_mode: bool,
@@ -437,28 +566,58 @@ pub struct EGraph {
     pub test_proofs: bool,
     pub match_limit: usize,
     pub node_limit: usize,
+    /// The wall-clock time each `run-schedule` may take.
+    pub time_limit: Option<Duration>,
+    /// An approximate limit on the memory used by the database, in bytes.
+    pub memory_limit: usize,
+    /// When the running schedule must stop, from `time_limit`.
+    run_deadline: Option<Instant>,
+    cancellation: CancellationToken,
+    progress_callback: Option<Rc<dyn Fn(&RunProgress)>>,
     pub fact_directory: Option<PathBuf>,
     pub seminaive: bool,
     type_info: TypeInfo,
+    /// The costs found by extracting with [`TreeCost`], kept between
+    /// extractions and updated with the rows added since.
+    extraction: RefCell<Rc<ExtractionState<TreeCost>>>,
+    /// The rows set by [`EGraph::set_preferred_choices`].
+    preferred_choices: Choices,
     extract_report: Option<ExtractReport>,
     /// The run report for the most recent run of a schedule.
     recent_run_report: Option<RunReport>,
     /// The run report unioned over all runs so far.
     overall_run_report: RunReport,
+    /// The term dag holding the terms of all recorded proofs.
+    proof_termdag: TermDag,
+    /// Proofs recorded by `check` and `extract` while proofs are enabled.
+    proofs: Vec<Proof>,
+    /// The number of recorded proofs that `check-proof` has already counted.
+    checked_proofs: usize,
+    /// The top-level actions run while proofs are enabled, which the
+    /// proofs of the unions they performed refer to.
+    proof_actions: Vec<crate::core::ResolvedCoreActions>,
     msgs: Vec<String>,
 }
 
 impl Default for EGraph {
     fn default() -> Self {
         let mut egraph = Self {
-            egraphs: vec![],
+            scopes: vec![],
             unionfind: Default::default(),
             functions: Default::default(),
             rulesets: Default::default(),
+            declarations: vec![],
             rule_last_run_timestamp: Default::default(),
+            rule_backoff: Default::default(),
+            iteration: 0,
             desugar: Desugar::default(),
             match_limit: usize::MAX,
             node_limit: usize::MAX,
+            time_limit: None,
+            memory_limit: usize::MAX,
+            run_deadline: None,
+            cancellation: Default::default(),
+            progress_callback: None,
             timestamp: 0,
             run_mode: RunMode::Normal,
             proofs_enabled: false,
@@ -467,9 +626,15 @@ impl Default for EGraph {
             test_proofs: false,
             fact_directory: None,
             seminaive: true,
+            extraction: Default::default(),
+            preferred_choices: Default::default(),
             extract_report: None,
             recent_run_report: None,
             overall_run_report: Default::default(),
+            proof_termdag: Default::default(),
+            proofs: vec![],
+            checked_proofs: 0,
+            proof_actions: vec![],
             msgs: Default::default(),
             type_info: Default::default(),
         };
@@ -480,6 +645,38 @@ impl Default for EGraph {
     }
 }
 
+/// What [`EGraph::pop`] needs to return to the state at the matching
+/// [`EGraph::push`].
+#[derive(Clone)]
+struct Scope {
+    /// Whether the scope was opened by [`EGraph::mark_undo_point`] rather
+    /// than [`EGraph::push`].
+    undo_point: bool,
+    /// A copy of the e-graph without its [`Database`].
+    egraph: Box<EGraph>,
+    functions: HashMap<Symbol, FunctionCheckpoint>,
+    unionfind: unionfind::Checkpoint,
+    num_proofs: usize,
+    num_proof_terms: usize,
+}
+
+/// The parts of an [`EGraph`] that `push` does not copy: the database, which
+/// grows with the data rather than the program and is rolled back by `pop`
+/// using undo logs, the scopes, reports, and messages, which `pop` keeps, and
+/// the cached extraction costs, which `pop` discards.
+struct Database {
+    functions: HashMap<Symbol, Function>,
+    unionfind: UnionFind,
+    proof_termdag: TermDag,
+    proofs: Vec<Proof>,
+    scopes: Vec<Scope>,
+    extraction: RefCell<Rc<ExtractionState<TreeCost>>>,
+    extract_report: Option<ExtractReport>,
+    recent_run_report: Option<RunReport>,
+    overall_run_report: RunReport,
+    msgs: Vec<String>,
+}
+
 #[derive(Debug, Error)]
 #[error("Not found: {0}")]
 pub struct NotFoundError(Expr);
@@ -491,6 +688,24 @@ pub struct NotFoundError(Expr);
 struct SearchResult {
     all_matches: Vec<Value>,
     did_match: bool,
+    /// Whether the search stopped early because the rule hit its match limit.
+    over_limit: bool,
+}
+
+/// How often a rule has been banned by the backoff scheduler,
+/// and the iteration its current ban ends.
+#[derive(Clone, Debug, Default)]
+struct RuleBackoff {
+    times_banned: u32,
+    banned_until: usize,
+}
+
+impl RuleBackoff {
+    /// Scale a base limit or ban length by the number of previous bans.
+    fn scale(&self, base: usize) -> usize {
+        let factor = 1usize.checked_shl(self.times_banned).unwrap_or(usize::MAX);
+        base.saturating_mul(factor)
+    }
 }
 
 impl EGraph {
@@ -506,35 +721,160 @@ impl EGraph {
         self.interactive_mode
     }
 
+    /// Open a new scope, which [`EGraph::pop`] discards.
+    /// This takes time proportional to the size of the program,
+    /// but not to the size of the database.
     pub fn push(&mut self) {
-        self.egraphs.push(self.clone());
+        self.open_scope(false);
+    }
+
+    /// Mark a point that [`EGraph::undo`] returns to, such as before each
+    /// command of a REPL. Undo points are scopes that `pop` passes through,
+    /// so that a program's own `push` and `pop` work around them.
+    pub fn mark_undo_point(&mut self) {
+        self.open_scope(true);
+    }
+
+    /// The number of undo points that [`EGraph::undo`] can return to.
+    pub fn num_undo_points(&self) -> usize {
+        self.scopes.iter().filter(|scope| scope.undo_point).count()
     }
 
-    /// Pop the current egraph off the stack, replacing
-    /// it with the previously pushed egraph.
-    /// It preserves the run report and messages from the popped
-    /// egraph.
+    /// Undo every change made since the last undo point, closing any scopes
+    /// pushed since.
+    pub fn undo(&mut self) -> Result<(), Error> {
+        if self.num_undo_points() == 0 {
+            return Err(Error::Undo);
+        }
+        while !self.close_scope() {}
+        Ok(())
+    }
+
+    /// Forget the oldest undo point, keeping the changes made since it, so
+    /// that the history kept for [`EGraph::undo`] can be bounded.
+    pub fn forget_oldest_undo_point(&mut self) {
+        let Some(i) = self.scopes.iter().position(|scope| scope.undo_point) else {
+            return;
+        };
+        let scope = self.scopes.remove(i);
+        for (name, checkpoint) in scope.functions {
+            self.functions.get_mut(&name).unwrap().forget(checkpoint);
+        }
+        let (before, after) = self.scopes.split_at_mut(i);
+        self.unionfind.forget(
+            before.last().map(|scope| &scope.unionfind),
+            scope.unionfind,
+            after.first_mut().map(|scope| &mut scope.unionfind),
+        );
+    }
+
+    fn open_scope(&mut self, undo_point: bool) {
+        let functions = self
+            .functions
+            .iter_mut()
+            .map(|(name, function)| (*name, function.checkpoint()))
+            .collect();
+        let unionfind = self.unionfind.checkpoint();
+        let num_proofs = self.proofs.len();
+        let num_proof_terms = self.proof_termdag.size();
+
+        let database = self.take_database();
+        let egraph = Box::new(self.clone());
+        self.restore_database(database);
+
+        self.scopes.push(Scope {
+            undo_point,
+            egraph,
+            functions,
+            unionfind,
+            num_proofs,
+            num_proof_terms,
+        });
+    }
+
+    fn take_database(&mut self) -> Database {
+        Database {
+            functions: mem::take(&mut self.functions),
+            unionfind: mem::take(&mut self.unionfind),
+            proof_termdag: mem::take(&mut self.proof_termdag),
+            proofs: mem::take(&mut self.proofs),
+            scopes: mem::take(&mut self.scopes),
+            extraction: mem::take(&mut self.extraction),
+            extract_report: self.extract_report.take(),
+            recent_run_report: self.recent_run_report.take(),
+            overall_run_report: mem::take(&mut self.overall_run_report),
+            msgs: mem::take(&mut self.msgs),
+        }
+    }
+
+    fn restore_database(&mut self, database: Database) {
+        self.functions = database.functions;
+        self.unionfind = database.unionfind;
+        self.proof_termdag = database.proof_termdag;
+        self.proofs = database.proofs;
+        self.scopes = database.scopes;
+        self.extraction = database.extraction;
+        self.extract_report = database.extract_report;
+        self.recent_run_report = database.recent_run_report;
+        self.overall_run_report = database.overall_run_report;
+        self.msgs = database.msgs;
+    }
+
+    /// A token that cancels schedules run by this e-graph,
+    /// see [`CancellationToken`].
+    pub fn cancellation_token(&self) -> CancellationToken {
+        self.cancellation.clone()
+    }
+
+    /// Call `callback` after every iteration of running a ruleset.
+    pub fn set_progress_callback(&mut self, callback: impl Fn(&RunProgress) + 'static) {
+        self.progress_callback = Some(Rc::new(callback));
+    }
+
+    pub fn clear_progress_callback(&mut self) {
+        self.progress_callback = None;
+    }
+
+    /// Close the innermost scope, undoing every change made since
+    /// the matching [`EGraph::push`], along with any undo points marked since.
+    /// This takes time proportional to the size of the program
+    /// and the number of changes to undo.
+    /// It preserves the run report, messages, and progress callback
+    /// from the popped scope.
     pub fn pop(&mut self) -> Result<(), Error> {
-        match self.egraphs.pop() {
-            Some(e) => {
-                // Copy the reports and messages from the popped egraph
-                let extract_report = self.extract_report.clone();
-                let recent_run_report = self.recent_run_report.clone();
-                let overall_run_report = self.overall_run_report.clone();
-                let messages = self.msgs.clone();
-
-                *self = e;
-                self.extract_report = extract_report.or(self.extract_report.clone());
-                // We union the run reports, meaning
-                // that statistics are shared across
-                // push/pop
-                self.recent_run_report = recent_run_report.or(self.recent_run_report.clone());
-                self.overall_run_report = overall_run_report;
-                self.msgs = messages;
-                Ok(())
-            }
-            None => Err(Error::Pop),
+        if self.scopes.iter().all(|scope| scope.undo_point) {
+            return Err(Error::Pop);
         }
+        while self.close_scope() {}
+        Ok(())
+    }
+
+    /// Close the innermost scope, which must exist, returning whether it was
+    /// an undo point.
+    fn close_scope(&mut self) -> bool {
+        let scope = self.scopes.pop().unwrap();
+
+        // functions declared in the scope have no checkpoint
+        self.functions
+            .retain(|name, _| scope.functions.contains_key(name));
+        for (name, checkpoint) in scope.functions {
+            self.functions.get_mut(&name).unwrap().rollback(checkpoint);
+        }
+        self.unionfind.rollback(scope.unionfind);
+        self.proofs.truncate(scope.num_proofs);
+        self.proof_termdag.truncate(scope.num_proof_terms);
+
+        // We keep the reports and messages, meaning
+        // that statistics are shared across
+        // push/pop
+        let database = self.take_database();
+        let progress_callback = self.progress_callback.take();
+        *self = *scope.egraph;
+        self.restore_database(database);
+        self.progress_callback = progress_callback;
+        // functions declared in the scope may be declared again differently
+        self.extraction = Default::default();
+        scope.undo_point
     }
 
     pub fn union(&mut self, id1: Id, id2: Id, sort: Symbol) -> Id {
@@ -646,21 +986,40 @@ impl EGraph {
     }
 
     pub fn rebuild(&mut self) -> Result<usize, Error> {
+        let (updates, _) = self.rebuild_until_cancelled(false)?;
+        Ok(updates)
+    }
+
+    /// Rebuild until the database is canonical or, if `cancellable`,
+    /// until the run is cancelled between two rounds.
+    /// A cancelled rebuild leaves the database non-canonical;
+    /// a later [`EGraph::rebuild`] finishes the job.
+    fn rebuild_until_cancelled(
+        &mut self,
+        cancellable: bool,
+    ) -> Result<(usize, Option<StopReason>), Error> {
         self.unionfind.clear_recent_ids();
 
         let mut updates = 0;
         loop {
             let new = self.rebuild_one()?;
             log::debug!("{new} rebuilds?");
-            self.unionfind.clear_recent_ids();
             updates += new;
+            if new > 0 && cancellable {
+                // stop before the ids this round made non-canonical are
+                // cleared, so the next rebuild still processes them
+                if let Some(reason) = self.cancelled() {
+                    return Ok((updates, Some(reason)));
+                }
+            }
+            self.unionfind.clear_recent_ids();
             if new == 0 {
                 break;
             }
         }
 
         self.debug_assert_invariants();
-        Ok(updates)
+        Ok((updates, None))
     }
 
     fn rebuild_one(&mut self) -> Result<usize, Error> {
@@ -816,15 +1175,7 @@ impl EGraph {
             Ok(())
         } else {
             // Print size of all functions
-            let mut lens = self
-                .functions
-                .iter()
-                .map(|(sym, f)| (*sym, f.nodes.len()))
-                .collect::<Vec<_>>();
-
-            // Function name's alphabetical order
-            lens.sort_by_key(|(name, _)| name.as_str());
-
+            let lens = self.table_sizes();
             for (sym, len) in &lens {
                 log::info!("Function {} has size {}", sym, len);
             }
@@ -840,94 +1191,195 @@ impl EGraph {
         }
     }
 
+    /// The number of rows in each function, in alphabetical order.
+    pub fn table_sizes(&self) -> Vec<(Symbol, usize)> {
+        let mut lens = self
+            .functions
+            .iter()
+            .map(|(sym, f)| (*sym, f.nodes.len()))
+            .collect::<Vec<_>>();
+        lens.sort_by_key(|(name, _)| name.as_str());
+        lens
+    }
+
     // returns whether the egraph was updated
-    fn run_schedule(&mut self, sched: &ResolvedSchedule) -> RunReport {
+    fn run_schedule(&mut self, sched: &ResolvedSchedule) -> Result<RunReport, Error> {
         match sched {
             ResolvedSchedule::Run(config) => self.run_rules(config),
             ResolvedSchedule::Repeat(limit, sched) => {
                 let mut report = RunReport::default();
                 for _i in 0..*limit {
-                    let rec = self.run_schedule(sched);
+                    let rec = self.run_schedule(sched)?;
                     report = report.union(&rec);
-                    if !rec.updated {
-                        break;
+                    if !rec.updated || rec.stop_reason.is_hard_limit() {
+                        return Ok(report);
                     }
                 }
-                report
+                report.stop_reason = StopReason::IterationLimit;
+                Ok(report)
             }
             ResolvedSchedule::Saturate(sched) => {
                 let mut report = RunReport::default();
                 loop {
-                    let rec = self.run_schedule(sched);
+                    let rec = self.run_schedule(sched)?;
                     report = report.union(&rec);
-                    if !rec.updated {
+                    if !rec.updated || rec.stop_reason.is_hard_limit() {
                         break;
                     }
                 }
-                report
+                Ok(report)
             }
             ResolvedSchedule::Sequence(scheds) => {
                 let mut report = RunReport::default();
                 for sched in scheds {
-                    report = report.union(&self.run_schedule(sched));
+                    report = report.union(&self.run_schedule(sched)?);
+                    if report.stop_reason.is_hard_limit() {
+                        break;
+                    }
                 }
-                report
+                Ok(report)
             }
         }
     }
 
+    fn cancelled(&self) -> Option<StopReason> {
+        if self.cancellation.is_cancelled() {
+            log::warn!("Run cancelled. Stopping!");
+            return Some(StopReason::Cancelled);
+        }
+        None
+    }
+
+    /// The limit the database or the running schedule has exceeded, if any.
+    fn exceeded_limit(&self) -> Option<StopReason> {
+        if self.num_tuples() > self.node_limit {
+            log::warn!("Node limit reached, {} nodes. Stopping!", self.num_tuples());
+            return Some(StopReason::NodeLimit);
+        }
+        if self
+            .run_deadline
+            .is_some_and(|deadline| Instant::now() > deadline)
+        {
+            log::warn!("Time limit reached. Stopping!");
+            return Some(StopReason::TimeLimit);
+        }
+        // computed last since it looks at every table
+        let memory = self.approx_memory_usage();
+        if memory > self.memory_limit {
+            log::warn!("Memory limit reached, about {memory} bytes. Stopping!");
+            return Some(StopReason::MemoryLimit);
+        }
+        None
+    }
+
+    /// A rough estimate of the memory used by the database, in bytes.
+    fn approx_memory_usage(&self) -> usize {
+        let tables: usize = self
+            .functions
+            .values()
+            .map(|f| f.nodes.approx_bytes())
+            .sum();
+        tables + self.unionfind.approx_bytes()
+    }
+
     /// Extract a value to a [`TermDag`] and [`Term`]
     /// in the [`TermDag`].
     /// See also extract_value_to_string for convenience.
-    pub fn extract_value(&self, value: Value) -> (TermDag, Term) {
+    pub fn extract_value(&self, value: Value) -> Result<(TermDag, Term), Error> {
         let mut termdag = TermDag::default();
         let sort = self.type_info().sorts.get(&value.tag).unwrap();
-        let term = self.extract(value, &mut termdag, sort).1;
-        (termdag, term)
+        let term = self.extract(value, &mut termdag, sort)?.1;
+        Ok((termdag, term))
     }
 
     /// Extract a value to a string for printing.
     /// See also extract_value for more control.
-    pub fn extract_value_to_string(&self, value: Value) -> String {
-        let (termdag, term) = self.extract_value(value);
-        termdag.to_string(&term)
+    pub fn extract_value_to_string(&self, value: Value) -> Result<String, Error> {
+        let (termdag, term) = self.extract_value(value)?;
+        Ok(termdag.to_string(&term))
     }
 
-    fn run_rules(&mut self, config: &ResolvedRunConfig) -> RunReport {
+    fn run_rules(&mut self, config: &ResolvedRunConfig) -> Result<RunReport, Error> {
         let mut report: RunReport = Default::default();
 
+        // a limit may have been exceeded by the previous run or by actions since
+        if let Some(reason) = self.cancelled().or_else(|| self.exceeded_limit()) {
+            report.stop_reason = reason;
+            return Ok(report);
+        }
+
         // first rebuild
         let rebuild_start = Instant::now();
-        let updates = self.rebuild_nofail();
+        let (updates, cancelled) = self.rebuild_until_cancelled(true)?;
         log::debug!("database size: {}", self.num_tuples());
         log::debug!("Made {updates} updates");
         // add to the rebuild time for this ruleset
         report.add_ruleset_rebuild_time(config.ruleset, rebuild_start.elapsed());
         self.timestamp += 1;
+        if let Some(reason) = cancelled.or_else(|| self.cancelled()) {
+            report.stop_reason = reason;
+            return Ok(report);
+        }
 
-        let GenericRunConfig { ruleset, until } = config;
+        let GenericRunConfig {
+            ruleset,
+            until,
+            backoff,
+        } = config;
 
         if let Some(facts) = until {
-            if self.check_facts(facts).is_ok() {
+            if self.facts_match(facts)? {
                 log::info!(
                     "Breaking early because of facts:\n {}!",
                     ListDisplay(facts, "\n")
                 );
-                return report;
+                report.stop_reason = StopReason::UntilSatisfied;
+                return Ok(report);
             }
         }
 
-        let subreport = self.step_rules(*ruleset);
+        let n_unions_before = self.unionfind.n_unions();
+        let subreport = self.step_rules(*ruleset, *backoff)?;
+        let skipped_rules = subreport.stop_reason == StopReason::MatchLimit;
         report = report.union(&subreport);
+        if let Some(callback) = &self.progress_callback {
+            callback(&RunProgress {
+                iteration: self.iteration,
+                ruleset: *ruleset,
+                num_tuples: self.num_tuples(),
+                num_unions: self.unionfind.n_unions() - n_unions_before,
+            });
+        }
+        self.iteration += 1;
+
+        // Like egg, don't let a backoff run saturate while rules are banned:
+        // lift the bans and report an update so the schedule keeps going.
+        if backoff.is_some() && !report.updated {
+            let iteration = self.iteration;
+            for (rule_name, ban) in self.rule_backoff.iter_mut() {
+                if ban.banned_until > iteration {
+                    log::debug!("Unbanning {rule_name} since no other rule made progress");
+                    ban.banned_until = iteration;
+                    report.updated = true;
+                }
+            }
+        }
 
         log::debug!("database size: {}", self.num_tuples());
         self.timestamp += 1;
 
-        if self.num_tuples() > self.node_limit {
-            log::warn!("Node limit reached, {} nodes. Stopping!", self.num_tuples());
-        }
+        report.stop_reason =
+            if let Some(reason) = self.cancelled().or_else(|| self.exceeded_limit()) {
+                reason
+            } else if report.updated {
+                StopReason::IterationLimit
+            } else if skipped_rules {
+                StopReason::MatchLimit
+            } else {
+                StopReason::Saturated
+            };
 
-        report
+        Ok(report)
     }
 
     /// Search all the rules in a ruleset.
@@ -935,6 +1387,7 @@ impl EGraph {
     fn search_rules(
         &self,
         ruleset: Symbol,
+        backoff: Option<Backoff>,
         run_report: &mut RunReport,
         search_results: &mut HashMap<Symbol, SearchResult>,
     ) {
@@ -945,11 +1398,33 @@ impl EGraph {
                 let search_start = Instant::now();
 
                 for (rule_name, rule) in copy_rules.iter() {
+                    let match_limit = match backoff {
+                        Some(backoff) => {
+                            let ban = self.rule_backoff.get(rule_name).cloned();
+                            let ban = ban.unwrap_or_default();
+                            if ban.banned_until > self.iteration {
+                                log::trace!(
+                                    "Skipping {rule_name}, banned until {}",
+                                    ban.banned_until
+                                );
+                                continue;
+                            }
+                            ban.scale(backoff.match_limit)
+                        }
+                        None => self.match_limit,
+                    };
                     let mut all_matches = vec![];
                     let rule_search_start = Instant::now();
                     let mut did_match = false;
+                    let mut num_matches = 0;
+                    let mut over_limit = false;
                     let timestamp = self.rule_last_run_timestamp.get(rule_name).unwrap_or(&0);
                     self.run_query(&rule.query, *timestamp, false, |values| {
+                        if num_matches >= match_limit {
+                            over_limit = true;
+                            return Err(());
+                        }
+                        num_matches += 1;
                         did_match = true;
                         assert_eq!(values.len(), rule.query.vars.len());
                         all_matches.extend_from_slice(values);
@@ -967,6 +1442,7 @@ impl EGraph {
                         SearchResult {
                             all_matches,
                             did_match,
+                            over_limit,
                         },
                     );
                 }
@@ -977,7 +1453,7 @@ impl EGraph {
             Ruleset::Combined(_name, sub_rulesets) => {
                 let start_time = Instant::now();
                 for sub_ruleset in sub_rulesets {
-                    self.search_rules(*sub_ruleset, run_report, search_results);
+                    self.search_rules(*sub_ruleset, backoff, run_report, search_results);
                 }
                 let search_time = start_time.elapsed();
                 run_report.add_ruleset_search_time(ruleset, search_time);
@@ -988,9 +1464,10 @@ impl EGraph {
     fn apply_rules(
         &mut self,
         ruleset: Symbol,
+        backoff: Option<Backoff>,
         run_report: &mut RunReport,
         search_results: &HashMap<Symbol, SearchResult>,
-    ) {
+    ) -> Result<(), Error> {
         // TODO this clone is not efficient
         let rules = self.rulesets.get(&ruleset).unwrap().clone();
         match rules {
@@ -998,13 +1475,39 @@ impl EGraph {
                 let apply_start = Instant::now();
                 let rule_names = compiled_rules.keys().cloned().collect::<Vec<_>>();
                 for rule_name in rule_names {
-                    let SearchResult {
+                    // banned rules are not searched
+                    let Some(SearchResult {
                         all_matches,
                         did_match,
-                    } = search_results.get(&rule_name).unwrap();
+                        over_limit,
+                    }) = search_results.get(&rule_name)
+                    else {
+                        continue;
+                    };
                     let rule = compiled_rules.get(&rule_name).unwrap();
                     let num_vars = rule.query.vars.len();
 
+                    // skip rules over their limit without advancing their timestamp,
+                    // so their matches are found again on a later run
+                    if *over_limit {
+                        if let Some(backoff) = backoff {
+                            let ban = self.rule_backoff.entry(rule_name).or_default();
+                            let ban_length = ban.scale(backoff.ban_length);
+                            ban.times_banned += 1;
+                            ban.banned_until = self.iteration + ban_length;
+                            log::info!(
+                                "Banning {} for {ban_length} iterations",
+                                RunReport::truncate_rule_name(rule_name)
+                            );
+                        } else {
+                            log::warn!(
+                                "Match limit reached for {}, skipping it",
+                                RunReport::truncate_rule_name(rule_name)
+                            );
+                        }
+                        continue;
+                    }
+
                     // make sure the query requires matches
                     if num_vars != 0 {
                         run_report.add_rule_num_matches(rule_name, all_matches.len() / num_vars);
@@ -1021,47 +1524,87 @@ impl EGraph {
                     if num_vars == 0 {
                         if *did_match {
                             stack.clear();
-                            self.run_actions(stack, &[], &rule.program, true)
-                                .unwrap_or_else(|e| {
-                                    panic!("error while running actions for {rule_name}: {e}")
+                            if self.proofs_enabled {
+                                self.unionfind.set_reason(Justification::Rule {
+                                    name: rule_name,
+                                    subst: vec![],
                                 });
+                            }
+                            self.run_rule_actions(rule_name, stack, &[], &rule.program)?;
                         }
                     } else {
                         for values in all_matches.chunks(num_vars) {
                             stack.clear();
-                            self.run_actions(stack, values, &rule.program, true)
-                                .unwrap_or_else(|e| {
-                                    panic!("error while running actions for {rule_name}: {e}")
+                            if self.proofs_enabled {
+                                self.unionfind.set_reason(Justification::Rule {
+                                    name: rule_name,
+                                    subst: rule
+                                        .query
+                                        .vars
+                                        .keys()
+                                        .copied()
+                                        .zip(values.iter().copied())
+                                        .collect(),
                                 });
+                            }
+                            self.run_rule_actions(rule_name, stack, values, &rule.program)?;
                         }
                     }
 
                     // add to the rule's apply time
                     run_report.add_rule_apply_time(rule_name, rule_apply_start.elapsed());
                 }
+                self.unionfind.set_reason(Justification::Action(None));
                 run_report.add_ruleset_apply_time(ruleset, apply_start.elapsed());
             }
             Ruleset::Combined(_name, sub_rulesets) => {
                 let start_time = Instant::now();
                 for sub_ruleset in sub_rulesets {
-                    self.apply_rules(sub_ruleset, run_report, search_results);
+                    self.apply_rules(sub_ruleset, backoff, run_report, search_results)?;
                 }
                 let apply_time = start_time.elapsed();
                 run_report.add_ruleset_apply_time(ruleset, apply_time);
             }
         }
+        Ok(())
     }
 
-    fn step_rules(&mut self, ruleset: Symbol) -> RunReport {
+    /// Run the actions of a rule on one of its matches. If they fail, the
+    /// e-graph is left with the actions of the matches before it.
+    fn run_rule_actions(
+        &mut self,
+        rule_name: Symbol,
+        stack: &mut Vec<Value>,
+        subst: &[Value],
+        program: &Program,
+    ) -> Result<(), Error> {
+        self.run_actions(stack, subst, program, true).map_err(|e| {
+            self.unionfind.set_reason(Justification::Action(None));
+            Error::RuleError(rule_name, Box::new(e))
+        })
+    }
+
+    fn step_rules(
+        &mut self,
+        ruleset: Symbol,
+        backoff: Option<Backoff>,
+    ) -> Result<RunReport, Error> {
         let n_unions_before = self.unionfind.n_unions();
         let mut run_report = Default::default();
         let mut search_results = HashMap::<Symbol, SearchResult>::default();
-        self.search_rules(ruleset, &mut run_report, &mut search_results);
-        self.apply_rules(ruleset, &mut run_report, &search_results);
+        self.search_rules(ruleset, backoff, &mut run_report, &mut search_results);
+        // drop the matches of a cancelled search, so no rule is half-applied
+        if !self.cancellation.is_cancelled() {
+            self.apply_rules(ruleset, backoff, &mut run_report, &search_results)?;
+        }
         run_report.updated |=
             self.did_change_tables() || n_unions_before != self.unionfind.n_unions();
+        // without a backoff, a rule over the match limit is skipped for good
+        if backoff.is_none() && search_results.values().any(|result| result.over_limit) {
+            run_report.stop_reason = StopReason::MatchLimit;
+        }
 
-        run_report
+        Ok(run_report)
     }
 
     fn did_change_tables(&self) -> bool {
@@ -1082,20 +1625,23 @@ impl EGraph {
     ) -> Result<Symbol, Error> {
         let name = Symbol::from(name);
         let core_rule = rule.to_canonicalized_core_rule(self.type_info())?;
-        let (query, actions) = (core_rule.body, core_rule.head);
-
-        let vars = query.get_vars();
-        let query = self.compile_gj_query(query, &vars);
+        let vars = core_rule.body.get_vars();
+        let query = self.compile_gj_query(core_rule.body.clone(), &vars);
 
         let program = self
-            .compile_actions(&vars, &actions)
+            .compile_actions(&vars, &core_rule.head)
             .map_err(Error::TypeErrors)?;
-        let compiled_rule = CompiledRule { query, program };
+        let compiled_rule = CompiledRule {
+            query,
+            program,
+            rule,
+            core: core_rule,
+        };
         if let Some(rules) = self.rulesets.get_mut(&ruleset) {
             match rules {
                 Ruleset::Rules(_, rules) => {
                     match rules.entry(name) {
-                        Entry::Occupied(_) => panic!("Rule '{name}' was already present"),
+                        Entry::Occupied(_) => return Err(Error::RuleAlreadyPresent(name)),
                         Entry::Vacant(e) => e.insert(compiled_rule),
                     };
                     Ok(name)
@@ -1125,14 +1671,30 @@ impl EGraph {
         let program = self
             .compile_actions(&Default::default(), &actions)
             .map_err(Error::TypeErrors)?;
+        if self.proofs_enabled {
+            let index = self.proof_actions.len();
+            self.proof_actions.push(actions);
+            self.unionfind
+                .set_reason(Justification::Action(Some(index)));
+        }
         let mut stack = vec![];
-        self.run_actions(&mut stack, &[], &program, true)?;
-        Ok(())
+        let result = self.run_actions(&mut stack, &[], &program, true);
+        self.unionfind.set_reason(Justification::Action(None));
+        result
+    }
+
+    /// Parse an expression and return its sort, without evaluating it.
+    pub fn typecheck_expr(&self, input: &str) -> Result<ArcSort, Error> {
+        let expr = parse_expr(input)?;
+        let expr = self
+            .type_info()
+            .typecheck_expr(&expr, &Default::default())?;
+        Ok(expr.output_type(self.type_info()))
     }
 
     pub fn eval_expr(&mut self, expr: &Expr) -> Result<(ArcSort, Value), Error> {
         let fresh_name = self.desugar.get_fresh();
-        let command = Command::Action(Action::Let((), fresh_name, expr.clone()));
+        let command = Command::Action(Action::Let(DUMMY_SPAN.clone(), fresh_name, expr.clone()));
         self.run_program(vec![command])?;
         // find the table with the same name as the fresh name
         let func = self.functions.get(&fresh_name).unwrap();
@@ -1162,57 +1724,95 @@ impl EGraph {
         Ok(stack.pop().unwrap())
     }
 
-    fn add_combined_ruleset(&mut self, name: Symbol, rulesets: Vec<Symbol>) {
+    /// Extract a term obeying the `:forbid` and `:require-root` options.
+    fn extract_constrained(
+        &self,
+        expr: &ResolvedExpr,
+        value: Value,
+        termdag: &mut TermDag,
+        sort: &ArcSort,
+        options: &ExtractOptions,
+    ) -> Result<(usize, Term), Error> {
+        if options.require_root.is_some() && !sort.is_eq_sort() {
+            return Err(Error::ExtractError(format!(
+                "{expr} has no root function, since {} is not an eq sort",
+                sort.name()
+            )));
+        }
+        self.extract_filtered(
+            value,
+            termdag,
+            sort,
+            |sym, _| !options.forbid.contains(&sym),
+            |sym, _| options.require_root.is_none() || options.require_root == Some(sym),
+        )
+        .ok_or_else(|| Error::ExtractError(format!("no term for {expr} satisfies the constraints")))
+    }
+
+    fn add_combined_ruleset(&mut self, name: Symbol, rulesets: Vec<Symbol>) -> Result<(), Error> {
         match self.rulesets.entry(name) {
-            Entry::Occupied(_) => panic!("Ruleset '{name}' was already present"),
+            Entry::Occupied(_) => return Err(Error::RulesetAlreadyPresent(name)),
             Entry::Vacant(e) => e.insert(Ruleset::Combined(name, rulesets)),
         };
+        Ok(())
     }
 
-    fn add_ruleset(&mut self, name: Symbol) {
+    fn add_ruleset(&mut self, name: Symbol) -> Result<(), Error> {
         match self.rulesets.entry(name) {
-            Entry::Occupied(_) => panic!("Ruleset '{name}' was already present"),
+            Entry::Occupied(_) => return Err(Error::RulesetAlreadyPresent(name)),
             Entry::Vacant(e) => e.insert(Ruleset::Rules(name, Default::default())),
         };
+        Ok(())
     }
 
-    fn set_option(&mut self, name: &str, value: ResolvedExpr) {
+    fn set_option(&mut self, name: &str, value: ResolvedExpr) -> Result<(), Error> {
+        let invalid = |expected: &str| {
+            Error::OptionError(format!("{name} must be {expected}, but got {value}"))
+        };
+        let count = |value: &ResolvedExpr| match value {
+            ResolvedExpr::Lit(_ann, Literal::Int(i)) => usize::try_from(*i).ok(),
+            _ => None,
+        };
         match name {
             "enable_proofs" => {
-                self.proofs_enabled = true;
+                self.enable_proofs();
             }
             "interactive_mode" => {
                 if let ResolvedExpr::Lit(_ann, Literal::Int(i)) = value {
                     self.interactive_mode = i != 0;
                 } else {
-                    panic!("interactive_mode must be an integer");
+                    return Err(invalid("an integer"));
                 }
             }
             "match_limit" => {
-                if let ResolvedExpr::Lit(_ann, Literal::Int(i)) = value {
-                    self.match_limit = i as usize;
-                } else {
-                    panic!("match_limit must be an integer");
-                }
+                self.match_limit = count(&value).ok_or_else(|| invalid("a natural number"))?;
             }
             "node_limit" => {
-                if let ResolvedExpr::Lit(_ann, Literal::Int(i)) = value {
-                    self.node_limit = i as usize;
-                } else {
-                    panic!("node_limit must be an integer");
-                }
+                self.node_limit = count(&value).ok_or_else(|| invalid("a natural number"))?;
             }
-            _ => panic!("Unknown option '{}'", name),
+            "time_limit" => {
+                let seconds = match &value {
+                    ResolvedExpr::Lit(_ann, Literal::Int(i)) => *i as f64,
+                    ResolvedExpr::Lit(_ann, Literal::F64(f)) => f.into_inner(),
+                    _ => f64::NAN,
+                };
+                let limit = Duration::try_from_secs_f64(seconds)
+                    .map_err(|_| invalid("a number of seconds"))?;
+                self.time_limit = Some(limit);
+            }
+            "memory_limit" => {
+                let megabytes =
+                    count(&value).ok_or_else(|| invalid("an integer number of megabytes"))?;
+                self.memory_limit = megabytes.saturating_mul(1 << 20);
+            }
+            _ => return Err(Error::OptionError(format!("Unknown option '{name}'"))),
         }
+        Ok(())
     }
 
-    fn check_facts(&mut self, facts: &[ResolvedFact]) -> Result<(), Error> {
-        let rule = ast::ResolvedRule {
-            head: ResolvedActions::default(),
-            body: facts.to_vec(),
-        };
-        let core_rule = rule.to_canonicalized_core_rule(self.type_info())?;
-        let query = core_rule.body;
+    /// Whether the conjunction of `facts` has at least one match.
+    fn facts_match(&mut self, facts: &[ResolvedFact]) -> Result<bool, Error> {
+        let query = self.facts_query(facts)?;
         let ordering = &query.get_vars();
         let query = self.compile_gj_query(query, ordering);
 
@@ -1222,14 +1822,33 @@ impl EGraph {
             matched = true;
             Err(())
         });
-        if !matched {
-            // TODO add useful info here
-            Err(Error::CheckError(
-                facts.iter().map(|f| f.clone().make_unresolved()).collect(),
-            ))
-        } else {
-            Ok(())
+        Ok(matched)
+    }
+
+    fn facts_query(
+        &mut self,
+        facts: &[ResolvedFact],
+    ) -> Result<core::Query<ResolvedCall, ResolvedVar>, Error> {
+        let rule = ast::ResolvedRule {
+            head: ResolvedActions::default(),
+            body: facts.to_vec(),
+        };
+        let core_rule = rule.to_canonicalized_core_rule(self.type_info())?;
+        Ok(core_rule.body)
+    }
+
+    fn check_facts(&mut self, facts: &[ResolvedFact]) -> Result<(), Error> {
+        if self.facts_match(facts)? {
+            return Ok(());
         }
+        let query = self.facts_query(facts)?;
+        let nearest = self
+            .partial_match(&query)
+            .expect("a query with no matches has a failing atom");
+        Err(Error::CheckError(
+            facts.iter().map(|f| f.clone().make_unresolved()).collect(),
+            nearest,
+        ))
     }
 
     fn run_command(&mut self, command: ResolvedNCommand) -> Result<(), Error> {
@@ -1244,10 +1863,20 @@ impl EGraph {
 
         self.debug_assert_invariants();
 
+        let declaration = matches!(
+            command,
+            ResolvedNCommand::Sort(..)
+                | ResolvedNCommand::Function(_)
+                | ResolvedNCommand::AddRuleset(_)
+                | ResolvedNCommand::UnstableCombinedRuleset(..)
+                | ResolvedNCommand::NormRule { .. }
+        )
+        .then(|| command.clone());
+
         match command {
             ResolvedNCommand::SetOption { name, value } => {
                 let str = format!("Set option {} to {}", name, value);
-                self.set_option(name.into(), value);
+                self.set_option(name.into(), value)?;
                 log::info!("{}", str)
             }
             // Sorts are already declared during typechecking
@@ -1259,11 +1888,11 @@ impl EGraph {
                 log::info!("Declared function {}.", fdecl.name)
             }
             ResolvedNCommand::AddRuleset(name) => {
-                self.add_ruleset(name);
+                self.add_ruleset(name)?;
                 log::info!("Declared ruleset {name}.");
             }
             ResolvedNCommand::UnstableCombinedRuleset(name, others) => {
-                self.add_combined_ruleset(name, others);
+                self.add_combined_ruleset(name, others)?;
                 log::info!("Declared ruleset {name}.");
             }
             ResolvedNCommand::NormRule {
@@ -1275,11 +1904,29 @@ impl EGraph {
                 log::info!("Declared rule {name}.")
             }
             ResolvedNCommand::RunSchedule(sched) => {
+                self.run_deadline = self.time_limit.map(|limit| Instant::now() + limit);
                 let report = self.run_schedule(&sched);
+                self.run_deadline = None;
+                let report = match report {
+                    Ok(report) => report,
+                    Err(error) => {
+                        // keep the e-graph usable after a failed rule; the
+                        // original error is the one worth reporting
+                        let _ = self.rebuild();
+                        return Err(error);
+                    }
+                };
                 log::info!("Ran schedule {}.", sched);
+                log::info!("Stopped because {}.", report.stop_reason);
                 log::info!("Report: {}", report);
+                let cancelled = report.stop_reason == StopReason::Cancelled;
                 self.overall_run_report = self.overall_run_report.union(&report);
                 self.recent_run_report = Some(report);
+                if cancelled {
+                    self.cancellation.reset();
+                    self.rebuild()?;
+                    return Err(Error::Cancelled);
+                }
             }
             ResolvedNCommand::PrintOverallStatistics => {
                 log::info!("Overall statistics:\n{}", self.overall_run_report);
@@ -1288,14 +1935,80 @@ impl EGraph {
             ResolvedNCommand::Check(facts) => {
                 self.check_facts(&facts)?;
                 log::info!("Checked fact {:?}.", facts);
+                if self.proofs_enabled {
+                    self.prove_facts(&facts)?;
+                }
+            }
+            ResolvedNCommand::CheckProof => {
+                let checked = self.check_new_proofs()?;
+                log::info!("Checked {checked} proofs.");
+            }
+            ResolvedNCommand::Explain(lhs, rhs) => {
+                let explanation = self.explain_resolved(&lhs, &rhs)?;
+                log::info!("Explained {lhs} = {rhs}.");
+                self.print_msg(explanation.to_string());
+            }
+            ResolvedNCommand::Extract { expr, options } => {
+                let value = self.eval_resolved_expr(&expr, true)?;
+                let sort = expr.output_type(self.type_info());
+                let constrained = !options.forbid.is_empty() || options.require_root.is_some();
+                if options.mode != ExtractMode::Tree && (options.best.is_some() || constrained) {
+                    return Err(Error::ExtractError(format!(
+                        ":best, :forbid, and :require-root cannot be used in {} mode",
+                        options.mode
+                    )));
+                }
+                if options.best.is_some() && constrained {
+                    return Err(Error::ExtractError(
+                        ":best cannot be combined with :forbid or :require-root".into(),
+                    ));
+                }
+                let mut termdag = TermDag::default();
+                if let Some(k) = options.best {
+                    let best = self.extract_k_best(value, &mut termdag, &sort, k)?;
+                    log::info!("extracted the best {k} terms:");
+                    let mut msg = String::from("(\n");
+                    for (cost, term) in &best {
+                        let extracted = termdag.to_string(term);
+                        log::info!("   cost {cost}: {extracted}");
+                        msg += &format!("   {extracted}\n");
+                    }
+                    msg += ")";
+                    self.print_msg(msg);
+                    self.extract_report = Some(ExtractReport::Ranked {
+                        termdag,
+                        terms: best,
+                    });
+                } else {
+                    let (cost, term) = if constrained {
+                        self.extract_constrained(&expr, value, &mut termdag, &sort, &options)?
+                    } else {
+                        self.extract_with_mode(value, &mut termdag, &sort, options.mode)?
+                    };
+                    let extracted = termdag.to_string(&term);
+                    log::info!("extracted with {} cost {cost}: {extracted}", options.mode);
+                    self.print_msg(extracted);
+                    self.extract_report = Some(ExtractReport::Best {
+                        termdag,
+                        cost,
+                        term,
+                    });
+                    if self.proofs_enabled {
+                        self.prove_extraction(&expr)?;
+                    }
+                }
             }
-            ResolvedNCommand::CheckProof => log::error!("TODO implement proofs"),
             ResolvedNCommand::CoreAction(action) => match &action {
-                ResolvedAction::Let((), name, contents) => {
+                ResolvedAction::Let(_, name, contents) => {
                     panic!("Globals should have been desugared away: {name} = {contents}")
                 }
                 _ => {
                     self.eval_actions(&ResolvedActions::new(vec![action.clone()]))?;
+                    if let ResolvedAction::Extract(_, expr, _) = &action {
+                        if self.proofs_enabled {
+                            self.prove_extraction(expr)?;
+                        }
+                    }
                 }
             },
             ResolvedNCommand::Push(n) => {
@@ -1322,8 +2035,27 @@ impl EGraph {
                     return Err(Error::ExpectFail);
                 }
             }
-            ResolvedNCommand::Input { name, file } => {
-                self.input_file(name, file)?;
+            ResolvedNCommand::Input {
+                name,
+                file,
+                options,
+            } => {
+                self.input_file(name, &file, &options)?;
+            }
+            #[cfg(any(feature = "bin", feature = "serde-1"))]
+            ResolvedNCommand::InputJson {
+                name,
+                file,
+                columns,
+            } => {
+                self.input_json_file(name, &file, columns.as_deref())?;
+            }
+            #[cfg(not(any(feature = "bin", feature = "serde-1")))]
+            ResolvedNCommand::InputJson { file, .. } => {
+                return Err(Error::JsonInputError(
+                    file.into(),
+                    "egglog was built without serde_json".into(),
+                ));
             }
             ResolvedNCommand::Output { file, exprs } => {
                 let mut filename = self.fact_directory.clone().unwrap_or_default();
@@ -1339,87 +2071,62 @@ impl EGraph {
                 for expr in exprs {
                     let value = self.eval_resolved_expr(&expr, true)?;
                     let expr_type = expr.output_type(self.type_info());
-                    let term = self.extract(value, &mut termdag, &expr_type).1;
+                    let term = self.extract(value, &mut termdag, &expr_type)?.1;
                     use std::io::Write;
                     writeln!(f, "{}", termdag.to_string(&term))
                         .map_err(|e| Error::IoError(filename.clone(), e))?;
                 }
 
-                log::info!("Output to '{filename:?}'.")
+                log::info!("Output to '{}'.", filename.display())
             }
-        };
-        Ok(())
-    }
-
-    fn input_file(&mut self, func_name: Symbol, file: String) -> Result<(), Error> {
-        let function_type = self
-            .type_info()
-            .lookup_user_func(func_name)
-            .unwrap_or_else(|| panic!("Unrecognized function name {}", func_name));
-        let func = self.functions.get_mut(&func_name).unwrap();
-
-        let mut filename = self.fact_directory.clone().unwrap_or_default();
-        filename.push(file.as_str());
-
-        // check that the function uses supported types
-
-        for t in &func.schema.input {
-            match t.name().as_str() {
-                "i64" | "String" => {}
-                s => panic!("Unsupported type {} for input", s),
+            #[cfg(feature = "serde-1")]
+            ResolvedNCommand::Save(file) => {
+                let mut filename = self.fact_directory.clone().unwrap_or_default();
+                filename.push(file.as_str());
+                self.save_snapshot(&filename)?;
+                log::info!("Saved snapshot to '{}'.", filename.display())
             }
-        }
-
-        if !function_type.is_datatype {
-            match func.schema.output.name().as_str() {
-                "i64" | "String" | "Unit" => {}
-                s => panic!("Unsupported type {} for input", s),
+            #[cfg(feature = "serde-1")]
+            ResolvedNCommand::Load(file) => {
+                let mut filename = self.fact_directory.clone().unwrap_or_default();
+                filename.push(file.as_str());
+                self.load_snapshot(&filename)?;
+                log::info!("Loaded snapshot from '{}'.", filename.display())
             }
-        }
-
-        log::info!("Opening file '{:?}'...", filename);
-        let mut f = File::open(filename).unwrap();
-        let mut contents = String::new();
-        f.read_to_string(&mut contents).unwrap();
-
-        let mut actions: Vec<Action> = vec![];
-        let mut str_buf: Vec<&str> = vec![];
-        for line in contents.lines() {
-            str_buf.clear();
-            str_buf.extend(line.split('\t').map(|s| s.trim()));
-            if str_buf.is_empty() {
-                continue;
+            ResolvedNCommand::ImportEgraph(file) => {
+                let mut filename = self.fact_directory.clone().unwrap_or_default();
+                filename.push(file.as_str());
+                let serialized = egraph_serialize::EGraph::from_json_file(&filename)
+                    .map_err(|e| Error::IoError(filename.clone(), e))?;
+                self.import_serialized(&serialized)?;
+                log::info!("Imported e-graph from '{filename:?}'.")
             }
-
-            let parse = |s: &str| -> Expr {
-                if let Ok(i) = s.parse() {
-                    Expr::Lit((), Literal::Int(i))
-                } else {
-                    Expr::Lit((), Literal::String(s.into()))
-                }
-            };
-
-            let mut exprs: Vec<Expr> = str_buf.iter().map(|&s| parse(s)).collect();
-
-            actions.push(
-                if function_type.is_datatype || function_type.output.name() == UNIT_SYM.into() {
-                    Action::Expr((), Expr::Call((), func_name, exprs))
-                } else {
-                    let out = exprs.pop().unwrap();
-                    Action::Set((), func_name, exprs, out)
-                },
-            );
-        }
-        let num_facts = actions.len();
-        let commands = actions
-            .into_iter()
-            .map(NCommand::CoreAction)
-            .collect::<Vec<_>>();
-        let commands: Vec<_> = self.type_info_mut().typecheck_program(&commands)?;
-        for command in commands {
-            self.run_command(command)?;
+            ResolvedNCommand::Dump(file) => {
+                let mut filename = self.fact_directory.clone().unwrap_or_default();
+                filename.push(file.as_str());
+                std::fs::write(&filename, self.to_program())
+                    .map_err(|e| Error::IoError(filename.clone(), e))?;
+                log::info!("Dumped the database to '{filename:?}'.")
+            }
+            ResolvedNCommand::ExportFunction {
+                name,
+                file,
+                options,
+            } => {
+                let mut filename = self.fact_directory.clone().unwrap_or_default();
+                filename.push(file.as_str());
+                self.export_function(name, &filename, &options)?;
+            }
+            #[cfg(not(feature = "serde-1"))]
+            ResolvedNCommand::Save(_) | ResolvedNCommand::Load(_) => {
+                return Err(Error::SnapshotError(
+                    "egglog was built without the serde-1 feature".into(),
+                ));
+            }
+        };
+        if let Some(declaration) = declaration {
+            self.declarations.push(declaration);
         }
-        log::info!("Read {num_facts} facts into {func_name} from '{file}'.");
         Ok(())
     }
 
@@ -1452,38 +2159,99 @@ impl EGraph {
     /// Run a program, represented as an AST.
     /// Return a list of messages.
     pub fn run_program(&mut self, program: Vec<Command>) -> Result<Vec<String>, Error> {
-        for command in program {
-            // Important to process each command individually
-            // because push and pop create new scopes
-            for processed in self.process_command(command)? {
-                if self.run_mode.show_egglog() {
-                    // In show_egglog mode, we still need to run scope-related commands (Push/Pop) to make
-                    // the program well-scoped.
-                    match &processed {
-                        ResolvedNCommand::Push(..) | ResolvedNCommand::Pop(..) => {
-                            self.run_command(processed.clone())?;
-                        }
-                        _ => {}
-                    };
-                    self.print_msg(processed.to_command().to_string());
-                    continue;
-                }
+        let program = program
+            .into_iter()
+            .map(|command| (DUMMY_SPAN.clone(), command))
+            .collect();
+        self.run_spanned_program(program)
+    }
 
-                self.run_command(processed)?;
-            }
+    /// Run a program with the span of each command, as parsed by
+    /// [`EGraph::parse_source`]. Errors are located at the command that
+    /// caused them, and `include`d files are read with their spans.
+    pub fn run_spanned_program(
+        &mut self,
+        program: Vec<(Span, Command)>,
+    ) -> Result<Vec<String>, Error> {
+        // a cancel that arrived while nothing was running is stale
+        self.cancellation.reset();
+        self.check_proofs_supported(&program)?;
+        for (span, command) in program {
+            self.run_spanned_command(command)
+                .map_err(|error| error.located(span))?;
         }
         log::logger().flush();
 
         Ok(self.flush_msgs())
     }
 
+    fn run_spanned_command(&mut self, command: Command) -> Result<(), Error> {
+        // A program's own function shadows the file command of the same name.
+        let command = match command.as_call() {
+            Some((head, call)) if self.type_info.lookup_user_func(head).is_some() => {
+                Command::Action(call)
+            }
+            _ => command,
+        };
+        if let Command::Include(file) = &command {
+            let contents =
+                std::fs::read_to_string(file).map_err(|e| Error::IoError(file.into(), e))?;
+            let program = self.desugar.parse_source(Some(file.clone()), &contents)?;
+            self.check_proofs_supported(&program)?;
+            for (span, command) in program {
+                self.run_spanned_command(command)
+                    .map_err(|error| error.located(span))?;
+            }
+            return Ok(());
+        }
+        // Important to process each command individually
+        // because push and pop create new scopes
+        for processed in self.process_command(command)? {
+            if self.run_mode.show_egglog() {
+                // In show_egglog mode, we still need to run scope-related commands (Push/Pop) to make
+                // the program well-scoped.
+                match &processed {
+                    ResolvedNCommand::Push(..) | ResolvedNCommand::Pop(..) => {
+                        self.run_command(processed.clone())?;
+                    }
+                    _ => {}
+                };
+                self.print_msg(processed.to_command().to_string());
+                continue;
+            }
+
+            self.run_command(processed)?;
+        }
+        Ok(())
+    }
+
     pub fn parse_program(&self, input: &str) -> Result<Vec<Command>, Error> {
         self.desugar.parse_program(input)
     }
 
+    /// Parse a program read from the named file, with the span of each
+    /// command.
+    pub fn parse_source(
+        &self,
+        filename: Option<String>,
+        input: &str,
+    ) -> Result<Vec<(Span, Command)>, Error> {
+        self.desugar.parse_source(filename, input)
+    }
+
     pub fn parse_and_run_program(&mut self, input: &str) -> Result<Vec<String>, Error> {
-        let parsed = self.desugar.parse_program(input)?;
-        self.run_program(parsed)
+        self.parse_and_run_source(None, input)
+    }
+
+    /// Parse and run a program read from the named file, so that errors
+    /// report where in the file they happened.
+    pub fn parse_and_run_source(
+        &mut self,
+        filename: Option<String>,
+        input: &str,
+    ) -> Result<Vec<String>, Error> {
+        let parsed = self.desugar.parse_source(filename, input)?;
+        self.run_spanned_program(parsed)
     }
 
     pub fn num_tuples(&self) -> usize {
@@ -1579,8 +2347,8 @@ pub enum Error {
     TypeError(#[from] TypeError),
     #[error("Errors:\n{}", ListDisplay(.0, "\n"))]
     TypeErrors(Vec<TypeError>),
-    #[error("Check failed: \n{}", ListDisplay(.0, "\n"))]
-    CheckError(Vec<Fact>),
+    #[error("Check failed: \n{}\nNearest match: {1}", ListDisplay(.0, "\n"))]
+    CheckError(Vec<Fact>, PartialMatch),
     #[error("No such ruleset: {0}")]
     NoSuchRuleset(Symbol),
     #[error("Attempted to add a rule to combined ruleset {0}. Combined rulesets may only depend on other rulesets.")]
@@ -1591,12 +2359,87 @@ pub enum Error {
     MergeError(Symbol, Value, Value),
     #[error("Tried to pop too much")]
     Pop,
+    #[error("Nothing to undo")]
+    Undo,
     #[error("Command should have failed.")]
     ExpectFail,
     #[error("IO error: {0}: {1}")]
     IoError(PathBuf, std::io::Error),
     #[error("Cannot subsume function with merge: {0}")]
     SubsumeMergeError(Symbol),
+    #[error("Proof error: {0}")]
+    ProofError(String),
+    #[error("Run cancelled")]
+    Cancelled,
+    #[error("Snapshot error: {0}")]
+    SnapshotError(String),
+    #[error("Extraction error: {0}")]
+    ExtractError(String),
+    #[error("Import error: {0}")]
+    ImportError(String),
+    #[error("Input error: {0}:{1}: {2}")]
+    InputError(PathBuf, usize, String),
+    #[error("Input error: {0}: {1}")]
+    JsonInputError(PathBuf, String),
+    #[error("Option error: {0}")]
+    OptionError(String),
+    #[error("Ruleset {0} was already present")]
+    RulesetAlreadyPresent(Symbol),
+    #[error("Rule {0} was already present")]
+    RuleAlreadyPresent(Symbol),
+    #[error("Error while running actions for {0}: {1}")]
+    RuleError(Symbol, Box<Error>),
+    #[error("{}", LocatedDisplay(.0, .1))]
+    Located(Span, Box<Error>),
+}
+
+impl Error {
+    /// Locate an error at a span of the program, unless it is already
+    /// located or the span was not parsed from a program.
+    pub fn located(self, span: Span) -> Self {
+        match self {
+            Error::Located(..) => self,
+            _ if span.is_dummy() => self,
+            error => Error::Located(span, Box::new(error)),
+        }
+    }
+
+    /// The error without its location.
+    pub fn inner(&self) -> &Error {
+        match self {
+            Error::Located(_, error) => error.inner(),
+            error => error,
+        }
+    }
+
+    /// Where in the program the error happened, if known.
+    pub fn span(&self) -> Option<&Span> {
+        match self {
+            Error::Located(span, _) => Some(span),
+            _ => None,
+        }
+    }
+}
+
+/// `file:line:col: error` followed by the line of the error. Parse errors
+/// already give their location, so their byte offsets are shown as
+/// `file:line:col` instead.
+struct LocatedDisplay<'a>(&'a Span, &'a Error);
+
+impl Display for LocatedDisplay<'_> {
+    fn fmt(&self, f: &mut std::fmt::Formatter<'_>) -> std::fmt::Result {
+        let LocatedDisplay(span, error) = self;
+        match error {
+            Error::ParseError(error) => {
+                let error = error
+                    .clone()
+                    .map_location(|offset| Span::new(span.0.clone(), offset, offset).to_string());
+                write!(f, "{error}")?;
+            }
+            error => write!(f, "{span}: {error}")?,
+        }
+        write!(f, "\n{}", span.snippet())
+    }
 }
 
 #[cfg(test)]
@@ -1606,7 +2449,7 @@ mod tests {
     use crate::{
         constraint::SimpleTypeConstraint,
         sort::{FromSort, I64Sort, IntoSort, Sort, VecSort},
-        EGraph, PrimitiveLike, Value,
+        EGraph, PrimitiveLike, StopReason, Symbol, Value,
     };
 
     struct InnerProduct {
@@ -1673,4 +2516,141 @@ mod tests {
             )
             .unwrap();
     }
+
+    #[test]
+    fn test_cancel_during_rebuild() {
+        let mut egraph = EGraph::default();
+        egraph
+            .parse_and_run_program(
+                "
+                (datatype Math (Var String) (G Math) (F Math))
+                (let x (G (F (G (F (Var \"a\"))))))
+                (let y (G (F (G (F (Var \"b\"))))))
+                (union (Var \"a\") (Var \"b\"))
+            ",
+            )
+            .unwrap();
+
+        // whichever of F and G is rebuilt first, congruence needs more than
+        // one round to reach x and y, and a cancelled rebuild stops after one
+        egraph.cancellation_token().cancel();
+        let (_, reason) = egraph.rebuild_until_cancelled(true).unwrap();
+        assert_eq!(reason, Some(StopReason::Cancelled));
+
+        egraph.cancellation_token().reset();
+        assert!(egraph.rebuild().unwrap() > 0);
+        egraph.parse_and_run_program("(check (= x y))").unwrap();
+    }
+
+    /// Everything about the database that `pop` must restore.
+    fn database_state(egraph: &EGraph) -> Vec<String> {
+        let mut state = vec![format!("{:?}", egraph.unionfind)];
+        let mut names: Vec<_> = egraph.functions.keys().copied().collect();
+        names.sort_by_key(|name| name.as_str());
+        for name in names {
+            let function = &egraph.functions[&name];
+            state.push(format!("{name}: {:?}", function.nodes));
+            for (inputs, output) in function.nodes.iter(true) {
+                assert_eq!(function.get(inputs), Some(output.value));
+                if function.schema.output.is_eq_sort() {
+                    state.push(egraph.extract_value_to_string(output.value).unwrap());
+                }
+            }
+        }
+        state
+    }
+
+    #[test]
+    fn test_pop_restores_database() {
+        let mut egraph = EGraph::default();
+        egraph
+            .parse_and_run_program(
+                "
+                (datatype Math (Num i64) (Add Math Math) (Mul Math Math))
+                (function best () i64 :merge (max old new))
+                (rewrite (Add a b) (Add b a))
+                (rewrite (Mul a (Num 1)) a)
+                (let x (Add (Num 1) (Num 2)))
+                (let y (Mul (Add (Num 3) (Num 4)) (Num 1)))
+                (set (best) 0)
+                (run 2)
+            ",
+            )
+            .unwrap();
+        let before = database_state(&egraph);
+
+        egraph.push();
+        egraph
+            .parse_and_run_program(
+                "
+                (union (Num 1) (Num 2))
+                (union (Num 3) (Num 4))
+                (delete (Mul (Add (Num 3) (Num 4)) (Num 1)))
+                (Mul x (Num 1))
+                (run 3)
+                (check (= (Mul x (Num 1)) x))
+            ",
+            )
+            .unwrap();
+        // rows added in the scope are compacted away once they are stale
+        for i in 1..100 {
+            egraph
+                .parse_and_run_program(&format!("(set (best) {i})"))
+                .unwrap();
+        }
+        assert!(egraph.functions[&Symbol::from("best")].nodes.num_offsets() < 10);
+        assert_ne!(database_state(&egraph), before);
+        egraph.pop().unwrap();
+
+        assert_eq!(database_state(&egraph), before);
+    }
+
+    #[test]
+    fn test_forget_undo_point() {
+        let mut egraph = EGraph::default();
+        egraph
+            .parse_and_run_program(
+                "
+                (datatype Math (Num i64) (Add Math Math))
+                (function best () i64 :merge (max old new))
+                (let x (Add (Num 1) (Num 2)))
+                (set (best) 0)
+            ",
+            )
+            .unwrap();
+        let before = database_state(&egraph);
+        egraph.push();
+        egraph.mark_undo_point();
+        egraph
+            .parse_and_run_program(
+                "
+                (union (Num 1) (Num 2))
+                (delete (Add (Num 1) (Num 1)))
+                (let y (Add x (Num 3)))
+                (set (best) 1)
+            ",
+            )
+            .unwrap();
+        egraph.mark_undo_point();
+        egraph.parse_and_run_program("(union y x)").unwrap();
+        for i in 2..100 {
+            egraph
+                .parse_and_run_program(&format!("(set (best) {i})"))
+                .unwrap();
+        }
+
+        egraph.forget_oldest_undo_point();
+        assert_eq!(egraph.num_undo_points(), 1);
+        egraph.undo().unwrap();
+        assert!(matches!(egraph.undo(), Err(crate::Error::Undo)));
+        // the changes made since the forgotten undo point are kept
+        egraph
+            .parse_and_run_program("(check (= (Num 1) (Num 2)) (= (best) 1))")
+            .unwrap();
+        assert!(egraph.parse_and_run_program("(check (= y x))").is_err());
+
+        // and popping the scope around them still undoes them
+        egraph.pop().unwrap();
+        assert_eq!(database_state(&egraph), before);
+    }
 }
@@ -1,6 +1,9 @@
 use clap::Parser;
-use egglog::{EGraph, Error, RunMode, SerializeConfig};
-use std::io::{self, BufRead, BufReader};
+use egglog::ast::{Command, Span};
+use egglog::{format_program, EGraph, Error, RunMode, SerializeConfig};
+use rustyline::error::ReadlineError;
+use rustyline::DefaultEditor;
+use std::io::{self, BufRead, IsTerminal};
 use std::path::PathBuf;
 
 #[derive(Debug, Parser)]
@@ -35,6 +38,10 @@ struct Args {
     to_svg: bool,
     #[clap(long)]
     serialize_split_primitive_outputs: bool,
+    /// Format the input files in place instead of running them,
+    /// or format stdin to stdout if there are no input files.
+    #[clap(long)]
+    fmt: bool,
 }
 
 #[allow(clippy::disallowed_macros)]
@@ -48,6 +55,11 @@ fn main() {
 
     let args = Args::parse();
 
+    if args.fmt {
+        format_inputs(&args.inputs);
+        return;
+    }
+
     let mk_egraph = || {
         let mut egraph = EGraph::default();
         egraph.set_reserved_symbol(args.reserved_symbol.clone().into());
@@ -66,33 +78,13 @@ fn main() {
     };
 
     if args.inputs.is_empty() {
-        let stdin = io::stdin();
         log::info!("Welcome to Egglog!");
-        let mut egraph = mk_egraph();
-
-        for line in BufReader::new(stdin).lines() {
-            match line {
-                Ok(line_str) => match egraph.parse_and_run_program(&line_str) {
-                    Ok(msgs) => {
-                        for msg in msgs {
-                            println!("{msg}");
-                        }
-                    }
-                    Err(err) => {
-                        log::error!("{err}");
-                    }
-                },
-                Err(err) => {
-                    log::error!("{err}");
-                    std::process::exit(1)
-                }
-            }
-            log::logger().flush();
-            if egraph.is_interactive_mode() {
-                println!("(done)");
-            }
+        let mut repl = Repl::new(mk_egraph());
+        if io::stdin().is_terminal() {
+            repl.run_terminal();
+        } else {
+            repl.run_lines(io::stdin().lock());
         }
-
         std::process::exit(1)
     }
 
@@ -102,55 +94,13 @@ fn main() {
             panic!("Failed to read file {arg}")
         });
         let mut egraph = mk_egraph();
-        let already_enables = program_read.starts_with("(set-option enable_proofs 1)");
-        let (program, program_offset) = if args.proofs && !already_enables {
-            let expr = "(set-option enable_proofs 1)\n";
-            (format!("{}{}", expr, program_read), expr.len())
-        } else {
-            (program_read, 0)
-        };
-
-        match egraph.parse_and_run_program(&program) {
+        match egraph.parse_and_run_source(Some(input.to_string_lossy().into()), &program_read) {
             Ok(msgs) => {
                 for msg in msgs {
                     println!("{msg}");
                 }
             }
             Err(err) => {
-                let err = match err {
-                    Error::ParseError(err) => err
-                        .map_location(|byte_offset| {
-                            let byte_offset = byte_offset - program_offset;
-                            let (line_num, sum_offset) = std::iter::once(0)
-                                .chain(program[program_offset..].split_inclusive('\n').scan(
-                                    0,
-                                    |sum_offset, l| {
-                                        *sum_offset += l.len();
-
-                                        if *sum_offset > byte_offset {
-                                            None
-                                        } else {
-                                            Some(*sum_offset)
-                                        }
-                                    },
-                                ))
-                                .enumerate()
-                                .last()
-                                // No panic because of the initial 0
-                                .unwrap();
-                            {
-                                format!(
-                                    "{}:{}:{}",
-                                    input.display(),
-                                    line_num + 1,
-                                    // TODO: Show utf8 aware character count
-                                    byte_offset - sum_offset + 1
-                                )
-                            }
-                        })
-                        .to_string(),
-                    err => err.to_string(),
-                };
                 log::error!("{err}");
                 std::process::exit(1)
             }
@@ -191,3 +141,388 @@ fn main() {
         }
     }
 }
+
+#[allow(clippy::disallowed_macros)]
+fn format_inputs(inputs: &[PathBuf]) {
+    if inputs.is_empty() {
+        let program = io::read_to_string(io::stdin()).unwrap();
+        match format_program(&program) {
+            Ok(formatted) => print!("{formatted}"),
+            Err(err) => {
+                log::error!("{err}");
+                std::process::exit(1)
+            }
+        }
+        return;
+    }
+    for input in inputs {
+        let program = std::fs::read_to_string(input).unwrap_or_else(|_| {
+            let arg = input.to_string_lossy();
+            panic!("Failed to read file {arg}")
+        });
+        match format_program(&program) {
+            Ok(formatted) => std::fs::write(input, formatted).unwrap(),
+            Err(err) => {
+                log::error!("{}: {err}", input.display());
+                std::process::exit(1)
+            }
+        }
+    }
+}
+
+const REPL_HELP: &str = "\
+Commands may span several lines, and run once their parentheses balance.
+:sizes         show the number of rows in each table (also :tables)
+:rules         show the rulesets and their rules
+:type <expr>   show the sort of an expression without evaluating it
+:undo          undo the last command
+:load <file>   run the commands in a file
+:help          show this message";
+
+/// The prompt for a new command, and for the next line of an unfinished one.
+const PROMPT: &str = "> ";
+const CONTINUATION_PROMPT: &str = "... ";
+
+/// The number of commands `:undo` can undo by default.
+const UNDO_LIMIT: usize = 100;
+
+/// The read-eval-print loop run when no input files are given. Each
+/// command runs after an undo point, so that it is rolled back when it
+/// fails and can be undone with `:undo`.
+struct Repl {
+    egraph: EGraph,
+    /// Lines read so far of a command whose parentheses do not balance yet.
+    buffer: String,
+    /// The number of commands that can be undone. Older undo points are
+    /// forgotten, since each keeps a copy of the program.
+    undo_limit: usize,
+}
+
+#[allow(clippy::disallowed_macros)]
+impl Repl {
+    fn new(egraph: EGraph) -> Self {
+        Repl {
+            egraph,
+            buffer: String::new(),
+            undo_limit: UNDO_LIMIT,
+        }
+    }
+
+    /// Read lines with editing and history from a terminal.
+    fn run_terminal(&mut self) {
+        let mut editor = match DefaultEditor::new() {
+            Ok(editor) => editor,
+            Err(err) => {
+                log::error!("{err}");
+                return self.run_lines(io::stdin().lock());
+            }
+        };
+        println!("Type :help for help.");
+        loop {
+            let prompt = if self.buffer.is_empty() {
+                PROMPT
+            } else {
+                CONTINUATION_PROMPT
+            };
+            match editor.readline(prompt) {
+                Ok(line) => {
+                    if !line.trim().is_empty() {
+                        let _ = editor.add_history_entry(line.as_str());
+                    }
+                    self.print_line(&line);
+                }
+                // Ctrl-C discards the command being typed
+                Err(ReadlineError::Interrupted) => self.buffer.clear(),
+                Err(ReadlineError::Eof) => return,
+                Err(err) => {
+                    log::error!("{err}");
+                    std::process::exit(1)
+                }
+            }
+        }
+    }
+
+    /// Read lines without a prompt, as when egglog is driven by another
+    /// tool. In interactive mode `(done)` is printed after each line, after
+    /// the continuation prompt if the line leaves a command unfinished.
+    fn run_lines(&mut self, input: impl BufRead) {
+        for line in input.lines() {
+            match line {
+                Ok(line) => self.print_line(&line),
+                Err(err) => {
+                    log::error!("{err}");
+                    std::process::exit(1)
+                }
+            }
+        }
+    }
+
+    fn print_line(&mut self, line: &str) {
+        for output in self.read_line(line) {
+            println!("{output}");
+        }
+    }
+
+    /// Add a line to the buffer, and run the buffer once its parentheses
+    /// balance, returning what the line shows. A line starting with `:`
+    /// before any command is a meta-command.
+    fn read_line(&mut self, line: &str) -> Vec<String> {
+        let mut output = vec![];
+        let result = match line.trim_start().strip_prefix(':') {
+            Some(meta) if self.buffer.is_empty() => self.run_meta(meta).map(|shown| {
+                if !shown.is_empty() {
+                    output.push(shown);
+                }
+            }),
+            _ => {
+                self.buffer.push_str(line);
+                self.buffer.push('\n');
+                if is_balanced(&self.buffer) {
+                    let input = std::mem::take(&mut self.buffer);
+                    self.run(&input, &mut output).map_err(|err| err.to_string())
+                } else {
+                    Ok(())
+                }
+            }
+        };
+        if let Err(err) = result {
+            log::error!("{err}");
+        }
+        log::logger().flush();
+        if self.egraph.is_interactive_mode() {
+            if !self.buffer.is_empty() {
+                output.push(CONTINUATION_PROMPT.trim_end().into());
+            }
+            output.push("(done)".into());
+        }
+        output
+    }
+
+    fn run(&mut self, input: &str, output: &mut Vec<String>) -> Result<(), Error> {
+        let commands = self.egraph.parse_source(None, input)?;
+        commands
+            .into_iter()
+            .try_for_each(|(span, command)| self.run_command(span, command, output))
+    }
+
+    /// Run a command after a new undo point, undoing it if the command fails.
+    /// `push` and `pop` scopes are kept by the e-graph, which `pop`s through
+    /// the undo points marked since the matching `push`.
+    fn run_command(
+        &mut self,
+        span: Span,
+        command: Command,
+        output: &mut Vec<String>,
+    ) -> Result<(), Error> {
+        self.egraph.mark_undo_point();
+        let undo_points = self.egraph.num_undo_points();
+        match self.egraph.run_spanned_program(vec![(span, command)]) {
+            Ok(messages) => {
+                output.extend(messages);
+                while self.egraph.num_undo_points() > self.undo_limit {
+                    self.egraph.forget_oldest_undo_point();
+                }
+                Ok(())
+            }
+            Err(err) => {
+                // unless a `pop` in the command already closed the undo point
+                if self.egraph.num_undo_points() == undo_points {
+                    self.egraph.undo()?;
+                }
+                Err(err)
+            }
+        }
+    }
+
+    /// Run a meta-command, returning what it shows.
+    fn run_meta(&mut self, input: &str) -> Result<String, String> {
+        let (name, arg) = match input.trim().split_once(char::is_whitespace) {
+            Some((name, arg)) => (name, arg.trim()),
+            None => (input.trim(), ""),
+        };
+        match name {
+            "sizes" | "tables" => {
+                let sizes: Vec<_> = self
+                    .egraph
+                    .table_sizes()
+                    .into_iter()
+                    .map(|(name, size)| format!("{name}: {size}"))
+                    .collect();
+                Ok(sizes.join("\n"))
+            }
+            "rules" => Ok(self.egraph.rules_program()),
+            "type" => match self.egraph.typecheck_expr(arg) {
+                Ok(sort) => Ok(sort.name().to_string()),
+                Err(err) => Err(err.to_string()),
+            },
+            "undo" => match self.egraph.undo() {
+                Ok(()) => Ok(String::new()),
+                Err(err) => Err(err.to_string()),
+            },
+            "load" => {
+                let mut output = vec![];
+                let include = format!("(include \"{}\")", arg.replace('"', "\"\""));
+                match self.run(&include, &mut output) {
+                    Ok(()) => Ok(output.join("\n")),
+                    Err(err) => Err(err.to_string()),
+                }
+            }
+            "help" => Ok(REPL_HELP.into()),
+            _ => Err(format!("Unknown command :{name}, see :help")),
+        }
+    }
+}
+
+/// Whether every open paren or bracket in the input has been closed,
+/// ignoring those in strings and comments. Extra closing parens count as
+/// balanced, so that the parser reports them.
+fn is_balanced(input: &str) -> bool {
+    let mut depth = 0i32;
+    let mut chars = input.chars();
+    while let Some(c) = chars.next() {
+        match c {
+            '(' | '[' => depth += 1,
+            ')' | ']' => depth -= 1,
+            ';' => {
+                chars.by_ref().find(|&c| c == '\n');
+            }
+            // skip strings, and wait for the rest of an unclosed one. A doubled
+            // quote escapes a quote, and reads here as two strings.
+            '"' if !chars.by_ref().any(|c| c == '"') => return false,
+            _ => {}
+        }
+    }
+    depth <= 0
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    #[test]
+    fn test_is_balanced() {
+        assert!(is_balanced(""));
+        assert!(is_balanced("(let x (Num 1))"));
+        assert!(!is_balanced("(let x\n  (Num 1)"));
+        assert!(is_balanced("(vec-of [1 2])"));
+        // extra closing parens are left for the parser to report
+        assert!(is_balanced("(let x 1))"));
+        // parens in strings and comments do not count
+        assert!(is_balanced("(input f \"(\")"));
+        assert!(is_balanced("(let x 1) ; ("));
+        assert!(!is_balanced("(let x ; )\n"));
+        // an unclosed string waits for the rest of it
+        assert!(!is_balanced("(input f \"a)"));
+    }
+
+    fn run_lines(repl: &mut Repl, lines: &str) {
+        for line in lines.lines() {
+            repl.read_line(line);
+        }
+    }
+
+    fn size(repl: &mut Repl, table: &str) -> usize {
+        let sizes = repl.run_meta("sizes").unwrap();
+        assert_eq!(sizes, repl.run_meta("tables").unwrap());
+        sizes
+            .lines()
+            .find_map(|line| line.strip_prefix(&format!("{table}: ")))
+            .map_or(0, |size| size.parse().unwrap())
+    }
+
+    #[test]
+    fn test_undo() {
+        let mut repl = Repl::new(EGraph::default());
+        run_lines(
+            &mut repl,
+            "(datatype Math (Num i64))
+             (let x (Num 1))
+             (let y
+               (Num 2))",
+        );
+        assert_eq!(size(&mut repl, "Num"), 2);
+        assert_eq!(repl.run_meta("undo"), Ok(String::new()));
+        assert_eq!(size(&mut repl, "Num"), 1);
+
+        // a failing command is rolled back, and is not undone by `:undo`
+        run_lines(&mut repl, "(let z (Num 3)) (fail (union (Num 7) (Num 8)))");
+        assert_eq!(size(&mut repl, "Num"), 2);
+        repl.run_meta("undo").unwrap();
+        assert_eq!(size(&mut repl, "Num"), 1);
+
+        // `pop` undoes the commands since the matching `push`
+        run_lines(&mut repl, "(push)\n(let z (Num 3))\n(let w (Num 4))");
+        assert_eq!(size(&mut repl, "Num"), 3);
+        run_lines(&mut repl, "(pop)");
+        assert_eq!(size(&mut repl, "Num"), 1);
+        assert!(repl.run_meta("undo").is_ok());
+        assert!(repl.run_meta("undo").is_ok());
+        assert!(repl.run_meta("undo").is_ok());
+        assert_eq!(repl.run_meta("undo"), Err("Nothing to undo".into()));
+    }
+
+    #[test]
+    fn test_undo_limit() {
+        let mut repl = Repl::new(EGraph::default());
+        repl.undo_limit = 2;
+        run_lines(
+            &mut repl,
+            "(datatype Math (Num i64))
+             (push)
+             (let x (Num 1))
+             (let y (Num 2))
+             (let z (Num 3))",
+        );
+        assert_eq!(repl.egraph.num_undo_points(), 2);
+        repl.run_meta("undo").unwrap();
+        repl.run_meta("undo").unwrap();
+        assert_eq!(size(&mut repl, "Num"), 1);
+        assert!(repl.run_meta("undo").is_err());
+
+        // forgotten undo points leave the program's own scopes alone
+        run_lines(&mut repl, "(pop)");
+        assert_eq!(size(&mut repl, "Num"), 0);
+        assert_eq!(repl.run_meta("type (Num 1)"), Ok("Math".into()));
+    }
+
+    #[test]
+    fn test_meta_commands() {
+        let mut repl = Repl::new(EGraph::default());
+        let path = std::env::temp_dir().join(format!("egglog-repl-{}.egg", std::process::id()));
+        std::fs::write(
+            &path,
+            "(datatype Math (Num i64))\n(push)\n(let x (Num 1))\n",
+        )
+        .unwrap();
+        let loaded = repl.run_meta(&format!("load {}", path.display()));
+        std::fs::remove_file(&path).unwrap();
+        assert_eq!(loaded, Ok(String::new()));
+        assert_eq!(size(&mut repl, "Num"), 1);
+
+        assert_eq!(repl.run_meta("type (Num 1)"), Ok("Math".into()));
+        assert_eq!(repl.run_meta(" type  x "), Ok("Math".into()));
+        assert!(repl.run_meta("type (Mul 1)").is_err());
+        assert!(repl.run_meta("frobnicate").is_err());
+
+        // the `push` in the file is matched by a `pop` at the prompt
+        run_lines(&mut repl, "(pop)");
+        assert_eq!(size(&mut repl, "Num"), 0);
+        assert_eq!(repl.run_meta("type (Num 1)"), Ok("Math".into()));
+        assert!(repl.run_meta("load /nonexistent/file.egg").is_err());
+    }
+
+    #[test]
+    fn test_interactive_output() {
+        let mut repl = Repl::new(EGraph::default());
+        assert_eq!(
+            repl.read_line("(set-option interactive_mode 1)"),
+            ["(done)"]
+        );
+        assert_eq!(repl.read_line("(datatype Math (Num i64))"), ["(done)"]);
+        // an unfinished command is acknowledged with the continuation prompt
+        assert_eq!(repl.read_line("(extract"), ["...", "(done)"]);
+        assert_eq!(repl.read_line("  (Num 1))"), ["(Num 1)", "(done)"]);
+        assert_eq!(repl.read_line(":type (Num 1)"), ["Math", "(done)"]);
+        assert_eq!(repl.read_line("(check (Num 2))"), ["(done)"]);
+    }
+}
@@ -0,0 +1,1609 @@
+//! Proof production and checking.
+//!
+//! When proofs are enabled (`(set-option enable_proofs 1)`), the union-find
+//! keeps a [`ProofForest`] alongside its parent pointers, in the style of the
+//! explanations in egg. Every id created for a new row remembers that row, and
+//! every union adds an edge to the forest labelled with the reason for it: the
+//! rule (and the substitution it matched) or the top-level action that
+//! performed it, or congruence when rebuilding merged two rows whose inputs
+//! became equal. A proof that two ids are equal follows the path between them
+//! in the forest, and congruence edges are justified recursively by proofs
+//! about the inputs of the two rows.
+//!
+//! A union is explained only with the unions performed before it: the terms
+//! a rule's actions built are connected to the ids the actions computed for
+//! them through rows whose ids and inputs were already equal to those terms,
+//! so explanations never depend on themselves.
+//!
+//! `check` and `extract` record a [`Proof`] for their ground facts and
+//! expressions, checked against the database as it is when they run, and
+//! `check-proof` reports how many were recorded since the last
+//! `check-proof`. A rule step is checked by matching the rule's query
+//! under the step's substitution, or by finding the union the rule performed
+//! with that substitution when its query held, and by building the two sides
+//! of the step from the rule's actions, so the database is never taken as
+//! evidence that the two sides are equal.
+use crate::core::{
+    GenericAtom, GenericAtomTerm, GenericCoreAction, ResolvedAtomTerm, ResolvedCoreActions,
+};
+use crate::*;
+use std::mem;
+use std::rc::Rc;
+
+/// The reason two ids were unioned.
+#[derive(Debug, Clone, PartialEq, Eq)]
+pub(crate) enum Justification {
+    /// A rule's actions performed the union. The substitution maps the
+    /// variables of the rule's query to the values it matched.
+    Rule {
+        name: Symbol,
+        subst: Vec<(Symbol, Value)>,
+    },
+    /// A top-level action performed the union. This is the index of the
+    /// action among those the e-graph ran while proofs were enabled, or
+    /// `None` for unions that no action performed, such as those made by
+    /// loading a snapshot.
+    Action(Option<usize>),
+    /// Rebuilding found two rows of the same function whose inputs are equal.
+    Congruence,
+}
+
+impl Default for Justification {
+    fn default() -> Self {
+        Justification::Action(None)
+    }
+}
+
+/// The proof forest kept by [`UnionFind`] when proofs are enabled.
+///
+/// The forest has the same connected components as the union-find, but its
+/// edges are exactly the unions that were performed, so the path between two
+/// ids is a sequence of justified unions.
+#[derive(Debug, Clone, Default)]
+pub(crate) struct ProofForest {
+    /// The parent of each id in the forest, with the edge to it. Roots are
+    /// their own parent and have no edge.
+    parents: Vec<(Id, Option<Edge>)>,
+    /// The number of edges added so far, which is the time of the next one.
+    n_edges: usize,
+    /// The row each id was created for, if it was created for a row.
+    nodes: Vec<Option<(Symbol, ValueVec)>>,
+    /// The id created for each row, keyed by the row's current inputs.
+    origins: HashMap<(Symbol, ValueVec), Id>,
+    /// The justification recorded for unions performed right now.
+    pub(crate) reason: Justification,
+    /// The number of open checkpoints. Changes are only logged when positive.
+    n_checkpoints: usize,
+    undo_log: Vec<Undo>,
+    /// The number of entries dropped from the front of `undo_log` when the
+    /// outermost checkpoint was forgotten, which the positions in
+    /// checkpoints count.
+    log_start: usize,
+}
+
+/// An edge of a [`ProofForest`]: a union, with the reason for it and the
+/// number of unions performed before it.
+#[derive(Debug, Clone)]
+struct Edge {
+    justification: Justification,
+    time: usize,
+}
+
+/// A change to a [`ProofForest`], logged with the previous state so that it
+/// can be undone.
+#[derive(Debug, Clone)]
+enum Undo {
+    Parent(Id, (Id, Option<Edge>)),
+    Node(Id, Option<(Symbol, ValueVec)>),
+    Origin((Symbol, ValueVec), Option<Id>),
+}
+
+/// The state of a [`ProofForest`] that [`ProofForest::rollback`] returns to.
+#[derive(Debug, Clone)]
+pub(crate) struct Checkpoint {
+    num_ids: usize,
+    n_edges: usize,
+    log_len: usize,
+    reason: Justification,
+}
+
+impl ProofForest {
+    pub(crate) fn new(num_ids: usize) -> Self {
+        let mut forest = ProofForest::default();
+        for i in 0..num_ids {
+            forest.add_id(Id::from(i));
+        }
+        forest
+    }
+
+    /// Start logging changes, so that the forest can be rolled back to its
+    /// current state.
+    pub(crate) fn checkpoint(&mut self) -> Checkpoint {
+        self.n_checkpoints += 1;
+        Checkpoint {
+            num_ids: self.parents.len(),
+            n_edges: self.n_edges,
+            log_len: self.log_start + self.undo_log.len(),
+            reason: self.reason.clone(),
+        }
+    }
+
+    /// Undo every change made since the given checkpoint was taken, which
+    /// must be the most recent open checkpoint.
+    pub(crate) fn rollback(&mut self, checkpoint: Checkpoint) {
+        for undo in self
+            .undo_log
+            .drain(checkpoint.log_len - self.log_start..)
+            .rev()
+        {
+            match undo {
+                Undo::Parent(id, parent) => self.parents[usize::from(id)] = parent,
+                Undo::Node(id, node) => self.nodes[usize::from(id)] = node,
+                Undo::Origin(key, Some(id)) => {
+                    self.origins.insert(key, id);
+                }
+                Undo::Origin(key, None) => {
+                    self.origins.remove(&key);
+                }
+            }
+        }
+        self.parents.truncate(checkpoint.num_ids);
+        self.nodes.truncate(checkpoint.num_ids);
+        self.n_edges = checkpoint.n_edges;
+        self.reason = checkpoint.reason;
+        self.n_checkpoints -= 1;
+    }
+
+    /// Forget an open checkpoint, keeping the changes made since it was
+    /// taken, given the checkpoints taken just before and after it. Rolling
+    /// back to the one before then undoes those changes as well.
+    pub(crate) fn forget(&mut self, prev: Option<&Checkpoint>, next: Option<&Checkpoint>) {
+        self.n_checkpoints -= 1;
+        if prev.is_none() {
+            // nothing rolls back to before the next checkpoint anymore
+            let end = next.map_or(self.log_start + self.undo_log.len(), |next| next.log_len);
+            self.undo_log.drain(..end - self.log_start);
+            self.log_start = end;
+        }
+    }
+
+    fn log(&mut self, undo: impl FnOnce() -> Undo) {
+        if self.n_checkpoints > 0 {
+            self.undo_log.push(undo());
+        }
+    }
+
+    fn set_parent(&mut self, id: Id, parent: (Id, Option<Edge>)) -> (Id, Option<Edge>) {
+        let old = mem::replace(&mut self.parents[usize::from(id)], parent);
+        self.log(|| Undo::Parent(id, old.clone()));
+        old
+    }
+
+    fn set_origin(&mut self, key: (Symbol, ValueVec), id: Option<Id>) {
+        let old = match id {
+            Some(id) => self.origins.insert(key.clone(), id),
+            None => self.origins.remove(&key),
+        };
+        self.log(|| Undo::Origin(key, old));
+    }
+
+    pub(crate) fn add_id(&mut self, id: Id) {
+        debug_assert_eq!(usize::from(id), self.parents.len());
+        self.parents.push((id, None));
+        self.nodes.push(None);
+    }
+
+    /// Remember that `id` was created for the row `func(inputs)`.
+    pub(crate) fn record_node(&mut self, id: Id, func: Symbol, inputs: &[Value]) {
+        let inputs: ValueVec = inputs.iter().copied().collect();
+        let old = self.nodes[usize::from(id)].replace((func, inputs.clone()));
+        self.log(|| Undo::Node(id, old));
+        self.set_origin((func, inputs), Some(id));
+    }
+
+    pub(crate) fn node(&self, id: Id) -> Option<&(Symbol, ValueVec)> {
+        self.nodes.get(usize::from(id))?.as_ref()
+    }
+
+    /// The id created for the row currently stored under `func(inputs)`.
+    pub(crate) fn origin(&self, func: Symbol, inputs: &[Value]) -> Option<Id> {
+        self.origins
+            .get(&(func, inputs.iter().copied().collect()))
+            .copied()
+    }
+
+    /// Track a row that rebuilding moved from `old` to `new`. If another row
+    /// already lived at `new`, the two were merged by congruence and the
+    /// existing row keeps its origin.
+    pub(crate) fn move_origin(&mut self, func: Symbol, old: &[Value], new: &[Value]) {
+        if old == new {
+            return;
+        }
+        let old = (func, old.iter().copied().collect());
+        if let Some(&id) = self.origins.get(&old) {
+            self.set_origin(old, None);
+            let new = (func, new.iter().copied().collect());
+            if !self.origins.contains_key(&new) {
+                self.set_origin(new, Some(id));
+            }
+        }
+    }
+
+    /// Add an edge between two ids in different trees of the forest.
+    pub(crate) fn add_edge(&mut self, a: Id, b: Id, justification: Justification) {
+        self.make_root(a);
+        let time = self.n_edges;
+        self.n_edges += 1;
+        self.set_parent(
+            a,
+            (
+                b,
+                Some(Edge {
+                    justification,
+                    time,
+                }),
+            ),
+        );
+    }
+
+    /// Reverse the edges on the path from `id` to its root, making `id` the
+    /// root of its tree.
+    fn make_root(&mut self, id: Id) {
+        let mut prev = (id, None);
+        let mut cur = id;
+        loop {
+            let (parent, edge) = self.set_parent(cur, prev);
+            if parent == cur {
+                break;
+            }
+            prev = (cur, edge);
+            cur = parent;
+        }
+    }
+
+    /// The edges on the path from `a` to `b`, oriented from `a` towards `b`.
+    /// Returns `None` if the two ids are not connected in the forest.
+    ///
+    /// Since the forest has a single path between two ids, the ids were
+    /// equal before some union exactly when every edge on the path is older.
+    fn path(&self, a: Id, b: Id) -> Option<Vec<(Id, Id, &Edge)>> {
+        let mut a_ancestors = vec![a];
+        let mut positions = HashMap::<Id, usize>::default();
+        positions.insert(a, 0);
+        let mut cur = a;
+        loop {
+            let (parent, _) = &self.parents[usize::from(cur)];
+            if *parent == cur {
+                break;
+            }
+            cur = *parent;
+            positions.insert(cur, a_ancestors.len());
+            a_ancestors.push(cur);
+        }
+
+        let mut b_side = vec![];
+        let mut cur = b;
+        while !positions.contains_key(&cur) {
+            let (parent, edge) = &self.parents[usize::from(cur)];
+            b_side.push((cur, *parent, edge.as_ref()?));
+            if *parent == cur {
+                return None;
+            }
+            cur = *parent;
+        }
+
+        let mut path = vec![];
+        for pair in a_ancestors[..=positions[&cur]].windows(2) {
+            let edge = self.parents[usize::from(pair[0])].1.as_ref()?;
+            path.push((pair[0], pair[1], edge));
+        }
+        for (child, parent, edge) in b_side.into_iter().rev() {
+            path.push((parent, child, edge));
+        }
+        Some(path)
+    }
+
+    /// The time from which the two ids have been equal: one past the newest
+    /// edge on the path between them. Returns `None` if they are not
+    /// connected in the forest.
+    fn joined_at(&self, mut a: Id, mut b: Id) -> Option<usize> {
+        let depth = |mut id: Id| {
+            let mut depth = 0;
+            while self.parents[usize::from(id)].0 != id {
+                id = self.parents[usize::from(id)].0;
+                depth += 1;
+            }
+            depth
+        };
+        let (mut depth_a, mut depth_b) = (depth(a), depth(b));
+        let mut joined = 0;
+        let mut up = |id: &mut Id| {
+            let (parent, edge) = &self.parents[usize::from(*id)];
+            joined = joined.max(edge.as_ref().map_or(0, |edge| edge.time + 1));
+            *id = *parent;
+        };
+        while depth_a > depth_b {
+            up(&mut a);
+            depth_a -= 1;
+        }
+        while depth_b > depth_a {
+            up(&mut b);
+            depth_b -= 1;
+        }
+        while a != b {
+            if self.parents[usize::from(a)].0 == a {
+                return None;
+            }
+            up(&mut a);
+            up(&mut b);
+        }
+        Some(joined)
+    }
+
+    /// The substitutions of the unions performed by `rule`.
+    fn firings(&self, rule: Symbol) -> impl Iterator<Item = &[(Symbol, Value)]> + '_ {
+        self.parents
+            .iter()
+            .filter_map(move |(_, edge)| match &edge.as_ref()?.justification {
+                Justification::Rule { name, subst } if *name == rule => Some(&subst[..]),
+                _ => None,
+            })
+    }
+
+    /// The ids created for rows, grouped by the function of the row and
+    /// the current canonical id of the e-class the id belongs to.
+    fn nodes_by_class(&self, find: impl Fn(Id) -> Id) -> HashMap<(Symbol, Id), Vec<Id>> {
+        let mut classes = HashMap::<(Symbol, Id), Vec<Id>>::default();
+        for (i, node) in self.nodes.iter().enumerate() {
+            if let Some((func, _)) = node {
+                let id = Id::from(i);
+                classes.entry((*func, find(id))).or_default().push(id);
+            }
+        }
+        classes
+    }
+}
+
+/// A proof that two terms are equal.
+///
+/// The terms of a proof live in a [`TermDag`]; see [`EGraph::get_proofs`].
+/// Subproofs are shared, since a proof that reuses the same equality many
+/// times can be exponentially larger written out as a tree.
+#[derive(Debug, Clone, PartialEq, Eq)]
+pub enum Proof {
+    /// A term is equal to itself.
+    Refl(Term),
+    /// A rule matched with the given substitution, and its actions built
+    /// the two terms and unioned them.
+    Rule {
+        rule: Symbol,
+        subst: Vec<(Symbol, Term)>,
+        lhs: Term,
+        rhs: Term,
+    },
+    /// A top-level action built the two terms and unioned them.
+    Action { lhs: Term, rhs: Term },
+    /// The two terms apply the same function to arguments that the child proofs
+    /// prove equal, in order.
+    Congruence {
+        lhs: Term,
+        rhs: Term,
+        children: Vec<Rc<Proof>>,
+    },
+    /// Looking up the left-hand side in its table gives the right-hand side.
+    Table { lhs: Term, rhs: Term },
+    /// Evaluating the primitive call on the left-hand side gives the right-hand side.
+    Compute { lhs: Term, rhs: Term },
+    /// Given proofs of `a = b`, `b = c`, ..., `y = z`, proves `a = z`.
+    Transitivity(Vec<Rc<Proof>>),
+    /// Given a proof of `a = b`, proves `b = a`.
+    Flip(Rc<Proof>),
+}
+
+impl Proof {
+    /// The left-hand side of the equation this proof proves.
+    pub fn lhs(&self) -> &Term {
+        match self {
+            Proof::Refl(term) => term,
+            Proof::Rule { lhs, .. }
+            | Proof::Action { lhs, .. }
+            | Proof::Congruence { lhs, .. }
+            | Proof::Table { lhs, .. }
+            | Proof::Compute { lhs, .. } => lhs,
+            Proof::Transitivity(proofs) => proofs[0].lhs(),
+            Proof::Flip(proof) => proof.rhs(),
+        }
+    }
+
+    /// The right-hand side of the equation this proof proves.
+    pub fn rhs(&self) -> &Term {
+        match self {
+            Proof::Refl(term) => term,
+            Proof::Rule { rhs, .. }
+            | Proof::Action { rhs, .. }
+            | Proof::Congruence { rhs, .. }
+            | Proof::Table { rhs, .. }
+            | Proof::Compute { rhs, .. } => rhs,
+            Proof::Transitivity(proofs) => proofs[proofs.len() - 1].rhs(),
+            Proof::Flip(proof) => proof.lhs(),
+        }
+    }
+
+    /// Chain proofs of `lhs = b`, `b = c`, ... into a single proof, dropping
+    /// reflexive steps.
+    fn chain(lhs: Term, proofs: Vec<Proof>) -> Proof {
+        let mut steps = vec![];
+        for proof in proofs {
+            match proof {
+                Proof::Refl(_) => {}
+                Proof::Transitivity(inner) => steps.extend(inner),
+                proof => steps.push(Rc::new(proof)),
+            }
+        }
+        match steps.len() {
+            0 => Proof::Refl(lhs),
+            1 => Self::unshare(steps.pop().unwrap()),
+            _ => Proof::Transitivity(steps),
+        }
+    }
+
+    fn flip(self) -> Proof {
+        match self {
+            Proof::Refl(term) => Proof::Refl(term),
+            Proof::Flip(proof) => Self::unshare(proof),
+            proof => Proof::Flip(Rc::new(proof)),
+        }
+    }
+
+    /// Take a shared proof, copying its outermost step if it is still shared.
+    fn unshare(proof: Rc<Proof>) -> Proof {
+        Rc::try_unwrap(proof).unwrap_or_else(|proof| (*proof).clone())
+    }
+
+    /// The subproofs this proof is built from.
+    fn subproofs(&self) -> &[Rc<Proof>] {
+        match self {
+            Proof::Congruence { children, .. } => children,
+            Proof::Transitivity(steps) => steps,
+            Proof::Flip(proof) => std::slice::from_ref(proof),
+            _ => &[],
+        }
+    }
+
+    /// Count how many times each shared subproof is used, visiting the
+    /// subproofs of each one only on its first use.
+    fn count_uses(&self, uses: &mut HashMap<*const Proof, usize>) {
+        for subproof in self.subproofs() {
+            let count = uses.entry(Rc::as_ptr(subproof)).or_default();
+            *count += 1;
+            if *count == 1 {
+                subproof.count_uses(uses);
+            }
+        }
+    }
+
+    /// Print the proof as an s-expression, with its terms taken from `termdag`.
+    ///
+    /// A subproof used more than once is printed once, as a binding of
+    /// `(Let ((p0 proof) (p1 proof) ...) proof)` around the whole proof,
+    /// and referred to by its name wherever it is used.
+    pub fn to_string(&self, termdag: &TermDag) -> String {
+        let mut uses = HashMap::default();
+        self.count_uses(&mut uses);
+        let mut printer = ProofPrinter {
+            termdag,
+            uses,
+            names: Default::default(),
+            bindings: vec![],
+        };
+        let proof = printer.print(self);
+        if printer.bindings.is_empty() {
+            proof
+        } else {
+            format!("(Let ({}) {proof})", printer.bindings.join(" "))
+        }
+    }
+}
+
+/// Prints a [`Proof`], naming the subproofs that are used more than once.
+struct ProofPrinter<'a> {
+    termdag: &'a TermDag,
+    uses: HashMap<*const Proof, usize>,
+    names: HashMap<*const Proof, String>,
+    bindings: Vec<String>,
+}
+
+impl<'a> ProofPrinter<'a> {
+    fn print_shared(&mut self, proof: &Rc<Proof>) -> String {
+        let ptr = Rc::as_ptr(proof);
+        if let Some(name) = self.names.get(&ptr) {
+            return name.clone();
+        }
+        let printed = self.print(proof);
+        if self.uses[&ptr] == 1 || matches!(**proof, Proof::Refl(_)) {
+            return printed;
+        }
+        let name = format!("p{}", self.bindings.len());
+        self.bindings.push(format!("({name} {printed})"));
+        self.names.insert(ptr, name.clone());
+        name
+    }
+
+    fn print(&mut self, proof: &Proof) -> String {
+        let termdag = self.termdag;
+        let term = |t: &Term| termdag.to_string(t);
+        let mut proofs = |ps: &[Rc<Proof>]| {
+            ps.iter()
+                .map(|p| format!(" {}", self.print_shared(p)))
+                .collect::<String>()
+        };
+        match proof {
+            Proof::Refl(t) => format!("(Refl {})", term(t)),
+            Proof::Rule {
+                rule,
+                subst,
+                lhs,
+                rhs,
+            } => {
+                let subst = subst
+                    .iter()
+                    .map(|(var, t)| format!("({var} {})", term(t)))
+                    .collect::<Vec<_>>()
+                    .join(" ");
+                format!(
+                    "(Rule {:?} ({subst}) {} {})",
+                    rule.as_str(),
+                    term(lhs),
+                    term(rhs)
+                )
+            }
+            Proof::Action { lhs, rhs } => format!("(Action {} {})", term(lhs), term(rhs)),
+            Proof::Congruence { lhs, rhs, children } => format!(
+                "(Congruence {} {}{})",
+                term(lhs),
+                term(rhs),
+                proofs(children)
+            ),
+            Proof::Table { lhs, rhs } => format!("(Table {} {})", term(lhs), term(rhs)),
+            Proof::Compute { lhs, rhs } => format!("(Compute {} {})", term(lhs), term(rhs)),
+            Proof::Transitivity(steps) => format!("(Transitivity{})", proofs(steps)),
+            Proof::Flip(proof) => format!("(Flip {})", self.print_shared(proof)),
+        }
+    }
+}
+
+fn proof_error(msg: impl Into<String>) -> Error {
+    Error::ProofError(msg.into())
+}
+
+/// What is known about connecting a term to an id.
+#[derive(Default)]
+struct Connection {
+    /// A proof, along with the time from which it holds.
+    found: Option<(Proof, usize)>,
+    /// The latest time at which no proof could be found.
+    missing: Option<usize>,
+}
+
+/// Builds proofs out of the proof forest of an [`EGraph`].
+struct ProofBuilder<'a> {
+    egraph: &'a EGraph,
+    forest: &'a ProofForest,
+    termdag: &'a mut TermDag,
+    terms: HashMap<Id, Term>,
+    explanations: HashMap<(Id, Id), Proof>,
+    /// Searches for proofs that a term equals an id.
+    connections: HashMap<(Term, Id), Connection>,
+    /// See [`ProofForest::nodes_by_class`], built on first use.
+    classes: Option<HashMap<(Symbol, Id), Vec<Id>>>,
+    /// See [`ProofBuilder::current_value`].
+    values: HashMap<Term, Option<Value>>,
+}
+
+impl<'a> ProofBuilder<'a> {
+    fn new(egraph: &'a EGraph, termdag: &'a mut TermDag) -> Result<Self, Error> {
+        let forest = egraph
+            .unionfind
+            .proofs()
+            .ok_or_else(|| proof_error("Proofs are not enabled"))?;
+        Ok(ProofBuilder {
+            egraph,
+            forest,
+            termdag,
+            terms: Default::default(),
+            explanations: Default::default(),
+            connections: Default::default(),
+            classes: None,
+            values: Default::default(),
+        })
+    }
+
+    fn sort(&self, value: &Value) -> Result<&'a ArcSort, Error> {
+        self.egraph
+            .get_sort_from_value(value)
+            .ok_or_else(|| proof_error(format!("Unknown sort {}", value.tag)))
+    }
+
+    /// The term for the row the id was created for.
+    fn term_of_id(&mut self, id: Id) -> Result<Term, Error> {
+        if let Some(term) = self.terms.get(&id) {
+            return Ok(term.clone());
+        }
+        let (func, inputs) = self
+            .forest
+            .node(id)
+            .ok_or_else(|| proof_error(format!("No term was recorded for id {id}")))?;
+        let children = inputs
+            .iter()
+            .map(|input| self.term_of_value(*input))
+            .collect::<Result<Vec<_>, _>>()?;
+        let term = self.termdag.app(*func, children);
+        self.terms.insert(id, term.clone());
+        Ok(term)
+    }
+
+    fn term_of_value(&mut self, value: Value) -> Result<Term, Error> {
+        let sort = self.sort(&value)?;
+        if sort.is_eq_sort() {
+            self.term_of_id(Id::from(value.bits as usize))
+        } else if sort.is_container_sort() {
+            Err(proof_error(format!(
+                "Proofs through container sort {} are not supported",
+                sort.name()
+            )))
+        } else {
+            let (_, expr) = sort.make_expr(self.egraph, value);
+            Ok(self.termdag.expr_to_term(&expr))
+        }
+    }
+
+    /// Prove that the terms for two ids are equal.
+    ///
+    /// Each union on the path between them is explained with the unions
+    /// performed before it, so explanations never depend on themselves.
+    fn explain(&mut self, a: Id, b: Id) -> Result<Proof, Error> {
+        if a == b {
+            return Ok(Proof::Refl(self.term_of_id(a)?));
+        }
+        if let Some(proof) = self.explanations.get(&(a, b)) {
+            return Ok(proof.clone());
+        }
+        let path = self.forest.path(a, b).ok_or_else(|| {
+            proof_error(format!("No unions were recorded between ids {a} and {b}"))
+        })?;
+        let steps = path
+            .into_iter()
+            .map(|(x, y, edge)| self.step(x, y, &edge.justification, edge.time))
+            .collect::<Result<Vec<_>, _>>()?;
+        let proof = Proof::chain(self.term_of_id(a)?, steps);
+        self.explanations.insert((a, b), proof.clone());
+        Ok(proof)
+    }
+
+    /// Prove a single edge of the forest, the union at `time`.
+    fn step(
+        &mut self,
+        x: Id,
+        y: Id,
+        justification: &Justification,
+        time: usize,
+    ) -> Result<Proof, Error> {
+        let lhs = self.term_of_id(x)?;
+        let rhs = self.term_of_id(y)?;
+        Ok(match justification {
+            Justification::Rule { name, subst } => {
+                let rule = self
+                    .egraph
+                    .compiled_rule(*name)
+                    .ok_or_else(|| proof_error(format!("Rule {name} no longer exists")))?;
+                let subst = subst
+                    .iter()
+                    .map(|(var, value)| Ok((*var, self.term_of_value(*value)?)))
+                    .collect::<Result<Vec<_>, Error>>()?;
+                let (to_lhs, built_lhs, built_rhs, from_rhs) =
+                    self.build_union(x, y, time, &rule.core.head, &subst)?;
+                let rule = Proof::Rule {
+                    rule: *name,
+                    subst,
+                    lhs: built_lhs,
+                    rhs: built_rhs,
+                };
+                Proof::chain(lhs, vec![to_lhs, rule, from_rhs])
+            }
+            Justification::Action(Some(index)) => {
+                let actions = &self.egraph.proof_actions[*index];
+                let (to_lhs, built_lhs, built_rhs, from_rhs) =
+                    self.build_union(x, y, time, actions, &[])?;
+                let action = Proof::Action {
+                    lhs: built_lhs,
+                    rhs: built_rhs,
+                };
+                Proof::chain(lhs, vec![to_lhs, action, from_rhs])
+            }
+            Justification::Action(None) => {
+                return Err(proof_error(format!(
+                    "No action recorded the union of {} and {}",
+                    self.termdag.to_string(&lhs),
+                    self.termdag.to_string(&rhs)
+                )))
+            }
+            Justification::Congruence => {
+                let (f, xs) = self.forest.node(x).unwrap();
+                let (g, ys) = self.forest.node(y).unwrap();
+                if f != g || xs.len() != ys.len() {
+                    return Err(proof_error(format!(
+                        "Congruence between ids {x} and {y} with different heads"
+                    )));
+                }
+                let children = xs
+                    .iter()
+                    .zip(ys.iter())
+                    .map(|(a, b)| Ok(Rc::new(self.explain_values(*a, *b)?)))
+                    .collect::<Result<Vec<_>, Error>>()?;
+                Proof::Congruence { lhs, rhs, children }
+            }
+        })
+    }
+
+    /// Find the union among `actions` that joined ids `x` and `y` at `time`,
+    /// and build its two sides with the substitution. Returns the two sides
+    /// along with proofs that connect them to the terms of `x` and `y`.
+    fn build_union(
+        &mut self,
+        x: Id,
+        y: Id,
+        time: usize,
+        actions: &ResolvedCoreActions,
+        subst: &[(Symbol, Term)],
+    ) -> Result<(Proof, Term, Term, Proof), Error> {
+        let unions = ActionUnions::new(actions);
+        let subst: HashMap<Symbol, Term> = subst.iter().cloned().collect();
+        let mut error = None;
+        for &(a, b) in &unions.pairs {
+            for (a, b) in [(a, b), (b, a)] {
+                let (Some(lhs), Some(rhs)) = (
+                    unions.build(self.termdag, &subst, a),
+                    unions.build(self.termdag, &subst, b),
+                ) else {
+                    continue;
+                };
+                let connected = self
+                    .connect(&lhs, x, time)
+                    .and_then(|to_lhs| Ok((to_lhs, self.connect(&rhs, y, time)?)));
+                match connected {
+                    Ok((to_lhs, from_rhs)) => return Ok((to_lhs.flip(), lhs, rhs, from_rhs)),
+                    Err(e) => error = Some(e),
+                }
+            }
+        }
+        Err(error.unwrap_or_else(|| proof_error(format!("No action unioned ids {x} and {y}"))))
+    }
+
+    /// Prove that a term built by an action is equal to the term of the id
+    /// the action computed for it, using only the unions performed before
+    /// the action's union at `time`.
+    fn connect(&mut self, term: &Term, id: Id, time: usize) -> Result<Proof, Error> {
+        match self.connection(term, id, time)? {
+            Some((proof, _)) => Ok(proof),
+            None => Err(proof_error(format!(
+                "{} was not equal to id {id} before the union being explained",
+                self.termdag.to_string(term)
+            ))),
+        }
+    }
+
+    /// Like [`ProofBuilder::connect`], also returning the time from which
+    /// the proof holds. Searches are shared between unions: a proof found
+    /// for an earlier union holds for later ones, and a term that could not
+    /// be connected at a later union cannot be connected at an earlier one.
+    fn connection(
+        &mut self,
+        term: &Term,
+        id: Id,
+        time: usize,
+    ) -> Result<Option<(Proof, usize)>, Error> {
+        if *term == self.term_of_id(id)? {
+            return Ok(Some((Proof::Refl(term.clone()), 0)));
+        }
+        let key = (term.clone(), id);
+        if let Some(connection) = self.connections.get(&key) {
+            match &connection.found {
+                Some((proof, joined)) if *joined <= time => {
+                    return Ok(Some((proof.clone(), *joined)))
+                }
+                _ if connection.missing.is_some_and(|missing| time <= missing) => return Ok(None),
+                _ => {}
+            }
+        }
+        let found = self.find_connection(term, id, time)?;
+        let connection = self.connections.entry(key).or_default();
+        match &found {
+            Some(found) => connection.found = Some(found.clone()),
+            None => connection.missing = connection.missing.max(Some(time)),
+        }
+        Ok(found)
+    }
+
+    /// Look for the row an action found for `term`: a row of the same
+    /// function whose id and inputs were already equal to `id` and to the
+    /// arguments of `term` at `time`.
+    fn find_connection(
+        &mut self,
+        term: &Term,
+        id: Id,
+        time: usize,
+    ) -> Result<Option<(Proof, usize)>, Error> {
+        let Term::App(head, args) = term else {
+            return Ok(None);
+        };
+        if let Some(value) = self.current_value(term) {
+            let find = |id| self.egraph.unionfind.find(id);
+            if find(Id::from(value.bits as usize)) != find(id) {
+                return Ok(None);
+            }
+        }
+        let args: Vec<Term> = args.iter().map(|arg| self.termdag.get(*arg)).collect();
+        let egraph = self.egraph;
+        let classes = self
+            .classes
+            .get_or_insert_with(|| self.forest.nodes_by_class(|id| egraph.unionfind.find(id)));
+        let candidates = classes
+            .get(&(*head, egraph.unionfind.find(id)))
+            .cloned()
+            .unwrap_or_default();
+        'candidates: for candidate in candidates {
+            let mut joined = match self.forest.joined_at(candidate, id) {
+                Some(joined) if joined <= time => joined,
+                _ => continue,
+            };
+            let (_, inputs) = self.forest.node(candidate).unwrap();
+            let mut children = vec![];
+            for (arg, input) in args.iter().zip(inputs.iter()) {
+                match self.connect_value(arg, *input, time) {
+                    Ok(Some((proof, child_joined))) => {
+                        joined = joined.max(child_joined);
+                        children.push(proof);
+                    }
+                    Ok(None) | Err(_) => continue 'candidates,
+                }
+            }
+            let congruence = self.congruence(term.clone(), *head, children);
+            let rest = self.explain(candidate, id)?;
+            let proof = Proof::chain(term.clone(), vec![congruence, rest]);
+            return Ok(Some((proof, joined)));
+        }
+        Ok(None)
+    }
+
+    /// The value a ground term evaluates to in the database, if every row it
+    /// needs is present. Equalities are never forgotten, so a term whose
+    /// value lies in another e-class than an id was never equal to it.
+    fn current_value(&mut self, term: &Term) -> Option<Value> {
+        if let Some(value) = self.values.get(term) {
+            return *value;
+        }
+        let value = match term {
+            Term::Lit(lit) => Some(self.egraph.eval_lit(lit)),
+            Term::Var(_) => None,
+            Term::App(head, children) => children
+                .iter()
+                .map(|child| self.current_value(&self.termdag.get(*child)))
+                .collect::<Option<Vec<_>>>()
+                .and_then(|values| match self.egraph.functions.get(head) {
+                    Some(func) => {
+                        let inputs: ValueVec =
+                            values.iter().map(|v| self.egraph.find(*v)).collect();
+                        func.get(&inputs)
+                    }
+                    None => {
+                        let plain = |v: &Value| {
+                            self.egraph
+                                .get_sort_from_value(v)
+                                .is_some_and(|sort| !sort.is_eq_sort() && !sort.is_container_sort())
+                        };
+                        values
+                            .iter()
+                            .all(plain)
+                            .then(|| self.egraph.apply_primitive(*head, &values))
+                            .flatten()
+                    }
+                }),
+        };
+        self.values.insert(term.clone(), value);
+        value
+    }
+
+    /// Like [`ProofBuilder::connection`], for a value of any sort.
+    fn connect_value(
+        &mut self,
+        term: &Term,
+        value: Value,
+        time: usize,
+    ) -> Result<Option<(Proof, usize)>, Error> {
+        if self.sort(&value)?.is_eq_sort() {
+            return self.connection(term, Id::from(value.bits as usize), time);
+        }
+        let (proof, computed) = self.prove_term(term)?;
+        Ok((computed == value).then_some((proof, 0)))
+    }
+
+    fn explain_values(&mut self, a: Value, b: Value) -> Result<Proof, Error> {
+        if self.sort(&a)?.is_eq_sort() {
+            self.explain(Id::from(a.bits as usize), Id::from(b.bits as usize))
+        } else if a == b {
+            Ok(Proof::Refl(self.term_of_value(a)?))
+        } else {
+            Err(proof_error(format!("Cannot prove {a:?} equal to {b:?}")))
+        }
+    }
+
+    fn congruence(&mut self, lhs: Term, head: Symbol, children: Vec<Proof>) -> Proof {
+        if children.iter().all(|p| matches!(p, Proof::Refl(_))) {
+            return Proof::Refl(lhs);
+        }
+        let rhs = self
+            .termdag
+            .app(head, children.iter().map(|p| p.rhs().clone()).collect());
+        let children = children.into_iter().map(Rc::new).collect();
+        Proof::Congruence { lhs, rhs, children }
+    }
+
+    /// Prove that a ground term is equal to the term of the id it evaluates to.
+    ///
+    /// Returns the proof along with that id, or the term's value if it is
+    /// primitive.
+    fn prove_term(&mut self, term: &Term) -> Result<(Proof, Value), Error> {
+        match term {
+            Term::Lit(lit) => Ok((Proof::Refl(term.clone()), self.egraph.eval_lit(lit))),
+            Term::Var(v) => Err(proof_error(format!(
+                "Cannot prove a term with variable {v}"
+            ))),
+            Term::App(head, children) => {
+                let mut proofs = vec![];
+                let mut values = vec![];
+                for child in children {
+                    let (proof, value) = self.prove_term(&self.termdag.get(*child))?;
+                    proofs.push(proof);
+                    values.push(value);
+                }
+                if let Some(func) = self.egraph.functions.get(head) {
+                    let inputs: ValueVec = values.iter().map(|v| self.egraph.find(*v)).collect();
+                    let output = func.get(&inputs).ok_or_else(|| {
+                        proof_error(format!(
+                            "{} is not in the database",
+                            self.termdag.to_string(term)
+                        ))
+                    })?;
+                    if !func.schema.output.is_eq_sort() {
+                        let congruence = self.congruence(term.clone(), *head, proofs);
+                        let rhs = self.term_of_value(output)?;
+                        let lookup = Proof::Table {
+                            lhs: congruence.rhs().clone(),
+                            rhs,
+                        };
+                        return Ok((Proof::chain(term.clone(), vec![congruence, lookup]), output));
+                    }
+                    let origin = self.forest.origin(*head, &inputs).ok_or_else(|| {
+                        proof_error(format!(
+                            "No origin was recorded for {}",
+                            self.termdag.to_string(term)
+                        ))
+                    })?;
+                    let (_, origin_inputs) = self.forest.node(origin).unwrap();
+                    let mut children = vec![];
+                    for ((proof, value), input) in proofs.into_iter().zip(values).zip(origin_inputs)
+                    {
+                        let lhs = proof.lhs().clone();
+                        let rest = self.explain_values(value, *input)?;
+                        children.push(Proof::chain(lhs, vec![proof, rest]));
+                    }
+                    let proof = self.congruence(term.clone(), *head, children);
+                    Ok((proof, Value::from_id(output.tag, origin)))
+                } else {
+                    // primitives are only evaluated over plain values
+                    let unsupported = |v: &&Value| match self.egraph.get_sort_from_value(v) {
+                        Some(sort) => sort.is_eq_sort() || sort.is_container_sort(),
+                        None => true,
+                    };
+                    if let Some(value) = values.iter().find(unsupported) {
+                        return Err(proof_error(format!(
+                            "Cannot prove primitive call {} over {value:?}",
+                            self.termdag.to_string(term)
+                        )));
+                    }
+                    let output = self.egraph.apply_primitive(*head, &values).ok_or_else(|| {
+                        proof_error(format!(
+                            "Failed to evaluate {}",
+                            self.termdag.to_string(term)
+                        ))
+                    })?;
+                    let congruence = self.congruence(term.clone(), *head, proofs);
+                    let rhs = self.term_of_value(output)?;
+                    let compute = Proof::Compute {
+                        lhs: congruence.rhs().clone(),
+                        rhs,
+                    };
+                    Ok((
+                        Proof::chain(term.clone(), vec![congruence, compute]),
+                        output,
+                    ))
+                }
+            }
+        }
+    }
+
+    /// Prove that two ground terms are equal.
+    fn prove_equal(&mut self, lhs: &Term, rhs: &Term) -> Result<Proof, Error> {
+        let (lhs_proof, lhs_value) = self.prove_term(lhs)?;
+        let (rhs_proof, rhs_value) = self.prove_term(rhs)?;
+        let middle = self.explain_values(lhs_value, rhs_value)?;
+        Ok(Proof::chain(
+            lhs.clone(),
+            vec![lhs_proof, middle, rhs_proof.flip()],
+        ))
+    }
+}
+
+impl EGraph {
+    /// All proofs recorded so far by `check` and `extract`, along with the
+    /// [`TermDag`] their terms live in.
+    pub fn get_proofs(&self) -> (&TermDag, &[Proof]) {
+        (&self.proof_termdag, &self.proofs)
+    }
+
+    pub(crate) fn enable_proofs(&mut self) {
+        self.proofs_enabled = true;
+        self.unionfind.enable_proofs();
+    }
+
+    /// Fail before running a program that uses proofs and container sorts
+    /// together, whichever comes first, since proofs cannot go through
+    /// container values.
+    pub(crate) fn check_proofs_supported(&self, program: &[(Span, Command)]) -> Result<(), Error> {
+        let enables_proofs = |command: &Command| matches!(command, Command::SetOption { name, .. } if name.as_str() == "enable_proofs");
+        if !self.proofs_enabled && !program.iter().any(|(_, command)| enables_proofs(command)) {
+            return Ok(());
+        }
+        let declared = self
+            .type_info()
+            .sorts
+            .values()
+            .find(|sort| sort.is_container_sort())
+            .map(|sort| sort.name());
+        let container = declared.or_else(|| {
+            program.iter().find_map(|(_, command)| match command {
+                Command::Sort(name, Some(_)) => Some(*name),
+                _ => None,
+            })
+        });
+        match container {
+            Some(name) => Err(proof_error(format!(
+                "Proofs are not supported with container sort {name}"
+            ))),
+            None => Ok(()),
+        }
+    }
+
+    /// Check a proof against the database it was built from, and record it.
+    /// Later commands may delete or merge the rows the proof looks up, so
+    /// it is not checked again by `check-proof`.
+    fn record_proof(&mut self, proof: Proof, what: impl Display) -> Result<(), Error> {
+        self.check_proof(&self.proof_termdag, &proof)?;
+        let msg = proof.to_string(&self.proof_termdag);
+        log::info!("Proof of {what}: {msg}");
+        self.print_msg(msg);
+        self.proofs.push(proof);
+        Ok(())
+    }
+
+    /// Record proofs for the ground facts of a successful `check`.
+    ///
+    /// Facts with variables have no single proof, and neither do facts that
+    /// apply a primitive such as `!=` to e-classes, so those are skipped.
+    pub(crate) fn prove_facts(&mut self, facts: &[ResolvedFact]) -> Result<(), Error> {
+        for fact in facts {
+            if let GenericFact::Fact(GenericExpr::Call(_, ResolvedCall::Primitive(prim), _)) = fact
+            {
+                if prim.input.iter().any(|sort| sort.is_eq_sort()) {
+                    log::info!("Not proving {fact}, which is not an equality");
+                    continue;
+                }
+            }
+            let mut termdag = mem::take(&mut self.proof_termdag);
+            let proof = match fact {
+                GenericFact::Eq(_, exprs) => exprs
+                    .iter()
+                    .map(|e| resolved_expr_to_term(e, &mut termdag))
+                    .collect::<Option<Vec<_>>>()
+                    .map(|terms| {
+                        let mut builder = ProofBuilder::new(self, &mut termdag)?;
+                        let steps = terms
+                            .windows(2)
+                            .map(|pair| builder.prove_equal(&pair[0], &pair[1]))
+                            .collect::<Result<Vec<_>, _>>()?;
+                        Ok(Proof::chain(terms[0].clone(), steps))
+                    }),
+                GenericFact::Fact(expr) => resolved_expr_to_term(expr, &mut termdag).map(|term| {
+                    ProofBuilder::new(self, &mut termdag)?
+                        .prove_term(&term)
+                        .map(|(proof, _)| proof)
+                }),
+            };
+            self.proof_termdag = termdag;
+            match proof {
+                Some(proof) => self.record_proof(proof?, fact)?,
+                None => log::info!("Not proving {fact}, which is not ground"),
+            }
+        }
+        Ok(())
+    }
+
+    /// Record a proof that the extracted term is equal to the expression it
+    /// was extracted from.
+    pub(crate) fn prove_extraction(&mut self, expr: &ResolvedExpr) -> Result<(), Error> {
+        let Some(ExtractReport::Best {
+            termdag: extract_termdag,
+            term: extracted,
+            ..
+        }) = &self.extract_report
+        else {
+            return Ok(());
+        };
+        let mut termdag = mem::take(&mut self.proof_termdag);
+        let extracted = termdag.expr_to_term(&extract_termdag.term_to_expr(extracted));
+        let proof = resolved_expr_to_term(expr, &mut termdag)
+            .map(|term| ProofBuilder::new(self, &mut termdag)?.prove_equal(&term, &extracted));
+        self.proof_termdag = termdag;
+        match proof {
+            Some(proof) => self.record_proof(proof?, format!("extraction of {expr}"))?,
+            None => log::info!("Not proving extraction of {expr}, which is not ground"),
+        }
+        Ok(())
+    }
+
+    /// Prove two ground expressions equal, building the proof's terms in `termdag`.
+    pub(crate) fn prove_exprs_equal(
+        &self,
+        lhs: &ResolvedExpr,
+        rhs: &ResolvedExpr,
+        termdag: &mut TermDag,
+    ) -> Result<Proof, Error> {
+        let lhs_term = resolved_expr_to_term(lhs, termdag);
+        let rhs_term = resolved_expr_to_term(rhs, termdag);
+        let (Some(lhs_term), Some(rhs_term)) = (lhs_term, rhs_term) else {
+            return Err(proof_error(format!(
+                "Cannot prove {lhs} = {rhs}, which is not ground"
+            )));
+        };
+        ProofBuilder::new(self, termdag)?.prove_equal(&lhs_term, &rhs_term)
+    }
+
+    /// Count the proofs recorded since the last call, each of which was
+    /// checked against the database when it was recorded.
+    pub(crate) fn check_new_proofs(&mut self) -> Result<usize, Error> {
+        if !self.proofs_enabled {
+            return Err(proof_error(
+                "Proofs are not enabled, use (set-option enable_proofs 1)",
+            ));
+        }
+        let checked = self.proofs.len() - self.checked_proofs;
+        self.checked_proofs = self.proofs.len();
+        Ok(checked)
+    }
+
+    /// Check a proof.
+    ///
+    /// Transitivity, congruence and computation steps are checked on their
+    /// own, and a table step by looking up its left-hand side. A rule step
+    /// is checked by matching the rule's query under the step's substitution
+    /// and then building both sides of the step with the rule's actions. An
+    /// action step must be built by one of the top-level actions that ran.
+    pub fn check_proof(&self, termdag: &TermDag, proof: &Proof) -> Result<(), Error> {
+        self.check_proof_step(termdag, proof, &mut Default::default())
+            .map(|_| ())
+    }
+
+    /// Check a shared subproof once, remembering the equation it proves.
+    fn check_shared_proof<'p>(
+        &self,
+        termdag: &TermDag,
+        proof: &'p Rc<Proof>,
+        checked: &mut HashMap<*const Proof, (&'p Term, &'p Term)>,
+    ) -> Result<(&'p Term, &'p Term), Error> {
+        if let Some(&equation) = checked.get(&Rc::as_ptr(proof)) {
+            return Ok(equation);
+        }
+        let equation = self.check_proof_step(termdag, proof, checked)?;
+        checked.insert(Rc::as_ptr(proof), equation);
+        Ok(equation)
+    }
+
+    /// Check a proof, returning the equation it proves.
+    fn check_proof_step<'p>(
+        &self,
+        termdag: &TermDag,
+        proof: &'p Proof,
+        checked: &mut HashMap<*const Proof, (&'p Term, &'p Term)>,
+    ) -> Result<(&'p Term, &'p Term), Error> {
+        let fail = |msg: &str| {
+            Err(proof_error(format!(
+                "{msg} in {}",
+                proof.to_string(termdag)
+            )))
+        };
+        match proof {
+            Proof::Refl(term) => Ok((term, term)),
+            Proof::Rule {
+                rule,
+                subst,
+                lhs,
+                rhs,
+            } => {
+                let Some(compiled) = self.compiled_rule(*rule) else {
+                    return fail("Unknown rule");
+                };
+                let holds = subst
+                    .iter()
+                    .map(|(var, term)| Some((*var, self.lookup_term(termdag, term)?)))
+                    .collect::<Option<HashMap<_, _>>>()
+                    .is_some_and(|values| {
+                        compiled
+                            .core
+                            .body
+                            .atoms
+                            .iter()
+                            .all(|atom| self.atom_holds(atom, &values))
+                    });
+                if !holds && !self.fired_with(termdag, *rule, subst) {
+                    return fail("Rule query does not match the substitution");
+                }
+                let subst: HashMap<Symbol, Term> = subst.iter().cloned().collect();
+                if !ActionUnions::new(&compiled.core.head).unions(termdag, &subst, lhs, rhs) {
+                    return fail("Rule actions do not union these terms");
+                }
+                Ok((lhs, rhs))
+            }
+            Proof::Action { lhs, rhs } => {
+                let subst = HashMap::default();
+                if !self
+                    .proof_actions
+                    .iter()
+                    .any(|actions| ActionUnions::new(actions).unions(termdag, &subst, lhs, rhs))
+                {
+                    return fail("No action unions these terms");
+                }
+                Ok((lhs, rhs))
+            }
+            Proof::Table { lhs, rhs } => {
+                let Term::App(head, children) = lhs else {
+                    return fail("Table lookup of a non-application");
+                };
+                let Some(func) = self.functions.get(head) else {
+                    return fail("Table lookup of a non-function");
+                };
+                if func.schema.output.is_eq_sort() {
+                    return fail("Table lookup of an e-class");
+                }
+                let inputs = children
+                    .iter()
+                    .map(|child| self.lookup_term(termdag, &termdag.get(*child)))
+                    .collect::<Option<Vec<_>>>();
+                let output = inputs.and_then(|inputs| func.get(&inputs));
+                if output.is_none() || output != self.eval_pure(termdag, rhs) {
+                    return fail("Incorrect table lookup");
+                }
+                Ok((lhs, rhs))
+            }
+            Proof::Compute { lhs, rhs } => {
+                let Term::App(head, _) = lhs else {
+                    return fail("Computation of a non-application");
+                };
+                if self.functions.contains_key(head) {
+                    return fail("Computation of a function call");
+                }
+                let computed = self.eval_pure(termdag, lhs);
+                if computed.is_none() || computed != self.eval_pure(termdag, rhs) {
+                    return fail("Incorrect computation");
+                }
+                Ok((lhs, rhs))
+            }
+            Proof::Congruence { lhs, rhs, children } => {
+                let (Term::App(f, xs), Term::App(g, ys)) = (lhs, rhs) else {
+                    return fail("Congruence between non-applications");
+                };
+                if f != g || xs.len() != ys.len() || xs.len() != children.len() {
+                    return fail("Congruence with mismatched heads or arities");
+                }
+                for ((x, y), child) in xs.iter().zip(ys).zip(children) {
+                    let (l, r) = self.check_shared_proof(termdag, child, checked)?;
+                    if *l != termdag.get(*x) || *r != termdag.get(*y) {
+                        return fail("Congruence argument not proven");
+                    }
+                }
+                Ok((lhs, rhs))
+            }
+            Proof::Transitivity(steps) => {
+                let Some(first) = steps.first() else {
+                    return fail("Empty transitivity");
+                };
+                let (lhs, mut rhs) = self.check_shared_proof(termdag, first, checked)?;
+                for step in &steps[1..] {
+                    let (l, r) = self.check_shared_proof(termdag, step, checked)?;
+                    if l != rhs {
+                        return fail("Transitivity steps do not connect");
+                    }
+                    rhs = r;
+                }
+                Ok((lhs, rhs))
+            }
+            Proof::Flip(inner) => {
+                let (lhs, rhs) = self.check_shared_proof(termdag, inner, checked)?;
+                Ok((rhs, lhs))
+            }
+        }
+    }
+
+    pub(crate) fn compiled_rule(&self, name: Symbol) -> Option<&CompiledRule> {
+        self.rulesets.values().find_map(|ruleset| match ruleset {
+            Ruleset::Rules(_, rules) => rules.get(&name),
+            Ruleset::Combined(_, _) => None,
+        })
+    }
+
+    /// Whether an atom of a rule's query holds when its variables have the
+    /// given values.
+    fn atom_holds(
+        &self,
+        atom: &GenericAtom<ResolvedCall, ResolvedVar>,
+        values: &HashMap<Symbol, Value>,
+    ) -> bool {
+        let args = atom
+            .args
+            .iter()
+            .map(|arg| match arg {
+                GenericAtomTerm::Var(var) => values.get(&var.name).copied(),
+                GenericAtomTerm::Literal(lit) => Some(self.eval_lit(lit)),
+                GenericAtomTerm::Global(_) => None,
+            })
+            .collect::<Option<Vec<_>>>();
+        let Some((output, inputs)) = args.as_ref().and_then(|args| args.split_last()) else {
+            return false;
+        };
+        let computed = match &atom.head {
+            ResolvedCall::Func(func) => self
+                .functions
+                .get(&func.name)
+                .and_then(|function| function.get(inputs))
+                .map(|value| self.find(value)),
+            ResolvedCall::Primitive(prim) => prim.primitive.apply(inputs, None),
+        };
+        computed == Some(*output)
+    }
+
+    /// Whether `rule` performed a union with the substitution, whose query
+    /// then held in the database of the time even if it no longer does.
+    fn fired_with(&self, termdag: &TermDag, rule: Symbol, subst: &[(Symbol, Term)]) -> bool {
+        let Some(forest) = self.unionfind.proofs() else {
+            return false;
+        };
+        forest.firings(rule).any(|fired| {
+            fired.len() == subst.len()
+                && fired
+                    .iter()
+                    .zip(subst)
+                    .all(|((var, value), (fired_var, term))| {
+                        var == fired_var && self.term_of_node(forest, termdag, term, *value)
+                    })
+        })
+    }
+
+    /// Whether `term` is the term the proofs of the forest write for `value`.
+    fn term_of_node(
+        &self,
+        forest: &ProofForest,
+        termdag: &TermDag,
+        term: &Term,
+        value: Value,
+    ) -> bool {
+        if !self
+            .get_sort_from_value(&value)
+            .is_some_and(|sort| sort.is_eq_sort())
+        {
+            return self.eval_pure(termdag, term) == Some(value);
+        }
+        let (Term::App(head, children), Some((func, inputs))) =
+            (term, forest.node(Id::from(value.bits as usize)))
+        else {
+            return false;
+        };
+        func == head
+            && children.len() == inputs.len()
+            && children.iter().zip(inputs.iter()).all(|(child, input)| {
+                self.term_of_node(forest, termdag, &termdag.get(*child), *input)
+            })
+    }
+
+    /// Evaluate a term made only of literals and primitive calls.
+    fn eval_pure(&self, termdag: &TermDag, term: &Term) -> Option<Value> {
+        match term {
+            Term::Lit(lit) => Some(self.eval_lit(lit)),
+            Term::Var(_) => None,
+            Term::App(head, _) if self.functions.contains_key(head) => None,
+            Term::App(head, children) => {
+                let values = children
+                    .iter()
+                    .map(|child| self.eval_pure(termdag, &termdag.get(*child)))
+                    .collect::<Option<Vec<_>>>()?;
+                self.apply_primitive(*head, &values)
+            }
+        }
+    }
+
+    /// Evaluate a ground term without adding anything to the database.
+    fn lookup_term(&self, termdag: &TermDag, term: &Term) -> Option<Value> {
+        match term {
+            Term::Lit(lit) => Some(self.eval_lit(lit)),
+            Term::Var(_) => None,
+            Term::App(head, children) => {
+                let values = children
+                    .iter()
+                    .map(|child| self.lookup_term(termdag, &termdag.get(*child)))
+                    .collect::<Option<Vec<_>>>()?;
+                if let Some(func) = self.functions.get(head) {
+                    func.get(&values).map(|value| self.find(value))
+                } else {
+                    self.apply_primitive(*head, &values)
+                }
+            }
+        }
+    }
+
+    /// Apply the primitive with the given name that accepts the sorts of `values`.
+    fn apply_primitive(&self, name: Symbol, values: &[Value]) -> Option<Value> {
+        let mut sorts = values
+            .iter()
+            .map(|value| self.get_sort_from_value(value).cloned())
+            .collect::<Option<Vec<_>>>()?;
+        for prim in self.type_info().primitives.get(&name)? {
+            for output in self.type_info().sorts.values() {
+                sorts.push(output.clone());
+                let accepted = prim.accept(&sorts);
+                sorts.pop();
+                if accepted {
+                    return prim.apply(values, None);
+                }
+            }
+        }
+        None
+    }
+}
+
+/// Convert a ground expression to a term, returning `None` if it has variables.
+fn resolved_expr_to_term(expr: &ResolvedExpr, termdag: &mut TermDag) -> Option<Term> {
+    Some(match expr {
+        GenericExpr::Lit(_, lit) => termdag.lit(lit.clone()),
+        GenericExpr::Var(_, _) => return None,
+        GenericExpr::Call(_, head, args) => {
+            let children = args
+                .iter()
+                .map(|arg| resolved_expr_to_term(arg, termdag))
+                .collect::<Option<Vec<_>>>()?;
+            termdag.app(call_name(head), children)
+        }
+    })
+}
+
+fn call_name(call: &ResolvedCall) -> Symbol {
+    match call {
+        ResolvedCall::Func(func) => func.name,
+        ResolvedCall::Primitive(prim) => prim.primitive.name(),
+    }
+}
+
+/// A term that core actions build: an atom term, or a call on atom terms.
+#[derive(Clone, Copy)]
+enum Built<'a> {
+    Atom(&'a ResolvedAtomTerm),
+    Call(Symbol, &'a [ResolvedAtomTerm]),
+}
+
+/// The pairs of terms that core actions union, either with `union` or by
+/// setting a row of a function, along with the terms their `let`s bind.
+struct ActionUnions<'a> {
+    lets: HashMap<Symbol, Built<'a>>,
+    pairs: Vec<(Built<'a>, Built<'a>)>,
+}
+
+impl<'a> ActionUnions<'a> {
+    fn new(actions: &'a ResolvedCoreActions) -> Self {
+        let mut lets = HashMap::default();
+        let mut pairs = vec![];
+        for action in &actions.0 {
+            match action {
+                GenericCoreAction::Let(var, head, args) => {
+                    lets.insert(var.name, Built::Call(call_name(head), args));
+                }
+                GenericCoreAction::LetAtomTerm(var, term) => {
+                    lets.insert(var.name, Built::Atom(term));
+                }
+                GenericCoreAction::Union(a, b) => pairs.push((Built::Atom(a), Built::Atom(b))),
+                GenericCoreAction::Set(head, args, value) => {
+                    pairs.push((Built::Call(call_name(head), args), Built::Atom(value)))
+                }
+                _ => {}
+            }
+        }
+        ActionUnions { lets, pairs }
+    }
+
+    /// Whether the actions union `lhs` and `rhs`, in either order, when the
+    /// variables of the substitution are bound to the given terms.
+    fn unions(
+        &self,
+        termdag: &TermDag,
+        subst: &HashMap<Symbol, Term>,
+        lhs: &Term,
+        rhs: &Term,
+    ) -> bool {
+        let matches = |term, built| self.matches(termdag, subst, term, built);
+        self.pairs.iter().any(|&(a, b)| {
+            (matches(lhs, a) && matches(rhs, b)) || (matches(lhs, b) && matches(rhs, a))
+        })
+    }
+
+    /// Whether `term` is the term that `built` builds.
+    fn matches(
+        &self,
+        termdag: &TermDag,
+        subst: &HashMap<Symbol, Term>,
+        term: &Term,
+        built: Built,
+    ) -> bool {
+        match built {
+            Built::Atom(GenericAtomTerm::Literal(lit)) => *term == Term::Lit(lit.clone()),
+            Built::Atom(GenericAtomTerm::Global(_)) => false,
+            Built::Atom(GenericAtomTerm::Var(var)) => match subst.get(&var.name) {
+                Some(bound) => bound == term,
+                None => self
+                    .lets
+                    .get(&var.name)
+                    .is_some_and(|&built| self.matches(termdag, subst, term, built)),
+            },
+            Built::Call(head, args) => match term {
+                Term::App(f, children) => {
+                    *f == head
+                        && children.len() == args.len()
+                        && children.iter().zip(args).all(|(child, arg)| {
+                            self.matches(termdag, subst, &termdag.get(*child), Built::Atom(arg))
+                        })
+                }
+                _ => false,
+            },
+        }
+    }
+
+    /// Build the term that `built` builds, or `None` if it uses a variable
+    /// that is not bound.
+    fn build(
+        &self,
+        termdag: &mut TermDag,
+        subst: &HashMap<Symbol, Term>,
+        built: Built,
+    ) -> Option<Term> {
+        match built {
+            Built::Atom(GenericAtomTerm::Literal(lit)) => Some(termdag.lit(lit.clone())),
+            Built::Atom(GenericAtomTerm::Global(_)) => None,
+            Built::Atom(GenericAtomTerm::Var(var)) => match subst.get(&var.name) {
+                Some(bound) => Some(bound.clone()),
+                None => self.build(termdag, subst, *self.lets.get(&var.name)?),
+            },
+            Built::Call(head, args) => {
+                let children = args
+                    .iter()
+                    .map(|arg| self.build(termdag, subst, Built::Atom(arg)))
+                    .collect::<Option<Vec<_>>>()?;
+                Some(termdag.app(head, children))
+            }
+        }
+    }
+}
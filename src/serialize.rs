@@ -1,11 +1,19 @@
+use egraph_serialize::{ClassId, NodeId};
 use ordered_float::NotNan;
 use std::collections::VecDeque;
 
+use indexmap::IndexMap;
+
 use crate::{
-    ast::{Id, ResolvedFunctionDecl},
+    ast::{
+        parse_expr, Actions, Command, FunctionCost, FunctionDecl, Id, ResolvedFunctionDecl, Schema,
+        Symbol,
+    },
+    extract::{declared_cost, ChoiceExtractor, Choices},
     function::table::hash_values,
-    util::HashMap,
-    EGraph, Value,
+    sort::SavedValue,
+    util::{HashMap, HashSet},
+    ArcSort, Cost, EGraph, Error, Term, TermDag, TreeCost, Value, UNIT_SYM,
 };
 
 pub struct SerializeConfig {
@@ -42,7 +50,7 @@ impl EGraph {
     /// For node costs:
     /// - Primitives: 1.0
     /// - Function without costs: 1.0
-    /// - Function with costs: the cost
+    /// - Function with costs: the cost, looked up in its cost table if it has one
     ///
     /// For node IDs:
     /// - Functions: Function name + hash of input values
@@ -135,7 +143,10 @@ impl EGraph {
                 egraph_serialize::Node {
                     op: decl.name.to_string(),
                     eclass,
-                    cost: NotNan::new(decl.cost.unwrap_or(1) as f64).unwrap(),
+                    cost: NotNan::new(
+                        declared_cost(self, &self.functions[&decl.name], input) as f64
+                    )
+                    .unwrap(),
                     children,
                 },
             );
@@ -217,6 +228,121 @@ impl EGraph {
     }
 }
 
+impl EGraph {
+    /// Extract the term for a value made of the nodes chosen in `choices`,
+    /// such as those picked by an external extractor run on the output of
+    /// [`EGraph::serialize`]. Each choice maps an e-class to one of its
+    /// nodes, named as in [`EGraph::serialize`]. Choices for the e-classes
+    /// of primitives are ignored, since a primitive is its own term, and
+    /// values that are not in eq sorts are extracted as their cheapest term.
+    ///
+    /// It is an error for a node to not be in the e-graph or not in its
+    /// chosen e-class, or for the nodes chosen for the e-classes reachable
+    /// from `value` to be missing or to form a cycle.
+    /// The returned cost is the cost of the term under [`TreeCost`].
+    /// ```
+    /// use egglog::{EGraph, SerializeConfig, TermDag};
+    /// let mut egraph = EGraph::default();
+    /// egraph
+    ///     .parse_and_run_program(
+    ///         "(datatype Math (Num i64) (Var String) (Mul Math Math) (Shl Math Math))
+    ///          (let expr (Mul (Var \"x\") (Num 2)))
+    ///          (union expr (Shl (Var \"x\") (Num 1)))",
+    ///     )
+    ///     .unwrap();
+    /// // choose every node except the shift
+    /// let serialized = egraph.serialize(SerializeConfig::default());
+    /// let choices: Vec<_> = serialized
+    ///     .nodes
+    ///     .iter()
+    ///     .filter(|(_, node)| node.op != "Shl")
+    ///     .map(|(id, node)| (node.eclass.clone(), id.clone()))
+    ///     .collect();
+    /// let (sort, value) = egraph
+    ///     .eval_expr(&egglog::ast::Expr::var("expr"))
+    ///     .unwrap();
+    /// let mut termdag = TermDag::default();
+    /// let (cost, term) = egraph
+    ///     .extract_choices(value, &mut termdag, &sort, choices.iter().map(|(c, n)| (c, n)))
+    ///     .unwrap();
+    /// assert_eq!(termdag.to_string(&term), "(Mul (Var \"x\") (Num 2))");
+    /// assert_eq!(cost, 5);
+    /// ```
+    pub fn extract_choices<'c>(
+        &self,
+        value: Value,
+        termdag: &mut TermDag,
+        arcsort: &ArcSort,
+        choices: impl IntoIterator<Item = (&'c ClassId, &'c NodeId)>,
+    ) -> Result<(Cost, Term), Error> {
+        let choices = self.resolve_choices(choices)?;
+        if !arcsort.is_eq_sort() {
+            return self.extract_with_cost_function(value, termdag, arcsort, TreeCost);
+        }
+        ChoiceExtractor::new(self, termdag, &choices, true).extract(value, termdag)
+    }
+
+    /// Make later extractions, including the `extract` command, extract the
+    /// e-classes in `choices` as their chosen nodes, named as for
+    /// [`EGraph::extract_choices`]. An e-class without a choice, or whose
+    /// chosen node is no longer in the e-graph, is extracted as its cheapest
+    /// node. Where the nodes lead back to an e-class being extracted, that
+    /// e-class is extracted as its cheapest term.
+    ///
+    /// This replaces the choices set before. They are kept until
+    /// [`EGraph::clear_preferred_choices`], or [`EGraph::pop`] of the scope
+    /// they were set in.
+    pub fn set_preferred_choices<'c>(
+        &mut self,
+        choices: impl IntoIterator<Item = (&'c ClassId, &'c NodeId)>,
+    ) -> Result<(), Error> {
+        self.preferred_choices = self.resolve_choices(choices)?;
+        Ok(())
+    }
+
+    /// Go back to extracting the cheapest term for every e-class.
+    pub fn clear_preferred_choices(&mut self) {
+        self.preferred_choices.clear();
+    }
+
+    /// The rows named by the choices for e-classes of eq sorts.
+    fn resolve_choices<'c>(
+        &self,
+        choices: impl IntoIterator<Item = (&'c ClassId, &'c NodeId)>,
+    ) -> Result<Choices, Error> {
+        let mut rows: HashMap<String, (Symbol, &[Value], Value)> = HashMap::default();
+        for function in self.functions.values() {
+            for (inputs, output) in function.nodes.iter(true) {
+                let node_id = format!("{}-{}", function.decl.name, hash_values(inputs));
+                rows.insert(node_id, (function.decl.name, inputs, output.value));
+            }
+        }
+
+        let mut resolved = Choices::default();
+        for (class, node) in choices {
+            // the e-classes of primitives are named after their values
+            let Ok(id) = class.to_string().parse::<usize>() else {
+                continue;
+            };
+            let Some(&(sym, inputs, output)) = rows.get(&node.to_string()) else {
+                return Err(Error::ExtractError(format!(
+                    "no node {node} in the e-graph"
+                )));
+            };
+            let in_class = id < self.unionfind.num_ids()
+                && self.get_sort_from_value(&output).unwrap().is_eq_sort()
+                && self.unionfind.find(Id::from(id)) == Id::from(self.find(output).bits as usize);
+            if !in_class {
+                return Err(Error::ExtractError(format!(
+                    "node {node} is not in e-class {class}"
+                )));
+            }
+            resolved.insert(Id::from(id), (sym, inputs.to_vec()));
+        }
+        Ok(resolved)
+    }
+}
+
 type NodeIDs = HashMap<egraph_serialize::ClassId, VecDeque<egraph_serialize::NodeId>>;
 
 /// Returns the node ID for the given class ID, rotating the queue
@@ -228,3 +354,260 @@ fn get_node_id(
     node_ids.rotate_left(1);
     Some(node_ids.front().unwrap().clone())
 }
+
+impl EGraph {
+    /// Build an e-graph from one written by [`EGraph::serialize`], declaring
+    /// its sorts and functions from the types of its e-classes.
+    /// See [`EGraph::import_serialized`].
+    pub fn from_serialized(serialized: &egraph_serialize::EGraph) -> Result<EGraph, Error> {
+        let mut egraph = EGraph::default();
+        egraph.import_serialized(serialized)?;
+        Ok(egraph)
+    }
+
+    /// Add the e-classes, primitive values, and rows of an e-graph written by
+    /// [`EGraph::serialize`] to this one.
+    ///
+    /// Sorts and functions that are not declared yet are declared from the
+    /// types of the e-classes they are used with. A function declared this
+    /// way gets the cost of its nodes if they all have the same one, but no
+    /// merge or default; declare it before importing to give it those.
+    /// The serialized e-graph only names the sorts of container values, so
+    /// container sorts such as `(sort MathVec (Vec Math))` must be declared
+    /// before importing, along with the sorts they contain.
+    /// Each serialized e-class becomes a new e-class, and rows that already
+    /// exist are merged as if they were set again.
+    ///
+    /// Only what was serialized can be imported: globals are never
+    /// serialized, and subsumed rows are imported as ordinary rows.
+    pub fn import_serialized(
+        &mut self,
+        serialized: &egraph_serialize::EGraph,
+    ) -> Result<(), Error> {
+        let mut importer = Importer::new(serialized);
+        importer.declare_sorts(self)?;
+        importer.declare_functions(self)?;
+        importer.add_rows(self)?;
+        self.rebuild()?;
+        Ok(())
+    }
+}
+
+/// Rebuilds the values and rows of a serialized e-graph.
+struct Importer<'a> {
+    serialized: &'a egraph_serialize::EGraph,
+    /// The node holding the value of each e-class of a primitive or container.
+    value_nodes: HashMap<&'a ClassId, &'a egraph_serialize::Node>,
+    values: HashMap<&'a ClassId, Value>,
+}
+
+impl<'a> Importer<'a> {
+    fn new(serialized: &'a egraph_serialize::EGraph) -> Self {
+        let value_nodes = serialized
+            .nodes
+            .iter()
+            .filter(|(id, node)| is_value_node(id, node))
+            .map(|(_, node)| (&node.eclass, node))
+            .collect();
+        Importer {
+            serialized,
+            value_nodes,
+            values: HashMap::default(),
+        }
+    }
+
+    fn function_nodes(&self) -> impl Iterator<Item = (&'a NodeId, &'a egraph_serialize::Node)> {
+        self.serialized
+            .nodes
+            .iter()
+            .filter(|(id, node)| !is_value_node(id, node))
+    }
+
+    fn type_name(&self, class: &ClassId) -> Result<Symbol, Error> {
+        self.serialized
+            .class_data
+            .get(class)
+            .and_then(|data| data.typ.as_deref())
+            .map(Symbol::from)
+            .ok_or_else(|| Error::ImportError(format!("e-class {class} has no type")))
+    }
+
+    fn sort(&self, egraph: &EGraph, class: &ClassId) -> Result<ArcSort, Error> {
+        let name = self.type_name(class)?;
+        egraph
+            .type_info()
+            .sorts
+            .get(&name)
+            .cloned()
+            .ok_or_else(|| Error::ImportError(format!("unknown sort {name}")))
+    }
+
+    fn class_of(&self, node: &NodeId) -> Result<&'a ClassId, Error> {
+        self.serialized
+            .nodes
+            .get(node)
+            .map(|node| &node.eclass)
+            .ok_or_else(|| Error::ImportError(format!("no node {node}")))
+    }
+
+    /// Declare the sorts of e-classes without values as eq sorts.
+    /// Sorts of values, such as containers, must be declared already.
+    fn declare_sorts(&self, egraph: &mut EGraph) -> Result<(), Error> {
+        let mut declared = HashSet::default();
+        for class in self.serialized.class_data.keys() {
+            let name = self.type_name(class)?;
+            if egraph.type_info().sorts.contains_key(&name) || !declared.insert(name) {
+                continue;
+            }
+            if self.value_nodes.contains_key(class) {
+                return Err(Error::ImportError(format!(
+                    "unknown sort {name}: container sorts must be declared before importing"
+                )));
+            }
+            egraph.run_program(vec![Command::Sort(name, None)])?;
+        }
+        Ok(())
+    }
+
+    /// Declare the functions of nodes that are not declared yet.
+    fn declare_functions(&self, egraph: &mut EGraph) -> Result<(), Error> {
+        let mut decls: IndexMap<Symbol, (Schema, Option<f64>)> = IndexMap::default();
+        for (_, node) in self.function_nodes() {
+            let name = Symbol::from(&node.op);
+            if egraph.functions.contains_key(&name) {
+                continue;
+            }
+            let cost = node.cost.into_inner();
+            if let Some((_, same_cost)) = decls.get_mut(&name) {
+                if *same_cost != Some(cost) {
+                    *same_cost = None;
+                }
+                continue;
+            }
+            let input = node
+                .children
+                .iter()
+                .map(|child| self.type_name(self.class_of(child)?))
+                .collect::<Result<_, _>>()?;
+            let output = self.type_name(&node.eclass)?;
+            decls.insert(name, (Schema::new(input, output), Some(cost)));
+        }
+
+        for (name, (schema, cost)) in decls {
+            let decl = if schema.output == UNIT_SYM.into() {
+                FunctionDecl::relation(name, schema.input)
+            } else {
+                FunctionDecl {
+                    name,
+                    schema,
+                    merge: None,
+                    merge_action: Actions::default(),
+                    default: None,
+                    cost: cost
+                        .filter(|&cost| cost != 1.0)
+                        .map(|cost| FunctionCost::Fixed(cost as usize)),
+                    unextractable: false,
+                    ignore_viz: false,
+                }
+            };
+            egraph.run_program(vec![Command::Function(decl)])?;
+        }
+        Ok(())
+    }
+
+    fn add_rows(&mut self, egraph: &mut EGraph) -> Result<(), Error> {
+        let nodes: Vec<_> = self.function_nodes().collect();
+        for (id, node) in nodes {
+            let name = Symbol::from(&node.op);
+            let mut row = vec![];
+            for child in &node.children {
+                row.push(self.value(egraph, self.class_of(child)?)?);
+            }
+            let output = self.value(egraph, &node.eclass)?;
+            let schema = &egraph.functions[&name].schema;
+            let well_typed = row.len() == schema.input.len()
+                && row
+                    .iter()
+                    .zip(&schema.input)
+                    .all(|(value, sort)| value.tag == sort.name())
+                && output.tag == schema.output.name();
+            if !well_typed {
+                return Err(Error::ImportError(format!(
+                    "ill-typed node {id} of function {name}"
+                )));
+            }
+            egraph.perform_set(name, output, &mut row)?;
+        }
+        Ok(())
+    }
+
+    /// The value of an e-class, making a new e-class for those of eq sorts.
+    fn value(&mut self, egraph: &mut EGraph, class: &'a ClassId) -> Result<Value, Error> {
+        if let Some(value) = self.values.get(class) {
+            return Ok(*value);
+        }
+        let sort = self.sort(egraph, class)?;
+        let value = if sort.is_eq_sort() {
+            Value::from_id(sort.name(), egraph.unionfind.make_set())
+        } else {
+            let node: &'a egraph_serialize::Node = self
+                .value_nodes
+                .get(class)
+                .copied()
+                .ok_or_else(|| Error::ImportError(format!("e-class {class} has no value")))?;
+            let bad_value = || {
+                Error::ImportError(format!(
+                    "cannot read {} as a value of sort {}",
+                    node.op,
+                    sort.name()
+                ))
+            };
+            if sort.is_container_sort() {
+                let mut elements = vec![];
+                for child in &node.children {
+                    let child_class = self.class_of(child)?;
+                    let element = self.value(egraph, child_class)?;
+                    let element_sort = self.sort(egraph, child_class)?;
+                    elements.push((element_sort, element));
+                }
+                // functions are named after the function, other containers after their sort
+                let saved = if node.op == sort.name().as_str() {
+                    SavedValue::Container(
+                        elements
+                            .iter()
+                            .map(|(sort, value)| sort.save_value(*value))
+                            .collect(),
+                    )
+                } else {
+                    SavedValue::Function(
+                        node.op.clone(),
+                        elements
+                            .iter()
+                            .map(|(sort, value)| (sort.name().to_string(), sort.save_value(*value)))
+                            .collect(),
+                    )
+                };
+                sort.load_value(egraph, &saved).ok_or_else(bad_value)?
+            } else {
+                let expr = parse_expr(&node.op).map_err(|_| bad_value())?;
+                let expr = egraph
+                    .type_info()
+                    .typecheck_expr(&expr, &Default::default())
+                    .map_err(|_| bad_value())?;
+                let value = egraph.eval_resolved_expr(&expr, false)?;
+                if value.tag != sort.name() {
+                    return Err(bad_value());
+                }
+                value
+            }
+        };
+        self.values.insert(class, value);
+        Ok(value)
+    }
+}
+
+/// Whether a node holds the value of a primitive or container, which is
+/// serialized as its own e-class.
+fn is_value_node(id: &NodeId, node: &egraph_serialize::Node) -> bool {
+    id.to_string() == node.eclass.to_string()
+}
@@ -0,0 +1,221 @@
+//! Snapshots of an [`EGraph`], saved to disk so that a long run can be
+//! resumed later or on another machine.
+//!
+//! A snapshot holds the program declared so far, as egglog source, together
+//! with the union-find and every row of every function. Values are saved as
+//! [`SavedValue`]s, which do not depend on the interned pools (strings,
+//! rationals, containers) of the process that saved them.
+//! The program is replayed when the snapshot is loaded, so sorts and
+//! primitives added from Rust must be added again before loading.
+use std::path::Path;
+
+use serde::{Deserialize, Serialize};
+
+use crate::*;
+
+/// Bumped whenever the format changes incompatibly.
+const SNAPSHOT_VERSION: u32 = 1;
+
+#[derive(Serialize, Deserialize)]
+struct Snapshot {
+    version: u32,
+    /// The commands declaring sorts, functions, rulesets, and rules, in order.
+    program: Vec<String>,
+    /// The global variables, with the names of their sorts.
+    globals: Vec<(String, String)>,
+    reserved_symbol: String,
+    /// The number of fresh symbols generated, so that none are reused.
+    fresh_symbols: usize,
+    timestamp: u32,
+    iteration: usize,
+    rule_timestamps: Vec<(String, u32)>,
+    unionfind: UnionFind,
+    functions: Vec<SavedFunction>,
+}
+
+#[derive(Serialize, Deserialize)]
+struct SavedFunction {
+    name: String,
+    /// The live rows, in the order they were inserted.
+    rows: Vec<SavedRow>,
+}
+
+#[derive(Serialize, Deserialize)]
+struct SavedRow {
+    inputs: Vec<SavedValue>,
+    output: SavedValue,
+    timestamp: u32,
+    subsumed: bool,
+}
+
+impl EGraph {
+    /// Save the declarations and the database of the egraph to a file,
+    /// to be loaded with [`EGraph::load_snapshot`].
+    /// Proofs, run reports, and options are not saved.
+    pub fn save_snapshot(&self, path: impl AsRef<Path>) -> Result<(), Error> {
+        let path = path.as_ref();
+        if self.proofs_enabled {
+            return Err(Error::SnapshotError(
+                "snapshots cannot be taken while proofs are enabled".into(),
+            ));
+        }
+        let snapshot = self.snapshot();
+        let file = File::create(path).map_err(|e| Error::IoError(path.into(), e))?;
+        serde_json::to_writer(std::io::BufWriter::new(file), &snapshot)
+            .map_err(|e| Error::SnapshotError(format!("{}: {e}", path.display())))
+    }
+
+    /// Load a snapshot saved by [`EGraph::save_snapshot`].
+    /// The egraph must not have declared anything yet.
+    pub fn load_snapshot(&mut self, path: impl AsRef<Path>) -> Result<(), Error> {
+        let path = path.as_ref();
+        if !self.declarations.is_empty() || !self.functions.is_empty() {
+            return Err(Error::SnapshotError(
+                "snapshots can only be loaded before any declarations".into(),
+            ));
+        }
+        let file = File::open(path).map_err(|e| Error::IoError(path.into(), e))?;
+        let snapshot: Snapshot = serde_json::from_reader(std::io::BufReader::new(file))
+            .map_err(|e| Error::SnapshotError(format!("{}: {e}", path.display())))?;
+        if snapshot.version != SNAPSHOT_VERSION {
+            return Err(Error::SnapshotError(format!(
+                "{}: unsupported snapshot version {}",
+                path.display(),
+                snapshot.version
+            )));
+        }
+        self.restore(snapshot)
+    }
+
+    fn snapshot(&self) -> Snapshot {
+        let program = self
+            .declarations
+            .iter()
+            .map(|command| command.to_command().to_string())
+            .collect();
+        let mut globals: Vec<_> = self
+            .type_info()
+            .global_types
+            .iter()
+            .map(|(name, sort)| (name.to_string(), sort.name().to_string()))
+            .collect();
+        globals.sort();
+        let mut rule_timestamps: Vec<_> = self
+            .rule_last_run_timestamp
+            .iter()
+            .map(|(name, ts)| (name.to_string(), *ts))
+            .collect();
+        rule_timestamps.sort();
+        let mut functions: Vec<_> = self
+            .functions
+            .iter()
+            .map(|(name, function)| SavedFunction {
+                name: name.to_string(),
+                rows: function
+                    .nodes
+                    .iter(true)
+                    .map(|(inputs, output)| SavedRow {
+                        inputs: inputs
+                            .iter()
+                            .zip(&function.schema.input)
+                            .map(|(value, sort)| sort.save_value(*value))
+                            .collect(),
+                        output: function.schema.output.save_value(output.value),
+                        timestamp: output.timestamp,
+                        subsumed: output.subsumed,
+                    })
+                    .collect(),
+            })
+            .collect();
+        // sorted so that snapshots of the same egraph are identical
+        functions.sort_by(|a, b| a.name.cmp(&b.name));
+        Snapshot {
+            version: SNAPSHOT_VERSION,
+            program,
+            globals,
+            reserved_symbol: self.desugar.fresh_gen.reserved_string().to_string(),
+            fresh_symbols: self.desugar.fresh_gen.count(),
+            timestamp: self.timestamp,
+            iteration: self.iteration,
+            rule_timestamps,
+            unionfind: self.unionfind.clone(),
+            functions,
+        }
+    }
+
+    fn restore(&mut self, snapshot: Snapshot) -> Result<(), Error> {
+        // Start after the saved fresh symbols, so that replaying the program
+        // does not generate names it already uses.
+        self.desugar.fresh_gen =
+            SymbolGen::resume(snapshot.reserved_symbol, snapshot.fresh_symbols);
+        // The program is already desugared, so don't add semi-naive
+        // variants of its rules a second time.
+        let seminaive = mem::replace(&mut self.seminaive, false);
+        let replayed = self.replay(&snapshot.program.join("\n"));
+        self.seminaive = seminaive;
+        replayed?;
+
+        for (name, sort) in snapshot.globals {
+            let sort = self
+                .type_info()
+                .sorts
+                .get(&Symbol::from(&sort))
+                .cloned()
+                .ok_or_else(|| Error::SnapshotError(format!("unknown sort {sort}")))?;
+            self.type_info_mut().global_types.insert(name.into(), sort);
+        }
+
+        self.unionfind = snapshot.unionfind;
+        for saved in snapshot.functions {
+            let name = Symbol::from(&saved.name);
+            let function = self
+                .functions
+                .get(&name)
+                .ok_or_else(|| Error::SnapshotError(format!("unknown function {name}")))?;
+            let bad_row = || Error::SnapshotError(format!("ill-typed row in function {name}"));
+            let mut rows = Vec::with_capacity(saved.rows.len());
+            for row in saved.rows {
+                let inputs = row
+                    .inputs
+                    .iter()
+                    .zip(&function.schema.input)
+                    .map(|(value, sort)| sort.load_value(self, value))
+                    .collect::<Option<Vec<_>>>()
+                    .filter(|inputs| inputs.len() == function.schema.input.len())
+                    .ok_or_else(bad_row)?;
+                let output = function
+                    .schema
+                    .output
+                    .load_value(self, &row.output)
+                    .ok_or_else(bad_row)?;
+                rows.push((inputs, output, row.timestamp, row.subsumed));
+            }
+
+            let function = self.functions.get_mut(&name).unwrap();
+            for (inputs, output, timestamp, subsumed) in rows {
+                function.insert_internal(&inputs, output, timestamp, false);
+                if subsumed {
+                    function.subsume(&inputs);
+                }
+            }
+        }
+
+        self.timestamp = snapshot.timestamp;
+        self.iteration = snapshot.iteration;
+        self.rule_last_run_timestamp = snapshot
+            .rule_timestamps
+            .into_iter()
+            .map(|(name, ts)| (name.into(), ts))
+            .collect();
+        Ok(())
+    }
+
+    fn replay(&mut self, program: &str) -> Result<(), Error> {
+        for command in self.parse_program(program)? {
+            for processed in self.process_command(command)? {
+                self.run_command(processed)?;
+            }
+        }
+        Ok(())
+    }
+}
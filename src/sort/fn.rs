@@ -62,13 +62,13 @@ impl FunctionSort {
         name: Symbol,
         args: &[Expr],
     ) -> Result<ArcSort, TypeError> {
-        if let [Expr::Call((), first, rest_args), Expr::Var((), output)] = args {
+        if let [Expr::Call(_, first, rest_args), Expr::Var(_, output)] = args {
             let output_sort = typeinfo
                 .sorts
                 .get(output)
                 .ok_or(TypeError::UndefinedSort(*output))?;
             let all_args = once(first).chain(rest_args.iter().map(|arg| {
-                if let Expr::Var((), arg) = arg {
+                if let Expr::Var(_, arg) = arg {
                     arg
                 } else {
                     panic!("function sort must be called with list of input sorts");
@@ -154,28 +154,39 @@ impl Sort for FunctionSort {
     fn make_expr(&self, egraph: &EGraph, value: Value) -> (Cost, Expr) {
         let mut termdag = TermDag::default();
         let extractor = Extractor::new(egraph, &mut termdag);
-        self.extract_expr(egraph, value, &extractor, &mut termdag)
+        extractor
+            .find_best_container(self, value)
             .expect("Extraction should be successful since extractor has been fully initialized")
     }
 
-    fn extract_expr(
-        &self,
-        _egraph: &EGraph,
-        value: Value,
-        extractor: &Extractor,
-        termdag: &mut TermDag,
-    ) -> Option<(Cost, Expr)> {
-        let ValueFunction(name, inputs) = ValueFunction::load(self, &value);
-        let (cost, args) = inputs.into_iter().try_fold(
-            (1usize, vec![Expr::Lit((), Literal::String(name))]),
-            |(cost, mut args), (sort, value)| {
-                let (new_cost, term) = extractor.find_best(value, termdag, &sort)?;
-                args.push(termdag.term_to_expr(&term));
-                Some((cost.saturating_add(new_cost), args))
-            },
-        )?;
-
-        Some((cost, Expr::call("unstable-fn", args)))
+    fn reconstruct_expr(&self, _egraph: &EGraph, value: Value, inner: Vec<Expr>) -> Expr {
+        let ValueFunction(name, _) = ValueFunction::load(self, &value);
+        let name = Expr::Lit(DUMMY_SPAN.clone(), Literal::String(name));
+        Expr::call("unstable-fn", std::iter::once(name).chain(inner))
+    }
+
+    fn save_value(&self, value: Value) -> SavedValue {
+        let ValueFunction(name, inputs) = self.get_value(&value);
+        let args = inputs
+            .into_iter()
+            .map(|(sort, value)| (sort.name().to_string(), sort.save_value(value)))
+            .collect();
+        SavedValue::Function(name.to_string(), args)
+    }
+
+    fn load_value(&self, egraph: &EGraph, saved: &SavedValue) -> Option<Value> {
+        let SavedValue::Function(name, args) = saved else {
+            return None;
+        };
+        let args = args
+            .iter()
+            .map(|(sort, value)| {
+                let sort = egraph.type_info().sorts.get(&Symbol::from(sort))?.clone();
+                let value = sort.load_value(egraph, value)?;
+                Some((sort, value))
+            })
+            .collect::<Option<Vec<_>>>()?;
+        ValueFunction(name.into(), args).store(self)
     }
 }
 
@@ -324,9 +335,9 @@ fn call_fn(egraph: &mut EGraph, name: &Symbol, types: Vec<ArcSort>, args: Vec<Va
     let binding = IndexSet::from_iter(arg_vars.clone());
     let resolved_args = arg_vars
         .into_iter()
-        .map(|v| ResolvedExpr::Var((), v))
+        .map(|v| ResolvedExpr::Var(DUMMY_SPAN.clone(), v))
         .collect();
-    let expr = ResolvedExpr::Call((), resolved_call, resolved_args);
+    let expr = ResolvedExpr::Call(DUMMY_SPAN.clone(), resolved_call, resolved_args);
     // Similar to how the merge function is created in `Function::new`
     let (actions, mapped_expr) = expr
         .to_core_actions(
@@ -76,7 +76,10 @@ impl Sort for I64Sort {
 
     fn make_expr(&self, _egraph: &EGraph, value: Value) -> (Cost, Expr) {
         assert!(value.tag == self.name());
-        (1, Expr::Lit((), Literal::Int(value.bits as _)))
+        (
+            1,
+            Expr::Lit(DUMMY_SPAN.clone(), Literal::Int(value.bits as _)),
+        )
     }
 }
 
@@ -29,7 +29,7 @@ impl MapSort {
         name: Symbol,
         args: &[Expr],
     ) -> Result<ArcSort, TypeError> {
-        if let [Expr::Var((), k), Expr::Var((), v)] = args {
+        if let [Expr::Var(_, k), Expr::Var(_, v)] = args {
             let k = typeinfo.sorts.get(k).ok_or(TypeError::UndefinedSort(*k))?;
             let v = typeinfo.sorts.get(v).ok_or(TypeError::UndefinedSort(*v))?;
 
@@ -153,30 +153,46 @@ impl Sort for MapSort {
     fn make_expr(&self, egraph: &EGraph, value: Value) -> (Cost, Expr) {
         let mut termdag = TermDag::default();
         let extractor = Extractor::new(egraph, &mut termdag);
-        self.extract_expr(egraph, value, &extractor, &mut termdag)
+        extractor
+            .find_best_container(self, value)
             .expect("Extraction should be successful since extractor has been fully initialized")
     }
 
-    fn extract_expr(
-        &self,
-        _egraph: &EGraph,
-        value: Value,
-        extractor: &Extractor,
-        termdag: &mut TermDag,
-    ) -> Option<(Cost, Expr)> {
+    fn reconstruct_expr(&self, _egraph: &EGraph, _value: Value, inner: Vec<Expr>) -> Expr {
+        // keys and values alternate, as in inner_values
+        inner
+            .chunks(2)
+            .rev()
+            .fold(Expr::call("map-empty", []), |map, kv| {
+                Expr::call("map-insert", [map, kv[0].clone(), kv[1].clone()])
+            })
+    }
+
+    fn save_value(&self, value: Value) -> SavedValue {
         let map = ValueMap::load(self, &value);
-        let mut expr = Expr::call("map-empty", []);
-        let mut cost = 0usize;
-        for (k, v) in map.iter().rev() {
-            let k = extractor.find_best(*k, termdag, &self.key)?;
-            let v = extractor.find_best(*v, termdag, &self.value)?;
-            cost = cost.saturating_add(k.0).saturating_add(v.0);
-            expr = Expr::call(
-                "map-insert",
-                [expr, termdag.term_to_expr(&k.1), termdag.term_to_expr(&v.1)],
-            )
+        SavedValue::Container(
+            map.into_iter()
+                .flat_map(|(k, v)| [self.key.save_value(k), self.value.save_value(v)])
+                .collect(),
+        )
+    }
+
+    fn load_value(&self, egraph: &EGraph, saved: &SavedValue) -> Option<Value> {
+        let SavedValue::Container(elems) = saved else {
+            return None;
+        };
+        if elems.len() % 2 != 0 {
+            return None;
         }
-        Some((cost, expr))
+        let map = elems
+            .chunks(2)
+            .map(|kv| {
+                let k = self.key.load_value(egraph, &kv[0])?;
+                let v = self.value.load_value(egraph, &kv[1])?;
+                Some((k, v))
+            })
+            .collect::<Option<ValueMap>>()?;
+        map.store(self)
     }
 }
 
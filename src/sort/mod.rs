@@ -86,20 +86,53 @@ pub trait Sort: Any + Send + Sync + Debug {
     /// Extracting an expression (with smallest cost) out of a primitive value
     fn make_expr(&self, egraph: &EGraph, value: Value) -> (Cost, Expr);
 
-    /// For values like EqSort containers, to make/extract an expression from it
-    /// requires an extractor. Moreover, the extraction may be unsuccessful if
-    /// the extractor is not fully initialized.
+    /// Build an expression for a container value from expressions for each
+    /// of its [`Sort::inner_values`], in the same order.
+    /// Only container sorts need to implement this method.
     ///
     /// The default behavior is to call make_expr
-    fn extract_expr(
-        &self,
-        egraph: &EGraph,
-        value: Value,
-        _extractor: &Extractor,
-        _termdag: &mut TermDag,
-    ) -> Option<(Cost, Expr)> {
-        Some(self.make_expr(egraph, value))
+    fn reconstruct_expr(&self, egraph: &EGraph, value: Value, inner: Vec<Expr>) -> Expr {
+        let _ = inner;
+        self.make_expr(egraph, value).1
     }
+
+    /// Convert a value to a form that can be loaded by another process, for snapshots.
+    ///
+    /// The default saves the bits of the value, which is only correct for sorts
+    /// whose values do not point into interned pools.
+    fn save_value(&self, value: Value) -> SavedValue {
+        SavedValue::Bits(value.bits)
+    }
+
+    /// Load a value saved by [`Sort::save_value`], interning it if needed.
+    fn load_value(&self, egraph: &EGraph, saved: &SavedValue) -> Option<Value> {
+        let _ = egraph;
+        match saved {
+            SavedValue::Bits(bits) => Some(Value {
+                tag: self.name(),
+                bits: *bits,
+            }),
+            _ => None,
+        }
+    }
+}
+
+/// A value in a form that does not depend on the interned pools of the
+/// process that created it. See [`Sort::save_value`].
+#[derive(Debug, Clone, PartialEq)]
+#[cfg_attr(feature = "serde-1", derive(serde::Serialize, serde::Deserialize))]
+pub enum SavedValue {
+    /// The bits of a value that means the same thing in every process,
+    /// like an integer or an e-class id.
+    Bits(u64),
+    String(String),
+    /// A rational, as its numerator and denominator.
+    Rational(i64, i64),
+    /// The elements of a container. For maps, keys and values alternate.
+    Container(Vec<SavedValue>),
+    /// A function value, as the name of the function and its partially
+    /// applied arguments with their sorts.
+    Function(String, Vec<(String, SavedValue)>),
 }
 
 #[derive(Debug)]
@@ -119,12 +119,24 @@ impl Sort for RationalSort {
             Expr::call(
                 "rational",
                 vec![
-                    Expr::Lit((), Literal::Int(numer)),
-                    Expr::Lit((), Literal::Int(denom)),
+                    Expr::Lit(DUMMY_SPAN.clone(), Literal::Int(numer)),
+                    Expr::Lit(DUMMY_SPAN.clone(), Literal::Int(denom)),
                 ],
             ),
         )
     }
+
+    fn save_value(&self, value: Value) -> SavedValue {
+        let rat = R::load(self, &value);
+        SavedValue::Rational(*rat.numer(), *rat.denom())
+    }
+
+    fn load_value(&self, _egraph: &EGraph, saved: &SavedValue) -> Option<Value> {
+        match saved {
+            SavedValue::Rational(numer, denom) => R::new(*numer, *denom).store(self),
+            _ => None,
+        }
+    }
 }
 
 impl FromSort for R {
@@ -28,7 +28,7 @@ impl SetSort {
         name: Symbol,
         args: &[Expr],
     ) -> Result<ArcSort, TypeError> {
-        if let [Expr::Var((), e)] = args {
+        if let [Expr::Var(_, e)] = args {
             let e = typeinfo.sorts.get(e).ok_or(TypeError::UndefinedSort(*e))?;
 
             if e.is_eq_container_sort() {
@@ -169,26 +169,38 @@ impl Sort for SetSort {
     fn make_expr(&self, egraph: &EGraph, value: Value) -> (Cost, Expr) {
         let mut termdag = TermDag::default();
         let extractor = Extractor::new(egraph, &mut termdag);
-        self.extract_expr(egraph, value, &extractor, &mut termdag)
+        extractor
+            .find_best_container(self, value)
             .expect("Extraction should be successful since extractor has been fully initialized")
     }
 
-    fn extract_expr(
-        &self,
-        _egraph: &EGraph,
-        value: Value,
-        extractor: &Extractor,
-        termdag: &mut TermDag,
-    ) -> Option<(Cost, Expr)> {
+    fn reconstruct_expr(&self, _egraph: &EGraph, _value: Value, inner: Vec<Expr>) -> Expr {
+        inner
+            .into_iter()
+            .rev()
+            .fold(Expr::call("set-empty", []), |set, e| {
+                Expr::call("set-insert", [set, e])
+            })
+    }
+
+    fn save_value(&self, value: Value) -> SavedValue {
         let set = ValueSet::load(self, &value);
-        let mut expr = Expr::call("set-empty", []);
-        let mut cost = 0usize;
-        for e in set.iter().rev() {
-            let e = extractor.find_best(*e, termdag, &self.element)?;
-            cost = cost.saturating_add(e.0);
-            expr = Expr::call("set-insert", [expr, termdag.term_to_expr(&e.1)])
-        }
-        Some((cost, expr))
+        SavedValue::Container(
+            set.into_iter()
+                .map(|e| self.element.save_value(e))
+                .collect(),
+        )
+    }
+
+    fn load_value(&self, egraph: &EGraph, saved: &SavedValue) -> Option<Value> {
+        let SavedValue::Container(elems) = saved else {
+            return None;
+        };
+        let set = elems
+            .iter()
+            .map(|e| self.element.load_value(egraph, e))
+            .collect::<Option<ValueSet>>()?;
+        set.store(self)
     }
 }
 
@@ -27,7 +27,18 @@ impl Sort for StringSort {
     fn make_expr(&self, _egraph: &EGraph, value: Value) -> (Cost, Expr) {
         assert!(value.tag == self.name);
         let sym = Symbol::from(NonZeroU32::new(value.bits as _).unwrap());
-        (1, Expr::Lit((), Literal::String(sym)))
+        (1, Expr::Lit(DUMMY_SPAN.clone(), Literal::String(sym)))
+    }
+
+    fn save_value(&self, value: Value) -> SavedValue {
+        SavedValue::String(Symbol::load(self, &value).to_string())
+    }
+
+    fn load_value(&self, _egraph: &EGraph, saved: &SavedValue) -> Option<Value> {
+        match saved {
+            SavedValue::String(s) => Symbol::from(s).store(self),
+            _ => None,
+        }
     }
 
     fn register_primitives(self: Arc<Self>, typeinfo: &mut TypeInfo) {
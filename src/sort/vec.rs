@@ -43,7 +43,7 @@ impl VecSort {
         name: Symbol,
         args: &[Expr],
     ) -> Result<ArcSort, TypeError> {
-        if let [Expr::Var((), e)] = args {
+        if let [Expr::Var(_, e)] = args {
             let e = typeinfo.sorts.get(e).ok_or(TypeError::UndefinedSort(*e))?;
 
             if e.is_eq_container_sort() {
@@ -168,35 +168,38 @@ impl Sort for VecSort {
     fn make_expr(&self, egraph: &EGraph, value: Value) -> (Cost, Expr) {
         let mut termdag = TermDag::default();
         let extractor = Extractor::new(egraph, &mut termdag);
-        self.extract_expr(egraph, value, &extractor, &mut termdag)
+        extractor
+            .find_best_container(self, value)
             .expect("Extraction should be successful since extractor has been fully initialized")
     }
 
-    fn extract_expr(
-        &self,
-        _egraph: &EGraph,
-        value: Value,
-        extractor: &Extractor,
-        termdag: &mut TermDag,
-    ) -> Option<(Cost, Expr)> {
-        let vec = ValueVec::load(self, &value);
-        let mut cost = 0usize;
-
-        if vec.is_empty() {
-            Some((cost, Expr::call("vec-empty", [])))
+    fn reconstruct_expr(&self, _egraph: &EGraph, _value: Value, inner: Vec<Expr>) -> Expr {
+        if inner.is_empty() {
+            Expr::call("vec-empty", [])
         } else {
-            let elems = vec
-                .into_iter()
-                .map(|e| {
-                    let e = extractor.find_best(e, termdag, &self.element)?;
-                    cost = cost.saturating_add(e.0);
-                    Some(termdag.term_to_expr(&e.1))
-                })
-                .collect::<Option<Vec<_>>>()?;
-
-            Some((cost, Expr::call("vec-of", elems)))
+            Expr::call("vec-of", inner)
         }
     }
+
+    fn save_value(&self, value: Value) -> SavedValue {
+        let vec = ValueVec::load(self, &value);
+        SavedValue::Container(
+            vec.into_iter()
+                .map(|e| self.element.save_value(e))
+                .collect(),
+        )
+    }
+
+    fn load_value(&self, egraph: &EGraph, saved: &SavedValue) -> Option<Value> {
+        let SavedValue::Container(elems) = saved else {
+            return None;
+        };
+        let vec = elems
+            .iter()
+            .map(|e| self.element.load_value(egraph, e))
+            .collect::<Option<ValueVec>>()?;
+        vec.store(self)
+    }
 }
 
 impl IntoSort for ValueVec {
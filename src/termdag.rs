@@ -1,5 +1,5 @@
 use crate::{
-    ast::{Expr, Literal},
+    ast::{Expr, Literal, DUMMY_SPAN},
     util::{HashMap, HashSet},
     Symbol,
 };
@@ -96,6 +96,52 @@ impl TermDag {
         node
     }
 
+    /// Remove the nodes added after the DAG had `size` nodes.
+    pub(crate) fn truncate(&mut self, size: usize) {
+        for node in self.nodes.drain(size.min(self.nodes.len())..) {
+            self.hashcons.remove(&node);
+        }
+    }
+
+    /// Drop the nodes that none of the given terms use, updating the terms
+    /// to the new ids.
+    pub(crate) fn retain<'a>(&mut self, terms: impl IntoIterator<Item = &'a mut Term>) {
+        let mut terms: Vec<&mut Term> = terms.into_iter().collect();
+        let mut live = vec![false; self.nodes.len()];
+        let mut stack: Vec<TermId> = terms.iter().map(|term| self.lookup(term)).collect();
+        while let Some(id) = stack.pop() {
+            if !std::mem::replace(&mut live[id], true) {
+                if let Term::App(_, children) = &self.nodes[id] {
+                    stack.extend(children);
+                }
+            }
+        }
+
+        // children are added before their parents, so one pass renumbers them
+        let old = std::mem::take(self);
+        let mut new_ids = vec![0; old.nodes.len()];
+        for (id, node) in old.nodes.into_iter().enumerate() {
+            if !live[id] {
+                continue;
+            }
+            let node = match node {
+                Term::App(sym, children) => {
+                    Term::App(sym, children.iter().map(|child| new_ids[*child]).collect())
+                }
+                node => node,
+            };
+            new_ids[id] = self.nodes.len();
+            self.add_node(&node);
+        }
+        for term in terms.iter_mut() {
+            if let Term::App(_, children) = term {
+                for child in children.iter_mut() {
+                    *child = new_ids[*child];
+                }
+            }
+        }
+    }
+
     fn add_node(&mut self, node: &Term) {
         if self.hashcons.get(node).is_none() {
             let idx = self.nodes.len();
@@ -111,9 +157,9 @@ impl TermDag {
     /// share subterms.
     pub fn expr_to_term(&mut self, expr: &Expr) -> Term {
         let res = match expr {
-            Expr::Lit((), lit) => Term::Lit(lit.clone()),
-            Expr::Var((), v) => Term::Var(*v),
-            Expr::Call((), op, args) => {
+            Expr::Lit(_, lit) => Term::Lit(lit.clone()),
+            Expr::Var(_, v) => Term::Var(*v),
+            Expr::Call(_, op, args) => {
                 let args = args
                     .iter()
                     .map(|a| {
@@ -133,8 +179,8 @@ impl TermDag {
     /// Panics if the term contains subterms that are not in the DAG.
     pub fn term_to_expr(&self, term: &Term) -> Expr {
         match term {
-            Term::Lit(lit) => Expr::Lit((), lit.clone()),
-            Term::Var(v) => Expr::Var((), *v),
+            Term::Lit(lit) => Expr::Lit(DUMMY_SPAN.clone(), lit.clone()),
+            Term::Var(v) => Expr::Var(DUMMY_SPAN.clone(), *v),
             Term::App(op, args) => {
                 let args = args
                     .iter()
@@ -143,7 +189,7 @@ impl TermDag {
                         self.term_to_expr(&term)
                     })
                     .collect();
-                Expr::Call((), *op, args)
+                Expr::Call(DUMMY_SPAN.clone(), *op, args)
             }
         }
     }
@@ -233,7 +279,7 @@ mod tests {
         let (td, t) = parse_term(s);
         match_term_app!(t; {
             ("f", [_, x, _, _]) =>
-                assert_eq!(td.term_to_expr(&td.get(*x)), ast::Expr::Var((), Symbol::new("x"))),
+                assert_eq!(td.term_to_expr(&td.get(*x)), ast::Expr::Var(DUMMY_SPAN.clone(), Symbol::new("x"))),
             (head, _) => panic!("unexpected head {}, in {}:{}:{}", head, file!(), line!(), column!())
         })
     }
@@ -263,4 +309,14 @@ mod tests {
         let t2 = td.app("f".into(), vec![g.clone(), x, seven, g]);
         assert_eq!(t, t2);
     }
+
+    #[test]
+    fn test_retain() {
+        let (mut td, _) = parse_term("(f (g x y) z)");
+        let mut t = td.expr_to_term(&crate::ast::parse_expr("(h (g x y) x)").unwrap());
+        assert_eq!(td.size(), 6);
+        td.retain([&mut t]);
+        assert_eq!(td.size(), 4);
+        assert_eq!(td.to_string(&t), "(h (g x y) x)");
+    }
 }
@@ -190,7 +190,7 @@ impl TypeInfo {
                     // not a global reference, but a global binding
                     is_global_ref: false,
                 };
-                ResolvedNCommand::CoreAction(ResolvedAction::Let((), var, expr))
+                ResolvedNCommand::CoreAction(ResolvedAction::Let(DUMMY_SPAN.clone(), var, expr))
             }
             NCommand::CoreAction(action) => {
                 ResolvedNCommand::CoreAction(self.typecheck_action(action, &Default::default())?)
@@ -212,6 +212,26 @@ impl TypeInfo {
             }
             NCommand::PrintOverallStatistics => ResolvedNCommand::PrintOverallStatistics,
             NCommand::CheckProof => ResolvedNCommand::CheckProof,
+            NCommand::Explain(lhs, rhs) => {
+                let lhs = self.typecheck_expr(lhs, &Default::default())?;
+                let rhs = self.typecheck_expr(rhs, &Default::default())?;
+                let (lhs_sort, rhs_sort) = (lhs.output_type(self), rhs.output_type(self));
+                if lhs_sort.name() != rhs_sort.name() {
+                    return Err(TypeError::TypeMismatch(lhs_sort, rhs_sort));
+                }
+                ResolvedNCommand::Explain(lhs, rhs)
+            }
+            NCommand::Extract { expr, options } => {
+                for name in options.forbid.iter().chain(&options.require_root) {
+                    if !self.func_types.contains_key(name) {
+                        return Err(TypeError::UnboundFunction(*name));
+                    }
+                }
+                ResolvedNCommand::Extract {
+                    expr: self.typecheck_expr(expr, &Default::default())?,
+                    options: options.clone(),
+                }
+            }
             NCommand::PrintTable(table, size) => ResolvedNCommand::PrintTable(*table, *size),
             NCommand::PrintSize(n) => {
                 // Should probably also resolve the function symbol here
@@ -227,10 +247,52 @@ impl TypeInfo {
                     exprs,
                 }
             }
-            NCommand::Input { name, file } => ResolvedNCommand::Input {
-                name: *name,
-                file: file.clone(),
-            },
+            NCommand::Input {
+                name,
+                file,
+                options,
+            } => {
+                if self.lookup_user_func(*name).is_none() {
+                    return Err(TypeError::UnboundFunction(*name));
+                }
+                ResolvedNCommand::Input {
+                    name: *name,
+                    file: file.clone(),
+                    options: options.clone(),
+                }
+            }
+            NCommand::InputJson {
+                name,
+                file,
+                columns,
+            } => {
+                if self.lookup_user_func(*name).is_none() {
+                    return Err(TypeError::UnboundFunction(*name));
+                }
+                ResolvedNCommand::InputJson {
+                    name: *name,
+                    file: file.clone(),
+                    columns: columns.clone(),
+                }
+            }
+            NCommand::Save(file) => ResolvedNCommand::Save(file.clone()),
+            NCommand::Load(file) => ResolvedNCommand::Load(file.clone()),
+            NCommand::ImportEgraph(file) => ResolvedNCommand::ImportEgraph(file.clone()),
+            NCommand::Dump(file) => ResolvedNCommand::Dump(file.clone()),
+            NCommand::ExportFunction {
+                name,
+                file,
+                options,
+            } => {
+                if !self.func_types.contains_key(name) {
+                    return Err(TypeError::UnboundFunction(*name));
+                }
+                ResolvedNCommand::ExportFunction {
+                    name: *name,
+                    file: file.clone(),
+                    options: options.clone(),
+                }
+            }
         };
         Ok(command)
     }
@@ -246,6 +308,21 @@ impl TypeInfo {
             return Err(TypeError::PrimitiveAlreadyBound(fdecl.name));
         }
         let ftype = self.function_to_functype(fdecl)?;
+        if let Some(FunctionCost::Table(table)) = fdecl.cost {
+            let table_type = self
+                .func_types
+                .get(&table)
+                .ok_or(TypeError::UnboundFunction(table))?;
+            let names = |sorts: &[ArcSort]| sorts.iter().map(|s| s.name()).collect::<Vec<_>>();
+            if table_type.output.name() != "i64".into()
+                || names(&table_type.input) != names(&ftype.input)
+            {
+                return Err(TypeError::BadCostTable {
+                    function: fdecl.name,
+                    table,
+                });
+            }
+        }
         if self.func_types.insert(fdecl.name, ftype).is_some() {
             return Err(TypeError::FunctionAlreadyBound(fdecl.name));
         }
@@ -288,7 +365,11 @@ impl TypeInfo {
             Schedule::Saturate(schedule) => {
                 ResolvedSchedule::Saturate(Box::new(self.typecheck_schedule(schedule)?))
             }
-            Schedule::Run(RunConfig { ruleset, until }) => {
+            Schedule::Run(RunConfig {
+                ruleset,
+                until,
+                backoff,
+            }) => {
                 let until = until
                     .as_ref()
                     .map(|facts| self.typecheck_facts(facts))
@@ -296,6 +377,7 @@ impl TypeInfo {
                 ResolvedSchedule::Run(ResolvedRunConfig {
                     ruleset: *ruleset,
                     until,
+                    backoff: *backoff,
                 })
             }
         };
@@ -398,12 +480,12 @@ impl TypeInfo {
         Ok(annotated_actions)
     }
 
-    fn typecheck_expr(
+    pub(crate) fn typecheck_expr(
         &self,
         expr: &Expr,
         binding: &IndexMap<Symbol, ArcSort>,
     ) -> Result<ResolvedExpr, TypeError> {
-        let action = Action::Expr((), expr.clone());
+        let action = Action::Expr(DUMMY_SPAN.clone(), expr.clone());
         let typechecked_action = self.typecheck_action(&action, binding)?;
         match typechecked_action {
             ResolvedAction::Expr(_, expr) => Ok(expr),
@@ -464,6 +546,10 @@ pub enum TypeError {
     UnboundFunction(Symbol),
     #[error("Function already bound {0}")]
     FunctionAlreadyBound(Symbol),
+    #[error(
+        "Cost table {table} of {function} must have the same inputs as {function} and output i64"
+    )]
+    BadCostTable { function: Symbol, table: Symbol },
     #[error("Function declarations are not allowed after a push.")]
     FunctionAfterPush(Symbol),
     #[error("Cannot set the datatype {} to a value. Did you mean to use union?", .0.name)]
@@ -509,8 +595,24 @@ mod test {
        ",
         );
         assert!(matches!(
-            res,
-            Err(Error::TypeError(TypeError::Arity { expected: 2, .. }))
+            res.unwrap_err().inner(),
+            Error::TypeError(TypeError::Arity { expected: 2, .. })
+        ));
+    }
+
+    #[test]
+    fn test_bad_cost_table() {
+        let mut egraph = EGraph::default();
+
+        let res = egraph.parse_and_run_program(
+            "
+            (function wide-cost (i64 i64) i64)
+            (function f (i64) i64 :cost wide-cost)
+       ",
+        );
+        assert!(matches!(
+            res.unwrap_err().inner(),
+            Error::TypeError(TypeError::BadCostTable { .. })
         ));
     }
 }
@@ -2,6 +2,12 @@
 //! halving for compression.
 //!
 //! This implementation uses interior mutability for `find`.
+//!
+//! The union-find can be rolled back to a [`Checkpoint`]. While one is open,
+//! unions that change the parent of an id that existed when it was taken are
+//! logged, and path compression leaves those ids alone, so that undoing the
+//! logged unions restores every class exactly.
+use crate::proofs::{self, Justification, ProofForest};
 use crate::util::HashMap;
 use crate::{Id, Symbol, Value};
 
@@ -14,8 +20,40 @@ use std::mem;
 pub struct UnionFind {
     parents: Vec<Cell<Id>>,
     n_unions: usize,
+    /// Ids created since the last rebuild, by sort. These only guide when
+    /// to rebuild, so they are not saved.
+    #[cfg_attr(feature = "serde-1", serde(skip))]
+    recent_ids: HashMap<Symbol, Vec<Id>>,
+    #[cfg_attr(feature = "serde-1", serde(skip))]
+    staged_ids: HashMap<Symbol, Vec<Id>>,
+    /// The justifications for unions, kept only when proofs are enabled.
+    #[cfg_attr(feature = "serde-1", serde(skip))]
+    proofs: Option<ProofForest>,
+    /// Unions that change the parents of ids below this bound are logged, so
+    /// that they can be undone, and these ids are not compressed. Zero when
+    /// there is no open checkpoint.
+    #[cfg_attr(feature = "serde-1", serde(skip))]
+    log_below: usize,
+    /// The ids whose parents changed, with their previous parents.
+    #[cfg_attr(feature = "serde-1", serde(skip))]
+    undo_log: Vec<(Id, Id)>,
+    /// The number of entries dropped from the front of `undo_log` when the
+    /// outermost checkpoint was forgotten, which the positions in
+    /// checkpoints count.
+    #[cfg_attr(feature = "serde-1", serde(skip))]
+    log_start: usize,
+}
+
+/// The state of a [`UnionFind`] that [`UnionFind::rollback`] returns to.
+#[derive(Debug, Clone)]
+pub(crate) struct Checkpoint {
+    num_ids: usize,
+    n_unions: usize,
+    log_len: usize,
+    prev_log_below: usize,
     recent_ids: HashMap<Symbol, Vec<Id>>,
     staged_ids: HashMap<Symbol, Vec<Id>>,
+    proofs: Option<proofs::Checkpoint>,
 }
 
 impl UnionFind {
@@ -25,13 +63,145 @@ impl UnionFind {
         self.n_unions
     }
 
+    /// The number of ids made so far.
+    pub(crate) fn num_ids(&self) -> usize {
+        self.parents.len()
+    }
+
+    /// A rough estimate of the memory used by the union-find, in bytes.
+    pub(crate) fn approx_bytes(&self) -> usize {
+        self.parents.capacity() * std::mem::size_of::<Cell<Id>>()
+    }
+
+    /// Start logging changes, so that the union-find can be rolled back to its
+    /// current state.
+    pub(crate) fn checkpoint(&mut self) -> Checkpoint {
+        let checkpoint = Checkpoint {
+            num_ids: self.parents.len(),
+            n_unions: self.n_unions,
+            log_len: self.log_start + self.undo_log.len(),
+            prev_log_below: self.log_below,
+            recent_ids: self.recent_ids.clone(),
+            staged_ids: self.staged_ids.clone(),
+            proofs: self.proofs.as_mut().map(ProofForest::checkpoint),
+        };
+        self.log_below = self.parents.len();
+        checkpoint
+    }
+
+    /// Undo every change made since the given checkpoint was taken, which
+    /// must be the most recent open checkpoint.
+    pub(crate) fn rollback(&mut self, checkpoint: Checkpoint) {
+        for (id, parent) in self
+            .undo_log
+            .drain(checkpoint.log_len - self.log_start..)
+            .rev()
+        {
+            self.parents[usize::from(id)].set(parent);
+        }
+        self.parents.truncate(checkpoint.num_ids);
+        self.n_unions = checkpoint.n_unions;
+        self.log_below = checkpoint.prev_log_below;
+        self.recent_ids = checkpoint.recent_ids;
+        self.staged_ids = checkpoint.staged_ids;
+        match (&mut self.proofs, checkpoint.proofs) {
+            (Some(forest), Some(checkpoint)) => forest.rollback(checkpoint),
+            // proofs were enabled since the checkpoint
+            (_, None) => self.proofs = None,
+            (None, Some(_)) => unreachable!("proofs cannot be disabled"),
+        }
+    }
+
+    /// Forget an open checkpoint, keeping the changes made since it was
+    /// taken, given the checkpoints taken just before and after it. Rolling
+    /// back to the one before then undoes those changes as well.
+    pub(crate) fn forget(
+        &mut self,
+        prev: Option<&Checkpoint>,
+        checkpoint: Checkpoint,
+        next: Option<&mut Checkpoint>,
+    ) {
+        if let (Some(forest), Some(_)) = (&mut self.proofs, &checkpoint.proofs) {
+            let prev = prev.and_then(|prev| prev.proofs.as_ref());
+            forest.forget(prev, next.as_ref().and_then(|next| next.proofs.as_ref()));
+        }
+        if prev.is_none() {
+            // nothing rolls back to before the next checkpoint anymore
+            let end = next
+                .as_ref()
+                .map_or(self.log_start + self.undo_log.len(), |next| next.log_len);
+            self.undo_log.drain(..end - self.log_start);
+            self.log_start = end;
+        }
+        // ids created since the previous checkpoint are dropped by rolling
+        // back to it, so they no longer need to be logged
+        match next {
+            Some(next) => next.prev_log_below = checkpoint.prev_log_below,
+            None => self.log_below = checkpoint.prev_log_below,
+        }
+    }
+
+    fn set_parent(&mut self, id: Id, parent: Id) {
+        if usize::from(id) < self.log_below {
+            let prev = self.parent(id).get();
+            self.undo_log.push((id, prev));
+        }
+        self.parent(id).set(parent);
+    }
+
     /// Create a fresh [`Id`].
     pub fn make_set(&mut self) -> Id {
         let res = Id::from(self.parents.len());
         self.parents.push(Cell::new(res));
+        if let Some(proofs) = &mut self.proofs {
+            proofs.add_id(res);
+        }
         res
     }
 
+    /// Start recording justifications for unions. Unions performed before
+    /// this call cannot be explained.
+    pub(crate) fn enable_proofs(&mut self) {
+        if self.proofs.is_none() {
+            self.proofs = Some(ProofForest::new(self.parents.len()));
+        }
+    }
+
+    pub(crate) fn proofs(&self) -> Option<&ProofForest> {
+        self.proofs.as_ref()
+    }
+
+    /// Set the justification recorded for subsequent unions.
+    pub(crate) fn set_reason(&mut self, reason: Justification) {
+        if let Some(proofs) = &mut self.proofs {
+            proofs.reason = reason;
+        }
+    }
+
+    /// Remember that `id` was created for the row `func(inputs)`.
+    pub(crate) fn record_node(&mut self, id: Id, func: Symbol, inputs: &[Value]) {
+        if let Some(proofs) = &mut self.proofs {
+            proofs.record_node(id, func, inputs);
+        }
+    }
+
+    /// Remember that the row `func(inputs)` was set to an existing value,
+    /// by giving the row an id of its own and unioning it with that value.
+    pub(crate) fn record_row(&mut self, func: Symbol, inputs: &[Value], value: Value) {
+        if self.proofs.is_some() {
+            let id = self.make_set();
+            self.record_node(id, func, inputs);
+            self.union(Id::from(value.bits as usize), id, value.tag);
+        }
+    }
+
+    /// Track a row that rebuilding moved from `old` to `new` inputs.
+    pub(crate) fn move_row(&mut self, func: Symbol, old: &[Value], new: &[Value]) {
+        if let Some(proofs) = &mut self.proofs {
+            proofs.move_origin(func, old, new);
+        }
+    }
+
     /// The number of ids that recently stopped being canonical.
     pub fn new_ids(&self, sort_filter: impl Fn(Symbol) -> bool) -> usize {
         self.recent_ids
@@ -70,15 +240,20 @@ impl UnionFind {
 
     /// Look up the canonical representative for the given [`Id`].
     pub fn find(&self, id: Id) -> Id {
-        let mut cur = self.parent(id);
+        let mut cur = id;
         loop {
-            let next = self.parent(cur.get());
-            if cur.get() == next.get() {
-                return cur.get();
+            let next = self.parent(cur).get();
+            let grand = self.parent(next).get();
+            if next == grand {
+                return next;
+            }
+            // Path halving, except for ids that a rollback must restore:
+            // their new parent may only be reachable through a union that
+            // the rollback undoes
+            if usize::from(cur) >= self.log_below {
+                let great = self.parent(grand).get();
+                self.parent(cur).set(great);
             }
-            // Path halving
-            let grand = self.parent(next.get());
-            cur.set(grand.get());
             cur = grand;
         }
     }
@@ -104,11 +279,45 @@ impl UnionFind {
     pub fn union(&mut self, id1: Id, id2: Id, sort: Symbol) -> Id {
         let (res, reparented) = self.do_union(id1, id2);
         if let Some(id) = reparented {
-            self.staged_ids.entry(sort).or_default().push(id)
+            self.staged_ids.entry(sort).or_default().push(id);
+            if let Some(proofs) = &mut self.proofs {
+                let reason = proofs.reason.clone();
+                proofs.add_edge(id1, id2, reason);
+            }
         }
         res
     }
 
+    /// Like [`union_values`], for the outputs of the rows `func(inputs1)` and
+    /// `func(inputs2)`, which rebuilding found to have equal inputs.
+    ///
+    /// [`union_values`]: UnionFind::union_values
+    pub(crate) fn union_congruent(
+        &mut self,
+        val1: Value,
+        val2: Value,
+        sort: Symbol,
+        func: Symbol,
+        inputs1: &[Value],
+        inputs2: &[Value],
+    ) -> Value {
+        let id1 = Id::from(val1.bits as usize);
+        let id2 = Id::from(val2.bits as usize);
+        let (res, reparented) = self.do_union(id1, id2);
+        if let Some(id) = reparented {
+            self.staged_ids.entry(sort).or_default().push(id);
+            if let Some(proofs) = &mut self.proofs {
+                let node1 = proofs.origin(func, inputs1).unwrap_or(id1);
+                let node2 = proofs.origin(func, inputs2).unwrap_or(id2);
+                proofs.add_edge(node1, node2, Justification::Congruence);
+            }
+        }
+        Value {
+            bits: usize::from(res) as u64,
+            tag: val1.tag,
+        }
+    }
+
     /// Merge the underlying equivalence classes for the two ids.
     ///
     /// This method does not update any metadata related to timestamps or sorts;
@@ -123,7 +332,7 @@ impl UnionFind {
         let id1 = self.find(id1);
         let id2 = self.find(id2);
         if id1 != id2 {
-            self.parent(id2).set(id1);
+            self.set_parent(id2, id1);
             self.n_unions += 1;
             (id1, Some(id2))
         } else {
@@ -85,10 +85,28 @@ impl SymbolGen {
         }
     }
 
+    /// A generator that continues after `count` generated symbols,
+    /// so that it does not repeat them.
+    pub(crate) fn resume(reserved_string: String, count: usize) -> Self {
+        Self {
+            gen: count,
+            ..Self::new(reserved_string)
+        }
+    }
+
     pub(crate) fn has_been_used(&self) -> bool {
         self.gen > 0
     }
 
+    /// The number of symbols generated so far.
+    pub(crate) fn count(&self) -> usize {
+        self.gen
+    }
+
+    pub(crate) fn reserved_string(&self) -> &str {
+        &self.reserved_string
+    }
+
     pub(crate) fn generate_special(&mut self, sym: &Symbol) -> Symbol {
         match self.special_reserved.get(sym) {
             Some(res) => res.into(),
@@ -7,6 +7,8 @@ use libtest_mimic::Trial;
 struct Run {
     path: PathBuf,
     resugar: bool,
+    format: bool,
+    proofs: bool,
 }
 
 impl Run {
@@ -15,7 +17,11 @@ impl Run {
         let program = std::fs::read_to_string(&self.path)
             .unwrap_or_else(|err| panic!("Couldn't read {:?}: {:?}", self.path, err));
 
-        if !self.resugar {
+        if self.format {
+            self.test_format(&program);
+        } else if self.proofs {
+            self.test_proofs(&program);
+        } else if !self.resugar {
             self.test_program(&program, "Top level error");
         } else {
             let mut egraph = EGraph::default();
@@ -58,6 +64,47 @@ impl Run {
         };
     }
 
+    /// Run the program with proofs enabled and check every proof it
+    /// recorded. Programs with container sorts must be rejected before
+    /// they start running.
+    fn test_proofs(&self, program: &str) {
+        let mut egraph = EGraph::default();
+        egraph.set_reserved_symbol("___".into());
+        let program = format!("(set-option enable_proofs 1)\n{program}\n(check-proof)");
+        match egraph.parse_and_run_program(&program) {
+            Ok(_) => {}
+            Err(Error::ProofError(msg))
+                if msg.starts_with("Proofs are not supported with container sort") =>
+            {
+                assert_eq!(egraph.num_tuples(), 0, "Program ran before being rejected");
+            }
+            Err(err) => panic!("Proofs failed: {err}"),
+        }
+    }
+
+    fn test_format(&self, program: &str) {
+        let formatted = match format_program(program) {
+            Ok(formatted) => formatted,
+            Err(_) if self.should_fail() => return,
+            Err(err) => panic!("Formatting failed: {err}"),
+        };
+        assert_eq!(
+            format_program(&formatted).unwrap(),
+            formatted,
+            "Formatting is not idempotent"
+        );
+        let egraph = EGraph::default();
+        let commands = |program: &str| -> Vec<String> {
+            let commands = egraph.parse_program(program).unwrap();
+            commands.iter().map(|command| command.to_string()).collect()
+        };
+        assert_eq!(
+            commands(&formatted),
+            commands(program),
+            "Formatting changed the program"
+        );
+    }
+
     fn into_trial(self) -> Trial {
         let name = self.name().to_string();
         Trial::test(name, move || {
@@ -76,6 +123,12 @@ impl Run {
                 if self.0.resugar {
                     write!(f, "_resugar")?;
                 }
+                if self.0.format {
+                    write!(f, "_fmt")?;
+                }
+                if self.0.proofs {
+                    write!(f, "_proofs")?;
+                }
                 Ok(())
             }
         }
@@ -95,15 +148,25 @@ fn generate_tests(glob: &str) -> Vec<Trial> {
         let run = Run {
             path: entry.unwrap().clone(),
             resugar: false,
+            format: false,
+            proofs: false,
         };
         let should_fail = run.should_fail();
 
         push_trial(run.clone());
+        push_trial(Run {
+            format: true,
+            ..run.clone()
+        });
         if !should_fail {
             push_trial(Run {
                 resugar: true,
                 ..run.clone()
             });
+            push_trial(Run {
+                proofs: true,
+                ..run.clone()
+            });
         }
     }
 
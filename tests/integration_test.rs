@@ -1,4 +1,10 @@
-use egglog::{ast::Expr, EGraph, ExtractReport, Function, Term, Value};
+use egglog::{
+    ast::{Command, ExportFormat, Expr, ExtractMode, Literal},
+    format_program,
+    sort::Sort,
+    CostFunction, EGraph, Error, ExtractReport, Function, Proof, Rewrite, RunProgress,
+    SerializeConfig, StopReason, Term, TermDag, Value,
+};
 use symbol_table::GlobalSymbol;
 
 #[test]
@@ -107,7 +113,7 @@ fn test_subsumed_unextractable_rebuild_arg() {
         panic!();
     };
     let expr = termdag.term_to_expr(&term);
-    assert_eq!(expr, Expr::Call((), GlobalSymbol::from("exp"), vec![]));
+    assert_eq!(expr, Expr::call("exp", []));
 }
 
 #[test]
@@ -155,7 +161,7 @@ fn test_subsumed_unextractable_rebuild_self() {
         panic!();
     };
     let expr = termdag.term_to_expr(&term);
-    assert_eq!(expr, Expr::Call((), GlobalSymbol::from("exp"), vec![]));
+    assert_eq!(expr, Expr::call("exp", []));
 }
 
 #[test]
@@ -360,3 +366,1253 @@ fn test_cant_subsume_merge() {
     );
     assert!(res.is_err());
 }
+
+#[test]
+fn test_proofs() {
+    use std::rc::Rc;
+
+    let mut egraph = EGraph::default();
+    egraph
+        .parse_and_run_program(
+            r#"
+            (set-option enable_proofs 1)
+            (datatype Math (Num i64) (Add Math Math))
+            (rewrite (Add a b) (Add b a))
+            (Add (Num 1) (Num 2))
+            (Add (Num 3) (Num 3))
+            (run 1)
+            (union (Num 3) (Num 4))
+            (check (= (Add (Num 1) (Num 2)) (Add (Num 2) (Num 1))))
+            (check (= (Add (Num 3) (Num 3)) (Add (Num 4) (Num 3))))
+            (extract (Add (Num 2) (Num 1)))
+            (check-proof)
+            "#,
+        )
+        .unwrap();
+
+    let (termdag, proofs) = egraph.get_proofs();
+    assert_eq!(proofs.len(), 3);
+    assert_eq!(termdag.to_string(proofs[0].lhs()), "(Add (Num 1) (Num 2))");
+    assert_eq!(termdag.to_string(proofs[0].rhs()), "(Add (Num 2) (Num 1))");
+    assert!(matches!(proofs[0], Proof::Rule { .. }));
+    let printed = proofs[1].to_string(termdag);
+    assert!(printed.contains("Congruence"), "{printed}");
+    assert!(printed.contains("(Action "), "{printed}");
+    assert_eq!(termdag.to_string(proofs[2].lhs()), "(Add (Num 2) (Num 1))");
+
+    // a proof whose steps do not connect is rejected
+    let mut termdag = termdag.clone();
+    let one = termdag.lit(Literal::Int(1));
+    let two = termdag.lit(Literal::Int(2));
+    let num_one = termdag.app("Num".into(), vec![one]);
+    let num_two = termdag.app("Num".into(), vec![two]);
+    let bogus = Proof::Transitivity(vec![
+        Rc::new(Proof::Refl(num_one)),
+        Rc::new(Proof::Refl(num_two)),
+    ]);
+    assert!(egraph.check_proof(&termdag, &bogus).is_err());
+
+    // (Num 3) and (Num 4) are equal in the database, but the rule's actions
+    // do not build them, and no action unions (Add (Num 1) (Num 2)) with
+    // (Add (Num 2) (Num 1))
+    let Proof::Rule { rule, subst, .. } = &proofs[0] else {
+        unreachable!()
+    };
+    let three = termdag.lit(Literal::Int(3));
+    let four = termdag.lit(Literal::Int(4));
+    let num_three = termdag.app("Num".into(), vec![three]);
+    let num_four = termdag.app("Num".into(), vec![four]);
+    let forged_rule = Proof::Rule {
+        rule: *rule,
+        subst: subst.clone(),
+        lhs: num_three.clone(),
+        rhs: num_four.clone(),
+    };
+    assert!(egraph.check_proof(&termdag, &forged_rule).is_err());
+    let forged_action = Proof::Action {
+        lhs: proofs[0].lhs().clone(),
+        rhs: proofs[0].rhs().clone(),
+    };
+    assert!(egraph.check_proof(&termdag, &forged_action).is_err());
+    let action = Proof::Action {
+        lhs: num_three,
+        rhs: num_four,
+    };
+    egraph.check_proof(&termdag, &action).unwrap();
+}
+
+#[test]
+fn test_explain_equal() {
+    let mut egraph = EGraph::default();
+    egraph
+        .parse_and_run_program(
+            r#"
+            (set-option enable_proofs 1)
+            (datatype Math (Num i64) (Var String) (Add Math Math) (Mul Math Math))
+            (rewrite (Add a b) (Add b a))
+            (rule ((= e (Mul a (Num 1)))) ((union e a)))
+            (Mul (Add (Var "x") (Num 0)) (Num 1))
+            (run 2)
+            "#,
+        )
+        .unwrap();
+    let num = |n: i64| Expr::call("Num", [Expr::lit(n)]);
+    let x = || Expr::call("Var", [Expr::lit(Literal::String("x".into()))]);
+    let product = Expr::call("Mul", [Expr::call("Add", [x(), num(0)]), num(1)]);
+    let flipped = Expr::call("Add", [num(0), x()]);
+    let explanation = egraph.explain_equal(&product, &flipped).unwrap();
+    let termdag = &explanation.termdag;
+
+    let steps: Vec<_> = explanation
+        .steps
+        .iter()
+        .map(|(rewrite, term)| (rewrite.to_string(), termdag.to_string(term)))
+        .collect();
+    assert_eq!(
+        steps,
+        [
+            (
+                "rule (rule ((= e (Mul a (Num 1)))) ((union e a)))".to_string(),
+                r#"(Add (Var "x") (Num 0))"#.to_string()
+            ),
+            (
+                "rule (rule ((= rewrite_var__ (Add a b))) ((union rewrite_var__ (Add b a))))"
+                    .to_string(),
+                r#"(Add (Num 0) (Var "x"))"#.to_string()
+            ),
+        ]
+    );
+
+    // each rule step records the terms its variables matched
+    let Rewrite::Rule { subst, .. } = &explanation.steps[0].0 else {
+        panic!("expected a rule step")
+    };
+    let subst: Vec<_> = subst
+        .iter()
+        .map(|(var, term)| (var.as_str(), termdag.to_string(term)))
+        .collect();
+    assert!(subst.contains(&("a", r#"(Add (Var "x") (Num 0))"#.to_string())));
+    assert!(subst.contains(&("e", r#"(Mul (Add (Var "x") (Num 0)) (Num 1))"#.to_string())));
+    assert!(explanation
+        .to_string()
+        .contains(r#"with a = (Add (Var "x") (Num 0))"#));
+}
+
+#[test]
+fn test_proof_failure_is_an_error() {
+    let mut egraph = EGraph::default();
+    let res = egraph.parse_and_run_program(
+        "
+        (set-option enable_proofs 1)
+        (datatype Math (Num i64))
+        (sort MathVec (Vec Math))
+        (function f (MathVec) Math)
+        (union (f (vec-of (Num 1))) (Num 2))
+        (check (= (f (vec-of (Num 1))) (Num 2)))
+        ",
+    );
+    assert!(matches!(res.unwrap_err().inner(), Error::ProofError(_)));
+    // the program is rejected before any of it runs
+    assert_eq!(egraph.num_tuples(), 0);
+}
+
+#[test]
+fn test_proof_of_rule_whose_query_no_longer_holds() {
+    // The rule fires while (Num 1) and (Num 2) differ, and its step is
+    // checked against that database even though they are equal later.
+    let mut egraph = EGraph::default();
+    egraph
+        .parse_and_run_program(
+            "
+            (set-option enable_proofs 1)
+            (datatype Math (Num i64) (Pair Math Math))
+            (rule ((Pair a b) (!= a b)) ((union (Pair a b) (Num 0))))
+            (Pair (Num 1) (Num 2))
+            (run 1)
+            (union (Num 1) (Num 2))
+            (check (= (Pair (Num 1) (Num 2)) (Num 0)))
+            (check-proof)
+            ",
+        )
+        .unwrap();
+    let (_, proofs) = egraph.get_proofs();
+    assert!(matches!(proofs[..], [Proof::Rule { .. }]));
+}
+
+#[test]
+fn test_stop_reasons() {
+    // Every Num spawns two more, so the database doubles each iteration.
+    let program = "
+        (datatype Math (Num i64))
+        (rule ((Num n)) ((Num (+ n 1)) (Num (* n 2))))
+        (Num 1)
+    ";
+    let stop_reason = |setup: &str, schedule: &str| {
+        let mut egraph = EGraph::default();
+        egraph.parse_and_run_program(program).unwrap();
+        egraph.parse_and_run_program(setup).unwrap();
+        egraph.parse_and_run_program(schedule).unwrap();
+        egraph.get_run_report().as_ref().unwrap().stop_reason
+    };
+
+    let saturate = "(run-schedule (saturate (run)))";
+    assert_eq!(stop_reason("", "(run 3)"), StopReason::IterationLimit);
+    assert_eq!(
+        stop_reason("", "(run 100 :until (Num 4))"),
+        StopReason::UntilSatisfied
+    );
+    assert_eq!(
+        stop_reason("(set-option node_limit 1000)", saturate),
+        StopReason::NodeLimit
+    );
+    assert_eq!(
+        stop_reason("(set-option time_limit 0)", saturate),
+        StopReason::TimeLimit
+    );
+    assert_eq!(
+        stop_reason("(set-option memory_limit 1)", saturate),
+        StopReason::MemoryLimit
+    );
+    // a rule over the match limit is skipped rather than reported as saturated
+    assert_eq!(
+        stop_reason("(set-option match_limit 1)", saturate),
+        StopReason::MatchLimit
+    );
+    // a limit stops the rest of the schedule too
+    assert_eq!(
+        stop_reason(
+            "(set-option node_limit 1000)",
+            "(run-schedule (repeat 100 (saturate (run)) (run)))"
+        ),
+        StopReason::NodeLimit
+    );
+
+    let mut egraph = EGraph::default();
+    egraph
+        .parse_and_run_program(
+            "(datatype Math (Num i64))
+             (rule ((Num n) (< n 3)) ((Num (+ n 1))))
+             (Num 0)
+             (run 10)",
+        )
+        .unwrap();
+    let report = egraph.get_run_report().as_ref().unwrap();
+    assert_eq!(report.stop_reason, StopReason::Saturated);
+}
+
+#[test]
+fn test_progress_and_cancellation() {
+    use std::{cell::RefCell, rc::Rc};
+
+    let mut egraph = EGraph::default();
+    egraph
+        .parse_and_run_program(
+            "(datatype Math (Num i64) (Add Math Math))
+             (rule ((Num n)) ((Num (+ n 1)) (union (Add (Num n) (Num 0)) (Num n))))
+             (Num 0)",
+        )
+        .unwrap();
+
+    let progress: Rc<RefCell<Vec<RunProgress>>> = Default::default();
+    let token = egraph.cancellation_token();
+    let log = progress.clone();
+    egraph.set_progress_callback(move |p| {
+        log.borrow_mut().push(p.clone());
+        if p.iteration == 2 {
+            token.cancel();
+        }
+    });
+    let result = egraph.parse_and_run_program("(run-schedule (saturate (run)))");
+    assert!(matches!(result.unwrap_err().inner(), Error::Cancelled));
+    let iterations: Vec<usize> = progress.borrow().iter().map(|p| p.iteration).collect();
+    assert_eq!(iterations, vec![0, 1, 2]);
+    assert!(progress.borrow().iter().all(|p| p.num_unions > 0));
+    assert_eq!(
+        egraph.get_run_report().as_ref().unwrap().stop_reason,
+        StopReason::Cancelled
+    );
+
+    // the e-graph is rebuilt and can keep running
+    egraph.clear_progress_callback();
+    egraph
+        .parse_and_run_program(
+            "(check (= (Add (Num 2) (Num 0)) (Num 2)))
+             (run 1)
+             (check (Num 4))",
+        )
+        .unwrap();
+
+    // cancelling from another thread stops a run that would never saturate
+    let token = egraph.cancellation_token();
+    let canceller = std::thread::spawn(move || {
+        std::thread::sleep(std::time::Duration::from_millis(50));
+        token.cancel();
+    });
+    let result = egraph.parse_and_run_program("(run-schedule (saturate (run)))");
+    canceller.join().unwrap();
+    assert!(matches!(result.unwrap_err().inner(), Error::Cancelled));
+}
+
+#[test]
+fn test_stale_cancel_is_cleared() {
+    let mut egraph = EGraph::default();
+    egraph
+        .parse_and_run_program(
+            "(datatype Math (Num i64))
+             (rule ((Num n)) ((Num (+ n 1))))
+             (Num 0)",
+        )
+        .unwrap();
+
+    // a cancel issued between runs does not stop the next one
+    egraph.cancellation_token().cancel();
+    egraph
+        .parse_and_run_program("(run 2) (check (Num 2))")
+        .unwrap();
+    assert_eq!(
+        egraph.get_run_report().as_ref().unwrap().stop_reason,
+        StopReason::IterationLimit
+    );
+    assert!(!egraph.cancellation_token().is_cancelled());
+}
+
+#[test]
+fn test_cancel_between_runs_of_a_program() {
+    let mut egraph = EGraph::default();
+    egraph
+        .parse_and_run_program(
+            "(datatype Math (Num i64))
+             (rule ((Num n)) ((Num (+ n 1))))
+             (Num 0)",
+        )
+        .unwrap();
+
+    // a cancel issued after the first run of a program stops the second
+    let token = egraph.cancellation_token();
+    egraph.set_progress_callback(move |_| token.cancel());
+    let result = egraph.parse_and_run_program("(run 1) (check (Num 1)) (run 1) (check (Num 2))");
+    assert!(matches!(result.unwrap_err().inner(), Error::Cancelled));
+    assert_eq!(
+        egraph.get_run_report().as_ref().unwrap().stop_reason,
+        StopReason::Cancelled
+    );
+    assert!(!egraph.cancellation_token().is_cancelled());
+
+    // the reported cancel does not carry over into the next program
+    egraph.clear_progress_callback();
+    egraph
+        .parse_and_run_program("(run 1) (check (Num 2))")
+        .unwrap();
+}
+
+#[test]
+fn test_snapshot_round_trip() {
+    let path = std::env::temp_dir().join(format!("egglog-snapshot-{}.json", std::process::id()));
+
+    let mut egraph = EGraph::default();
+    egraph
+        .parse_and_run_program(
+            r#"(datatype Math (Num i64) (Var String) (Add Math Math))
+               (sort MathVec (Vec Math))
+               (sort Names (Set String))
+               (function names (MathVec) Names :merge (set-union old new))
+               (function weight (Math) Rational :merge (min old new))
+               (ruleset comm)
+               (rewrite (Add a b) (Add b a) :ruleset comm)
+               (let x (Add (Var "x") (Num 1)))
+               (rule ((= e (Add (Var v) n))) ((set (names (vec-of e)) (set-of v))))
+               (set (weight x) (rational 1 3))
+               (run-schedule (saturate (run comm)) (run))"#,
+        )
+        .unwrap();
+    egraph.save_snapshot(&path).unwrap();
+
+    let mut loaded = EGraph::default();
+    loaded.load_snapshot(&path).unwrap();
+    assert_eq!(loaded.num_tuples(), egraph.num_tuples());
+    loaded
+        .parse_and_run_program(
+            r#"(check (= x (Add (Num 1) (Var "x"))))
+               (check (= (weight x) (rational 1 3)))
+               (check (= (names (vec-of x)) (set-of "x")))
+               (Add (Var "y") (Num 2))
+               (run comm 1)
+               (check (= (Add (Var "y") (Num 2)) (Add (Num 2) (Var "y"))))"#,
+        )
+        .unwrap();
+
+    // a snapshot can only be loaded into an egraph without declarations
+    assert!(matches!(
+        loaded.load_snapshot(&path),
+        Err(Error::SnapshotError(_))
+    ));
+
+    // the same through commands
+    let mut egraph = EGraph::default();
+    egraph
+        .parse_and_run_program(&format!(
+            r#"(load "{path}")
+               (run 1)
+               (check (names (vec-of (Add (Var "x") (Num 1)))))
+               (save "{path}")"#,
+            path = path.display()
+        ))
+        .unwrap();
+
+    std::fs::remove_file(&path).unwrap();
+}
+
+#[test]
+fn test_file_command_names_are_not_keywords() {
+    let mut egraph = EGraph::default();
+    egraph
+        .parse_and_run_program(
+            r#"(datatype Expr (Var String))
+               (relation load (Expr Expr))
+               (function dump (i64) i64)
+               (load (Var "*p") (Var "p"))
+               (set (dump 1) 2)
+               (rule ((load e p)) ((set (dump 2) 3)))
+               (run 1)
+               (check (= (dump 2) 3))"#,
+        )
+        .unwrap();
+
+    // calls that fit a command are read as the command
+    let commands = egraph
+        .parse_program(
+            r#"(load "a.json")
+               (export-function load "load.csv")
+               (export-function load "load.json" :format json)
+               (input-json dump "dump.json" :columns ("x" "y"))
+               (load (Var "a") (Var "b"))"#,
+        )
+        .unwrap();
+    assert!(matches!(&commands[0], Command::Load(file) if file == "a.json"));
+    assert!(matches!(&commands[1], Command::ExportFunction { file, .. } if file == "load.csv"));
+    assert!(matches!(
+        &commands[2],
+        Command::ExportFunction { options, .. } if options.format == ExportFormat::Json
+    ));
+    assert!(
+        matches!(&commands[3], Command::InputJson { columns: Some(columns), .. } if columns.len() == 2)
+    );
+    assert!(matches!(&commands[4], Command::Action(_)));
+
+    assert!(egraph
+        .parse_program(r#"(load (Var "a") "b" :columns ("x"))"#)
+        .is_err());
+}
+
+#[test]
+fn test_user_function_shadows_file_command() {
+    let mut egraph = EGraph::default();
+    egraph
+        .parse_and_run_program(
+            r#"(function save (String) i64)
+               (set (save "hello") 3)
+               (save "hello")
+               (check (= (save "hello") 3))"#,
+        )
+        .unwrap();
+    assert!(!std::path::Path::new("hello").exists());
+}
+
+#[test]
+fn test_import_serialized() {
+    let path = std::env::temp_dir().join(format!("egglog-import-{}.json", std::process::id()));
+
+    let mut egraph = EGraph::default();
+    egraph
+        .parse_and_run_program(
+            r#"(datatype Math (Num i64) (Var String) (Add Math Math) (Neg Math :cost 3))
+               (sort MathVec (Vec Math))
+               (function sum (MathVec) Math)
+               (function weight (Math) Rational :merge (min old new))
+               (relation leaf (Math))
+               (rewrite (Add a b) (Add b a))
+               (let x (Add (Var "x") (Num 1)))
+               (union (Neg (Num 1)) (Num -1))
+               (union (sum (vec-of (Num 1) x)) (Add (Num 1) x))
+               (set (weight x) (rational 1 3))
+               (leaf (Num 1))
+               (run 1)"#,
+        )
+        .unwrap();
+    egraph
+        .serialize(SerializeConfig::default())
+        .to_json_file(&path)
+        .unwrap();
+
+    // functions are declared from the types of their e-classes
+    let mut imported = EGraph::default();
+    let outputs = imported
+        .parse_and_run_program(&format!(
+            r#"(sort Math)
+               (sort MathVec (Vec Math))
+               (import-egraph "{path}")
+               (check (= (Add (Var "x") (Num 1)) (Add (Num 1) (Var "x"))))
+               (check (= (weight (Add (Var "x") (Num 1))) (rational 1 3)))
+               (check (= (sum (vec-of (Num 1) (Add (Var "x") (Num 1))))
+                         (Add (Num 1) (Add (Var "x") (Num 1)))))
+               (check (leaf (Num 1)))
+               (extract (Num -1))
+               (extract (Neg (Num 1)))"#,
+            path = path.display()
+        ))
+        .unwrap();
+    // the cost of `Neg` was imported too
+    assert_eq!(outputs, ["(Num -1)", "(Num -1)"]);
+
+    // importing into an egraph that declared the program merges with its rows
+    let mut declared = EGraph::default();
+    declared
+        .parse_and_run_program(
+            r#"(datatype Math (Num i64) (Var String) (Add Math Math) (Neg Math :cost 3))
+               (sort MathVec (Vec Math))
+               (function sum (MathVec) Math)
+               (function weight (Math) Rational :merge (min old new))
+               (relation leaf (Math))
+               (set (weight (Num 1)) (rational 1 2))"#,
+        )
+        .unwrap();
+    let serialized = egraph.serialize(SerializeConfig::default());
+    declared.import_serialized(&serialized).unwrap();
+    let num_tuples = declared.num_tuples();
+    // importing the same e-graph again only adds copies of its e-classes,
+    // which are merged with the first copies
+    declared.import_serialized(&serialized).unwrap();
+    assert_eq!(declared.num_tuples(), num_tuples);
+    declared
+        .parse_and_run_program(
+            r#"(check (= (Neg (Num 1)) (Num -1)))
+               (check (= (weight (Num 1)) (rational 1 2)))
+               (check (= (weight (Add (Var "x") (Num 1))) (rational 1 3)))"#,
+        )
+        .unwrap();
+
+    // containers cannot be imported without declaring their sorts
+    let Err(Error::ImportError(message)) = EGraph::from_serialized(&serialized) else {
+        panic!("expected an import error");
+    };
+    assert_eq!(
+        message,
+        "unknown sort MathVec: container sorts must be declared before importing"
+    );
+    let mut small = EGraph::default();
+    small
+        .parse_and_run_program("(datatype Math (Num i64) (Add Math Math)) (Add (Num 1) (Num 2))")
+        .unwrap();
+    let mut imported =
+        EGraph::from_serialized(&small.serialize(SerializeConfig::default())).unwrap();
+    let outputs = imported
+        .parse_and_run_program("(check (Add (Num 1) (Num 2))) (extract (Num 2))")
+        .unwrap();
+    assert_eq!(outputs, ["(Num 2)"]);
+
+    std::fs::remove_file(&path).unwrap();
+}
+
+#[test]
+fn test_dag_extraction() {
+    let mut egraph = EGraph::default();
+    egraph
+        .parse_and_run_program(
+            "(datatype T (F T T) (P T) (Q T) (LA :cost 4) (LB :cost 4) (Big :cost 5))
+             (let a (P (Big)))
+             (union a (LA))
+             (let b (Q (Big)))
+             (union b (LB))
+             (let r (F a b))",
+        )
+        .unwrap();
+    let (sort, value) = egraph.eval_expr(&Expr::var("r")).unwrap();
+    let extract = |mode| {
+        let mut termdag = TermDag::default();
+        let (cost, term) = egraph
+            .extract_with_mode(value, &mut termdag, &sort, mode)
+            .unwrap();
+        (cost, termdag.to_string(&term))
+    };
+    assert_eq!(extract(ExtractMode::Tree), (9, "(F (LA) (LB))".into()));
+    assert_eq!(extract(ExtractMode::Dag), (9, "(F (LA) (LB))".into()));
+    assert_eq!(
+        extract(ExtractMode::DagExact),
+        (8, "(F (P (Big)) (Q (Big)))".into())
+    );
+
+    egraph
+        .parse_and_run_program("(extract r :mode dag-exact)")
+        .unwrap();
+    assert!(matches!(
+        egraph.get_extract_report(),
+        Some(ExtractReport::Best { cost: 8, .. })
+    ));
+}
+
+/// Charges more for constants that do not fit in a byte.
+struct ImmediateCost;
+
+impl CostFunction for ImmediateCost {
+    type Cost = f64;
+
+    fn cost(&self, _: &EGraph, sym: GlobalSymbol, inputs: &[Value], input_costs: &[f64]) -> f64 {
+        let cost = match sym.as_str() {
+            "Const" if (inputs[0].bits as i64).abs() < 256 => 1.0,
+            "Const" => 8.0,
+            _ => 2.0,
+        };
+        cost + input_costs.iter().sum::<f64>()
+    }
+
+    fn value_cost(&self, _: &EGraph, _: &dyn Sort, _: Value, element_costs: &[f64]) -> f64 {
+        element_costs.iter().sum()
+    }
+}
+
+#[test]
+fn test_cost_function() {
+    let mut egraph = EGraph::default();
+    egraph
+        .parse_and_run_program(
+            "(datatype Expr (Const i64) (Mul Expr Expr))
+             (sort Exprs (Vec Expr))
+             (let big (Const 1000))
+             (union big (Mul (Const 10) (Const 100)))
+             (let exprs (vec-of big (Const 1)))",
+        )
+        .unwrap();
+    let big = egraph.eval_expr(&Expr::var("big")).unwrap();
+    let exprs = egraph.eval_expr(&Expr::var("exprs")).unwrap();
+    let extract = |(sort, value)| {
+        let mut termdag = TermDag::default();
+        let (cost, term) = egraph
+            .extract_with_cost_function(value, &mut termdag, &sort, ImmediateCost)
+            .unwrap();
+        (cost, termdag.to_string(&term))
+    };
+    assert_eq!(
+        extract(big.clone()),
+        (4.0, "(Mul (Const 10) (Const 100))".into())
+    );
+    assert_eq!(
+        extract(exprs),
+        (
+            5.0,
+            "(vec-of (Mul (Const 10) (Const 100)) (Const 1))".into()
+        )
+    );
+    // the default cost model prefers the single constant
+    let (sort, value) = big;
+    let (cost, _) = egraph
+        .extract(value, &mut TermDag::default(), &sort)
+        .unwrap();
+    assert_eq!(cost, 2);
+}
+
+#[test]
+fn test_cost_table() {
+    let mut egraph = EGraph::default();
+    let outputs = egraph
+        .parse_and_run_program(
+            "(function Const-cost (i64) i64)
+             (datatype Expr (Const i64 :cost Const-cost) (Shl Expr i64))
+             (set (Const-cost 1) 1)
+             (set (Const-cost 4096) 8)
+             (union (Const 4096) (Shl (Const 1) 12))
+             (extract (Const 4096))
+             (extract (Const 1))",
+        )
+        .unwrap();
+    assert_eq!(outputs, ["(Shl (Const 1) 12)", "(Const 1)"]);
+}
+
+#[test]
+fn test_incremental_extraction() {
+    let mut egraph = EGraph::default();
+    let outputs = egraph
+        .parse_and_run_program(
+            r#"(datatype Math (Num i64) (Var String) (Add Math Math) (Mul Math Math :cost 4) (Neg Math))
+               (let e (Mul (Var "a") (Num 2)))
+               (extract (Neg e))
+               (union e (Add (Var "a") (Var "a")))
+               (extract (Neg e))
+               (subsume (Add (Var "a") (Var "a")))
+               (extract e)
+               (union e (Var "c"))
+               (extract (Neg e))
+               (delete (Var "c"))
+               (extract e)
+               (push)
+               (union e (Var "b"))
+               (extract e)
+               (pop)
+               (extract e)"#,
+        )
+        .unwrap();
+    assert_eq!(
+        outputs,
+        [
+            r#"(Neg (Mul (Var "a") (Num 2)))"#,
+            r#"(Neg (Add (Var "a") (Var "a")))"#,
+            r#"(Mul (Var "a") (Num 2))"#,
+            r#"(Neg (Var "c"))"#,
+            r#"(Mul (Var "a") (Num 2))"#,
+            r#"(Var "b")"#,
+            r#"(Mul (Var "a") (Num 2))"#,
+        ]
+    );
+}
+
+#[test]
+fn test_extract_k_best() {
+    let mut egraph = EGraph::default();
+    let outputs = egraph
+        .parse_and_run_program(
+            "(datatype Math (Num i64) (Add Math Math))
+             (let one (Num 1))
+             (union one (Add (Num 0) one))
+             (extract (Add one one) :best 3)",
+        )
+        .unwrap();
+    assert_eq!(
+        outputs,
+        ["(\n   (Add (Num 1) (Num 1))\n   (Add (Num 1) (Add (Num 0) (Num 1)))\n   (Add (Add (Num 0) (Num 1)) (Num 1))\n)"]
+    );
+    let Some(ExtractReport::Ranked { terms, .. }) = egraph.get_extract_report() else {
+        panic!("expected a ranked extract report");
+    };
+    let costs: Vec<_> = terms.iter().map(|(cost, _)| *cost).collect();
+    assert_eq!(costs, [5, 8, 8]);
+
+    assert!(egraph
+        .parse_and_run_program("(extract (Add one one) :mode dag :best 3)")
+        .is_err());
+}
+
+#[test]
+fn test_constrained_extraction() {
+    let mut egraph = EGraph::default();
+    let outputs = egraph
+        .parse_and_run_program(
+            r#"(datatype Math (Num i64) (Var String) (Add Math Math) (Div Math Math) (Shl Math i64))
+               (let e (Div (Var "x") (Num 2)))
+               (union e (Shl (Var "x") -1))
+               (extract (Add e e))
+               (extract (Add e e) :forbid (Shl))"#,
+        )
+        .unwrap();
+    assert_eq!(
+        outputs,
+        [
+            r#"(Add (Shl (Var "x") -1) (Shl (Var "x") -1))"#,
+            r#"(Add (Div (Var "x") (Num 2)) (Div (Var "x") (Num 2)))"#,
+        ]
+    );
+    assert!(egraph
+        .parse_and_run_program("(extract (Add e e) :require-root Div)")
+        .is_err());
+    assert!(egraph
+        .parse_and_run_program("(extract e :forbid (Sub))")
+        .is_err());
+
+    // only forbid shifts by negative amounts
+    let (sort, value) = egraph.eval_expr(&Expr::var("e")).unwrap();
+    let mut termdag = TermDag::default();
+    let (_, term) = egraph
+        .extract_filtered(
+            value,
+            &mut termdag,
+            &sort,
+            |sym, inputs| sym.as_str() != "Shl" || (inputs[1].bits as i64) >= 0,
+            |_, _| true,
+        )
+        .unwrap();
+    assert_eq!(termdag.to_string(&term), r#"(Div (Var "x") (Num 2))"#);
+}
+
+#[test]
+fn test_dump_program() {
+    let path = std::env::temp_dir().join(format!("egglog-dump-{}.egg", std::process::id()));
+    let program = r#"(datatype Math (Num i64) (Var String) (Add Math Math) (Neg Math))
+        (sort MathVec (Vec Math))
+        (function sum (MathVec) Math)
+        (function weight (Math) Rational :merge (min old new))
+        (function depth (Math) i64 :merge (max old new) :default 0)
+        (relation leaf (Math))
+        (rewrite (Add a b) (Add b a))
+        (let x (Add (Var "x") (Num 1)))
+        (union (Neg (Num 1)) (Num -1))
+        (union (Neg (Neg (Var "y"))) (Var "y"))
+        (union (sum (vec-of (Num 1) x)) (Add (Num 1) x))
+        (set (weight x) (rational 1 3))
+        (set (depth x) 2)
+        (leaf (Num 1))
+        (Add (Var "z") (Num 0))
+        (subsume (Add (Var "z") (Num 0)))
+        (run 2)"#;
+    let sizes = "(print-size Num) (print-size Var) (print-size Add) (print-size Neg)
+        (print-size sum) (print-size weight) (print-size depth) (print-size leaf)";
+
+    let mut egraph = EGraph::default();
+    egraph.parse_and_run_program(program).unwrap();
+    let expected = egraph.parse_and_run_program(sizes).unwrap();
+    egraph
+        .parse_and_run_program(&format!(r#"(dump "{}")"#, path.display()))
+        .unwrap();
+    let dumped = std::fs::read_to_string(&path).unwrap();
+    assert_eq!(dumped, egraph.to_program());
+
+    let mut replayed = EGraph::default();
+    replayed.parse_and_run_program(&dumped).unwrap();
+    assert_eq!(replayed.parse_and_run_program(sizes).unwrap(), expected);
+    replayed
+        .parse_and_run_program(
+            r#"(check (= x (Add (Num 1) (Var "x"))))
+               (check (= (Neg (Num 1)) (Num -1)))
+               (check (= (Neg (Neg (Var "y"))) (Var "y")))
+               (check (= (sum (vec-of (Num 1) x)) (Add x (Num 1))))
+               (check (= (weight x) (rational 1 3)))
+               (check (= (depth x) 2))
+               (check (leaf (Num 1)))
+               (fail (check (Neg (Var "x"))))"#,
+        )
+        .unwrap();
+
+    // the subsumed row stays subsumed
+    let mut replayed = EGraph::default();
+    replayed
+        .parse_and_run_program(&format!("{dumped}(rewrite (Add a (Num 0)) a) (run 1)"))
+        .unwrap();
+    replayed
+        .parse_and_run_program(r#"(fail (check (= (Var "z") (Add (Var "z") (Num 0)))))"#)
+        .unwrap();
+
+    std::fs::remove_file(&path).unwrap();
+}
+
+#[test]
+fn test_extract_choices() {
+    let mut egraph = EGraph::default();
+    egraph
+        .parse_and_run_program(
+            r#"(datatype Math (Num i64) (Var String) (Add Math Math) (Mul Math Math :cost 3) (Shl Math Math))
+               (let e (Mul (Var "x") (Num 2)))
+               (union e (Shl (Var "x") (Num 1)))
+               (let one (Num 1))
+               (union one (Add (Num 1) (Num 0)))"#,
+        )
+        .unwrap();
+    let serialized = egraph.serialize(SerializeConfig::default());
+    // choose a node other than a shift or an addition for each e-class
+    let mut choices = indexmap::IndexMap::new();
+    for (id, node) in &serialized.nodes {
+        if node.op != "Shl" && node.op != "Add" {
+            choices.insert(node.eclass.clone(), id.clone());
+        }
+    }
+    let (sort, e) = egraph.eval_expr(&Expr::var("e")).unwrap();
+    let mut termdag = TermDag::default();
+    let (cost, term) = egraph
+        .extract_choices(e, &mut termdag, &sort, &choices)
+        .unwrap();
+    assert_eq!(termdag.to_string(&term), r#"(Mul (Var "x") (Num 2))"#);
+    assert_eq!(cost, 7);
+
+    egraph.push();
+    egraph.set_preferred_choices(&choices).unwrap();
+    let outputs = egraph
+        .parse_and_run_program(r#"(extract e) (extract (Add e (Var "y")))"#)
+        .unwrap();
+    assert_eq!(
+        outputs,
+        [
+            r#"(Mul (Var "x") (Num 2))"#,
+            r#"(Add (Mul (Var "x") (Num 2)) (Var "y"))"#,
+        ]
+    );
+    egraph.pop().unwrap();
+    let outputs = egraph.parse_and_run_program("(extract e)").unwrap();
+    assert_eq!(outputs, [r#"(Shl (Var "x") (Num 1))"#]);
+
+    // the addition is in the e-class of one of its own inputs
+    let mut cyclic = choices.clone();
+    for (id, node) in &serialized.nodes {
+        if node.op == "Add" {
+            cyclic.insert(node.eclass.clone(), id.clone());
+        }
+    }
+    let (_, one) = egraph.eval_expr(&Expr::var("one")).unwrap();
+    assert!(matches!(
+        egraph.extract_choices(one, &mut termdag, &sort, &cyclic),
+        Err(Error::ExtractError(_))
+    ));
+    egraph.set_preferred_choices(&cyclic).unwrap();
+    let outputs = egraph.parse_and_run_program("(extract one)").unwrap();
+    assert_eq!(outputs, ["(Add (Num 1) (Num 0))"]);
+
+    // choosing a node of another e-class
+    let e_class = serialized
+        .nodes
+        .values()
+        .find(|node| node.op == "Mul")
+        .unwrap()
+        .eclass
+        .clone();
+    let (var, _) = serialized
+        .nodes
+        .iter()
+        .find(|(_, node)| node.op == "Var")
+        .unwrap();
+    let mut wrong = choices.clone();
+    wrong.insert(e_class, var.clone());
+    assert!(egraph.set_preferred_choices(&wrong).is_err());
+}
+
+#[test]
+fn test_input_errors() {
+    let path = std::env::temp_dir().join(format!("egglog-input-{}.csv", std::process::id()));
+    let mut egraph = EGraph::default();
+    egraph
+        .parse_and_run_program(
+            "(datatype Math (Num i64))
+             (function f (Math bool) i64)",
+        )
+        .unwrap();
+    let input = |egraph: &mut EGraph, options: &str| {
+        egraph.parse_and_run_program(&format!(r#"(input f "{}" {options})"#, path.display()))
+    };
+
+    let result = input(&mut egraph, "");
+    assert!(matches!(result.unwrap_err().inner(), Error::IoError(..)));
+
+    // errors report the line they are on, counting blank lines
+    std::fs::write(&path, "(Num 1)\ttrue\t1\n\n(Num 2)\tyes\t2\n").unwrap();
+    let result = input(&mut egraph, "");
+    assert!(matches!(
+        result.unwrap_err().inner(),
+        Error::InputError(_, 3, _)
+    ));
+
+    std::fs::write(&path, "(Num 1)\ttrue\n").unwrap();
+    let result = input(&mut egraph, "");
+    assert!(matches!(
+        result.unwrap_err().inner(),
+        Error::InputError(_, 1, _)
+    ));
+
+    std::fs::write(&path, "(Num 1)\ttrue\t\"1\n").unwrap();
+    let result = input(&mut egraph, "");
+    assert!(matches!(
+        result.unwrap_err().inner(),
+        Error::InputError(_, 1, _)
+    ));
+
+    std::fs::write(&path, "x;y;z\n(Num 1);true;1\n").unwrap();
+    let result = input(&mut egraph, r#":delimiter ";" :columns ("x" "y" "w")"#);
+    assert!(matches!(
+        result.unwrap_err().inner(),
+        Error::InputError(_, 1, _)
+    ));
+
+    std::fs::remove_file(&path).unwrap();
+}
+
+#[test]
+fn test_error_locations() {
+    let mut egraph = EGraph::default();
+    let program = "(datatype Math (Num i64))\n(Num 1)\n  (check (= (Num 1) (Num 2)))\n";
+    let error = egraph
+        .parse_and_run_source(Some("checks.egg".into()), program)
+        .unwrap_err();
+    assert!(matches!(error.inner(), Error::CheckError(..)));
+    assert_eq!(error.span().unwrap().to_string(), "checks.egg:3:3");
+    let message = error.to_string();
+    assert!(message.starts_with("checks.egg:3:3: Check failed"));
+    assert!(message.ends_with(&format!(
+        "  |\n3 |   (check (= (Num 1) (Num 2)))\n  |   {}",
+        "^".repeat(27)
+    )));
+
+    // errors in included files are located in them
+    let path = std::env::temp_dir().join(format!("egglog-include-{}.egg", std::process::id()));
+    std::fs::write(&path, "(Num 3)\n(let x (Num \"three\"))\n").unwrap();
+    let error = egraph
+        .parse_and_run_source(
+            Some("main.egg".into()),
+            &format!("(Num 4)\n(include \"{}\")", path.display()),
+        )
+        .unwrap_err();
+    assert!(matches!(error.inner(), Error::TypeError(_)));
+    assert_eq!(
+        error.span().unwrap().to_string(),
+        format!("{}:2:1", path.display())
+    );
+    std::fs::remove_file(&path).unwrap();
+
+    let error = egraph
+        .parse_and_run_source(Some("parse.egg".into()), "(check\n  (= (Num 1) (Num 1)")
+        .unwrap_err();
+    assert!(matches!(error.inner(), Error::ParseError(_)));
+    assert_eq!(error.span().unwrap().to_string(), "parse.egg:2:21");
+}
+
+#[test]
+fn test_check_nearest_match() {
+    let mut egraph = EGraph::default();
+    egraph
+        .parse_and_run_program(
+            "(datatype Math (Num i64) (Add Math Math) (Neg Math))
+             (Add (Num 3) (Num 4))",
+        )
+        .unwrap();
+
+    let error = egraph
+        .parse_and_run_program("(check (= e (Add x y)) (= x (Num n)) (< n 2))")
+        .unwrap_err();
+    let Error::CheckError(_, nearest) = error.inner() else {
+        panic!("expected a check error");
+    };
+    assert_eq!(nearest.matched, 2);
+    assert!(nearest
+        .bindings
+        .contains(&("x".into(), "(Num 3)".to_string())));
+    assert!(nearest.bindings.contains(&("n".into(), "3".to_string())));
+    assert_eq!(nearest.failed, "(< n 2)");
+    assert!(nearest
+        .to_string()
+        .starts_with("atoms 1–2 matched with x=(Num 3)"));
+
+    let error = egraph
+        .parse_and_run_program("(check (Neg (Num 5)))")
+        .unwrap_err();
+    let Error::CheckError(_, nearest) = error.inner() else {
+        panic!("expected a check error");
+    };
+    assert_eq!(nearest.matched, 0);
+    assert_eq!(nearest.to_string(), "atom 1 `(Num 5)` had no rows");
+}
+
+#[test]
+fn test_recoverable_errors() {
+    let mut egraph = EGraph::default();
+    egraph
+        .parse_and_run_program(
+            "(datatype Math (Num i64))
+             (function Hidden () Math :unextractable)
+             (function num () i64)
+             (set (num) 1)
+             (function quotient () i64)
+             (ruleset divide)
+             (rule ((= x (num))) ((set (quotient) (/ x 0))) :ruleset divide)",
+        )
+        .unwrap();
+    let mut error = |program: &str| egraph.parse_and_run_program(program).unwrap_err();
+
+    assert!(matches!(
+        error("(set-option no_such_option 1)").inner(),
+        Error::OptionError(_)
+    ));
+    assert!(matches!(
+        error("(set-option match_limit -1)").inner(),
+        Error::OptionError(_)
+    ));
+    assert!(matches!(
+        error("(set-option time_limit \"soon\")").inner(),
+        Error::OptionError(_)
+    ));
+    assert!(matches!(
+        error("(ruleset divide)").inner(),
+        Error::RulesetAlreadyPresent(_)
+    ));
+    assert!(matches!(
+        error("(rule ((= x (num))) ((set (quotient) (/ x 0))) :ruleset divide)").inner(),
+        Error::RuleAlreadyPresent(_)
+    ));
+    assert!(matches!(
+        error("(include \"no/such/file.egg\")").inner(),
+        Error::IoError(..)
+    ));
+    match error("(run divide 1)").inner() {
+        Error::RuleError(_, inner) => assert!(matches!(**inner, Error::PrimitiveError(..))),
+        error => panic!("expected a rule error, got {error:?}"),
+    }
+    assert!(matches!(
+        error("(extract (Hidden))").inner(),
+        Error::ExtractError(_)
+    ));
+
+    // the e-graph is still usable afterwards
+    let outputs = egraph
+        .parse_and_run_program(
+            "(check (= (num) 1))
+             (ruleset divide-2)
+             (extract (Num 2))",
+        )
+        .unwrap();
+    assert_eq!(outputs, ["(Num 2)"]);
+}
+
+#[test]
+fn test_input_json_errors() {
+    let path = std::env::temp_dir().join(format!("egglog-input-{}.json", std::process::id()));
+    let mut egraph = EGraph::default();
+    egraph
+        .parse_and_run_program("(function f (i64 String) bool)")
+        .unwrap();
+    let mut input = |json: &str, columns: &str| {
+        std::fs::write(&path, json).unwrap();
+        let result = egraph
+            .parse_and_run_program(&format!(r#"(input-json f "{}" {columns})"#, path.display()));
+        match result.as_ref().map_err(Error::inner) {
+            Err(Error::JsonInputError(_, message)) => message.clone(),
+            _ => panic!("expected a JSON input error, got {result:?}"),
+        }
+    };
+
+    assert!(input(r#"[[1, "a", true], ["2", "b", false]]"#, "").starts_with("row 2:"));
+    assert!(input(r#"[[1, "a"]]"#, "").starts_with("row 1:"));
+    assert!(input(r#"[{"x": 1, "y": "a", "z": true}]"#, "").starts_with("row 1:"));
+    let columns = r#":columns ("x" "y" "z")"#;
+    assert!(input(r#"[{"x": 1, "y": "a", "z": true}, {"x": 1}]"#, columns).starts_with("row 2:"));
+    input(r#"{"x": 1}"#, "");
+    input("[[1, ", "");
+
+    std::fs::remove_file(&path).unwrap();
+}
+
+#[test]
+fn test_export_function() {
+    let dir = std::env::temp_dir();
+    let path = |ext: &str| dir.join(format!("egglog-export-{}.{ext}", std::process::id()));
+    let (csv, tsv, json) = (path("csv"), path("tsv"), path("json"));
+
+    let mut egraph = EGraph::default();
+    egraph
+        .parse_and_run_program(
+            r#"(datatype Math (Num i64) (Var String) (Add Math Math))
+               (function label (Math String) Rational)
+               (let three (Num 3))
+               (let x (Var "x"))
+               (union three (Add (Num 1) (Num 2)))
+               (set (label three "a, ""b""") (rational 1 2))
+               (set (label x "c") (rational -2 1))"#,
+        )
+        .unwrap();
+    // e-classes are written as their canonical ids at the time of export
+    let (_, three) = egraph.eval_expr(&Expr::var("three")).unwrap();
+    let (_, x) = egraph.eval_expr(&Expr::var("x")).unwrap();
+    let (three, x) = (egraph.find(three).bits, egraph.find(x).bits);
+    egraph
+        .parse_and_run_program(&format!(
+            r#"(export-function label "{}")
+               (export-function label "{}" :format tsv :terms)
+               (export-function label "{}" :format json :terms)"#,
+            csv.display(),
+            tsv.display(),
+            json.display()
+        ))
+        .unwrap();
+
+    assert_eq!(
+        std::fs::read_to_string(&csv).unwrap(),
+        format!("{three},\"a, \"\"b\"\"\",1/2\n{x},c,-2/1\n")
+    );
+    assert_eq!(
+        std::fs::read_to_string(&tsv).unwrap(),
+        "(Num 3)\t\"a, \"\"b\"\"\"\t1/2\n\"(Var \"\"x\"\")\"\tc\t-2/1\n"
+    );
+    assert_eq!(
+        std::fs::read_to_string(&json).unwrap(),
+        "[\n  [\"(Num 3)\", \"a, \\\"b\\\"\", \"1/2\"],\n  [\"(Var \\\"x\\\")\", \"c\", \"-2/1\"]\n]\n"
+    );
+
+    // the exported terms can be read back
+    egraph
+        .parse_and_run_program(&format!(
+            r#"(function relabel (Math String) Rational)
+               (input relabel "{}")
+               (check (= (relabel (Add (Num 1) (Num 2)) "a, ""b""") (rational 1 2)))
+               (check (= (relabel (Var "x") "c") (rational -2 1)))"#,
+            tsv.display()
+        ))
+        .unwrap();
+
+    for file in [csv, tsv, json] {
+        std::fs::remove_file(file).unwrap();
+    }
+}
+
+#[test]
+fn test_format_program() {
+    let program = "(datatype Math (Num i64) (Add Math Math)) ; numbers
+; commutativity
+(rewrite (Add a b) (Add b a) :ruleset comm)
+
+
+(rule ((= e (Add (Num a) (Num b)))) ((union e (Num (+ a b)))))
+(run-schedule (saturate (run comm)))
+";
+    let formatted = format_program(program).unwrap();
+    assert_eq!(
+        formatted,
+        "(datatype Math
+  (Num i64)
+  (Add Math Math)) ; numbers
+; commutativity
+(rewrite (Add a b)
+         (Add b a)
+         :ruleset comm)
+
+(rule ((= e (Add (Num a) (Num b))))
+      ((union e (Num (+ a b)))))
+(run-schedule
+  (saturate (run comm)))
+"
+    );
+    assert_eq!(format_program(&formatted).unwrap(), formatted);
+
+    assert!(matches!(
+        format_program("(rule ((Add a b))").unwrap_err().inner(),
+        Error::ParseError(_)
+    ));
+}
+
+#[test]
+fn test_inspect_egraph() {
+    let mut egraph = EGraph::default();
+    egraph
+        .parse_and_run_program(
+            r#"(datatype Math (Num i64) (Add Math Math))
+            (ruleset comm)
+            (rule ((= e (Add a b))) ((union e (Add b a))) :ruleset comm :name "swap")
+            (Add (Num 1) (Num 2))
+            (run comm 1)"#,
+        )
+        .unwrap();
+
+    let sizes: Vec<_> = egraph
+        .table_sizes()
+        .into_iter()
+        .map(|(name, size)| (name.to_string(), size))
+        .collect();
+    assert_eq!(sizes, [("Add".into(), 2), ("Num".into(), 2)]);
+
+    // the rules are shown as a program that declares them again
+    let rules = egraph.parse_program(&egraph.rules_program()).unwrap();
+    assert_eq!(rules.len(), 2);
+    assert!(matches!(&rules[0], Command::AddRuleset(name) if name.as_str() == "comm"));
+    assert!(matches!(
+        &rules[1],
+        Command::Rule { name, ruleset, .. } if name.as_str() == "swap" && ruleset.as_str() == "comm"
+    ));
+
+    let sort = egraph.typecheck_expr("(Add (Num 1) (Num 2))").unwrap();
+    assert_eq!(sort.name().as_str(), "Math");
+    assert_eq!(
+        egraph.typecheck_expr("(+ 1 2)").unwrap().name().as_str(),
+        "i64"
+    );
+    assert!(egraph.typecheck_expr("(Add 1 2)").is_err());
+    assert!(egraph.typecheck_expr("(Add").is_err());
+}